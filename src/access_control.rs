@@ -0,0 +1,124 @@
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{BoxFuture, FutureExt};
+
+use crate::packet::RejectReason;
+
+/// What a listener's access control callback decided about a caller, see
+/// `SrtSocketBuilder::access_control`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessDecision {
+    /// Let the caller connect, with settings of its own
+    Accept(AcceptParams),
+
+    /// Refuse the caller, telling it why
+    Reject(RejectReason),
+}
+
+/// Settings for one accepted connection, overriding the listener's
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AcceptParams {
+    latency: Option<Duration>,
+    passphrase: Option<String>,
+}
+
+impl AcceptParams {
+    /// Accept with the listener's settings
+    #[must_use]
+    pub fn new() -> Self {
+        AcceptParams::default()
+    }
+
+    /// The latency to offer this caller instead of the listener's. The caller
+    /// still gets its own latency if that's higher
+    #[must_use]
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+
+        self
+    }
+
+    /// The passphrase to unwrap this caller's key with instead of the
+    /// listener's, for callers with passphrases of their own. Like the
+    /// listener's, it has to be 10 to 79 characters long to work with the
+    /// reference implementation
+    #[cfg(feature = "crypto")]
+    #[must_use]
+    pub fn passphrase(mut self, passphrase: String) -> Self {
+        self.passphrase = Some(passphrase);
+
+        self
+    }
+
+    /// The passphrase to use, given the listener's
+    pub(crate) fn passphrase_or(&self, listener_passphrase: Option<&str>) -> Option<String> {
+        self.passphrase
+            .as_deref()
+            .or(listener_passphrase)
+            .map(String::from)
+    }
+
+    /// The latency to offer, given the listener's
+    pub(crate) fn latency_or(&self, listener_latency: Duration) -> Duration {
+        self.latency.unwrap_or(listener_latency)
+    }
+}
+
+type AccessControlFn =
+    dyn Fn(SocketAddr, Option<String>) -> BoxFuture<'static, AccessDecision> + Send + Sync;
+
+/// The access control callback, shared by every connection a listener accepts
+#[derive(Clone)]
+pub(crate) struct AccessControl(Arc<AccessControlFn>);
+
+impl AccessControl {
+    pub fn new<F, Fut>(callback: F) -> Self
+    where
+        F: Fn(SocketAddr, Option<String>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = AccessDecision> + Send + 'static,
+    {
+        AccessControl(Arc::new(move |addr, stream_id| {
+            callback(addr, stream_id).boxed()
+        }))
+    }
+
+    /// Decide about the caller at `addr` that asked for `stream_id`
+    pub async fn check(&self, addr: SocketAddr, stream_id: Option<String>) -> AccessDecision {
+        (self.0)(addr, stream_id).await
+    }
+}
+
+impl Debug for AccessControl {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "AccessControl")
+    }
+}
+
+/// Which addresses a listener answers handshakes from, consulted before the
+/// handshake is looked at
+#[derive(Clone)]
+pub(crate) struct AddressFilter(Arc<dyn Fn(SocketAddr) -> bool + Send + Sync>);
+
+impl AddressFilter {
+    pub fn new<F>(filter: F) -> Self
+    where
+        F: Fn(SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        AddressFilter(Arc::new(filter))
+    }
+
+    /// If handshakes from `addr` should be answered
+    pub fn allows(&self, addr: SocketAddr) -> bool {
+        (self.0)(addr)
+    }
+}
+
+impl Debug for AddressFilter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "AddressFilter")
+    }
+}
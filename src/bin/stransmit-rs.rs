@@ -63,17 +63,15 @@ enum DataType<'a> {
 }
 
 fn read_to_stream(read: impl AsyncRead + Unpin) -> impl Stream<Item = Result<Bytes, Error>> {
-    stream::unfold(read, |mut source| {
-        async move {
-            let mut buf = [0; 4096];
-            let bytes_read = match source.read(&mut buf[..]).await {
-                Ok(0) => return None,
-                Ok(bytes_read) => bytes_read,
-                Err(e) => return Some((Err(Error::from(e)), source)),
-            };
-
-            Some((Ok(Bytes::copy_from_slice(&buf[0..bytes_read])), source))
-        }
+    stream::unfold(read, |mut source| async move {
+        let mut buf = [0; 4096];
+        let bytes_read = match source.read(&mut buf[..]).await {
+            Ok(0) => return None,
+            Ok(bytes_read) => bytes_read,
+            Err(e) => return Some((Err(Error::from(e)), source)),
+        };
+
+        Some((Ok(Bytes::copy_from_slice(&buf[0..bytes_read])), source))
     })
 }
 
@@ -131,7 +129,7 @@ fn local_port_addr(url: &Url, kind: &str) -> Result<(u16, Option<SocketAddr>), E
     Ok(match url.host() {
         // no host means bind to the port specified
         None => (port, None),
-        Some(Host::Domain(d)) if d == "" => (port, None),
+        Some(Host::Domain("")) => (port, None),
 
         // if host is specified, bind to 0
         Some(Host::Domain(d)) => (
@@ -241,8 +239,7 @@ fn resolve_input<'a>(
                             input_url
                                 .query_pairs()
                                 .find_map(|(a, b)| if a == "rendezvous" { Some(b) } else { None })
-                                .as_ref()
-                                .map(|a| &**a),
+                                .as_deref(),
                         )?).local_port(input_local_port);
 
                         builder = add_srt_args(input_url.query_pairs(), builder)?;
@@ -266,7 +263,7 @@ fn resolve_input<'a>(
                         .map(Result::unwrap)
                         .boxed())
                 }
-                    .boxed()
+                .boxed()
             } else {
                 let file = file.to_owned();
                 async move {
@@ -274,7 +271,7 @@ fn resolve_input<'a>(
 
                     Ok(read_to_stream(f).map(Result::unwrap).boxed())
                 }
-                    .boxed()
+                .boxed()
             }
         }
     })
@@ -308,8 +305,7 @@ fn resolve_output<'a>(
                             output_url
                                 .query_pairs()
                                 .find_map(|(a, b)| if a == "rendezvous" { Some(b) } else { None })
-                                .as_ref()
-                                .map(|a| &**a),
+                                .as_deref(),
                         )?).local_port(output_local_port);
 
                         let is_multiplex = match (
@@ -355,7 +351,7 @@ fn resolve_output<'a>(
                         .sink_map_err(Error::from)
                         .boxed_sink())
                 }
-                    .boxed()
+                .boxed()
             } else {
                 let file = file.to_owned();
                 async move {
@@ -364,7 +360,7 @@ fn resolve_output<'a>(
                         .sink_map_err(Error::from)
                         .boxed_sink())
                 }
-                    .boxed()
+                .boxed()
             }
         }
     })
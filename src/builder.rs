@@ -1,27 +1,89 @@
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
 use std::time::Duration;
 
-use failure::{bail, Error};
-use rand;
-use tokio::net::UdpSocket;
+use failure::{bail, format_err, Error};
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::time::{delay_for, timeout};
 use tokio_util::udp::UdpFramed;
 
-use futures::{Sink, Stream};
+use futures::channel::oneshot;
+use futures::{future, select, Future, FutureExt, Sink, SinkExt, Stream};
 
-use crate::pending_connection;
+use crate::access_control::{AccessControl, AddressFilter};
+use crate::multiplex::{DEFAULT_HANDSHAKE_RATE, DEFAULT_MAX_PENDING_HANDSHAKES};
+use crate::packet::{RejectReason, SocketType};
+use crate::pending_connection::{self, HandshakeConfig};
+use crate::receiver::UndecryptableHandler;
+use crate::sender::SendDropHandler;
 use crate::socket::create_bidrectional_srt;
-use crate::MultiplexServer;
-use crate::{Packet, PacketCodec, SrtSocket};
+use crate::{AccessDecision, ConnectTimeout, KeyLengthMismatch, MultiplexServer, SrtListener};
+use crate::{
+    CongestCtrlType, Connection, Packet, PacketCodec, RetransmitAlgorithm, RetransmitPriority,
+    SendDropEvent, SocketID, SrtSocket, SrtVersion, StreamSocket, Udt4PacketCodec,
+    UndecryptableEvent,
+};
 
 pub type UnderlyingSocket = UdpFramed<PacketCodec>;
 
+/// The longest stream ID the reference implementation accepts
+const MAX_STREAM_ID_LEN: usize = 512;
+
+/// The lengths of passphrase the reference implementation accepts
+const PASSPHRASE_LEN: RangeInclusive<usize> = 10..=79;
+
+/// How long connecting may take by default, the same as the reference implementation
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long to wait before trying the next address, the connection attempt
+/// delay RFC 8305 recommends
+const DEFAULT_FALLBACK_DELAY: Duration = Duration::from_millis(250);
+
 /// Struct to build sockets
 #[derive(Debug, Clone)]
 pub struct SrtSocketBuilder {
     local_addr: SocketAddr,
     conn_type: ConnInitMethod,
-    latency: Duration,
-    crypto: Option<(u8, String)>,
+    receive_latency: Duration,
+    peer_latency: Duration,
+    passphrase: Option<String>,
+    key_length: Option<u8>,
+    enforced_encryption: bool,
+    #[cfg(feature = "crypto")]
+    key_refresh: Option<(u64, u64)>,
+    #[cfg(feature = "crypto")]
+    key_refresh_period: Option<Duration>,
+    undecryptable_handler: Option<UndecryptableHandler>,
+    send_drop_handler: Option<SendDropHandler>,
+    peer_stats: bool,
+    initial_send_rate: Option<u64>,
+    name: Option<String>,
+    min_send_interval: Option<Duration>,
+    precise_pacing: bool,
+    too_late_drop: bool,
+    retransmit_algorithm: RetransmitAlgorithm,
+    retransmit_priority: RetransmitPriority,
+    reorder_tolerance: u32,
+    ack_interval: Option<Duration>,
+    send_buffer_size: Option<usize>,
+    nonblocking_send: bool,
+    linger: Option<Duration>,
+    max_bandwidth: Option<u64>,
+    bandwidth_overhead: Option<u32>,
+    stream_id: Option<String>,
+    trace_packets: bool,
+    udt4: bool,
+    min_peer_version: Option<SrtVersion>,
+    congest_ctrl: CongestCtrlType,
+    socket_type: SocketType,
+    verify_peer_address: bool,
+    connect_timeout: Duration,
+    fallback_addrs: Vec<SocketAddr>,
+    fallback_delay: Duration,
+    address_filter: Option<AddressFilter>,
+    access_control: Option<AccessControl>,
+    max_pending_handshakes: usize,
+    handshake_rate: u32,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -41,8 +103,46 @@ impl SrtSocketBuilder {
         SrtSocketBuilder {
             local_addr: "0.0.0.0:0".parse().unwrap(),
             conn_type,
-            latency: Duration::from_millis(50),
-            crypto: None,
+            receive_latency: Duration::from_millis(50),
+            peer_latency: Duration::from_millis(50),
+            passphrase: None,
+            key_length: None,
+            enforced_encryption: false,
+            #[cfg(feature = "crypto")]
+            key_refresh: None,
+            #[cfg(feature = "crypto")]
+            key_refresh_period: None,
+            undecryptable_handler: None,
+            send_drop_handler: None,
+            peer_stats: false,
+            initial_send_rate: None,
+            name: None,
+            min_send_interval: None,
+            precise_pacing: false,
+            too_late_drop: false,
+            retransmit_algorithm: RetransmitAlgorithm::default(),
+            retransmit_priority: RetransmitPriority::default(),
+            reorder_tolerance: 0,
+            ack_interval: None,
+            send_buffer_size: None,
+            nonblocking_send: false,
+            linger: None,
+            max_bandwidth: None,
+            bandwidth_overhead: None,
+            stream_id: None,
+            trace_packets: false,
+            udt4: false,
+            min_peer_version: None,
+            congest_ctrl: CongestCtrlType::Live,
+            socket_type: SocketType::Datagram,
+            verify_peer_address: false,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            fallback_addrs: Vec::new(),
+            fallback_delay: DEFAULT_FALLBACK_DELAY,
+            address_filter: None,
+            access_control: None,
+            max_pending_handshakes: DEFAULT_MAX_PENDING_HANDSHAKES,
+            handshake_rate: DEFAULT_HANDSHAKE_RATE,
         }
     }
 
@@ -65,21 +165,513 @@ impl SrtSocketBuilder {
         self
     }
 
+    /// Set both the receive latency and the peer latency, 50ms by default
     #[must_use]
     pub fn latency(mut self, latency: Duration) -> Self {
-        self.latency = latency;
+        self.receive_latency = latency;
+        self.peer_latency = latency;
+
+        self
+    }
+
+    /// The TSBPD latency this end's receiver delivers with, at least. The
+    /// peer can ask for more with its peer latency, the larger one is used
+    #[must_use]
+    pub fn receive_latency(mut self, latency: Duration) -> Self {
+        self.receive_latency = latency;
+
+        self
+    }
+
+    /// The TSBPD latency the peer's receiver has to deliver with, at least.
+    /// The peer can use more if its receive latency is larger
+    #[must_use]
+    pub fn peer_latency(mut self, latency: Duration) -> Self {
+        self.peer_latency = latency;
+
+        self
+    }
+
+    /// Encrypt payloads with AES-CTR, with a key wrapped with `passphrase`
+    /// for the handshake, so the peer has to have the same passphrase
+    ///
+    /// The passphrase has to be 10 to 79 characters long, as in the reference
+    /// implementation
+    #[cfg(feature = "crypto")]
+    #[must_use]
+    pub fn passphrase(mut self, passphrase: String) -> Self {
+        self.passphrase = Some(passphrase);
+
+        self
+    }
+
+    /// Encrypt with a key of `len` bytes: 16, 24 or 32 for AES-128, AES-192
+    /// or AES-256. Defaults to 16
+    ///
+    /// Only applies with a passphrase. When connecting the key is sent to the
+    /// listener, which uses whatever length it's sent unless this is set.
+    /// When both ends set it, connecting fails with `KeyLengthMismatch` if
+    /// they differ
+    #[cfg(feature = "crypto")]
+    #[must_use]
+    pub fn key_length(mut self, len: u8) -> Self {
+        self.key_length = Some(len);
+
+        self
+    }
+
+    /// Refuse connections unless both ends have the same passphrase or
+    /// neither has one, instead of connecting with a peer that can't decrypt
+    /// what it's sent. Connecting then fails with `RejectReason::BadSecret`
+    /// or `RejectReason::Unsecure`
+    ///
+    /// Defaults to false. The reference implementation enforces encryption
+    /// by default
+    #[cfg(feature = "crypto")]
+    #[must_use]
+    pub fn enforced_encryption(mut self, enforced: bool) -> Self {
+        self.enforced_encryption = enforced;
+
+        self
+    }
+
+    /// Replace the key payloads are encrypted with after every `rate`
+    /// packets, announcing the new key to the peer `pre_announce` packets
+    /// before switching to it, and keeping the old one for as long after
+    ///
+    /// Only applies with a passphrase. `pre_announce` has to be at least one
+    /// and at most half of `rate`. Defaults to every 2^24 packets, announced
+    /// 2^12 packets before, as in the reference implementation
+    ///
+    /// See `key_refresh_period` to also replace the key after some time
+    #[cfg(feature = "crypto")]
+    #[must_use]
+    pub fn key_refresh(mut self, rate: u64, pre_announce: u64) -> Self {
+        self.key_refresh = Some((rate, pre_announce));
+
+        self
+    }
+
+    /// Also replace the key once it has been in use for `period`, for
+    /// streams slow enough that the refresh rate in packets takes too long.
+    /// The new key is announced when `period` runs out, and switched to the
+    /// `pre_announce` packets of `key_refresh` later
+    ///
+    /// Only applies with a passphrase. Defaults to only counting packets
+    #[cfg(feature = "crypto")]
+    #[must_use]
+    pub fn key_refresh_period(mut self, period: Duration) -> Self {
+        self.key_refresh_period = Some(period);
+
+        self
+    }
+
+    /// Call `handler` each time encrypted packets from the peer start being
+    /// dropped for there being no key to decrypt them with, see
+    /// `SrtSocket::on_undecryptable`
+    ///
+    /// The event's `km_state` tells a passphrase that doesn't match the
+    /// peer's apart from there being none on this end
+    #[must_use]
+    pub fn on_undecryptable<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(UndecryptableEvent) + Send + Sync + 'static,
+    {
+        self.undecryptable_handler = Some(UndecryptableHandler::new(handler));
+
+        self
+    }
+
+    /// Call `handler` each time the sender starts dropping messages instead
+    /// of retransmitting them, see `SrtSocket::on_send_drop`
+    #[must_use]
+    pub fn on_send_drop<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(SendDropEvent) + Send + Sync + 'static,
+    {
+        self.send_drop_handler = Some(SendDropHandler::new(handler));
+
+        self
+    }
+
+    /// Exchange receiver statistics with the peer, if it supports it
+    /// The statistics the peer sends are available from `SrtSocket::peer_stats`
+    ///
+    /// This is an extension to SRT, so it only works between two srt-rs sockets
+    #[must_use]
+    pub fn peer_stats(mut self, peer_stats: bool) -> Self {
+        self.peer_stats = peer_stats;
+
+        self
+    }
+
+    /// Pace sending at `rate` bits/s from the first packet, for when the link
+    /// capacity is known in advance
+    ///
+    /// The pacing assumes full size packets
+    #[must_use]
+    pub fn initial_send_rate(mut self, rate: u64) -> Self {
+        self.initial_send_rate = Some(rate);
+
+        self
+    }
+
+    /// A name for the connection, to tell it apart in logs, returned by
+    /// `SrtSocket::name` alongside its `Stats`
+    ///
+    /// This is only for diagnostics, and isn't sent to the peer
+    #[must_use]
+    pub fn name(mut self, name: String) -> Self {
+        self.name = Some(name);
+
+        self
+    }
+
+    /// Never send packets closer together than `interval`, capping the packet
+    /// rate whatever the congestion control calculates
+    #[must_use]
+    pub fn min_send_interval(mut self, interval: Duration) -> Self {
+        self.min_send_interval = Some(interval);
+
+        self
+    }
+
+    /// Space packets out to within tens of microseconds of the send period
+    /// congestion control or the max bandwidth asks for. The timer only has
+    /// millisecond resolution, so otherwise packets sent more often than that
+    /// come late and bunched up
+    ///
+    /// The sender spins while a packet is nearly due, taking more CPU time,
+    /// and makes up for packets that were sent late by sending the next ones
+    /// sooner. Defaults to false
+    #[must_use]
+    pub fn precise_pacing(mut self, enabled: bool) -> Self {
+        self.precise_pacing = enabled;
 
         self
     }
 
+    /// Drop packets that are older than the peer's latency plus a small margin
+    /// instead of retransmitting them, as the peer can't deliver them in time
+    /// any more, and tell the peer to stop waiting for them
+    ///
+    /// The dropped packets are counted in `Stats::send_dropped_packets`.
+    /// Buffer mode connections never drop, like file mode in the reference
+    /// implementation. Defaults to false
     #[must_use]
-    pub fn crypto(mut self, size: u8, passphrase: String) -> Self {
-        self.crypto = Some((size, passphrase));
+    pub fn too_late_drop(mut self, enabled: bool) -> Self {
+        self.too_late_drop = enabled;
 
         self
     }
 
-    pub async fn connect_with_sock<T>(self, mut socket: T) -> Result<SrtSocket, Error>
+    /// Which packets the peer reports lost are retransmitted, like
+    /// SRTO_RETRANSMITALGO
+    ///
+    /// `RetransmitAlgorithm::Reduced` only takes effect with peers that send
+    /// periodic NAK reports, as this crate does. Peers that don't are sent
+    /// unacknowledged packets again when they don't respond for a while,
+    /// whichever algorithm is used. Defaults to `RetransmitAlgorithm::EveryReport`
+    #[must_use]
+    pub fn retransmit_algorithm(mut self, algorithm: RetransmitAlgorithm) -> Self {
+        self.retransmit_algorithm = algorithm;
+
+        self
+    }
+
+    /// How retransmissions share the send slots with new data
+    ///
+    /// `RetransmitPriority::First` sends packets the peer reported lost
+    /// before any new data, recovering losses with the least latency.
+    /// `RetransmitPriority::Interleaved` retransmits one packet for every so
+    /// many new ones while both are waiting, so fresh data keeps flowing at
+    /// its pace during a burst of losses. Defaults to `RetransmitPriority::First`
+    #[must_use]
+    pub fn retransmit_priority(mut self, priority: RetransmitPriority) -> Self {
+        self.retransmit_priority = priority;
+
+        self
+    }
+
+    /// How many packets can arrive after a missing one before it's reported
+    /// lost, like SRTO_LOSSMAXTTL
+    ///
+    /// On paths that reorder packets, such as bonded cellular links, a packet
+    /// that's only late would otherwise be asked for again, and sent twice.
+    /// A gap that isn't filled is still reported in the next periodic NAK
+    /// report. Defaults to 0, reporting losses right away
+    #[must_use]
+    pub fn reorder_tolerance(mut self, packets: u32) -> Self {
+        self.reorder_tolerance = packets;
+
+        self
+    }
+
+    /// How often the receiving side sends full ACKs
+    ///
+    /// Each full ACK is answered with an ACK2, which is how the round trip
+    /// time is measured, so a shorter period tracks the RTT more closely at
+    /// the cost of more control packets. By default it starts at 10ms, then
+    /// follows the RTT as 4 * RTT + RTTVar + 10ms
+    #[must_use]
+    pub fn ack_interval(mut self, period: Duration) -> Self {
+        self.ack_interval = Some(period);
+
+        self
+    }
+
+    /// The most payload bytes that can be waiting to be sent or waiting for
+    /// an ACK, like SRTO_SNDBUF. Once they're reached, sending waits for room
+    /// instead of buffering more when the network is slower than the source
+    ///
+    /// Without it, only the flow window limits how much is buffered
+    #[must_use]
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+
+        self
+    }
+
+    /// Fail sending with `io::ErrorKind::WouldBlock` when there's no room for
+    /// more data instead of waiting for it, like turning off SRTO_SNDSYN
+    /// Defaults to false
+    #[must_use]
+    pub fn nonblocking_send(mut self, nonblocking: bool) -> Self {
+        self.nonblocking_send = nonblocking;
+
+        self
+    }
+
+    /// How long closing the socket waits for the data sent to be acknowledged,
+    /// retransmitting it as needed, before shutting down the connection
+    /// anyway, like SRTO_LINGER
+    ///
+    /// Defaults to waiting until everything is acknowledged
+    #[must_use]
+    pub fn linger(mut self, linger: Duration) -> Self {
+        self.linger = Some(linger);
+
+        self
+    }
+
+    /// Never send faster than `rate` bits/s, counting retransmissions and the
+    /// packet headers, whatever the congestion control calculates
+    ///
+    /// Packets are spaced out evenly to stay under it instead of being sent in
+    /// bursts, so links with shallow buffers don't drop them. Defaults to no
+    /// limit
+    #[must_use]
+    pub fn max_bandwidth(mut self, rate: u64) -> Self {
+        self.max_bandwidth = Some(rate);
+
+        self
+    }
+
+    /// Never send faster than `overhead` percent over the rate data is given
+    /// to the socket at, which is estimated from the data as it comes in
+    ///
+    /// The estimate is made over the first half second, then again every
+    /// second, so the max bandwidth follows the source's bitrate, leaving
+    /// `overhead` for retransmissions. Pauses in the data aren't counted, and
+    /// estimates under 1 Mbit/s are taken as that. Until the first estimate
+    /// there's no limit. `overhead` has to be 5 to 100, and this can't be
+    /// used along with `max_bandwidth`
+    #[must_use]
+    pub fn bandwidth_overhead(mut self, overhead: u32) -> Self {
+        self.bandwidth_overhead = Some(overhead);
+
+        self
+    }
+
+    /// An application defined identifier for the stream being connected to,
+    /// sent to the listener in the handshake and available from
+    /// `SrtSocket::stream_id` on both ends
+    ///
+    /// This is only sent when connecting, not when listening or in rendezvous
+    #[must_use]
+    pub fn stream_id(mut self, stream_id: String) -> Self {
+        self.stream_id = Some(stream_id);
+
+        self
+    }
+
+    /// Log a one line summary of every packet sent and received after the
+    /// connection is established, at the trace level to the
+    /// `PACKET_TRACE_TARGET` target (`srt::packet_trace`)
+    ///
+    /// Lines are prefixed with the `name` of the connection, if it has one
+    #[must_use]
+    pub fn trace_packets(mut self, trace_packets: bool) -> Self {
+        self.trace_packets = trace_packets;
+
+        self
+    }
+
+    /// Speak plain UDT v4 instead of SRT, to interoperate with UDT peers
+    ///
+    /// No SRT handshake extensions are sent, and the connection uses UDT's
+    /// semantics: there is no TSBPD, so every message is delivered, in order,
+    /// as soon as it is complete, and the ACK period is fixed. The socket type
+    /// is stream, which UDT uses for file transfer
+    ///
+    /// Encryption, stream IDs, peer stats and rendezvous aren't available.
+    /// A socket given to `connect_with_sock` has to parse packets with
+    /// `Udt4PacketCodec`, as UDT4 data packets are laid out differently
+    #[must_use]
+    pub fn udt4(mut self, udt4: bool) -> Self {
+        self.udt4 = udt4;
+
+        self
+    }
+
+    /// Refuse to connect to peers older than SRT `version`, like SRTO_MINVERSION
+    ///
+    /// The peer's version comes from its handshake. A listener refuses older
+    /// callers with `RejectReason::Version` and waits for the next one, and a
+    /// caller fails with that reason if the listener is too old
+    ///
+    /// This isn't available in UDT4 mode, where there is no SRT version
+    #[must_use]
+    pub fn min_peer_version(mut self, version: SrtVersion) -> Self {
+        self.min_peer_version = Some(version);
+
+        self
+    }
+
+    /// The congestion control to use, like SRTO_CONGESTION: live (the default)
+    /// for streaming or file for transferring as fast as the link allows
+    ///
+    /// Both ends have to use the same one. A listener refuses callers that
+    /// don't with `RejectReason::Congestion` and waits for the next one, and
+    /// a caller fails with that reason if the listener doesn't
+    ///
+    /// In UDT4 mode it isn't negotiated, and rendezvous is always live
+    #[must_use]
+    pub fn congestion_control(mut self, congest_ctrl: CongestCtrlType) -> Self {
+        self.congest_ctrl = congest_ctrl;
+
+        self
+    }
+
+    /// Check the address a peer's handshake says it was sent to against the
+    /// address set with `local_addr`, false by default
+    ///
+    /// A listener refuses conclusions that don't match as rogue, and
+    /// rendezvous ignores handshakes that don't. It needs `local_addr` to be
+    /// set, and only works when peers reach this end at that address, not
+    /// behind a NAT or port forwarding. Callers don't check anything
+    #[must_use]
+    pub fn verify_peer_address(mut self, verify: bool) -> Self {
+        self.verify_peer_address = verify;
+
+        self
+    }
+
+    /// Give up connecting if the handshake hasn't finished after `connect_timeout`,
+    /// 3 seconds by default, failing with a `ConnectTimeout` error
+    ///
+    /// Unanswered handshakes are resent less and less often until then. This
+    /// applies when connecting and in rendezvous, a listener waits as long as it
+    /// takes for a caller to show up
+    #[must_use]
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+
+        self
+    }
+
+    /// Other addresses the listener may be at, such as the rest of the
+    /// addresses its host name resolves to, to fall back on if connecting to
+    /// the one in `ConnInitMethod::Connect` is slow or fails
+    ///
+    /// The addresses are raced happy eyeballs style: each is tried in order,
+    /// `fallback_delay` after the one before or as soon as all the ones
+    /// before have failed, from a socket of its own. The first to connect is
+    /// returned and the others are abandoned, with a shutdown to any listener
+    /// that may already think it's connected
+    ///
+    /// This only applies to `connect`. Every socket is bound to the local
+    /// port, so leave it unset when there is more than one address of a family
+    #[must_use]
+    pub fn fallback_addresses<I>(mut self, addrs: I) -> Self
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        self.fallback_addrs = addrs.into_iter().collect();
+
+        self
+    }
+
+    /// How long to wait for a connection attempt before starting on the next
+    /// of the `fallback_addresses`, 250ms by default
+    #[must_use]
+    pub fn fallback_delay(mut self, delay: Duration) -> Self {
+        self.fallback_delay = delay;
+
+        self
+    }
+
+    /// Only answer handshakes from addresses `filter` returns true for, for
+    /// allow and deny lists
+    ///
+    /// `filter` is called before anything else is done with a handshake, and
+    /// handshakes from addresses it refuses are ignored without a reply
+    ///
+    /// This only applies when listening
+    #[must_use]
+    pub fn address_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        self.address_filter = Some(AddressFilter::new(filter));
+
+        self
+    }
+
+    /// Decide whether to accept each caller from its address and the stream
+    /// ID it asked for, if any
+    ///
+    /// `callback` is called during the handshake, once the caller has shown it
+    /// really is at that address. A refused caller is told the `RejectReason`
+    /// it was refused with, and the listener waits for the next one
+    ///
+    /// This only applies when listening
+    #[must_use]
+    pub fn access_control<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn(SocketAddr, Option<String>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = AccessDecision> + Send + 'static,
+    {
+        self.access_control = Some(AccessControl::new(callback));
+
+        self
+    }
+
+    /// The most handshakes a listener accepting many connections has in
+    /// progress at once, 256 by default. Callers over the limit are ignored
+    /// until others finish, so a flood of handshakes can't use up memory
+    ///
+    /// This only applies to `build_listener` and `build_multiplexed`
+    #[must_use]
+    pub fn max_pending_handshakes(mut self, max_pending_handshakes: usize) -> Self {
+        self.max_pending_handshakes = max_pending_handshakes;
+
+        self
+    }
+
+    /// The most new handshakes a listener accepting many connections starts
+    /// a second, 500 by default, with bursts of up to a second's worth allowed.
+    /// Handshakes over the limit are ignored, callers resend them
+    ///
+    /// This only applies to `build_listener` and `build_multiplexed`
+    #[must_use]
+    pub fn handshake_rate(mut self, handshake_rate: u32) -> Self {
+        self.handshake_rate = handshake_rate;
+
+        self
+    }
+
+    pub async fn connect_with_sock<T>(self, socket: T) -> Result<SrtSocket, Error>
     where
         T: Stream<Item = Result<(Packet, SocketAddr), Error>>
             + Sink<(Packet, SocketAddr), Error = Error>
@@ -87,59 +679,492 @@ impl SrtSocketBuilder {
             + Send
             + 'static,
     {
-        // validate crypto
-        match self.crypto {
-            // OK
-            None | Some((16, _)) | Some((24, _)) | Some((32, _)) => {
-                // TODO: Size validation
+        self.connect_as(socket, rand::random(), future::pending())
+            .await
+    }
+
+    /// Connect on `socket` with `local_sockid` as this end's socket ID, for
+    /// when packets are routed to it by socket ID
+    ///
+    /// Connecting to a listener is given up on once `abandon` resolves
+    pub(crate) async fn connect_as<T, A>(
+        self,
+        mut socket: T,
+        local_sockid: SocketID,
+        abandon: A,
+    ) -> Result<SrtSocket, Error>
+    where
+        T: Stream<Item = Result<(Packet, SocketAddr), Error>>
+            + Sink<(Packet, SocketAddr), Error = Error>
+            + Unpin
+            + Send
+            + 'static,
+        A: Future<Output = ()>,
+    {
+        if self.connect_timeout == Duration::from_secs(0) {
+            bail!("Connect timeout must be nonzero");
+        }
+        if let ConnInitMethod::Rendezvous(_) = self.conn_type {
+            if self.udt4 {
+                bail!("Rendezvous isn't available in UDT4 mode");
             }
-            // not
-            Some((size, _)) => {
-                bail!("Invalid crypto size: {}. Expected 16, 24, or 32", size);
+            if self.congest_ctrl != CongestCtrlType::Live {
+                bail!("Rendezvous can only use live congestion control");
+            }
+            if self.passphrase.is_some() {
+                bail!("Rendezvous can't be encrypted yet");
+            }
+            if self.socket_type == SocketType::Stream {
+                bail!("Rendezvous can only use message mode");
             }
         }
+        let options = self.socket_options()?;
+        let config = self.handshake_config()?;
 
         let conn = match self.conn_type {
-            ConnInitMethod::Listen => {
-                pending_connection::listen(&mut socket, rand::random(), self.latency).await?
-            }
+            ConnInitMethod::Listen => loop {
+                match pending_connection::listen(&mut socket, local_sockid, &config).await {
+                    // a caller refused by access control, for being too old,
+                    // for its congestion control or its key length doesn't
+                    // stop the listener
+                    Err(e)
+                        if e.downcast_ref::<RejectReason>().is_some()
+                            || e.downcast_ref::<KeyLengthMismatch>().is_some() =>
+                    {
+                        continue
+                    }
+                    res => break res?,
+                }
+            },
             ConnInitMethod::Connect(addr) => {
-                pending_connection::connect(
-                    &mut socket,
-                    addr,
-                    rand::random(),
-                    self.local_addr.ip(),
-                    self.latency,
-                    self.crypto.clone(),
-                )
-                .await?
-            }
-            ConnInitMethod::Rendezvous(remote_public) => {
-                pending_connection::rendezvous(
-                    &mut socket,
-                    rand::random(),
-                    self.local_addr.ip(),
-                    remote_public,
-                    self.latency,
-                )
-                .await?
+                let connect_timeout = self.connect_timeout;
+                let give_up = async move {
+                    select! {
+                        _ = delay_for(connect_timeout).fuse() => ConnectTimeout {
+                            remote: addr,
+                            timeout: connect_timeout,
+                        }
+                        .into(),
+                        _ = abandon.fuse() => format_err!("Abandoned connecting to {}", addr),
+                    }
+                };
+                pending_connection::connect(&mut socket, addr, local_sockid, &config, give_up)
+                    .await?
             }
+            ConnInitMethod::Rendezvous(remote_public) => timeout(
+                self.connect_timeout,
+                pending_connection::rendezvous(&mut socket, local_sockid, remote_public, &config),
+            )
+            .await
+            .map_err(|_| ConnectTimeout {
+                remote: remote_public,
+                timeout: self.connect_timeout,
+            })??,
         };
 
-        Ok(create_bidrectional_srt(socket, conn))
+        Ok(options.socket(socket, conn))
     }
 
     pub async fn connect(self) -> Result<SrtSocket, Error> {
-        let la = self.local_addr;
-        Ok(self
-            .connect_with_sock(UdpFramed::new(UdpSocket::bind(&la).await?, PacketCodec {}))
-            .await?)
+        if let ConnInitMethod::Connect(remote) = self.conn_type {
+            if !self.fallback_addrs.is_empty() {
+                return self.connect_any(remote).await;
+            }
+        }
+
+        self.connect_bound(future::pending()).await
+    }
+
+    /// Connect to `host`, a host name and port such as "example.com:9000",
+    /// instead of the address in `ConnInitMethod::Connect`
+    ///
+    /// Every address the name resolves to is raced like the
+    /// `fallback_addresses`, in the order the resolver returns them, followed
+    /// by the fallback addresses
+    pub async fn connect_host(mut self, host: &str) -> Result<SrtSocket, Error> {
+        match self.conn_type {
+            ConnInitMethod::Connect(_) => {}
+            _ => bail!("Cannot connect to a host with any connection mode other than connect"),
+        }
+
+        let mut addrs = lookup_host(host).await?;
+        let remote = addrs
+            .next()
+            .ok_or_else(|| format_err!("{} doesn't resolve to any address", host))?;
+        self.fallback_addrs = addrs.chain(self.fallback_addrs).collect();
+        self.conn_type = ConnInitMethod::Connect(remote);
+
+        self.connect().await
+    }
+
+    /// Connect from a UDP socket bound to the local address, giving up once
+    /// `abandon` resolves
+    async fn connect_bound<A>(self, abandon: A) -> Result<SrtSocket, Error>
+    where
+        A: Future<Output = ()>,
+    {
+        let sock = UdpSocket::bind(&self.local_addr).await?;
+        if self.udt4 {
+            self.connect_as(
+                UdpFramed::new(sock, Udt4PacketCodec),
+                rand::random(),
+                abandon,
+            )
+            .await
+        } else {
+            self.connect_as(UdpFramed::new(sock, PacketCodec), rand::random(), abandon)
+                .await
+        }
+    }
+
+    /// Connect in buffer mode, carrying a continuous byte stream instead of
+    /// messages, like SRTO_MESSAGEAPI turned off, see `StreamSocket`
+    ///
+    /// The handshake announces the mode, so the peer has to connect this way
+    /// too, or the connection is refused with `RejectReason::MessageApi`.
+    /// Listening this way accepts one caller in buffer mode, but rendezvous
+    /// and the sockets `build_listener` and `build_multiplexed` accept only
+    /// use message mode
+    pub async fn connect_stream(mut self) -> Result<StreamSocket, Error> {
+        self.socket_type = SocketType::Stream;
+
+        Ok(StreamSocket::new(self.connect().await?))
+    }
+
+    /// Connect in buffer mode over `socket`, see `connect_stream` and
+    /// `connect_with_sock`
+    pub async fn connect_stream_with_sock<T>(mut self, socket: T) -> Result<StreamSocket, Error>
+    where
+        T: Stream<Item = Result<(Packet, SocketAddr), Error>>
+            + Sink<(Packet, SocketAddr), Error = Error>
+            + Unpin
+            + Send
+            + 'static,
+    {
+        self.socket_type = SocketType::Stream;
+
+        Ok(StreamSocket::new(self.connect_with_sock(socket).await?))
+    }
+
+    /// Race connecting to `remote` and the fallback addresses
+    async fn connect_any(mut self, remote: SocketAddr) -> Result<SrtSocket, Error> {
+        let fallback_addrs = std::mem::take(&mut self.fallback_addrs);
+
+        // the attempts that lose are abandoned once this is dropped
+        let (_racing, abandon) = oneshot::channel::<()>();
+        let abandon = abandon.map(|_| ()).shared();
+
+        let attempts = Some(remote)
+            .into_iter()
+            .chain(fallback_addrs)
+            .map(|remote| {
+                let mut builder = self.clone();
+                builder.conn_type = ConnInitMethod::Connect(remote);
+                builder.local_addr = self.local_addr_for(remote);
+                let abandon = abandon.clone();
+
+                async move {
+                    let mut sock = builder.connect_bound(abandon.clone()).await?;
+                    // connected just after another attempt won
+                    if abandon.now_or_never().is_some() {
+                        sock.close().await?;
+                        bail!("Abandoned connection to {}", remote);
+                    }
+                    Ok(sock)
+                }
+                .boxed()
+            })
+            .collect();
+
+        pending_connection::staggered_race(attempts, self.fallback_delay).await
+    }
+
+    /// The address to bind to for connecting to `remote`, which may be of the
+    /// other address family than the local address
+    fn local_addr_for(&self, remote: SocketAddr) -> SocketAddr {
+        let port = self.local_addr.port();
+        match remote {
+            _ if remote.is_ipv4() == self.local_addr.is_ipv4() => self.local_addr,
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, port).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, port).into(),
+        }
+    }
+
+    /// Bind a listener that accepts connections from any number of callers,
+    /// yielding each as an `SrtSocket`
+    ///
+    /// The latency, initial send rate, minimum send interval, too late drop,
+    /// max bandwidth, bandwidth overhead, name, packet tracing, address filter
+    /// and access control apply to every connection
+    pub async fn build_listener(self) -> Result<SrtListener, Error> {
+        match self.conn_type {
+            ConnInitMethod::Listen => {}
+            _ => bail!("Cannot build a listener with any connection mode other than listen"),
+        }
+        if self.peer_stats {
+            bail!("Peer stats aren't available when accepting many connections");
+        }
+        if self.udt4 {
+            bail!("UDT4 mode isn't available when accepting many connections");
+        }
+        let options = self.socket_options()?;
+
+        Ok(SrtListener::new(self.bind_multiplexed().await?, options))
     }
 
     pub async fn build_multiplexed(self) -> Result<MultiplexServer, Error> {
         match self.conn_type {
-            ConnInitMethod::Listen => MultiplexServer::bind(&self.local_addr, self.latency).await,
+            ConnInitMethod::Listen => self.bind_multiplexed().await,
             _ => bail!("Cannot bind multiplexed with any connection mode other than listen"),
         }
     }
+
+    async fn bind_multiplexed(&self) -> Result<MultiplexServer, Error> {
+        if self.max_pending_handshakes == 0 {
+            bail!("Max pending handshakes must be nonzero");
+        }
+        if self.handshake_rate == 0 {
+            bail!("Handshake rate must be nonzero");
+        }
+        let config = self.handshake_config()?;
+
+        Ok(
+            MultiplexServer::bind(&self.local_addr, self.receive_latency)
+                .await?
+                .with_handshake_config(config)
+                .with_handshake_limits(self.max_pending_handshakes, self.handshake_rate),
+        )
+    }
+
+    /// The handshake settings, checked to be ones the handshake can be done with
+    fn handshake_config(&self) -> Result<HandshakeConfig, Error> {
+        match &self.passphrase {
+            Some(passphrase) if !PASSPHRASE_LEN.contains(&passphrase.len()) => bail!(
+                "Passphrase is {} characters, it has to be {} to {}",
+                passphrase.len(),
+                PASSPHRASE_LEN.start(),
+                PASSPHRASE_LEN.end()
+            ),
+            _ => {}
+        }
+        match self.key_length {
+            Some(len) if ![16, 24, 32].contains(&len) => {
+                bail!("Key length is {} bytes, it has to be 16, 24 or 32", len)
+            }
+            _ => {}
+        }
+        // callers don't check anything
+        if !matches!(self.conn_type, ConnInitMethod::Connect(_))
+            && self.verify_peer_address
+            && self.local_addr.ip().is_unspecified()
+        {
+            bail!("Verifying peer addresses needs a local address to check against");
+        }
+        if let Some(id) = &self.stream_id {
+            if id.len() > MAX_STREAM_ID_LEN {
+                bail!(
+                    "Stream ID is {} bytes, it can be at most {}",
+                    id.len(),
+                    MAX_STREAM_ID_LEN
+                );
+            }
+        }
+        if self.udt4 {
+            if self.passphrase.is_some() {
+                bail!("Encryption isn't available in UDT4 mode");
+            }
+            if self.stream_id.is_some() {
+                bail!("Stream IDs aren't available in UDT4 mode");
+            }
+            if self.peer_stats {
+                bail!("Peer stats aren't available in UDT4 mode");
+            }
+            if self.min_peer_version.is_some() {
+                bail!("Minimum peer version isn't available in UDT4 mode");
+            }
+        }
+
+        Ok(HandshakeConfig {
+            recv_latency: self.receive_latency,
+            peer_latency: self.peer_latency,
+            peer_stats: self.peer_stats,
+            udt4: self.udt4,
+            verify_local_addr: Some(self.local_addr.ip()).filter(|_| self.verify_peer_address),
+            min_peer_version: self.min_peer_version,
+            congest_ctrl: self.congest_ctrl,
+            socket_type: self.socket_type,
+            passphrase: self.passphrase.clone(),
+            key_length: self.key_length,
+            enforced_encryption: self.enforced_encryption,
+            stream_id: self.stream_id.clone(),
+            address_filter: self.address_filter.clone(),
+            access_control: self.access_control.clone(),
+        })
+    }
+
+    /// The settings sockets are made with once connected, checked to be ones
+    /// they can use
+    fn socket_options(&self) -> Result<SocketOptions, Error> {
+        if self.initial_send_rate == Some(0) {
+            bail!("Initial send rate must be nonzero");
+        }
+        if self.max_bandwidth == Some(0) {
+            bail!("Max bandwidth must be nonzero");
+        }
+        match self.bandwidth_overhead {
+            Some(_) if self.max_bandwidth.is_some() => {
+                bail!("Max bandwidth and bandwidth overhead can't both be set")
+            }
+            Some(overhead) if !(5..=100).contains(&overhead) => {
+                bail!("Bandwidth overhead is {}%, it has to be 5 to 100", overhead)
+            }
+            _ => {}
+        }
+        if self.send_buffer_size == Some(0) {
+            bail!("Send buffer size must be nonzero");
+        }
+        if self.retransmit_priority == RetransmitPriority::Interleaved(0) {
+            bail!("Interleaved retransmissions need at least one new packet between them");
+        }
+        if self.ack_interval == Some(Duration::from_secs(0)) {
+            bail!("ACK interval must be nonzero");
+        }
+        #[cfg(feature = "crypto")]
+        {
+            if self.key_refresh_period == Some(Duration::from_secs(0)) {
+                bail!("Key refresh period must be nonzero");
+            }
+            match self.key_refresh {
+                Some((rate, pre_announce)) if pre_announce == 0 || pre_announce > rate / 2 => {
+                    bail!(
+                    "Key pre-announce is {} packets, it has to be 1 to half the refresh rate of {}",
+                    pre_announce,
+                    rate
+                )
+                }
+                _ => {}
+            }
+        }
+
+        Ok(SocketOptions {
+            initial_send_rate: self.initial_send_rate,
+            min_send_interval: self.min_send_interval,
+            precise_pacing: self.precise_pacing,
+            too_late_drop: self.too_late_drop,
+            retransmit_algorithm: self.retransmit_algorithm,
+            retransmit_priority: self.retransmit_priority,
+            reorder_tolerance: self.reorder_tolerance,
+            ack_interval: self.ack_interval,
+            send_buffer_size: self.send_buffer_size,
+            nonblocking_send: self.nonblocking_send,
+            linger: self.linger,
+            max_bandwidth: self.max_bandwidth,
+            bandwidth_overhead: self.bandwidth_overhead,
+            #[cfg(feature = "crypto")]
+            key_refresh: self.key_refresh,
+            #[cfg(feature = "crypto")]
+            key_refresh_period: self.key_refresh_period,
+            undecryptable_handler: self.undecryptable_handler.clone(),
+            send_drop_handler: self.send_drop_handler.clone(),
+            name: self.name.clone(),
+            trace_packets: self.trace_packets,
+        })
+    }
+}
+
+/// What sockets are set up with once they're connected, the same for each
+/// one a listener accepts
+#[derive(Debug, Clone)]
+pub(crate) struct SocketOptions {
+    initial_send_rate: Option<u64>,
+    min_send_interval: Option<Duration>,
+    precise_pacing: bool,
+    too_late_drop: bool,
+    retransmit_algorithm: RetransmitAlgorithm,
+    retransmit_priority: RetransmitPriority,
+    reorder_tolerance: u32,
+    ack_interval: Option<Duration>,
+    send_buffer_size: Option<usize>,
+    nonblocking_send: bool,
+    linger: Option<Duration>,
+    max_bandwidth: Option<u64>,
+    bandwidth_overhead: Option<u32>,
+    #[cfg(feature = "crypto")]
+    key_refresh: Option<(u64, u64)>,
+    #[cfg(feature = "crypto")]
+    key_refresh_period: Option<Duration>,
+    undecryptable_handler: Option<UndecryptableHandler>,
+    send_drop_handler: Option<SendDropHandler>,
+    name: Option<String>,
+    trace_packets: bool,
+}
+
+impl SocketOptions {
+    /// The socket for `conn`, carried over `chan`
+    pub(crate) fn socket<T>(&self, chan: T, conn: Connection) -> SrtSocket
+    where
+        T: Stream<Item = Result<(Packet, SocketAddr), Error>>
+            + Sink<(Packet, SocketAddr), Error = Error>
+            + Send
+            + 'static,
+    {
+        let congest_ctrl = conn
+            .settings
+            .congest_ctrl
+            .congest_ctrl(self.initial_send_rate, conn.settings.max_packet_size);
+
+        let mut socket = create_bidrectional_srt(
+            chan,
+            conn,
+            congest_ctrl,
+            self.name.clone(),
+            self.trace_packets,
+        );
+        if let Some(interval) = self.min_send_interval {
+            socket.set_min_send_interval(interval);
+        }
+        if self.precise_pacing {
+            socket.set_precise_pacing(true);
+        }
+        if self.too_late_drop {
+            socket.set_too_late_drop(true);
+        }
+        socket.set_retransmit_algorithm(self.retransmit_algorithm);
+        socket.set_retransmit_priority(self.retransmit_priority);
+        socket.set_reorder_tolerance(self.reorder_tolerance);
+        if let Some(period) = self.ack_interval {
+            socket.set_ack_interval(period);
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size);
+        }
+        if self.nonblocking_send {
+            socket.set_nonblocking_send(true);
+        }
+        if let Some(linger) = self.linger {
+            socket.set_linger(linger);
+        }
+        if let Some(rate) = self.max_bandwidth {
+            socket.set_max_bandwidth(rate);
+        }
+        if let Some(overhead) = self.bandwidth_overhead {
+            socket.set_bandwidth_overhead(overhead);
+        }
+        #[cfg(feature = "crypto")]
+        if let Some((rate, pre_announce)) = self.key_refresh {
+            socket.set_key_refresh(rate, pre_announce);
+        }
+        #[cfg(feature = "crypto")]
+        if let Some(period) = self.key_refresh_period {
+            socket.set_key_refresh_period(period);
+        }
+        if let Some(handler) = &self.undecryptable_handler {
+            socket.set_undecryptable_handler(handler.clone());
+        }
+        if let Some(handler) = &self.send_drop_handler {
+            socket.set_send_drop_handler(handler.clone());
+        }
+
+        socket
+    }
 }
@@ -41,6 +41,7 @@ impl<T: Send + Sync + 'static> Sink<T> for Channel<T> {
 }
 
 impl<T> Channel<T> {
+    #[allow(clippy::self_named_constructors)]
     pub fn channel(buffer: usize) -> (Channel<T>, Channel<T>) {
         let (s1, r1) = mpsc::channel(buffer);
         let (s2, r2) = mpsc::channel(buffer);
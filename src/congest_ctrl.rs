@@ -1,4 +1,4 @@
-use crate::SeqNumber;
+use crate::{FileCongestCtrl, SeqNumber, SrtCongestCtrl};
 use std::time::Duration;
 
 /// Congestion control trait, sender side
@@ -16,6 +16,10 @@ pub trait CongestCtrl {
     /// On packet sent
     fn on_packet_sent(&mut self, _data: &CCData) {}
 
+    /// When the peer sends a congestion warning, asking for sending to slow
+    /// down as the one way delay to it keeps growing
+    fn on_congestion_warning(&mut self, _data: &CCData) {}
+
     /// Get the interval between sending packets
     fn send_interval(&self) -> Duration;
 
@@ -24,15 +28,100 @@ pub trait CongestCtrl {
     fn window_size(&self) -> u32;
 }
 
+impl<CC: CongestCtrl + ?Sized> CongestCtrl for Box<CC> {
+    fn init(&mut self, init_seq_num: SeqNumber) {
+        (**self).init(init_seq_num)
+    }
+
+    fn on_ack(&mut self, data: &CCData) {
+        (**self).on_ack(data)
+    }
+
+    fn on_nak(&mut self, largest_seq_in_ll: SeqNumber, data: &CCData) {
+        (**self).on_nak(largest_seq_in_ll, data)
+    }
+
+    fn on_packet_sent(&mut self, data: &CCData) {
+        (**self).on_packet_sent(data)
+    }
+
+    fn on_congestion_warning(&mut self, data: &CCData) {
+        (**self).on_congestion_warning(data)
+    }
+
+    fn send_interval(&self) -> Duration {
+        (**self).send_interval()
+    }
+
+    fn window_size(&self) -> u32 {
+        (**self).window_size()
+    }
+}
+
+/// Which congestion control a connection uses, like SRTO_CONGESTION
+///
+/// Both ends have to use the same one. It is agreed on in the handshake, where
+/// anything but live is named in a config extension
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CongestCtrlType {
+    /// For live streaming, sending as the data comes (`SrtCongestCtrl`)
+    #[default]
+    Live,
+
+    /// For file transfer, sending as fast as the link allows (`FileCongestCtrl`)
+    File,
+}
+
+impl CongestCtrlType {
+    /// The name used in the handshake, "live" or "file"
+    pub fn name(self) -> &'static str {
+        match self {
+            CongestCtrlType::Live => "live",
+            CongestCtrlType::File => "file",
+        }
+    }
+
+    /// The congestion control named `name` in a handshake, if it is one this crate has
+    pub fn from_name(name: &str) -> Option<CongestCtrlType> {
+        match name {
+            "live" => Some(CongestCtrlType::Live),
+            "file" => Some(CongestCtrlType::File),
+            _ => None,
+        }
+    }
+
+    /// Make the congestion control to send with, pacing packets of
+    /// `packet_size` bytes at `initial_send_rate` bits/s to start if given
+    pub fn congest_ctrl(
+        self,
+        initial_send_rate: Option<u64>,
+        packet_size: u32,
+    ) -> Box<dyn CongestCtrl + Send> {
+        match (self, initial_send_rate) {
+            (CongestCtrlType::Live, Some(rate)) => {
+                Box::new(SrtCongestCtrl::with_send_rate(rate, packet_size))
+            }
+            (CongestCtrlType::Live, None) => Box::new(SrtCongestCtrl::default()),
+            (CongestCtrlType::File, Some(rate)) => {
+                Box::new(FileCongestCtrl::with_send_rate(rate, packet_size))
+            }
+            (CongestCtrlType::File, None) => Box::new(FileCongestCtrl::default()),
+        }
+    }
+}
+
 /// Defines all the data that CC algorithms need
 pub struct CCData {
     /// Round trip time
     pub rtt: Duration,
 
+    /// Round trip time variance
+    pub rtt_var: Duration,
+
     /// The max segment size, in bytes
     pub max_segment_size: u32,
 
-    /// Estimated bandwidth, in bytes/sec
+    /// Estimated bandwidth, in packets/sec
     pub est_bandwidth: i32,
 
     /// The latest sequence number to be sent, sender only
@@ -42,3 +131,51 @@ pub struct CCData {
     /// the receiver sends this info to the sender in ACK packets
     pub packet_arr_rate: i32,
 }
+
+impl CCData {
+    /// The estimated bandwidth-delay product, in bytes, assuming full size packets
+    ///
+    /// Returns `None` if the bandwidth or RTT haven't been estimated yet
+    pub fn estimated_bdp(&self) -> Option<u64> {
+        if self.est_bandwidth <= 0 || self.rtt == Duration::from_secs(0) {
+            return None;
+        }
+
+        let bytes_per_sec = self.est_bandwidth as u128 * u128::from(self.max_segment_size);
+
+        Some((bytes_per_sec * self.rtt.as_micros() / 1_000_000) as u64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CCData;
+    use std::time::Duration;
+
+    fn cc_data(est_bandwidth: i32, rtt: Duration) -> CCData {
+        CCData {
+            rtt,
+            rtt_var: rtt / 2,
+            max_segment_size: 1500,
+            est_bandwidth,
+            latest_seq_num: None,
+            packet_arr_rate: 0,
+        }
+    }
+
+    #[test]
+    fn estimated_bdp() {
+        // 1000 packets/s * 1500 bytes * 100ms
+        assert_eq!(
+            cc_data(1000, Duration::from_millis(100)).estimated_bdp(),
+            Some(150_000)
+        );
+        assert_eq!(
+            cc_data(8000, Duration::from_micros(2_500)).estimated_bdp(),
+            Some(30_000)
+        );
+
+        assert_eq!(cc_data(0, Duration::from_millis(100)).estimated_bdp(), None);
+        assert_eq!(cc_data(1000, Duration::from_secs(0)).estimated_bdp(), None);
+    }
+}
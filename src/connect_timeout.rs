@@ -0,0 +1,29 @@
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The handshake with a peer didn't finish within `SrtSocketBuilder::connect_timeout`
+///
+/// Connecting fails with this error, it can be told apart from others by
+/// downcasting the `failure::Error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectTimeout {
+    /// The peer that was being connected to
+    pub remote: SocketAddr,
+
+    /// How long connecting was given
+    pub timeout: Duration,
+}
+
+impl Display for ConnectTimeout {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Connecting to {} timed out after {:?}",
+            self.remote, self.timeout
+        )
+    }
+}
+
+impl error::Error for ConnectTimeout {}
@@ -3,11 +3,31 @@ use std::{
     time::{Duration, Instant},
 };
 
-use crate::{Packet, SeqNumber, SocketID};
+#[cfg(feature = "crypto")]
+use crate::crypto::PayloadCipher;
+use crate::packet::{ControlTypes, KmState, SocketType, SrtShakeFlags};
+use crate::{CongestCtrlType, ControlPacket, Packet, SeqNumber, SocketID, SrtVersion, TimeStamp};
 
 pub struct Connection {
     pub settings: ConnectionSettings,
     pub hs_returner: HandshakeReturner,
+
+    /// Packets that were received while connecting that belong to the
+    /// established connection, to be handled as if they just arrived
+    pub early_data: Vec<(Packet, SocketAddr)>,
+
+    /// The stream ID the caller sent in the handshake, if any
+    pub stream_id: Option<String>,
+
+    /// The cipher to encrypt payloads with, if this end has a passphrase.
+    /// Unless `settings.km_state` is `Secured` the peer doesn't have the key
+    #[cfg(feature = "crypto")]
+    pub crypto: Option<PayloadCipher>,
+
+    /// The passphrase new keys are wrapped with when they replace the one
+    /// agreed on in the handshake
+    #[cfg(feature = "crypto")]
+    pub passphrase: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -27,16 +47,60 @@ pub struct ConnectionSettings {
     /// The first sequence number
     pub init_seq_num: SeqNumber,
 
-    /// The maximum packet size
+    /// The maximum packet size, the smaller of the two ends'
     pub max_packet_size: u32,
 
-    /// The maxiumum flow size
+    /// The maxiumum flow size, the smaller of the two ends'
     pub max_flow_size: u32,
 
-    /// The TSBPD latency configured by the user.
-    /// Not necessarily the actual decided on latency, which
-    /// is the max of both side's respective latencies.
+    /// The peer's SRT version, from its handshake extension. `None` if it
+    /// didn't send one, as in UDT4 mode
+    pub peer_version: Option<SrtVersion>,
+
+    /// The handshake extension flags both ends agreed on, empty if there was
+    /// no handshake extension
+    pub srt_flags: SrtShakeFlags,
+
+    /// The size in bytes of the key this end encrypts with, 0 if it doesn't
+    pub key_size: u8,
+
+    /// How the key material exchange in the handshake went: `Secured` if the
+    /// ends agreed on a key, `Unsecured` if the caller didn't send one, and
+    /// `NoSecret` or `BadSecret` if it did but the listener had no passphrase
+    /// or another one. Encrypted payloads can't be decrypted unless it's
+    /// `Secured`, and are dropped
+    pub km_state: KmState,
+
+    /// The TSBPD latency this end's receiver delivers with, agreed on in the
+    /// handshake as the larger of this end's receive latency and the peer's
+    /// peer latency
     pub tsbpd_latency: Duration,
+
+    /// The TSBPD latency the peer's receiver delivers with, agreed on in the
+    /// handshake as the larger of this end's peer latency and the peer's
+    /// receive latency
+    pub peer_tsbpd_latency: Duration,
+
+    /// If both sides agreed to send `PeerStats` packets
+    pub peer_stats: bool,
+
+    /// The congestion control both sides agreed on
+    pub congest_ctrl: CongestCtrlType,
+
+    /// Message or buffer mode, as agreed on in the handshake
+    ///
+    /// In buffer mode, like file mode in the reference implementation, there
+    /// is no TSBPD: payloads are delivered as soon as they're in order and
+    /// are never dropped for being late, so the whole stream arrives
+    pub socket_type: SocketType,
+
+    /// If the connection is plain UDT v4 instead of SRT
+    ///
+    /// There is no TSBPD, so messages are delivered as soon as they are
+    /// complete and are never dropped for being late, the ACK period stays at
+    /// SYN (10ms), and retransmitted packets aren't flagged, as UDT uses that
+    /// bit for the message number
+    pub udt4: bool,
 }
 
 /// handshake returner
@@ -46,15 +110,124 @@ pub struct ConnectionSettings {
 pub type HandshakeReturner = Box<dyn Fn(&Packet) -> Option<Packet> + Send>;
 
 impl ConnectionSettings {
-    /// Timestamp in us
-    pub fn get_timestamp(&self, at: Instant) -> i32 {
-        let elapsed = at - self.socket_start_time;
+    /// The TSBPD latency this end receives with, the larger of this end's
+    /// receive latency and the peer's peer latency. If the handshake had no
+    /// SRT extension to negotiate it in, the locally configured latency
+    pub fn latency(&self) -> Duration {
+        self.tsbpd_latency
+    }
 
-        elapsed.as_micros() as i32 // TODO: handle overflow here
+    /// Timestamp in us
+    pub fn get_timestamp(&self, at: Instant) -> TimeStamp {
+        TimeStamp::from_instant(self.socket_start_time, at)
     }
 
     /// Timestamp in us
-    pub fn get_timestamp_now(&self) -> i32 {
+    pub fn get_timestamp_now(&self) -> TimeStamp {
         self.get_timestamp(Instant::now())
     }
+
+    /// If `packet` is addressed to this connection's socket
+    ///
+    /// A destination socket id of zero is only allowed for handshakes, as the
+    /// peer doesn't know our socket id until the handshake is done (the reference
+    /// implementation sends both the induction and conclusion requests to zero).
+    /// Every other packet must have our socket id.
+    pub fn accepts_dest_sockid(&self, packet: &Packet) -> bool {
+        match packet {
+            Packet::Control(ControlPacket {
+                dest_sockid: SocketID(0),
+                control_type: ControlTypes::Handshake(_),
+                ..
+            }) => true,
+            _ => packet.dest_sockid() == self.local_sockid,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConnectionSettings;
+    use crate::packet::{
+        ControlTypes, HandshakeControlInfo, HandshakeVSInfo, KmState, PacketLocation, ShakeType,
+        SocketType, SrtShakeFlags,
+    };
+    use crate::{
+        CongestCtrlType, ControlPacket, DataEncryption, DataPacket, MsgNumber, Packet, SeqNumber,
+        SocketID, TimeStamp,
+    };
+
+    use bytes::Bytes;
+    use std::time::{Duration, Instant};
+
+    fn settings() -> ConnectionSettings {
+        ConnectionSettings {
+            remote: "127.0.0.1:1234".parse().unwrap(),
+            remote_sockid: SocketID(1),
+            local_sockid: SocketID(2),
+            socket_start_time: Instant::now(),
+            init_seq_num: SeqNumber::new_truncate(0),
+            max_packet_size: 1500,
+            max_flow_size: 8192,
+            peer_version: None,
+            srt_flags: SrtShakeFlags::empty(),
+            key_size: 0,
+            km_state: KmState::Unsecured,
+            tsbpd_latency: Duration::from_millis(50),
+            peer_tsbpd_latency: Duration::from_millis(50),
+            peer_stats: false,
+            congest_ctrl: CongestCtrlType::Live,
+            socket_type: SocketType::Datagram,
+            udt4: false,
+        }
+    }
+
+    fn data(dest_sockid: SocketID) -> Packet {
+        Packet::Data(DataPacket {
+            seq_number: SeqNumber::new_truncate(0),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            in_order_delivery: false,
+            retransmitted: false,
+            encryption: DataEncryption::None,
+            message_number: MsgNumber::new_truncate(0),
+            timestamp: TimeStamp(0),
+            dest_sockid,
+            payload: Bytes::new(),
+        })
+    }
+
+    fn control(dest_sockid: SocketID, control_type: ControlTypes) -> Packet {
+        Packet::Control(ControlPacket {
+            timestamp: TimeStamp(0),
+            dest_sockid,
+            control_type,
+        })
+    }
+
+    #[test]
+    fn zero_dest_sockid_only_for_handshakes() {
+        let settings = settings();
+
+        let handshake = ControlTypes::Handshake(HandshakeControlInfo {
+            init_seq_num: SeqNumber::new_truncate(0),
+            max_packet_size: 1500,
+            max_flow_size: 8192,
+            shake_type: ShakeType::Conclusion,
+            socket_id: SocketID(1),
+            syn_cookie: 0,
+            peer_addr: "127.0.0.1".parse().unwrap(),
+            info: HandshakeVSInfo::V4(SocketType::Datagram),
+        });
+
+        assert!(settings.accepts_dest_sockid(&control(SocketID(0), handshake.clone())));
+        assert!(settings.accepts_dest_sockid(&control(SocketID(2), handshake.clone())));
+        assert!(!settings.accepts_dest_sockid(&control(SocketID(3), handshake)));
+
+        assert!(!settings.accepts_dest_sockid(&data(SocketID(0))));
+        assert!(!settings.accepts_dest_sockid(&control(SocketID(0), ControlTypes::Ack2(1))));
+        assert!(!settings.accepts_dest_sockid(&control(SocketID(0), ControlTypes::Shutdown)));
+
+        assert!(settings.accepts_dest_sockid(&data(SocketID(2))));
+        assert!(settings.accepts_dest_sockid(&control(SocketID(2), ControlTypes::Shutdown)));
+    }
 }
@@ -1,10 +1,44 @@
+use std::convert::TryInto;
+use std::fmt::{self, Debug, Formatter};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
 use openssl::aes::{self, AesKey};
 use openssl::hash::MessageDigest;
 use openssl::pkcs5::pbkdf2_hmac;
 use openssl::rand::rand_bytes;
+use openssl::symm::{self, Cipher};
+
+use failure::{bail, format_err, Error};
+
+use crate::packet::{CipherType, DataEncryption, SrtKeyMessage};
+use crate::SeqNumber;
+
+/// The length in bytes of the key to use when none is set, AES-128's
+pub const DEFAULT_KEY_SIZE: u8 = 16;
+
+/// Packet type of a key material message
+const KM_PT: u8 = 2;
+
+/// "HAI", the PnP vendor ID of Haivision, that signs key material messages
+const KM_SIGN: u16 = 0x2029;
+
+/// The stream encapsulation of key material messages, SRT's
+const KM_SE: u8 = 2;
+
+/// How many PBKDF2 iterations derive the kek from the passphrase
+///
+/// https://github.com/Haivision/srt/blob/2ef4ef003c2006df1458de6d47fbe3d2338edf69/haicrypt/haicrypt.h#L73
+const PBKDF2_ITERATIONS: usize = 2048;
 
-use failure::{bail, Error};
+/// How many bytes from the end of the salt PBKDF2 is salted with
+///
+/// https://github.com/Haivision/srt/blob/2ef4ef003c2006df1458de6d47fbe3d2338edf69/haicrypt/haicrypt.h#L72
+const PBKDF2_SALT_LEN: usize = 8;
 
+/// Wraps keys with a key encrypting key derived from a passphrase, exactly as
+/// the reference implementation does, so key material is exchanged with it
+/// bit for bit
 pub struct CryptoManager {
     size: u8,
     passphrase: String,
@@ -13,7 +47,6 @@ pub struct CryptoManager {
     key: Vec<u8>,
 }
 
-#[allow(dead_code)] // TODO: remove and flesh out this struct
 impl CryptoManager {
     pub fn new(size: u8, passphrase: String) -> Self {
         let mut salt = [0; 16];
@@ -41,12 +74,11 @@ impl CryptoManager {
         }
     }
 
-    pub fn salt(&self) -> &[u8] {
-        &self.salt
-    }
-
     /// Generate the key encrypting key from the passphrase, caching it in the struct
     ///
+    /// Like the reference implementation, this is PBKDF2-HMAC-SHA1 of the
+    /// passphrase, salted with the end of the salt, and as long as the key
+    ///
     /// https://github.com/Haivision/srt/blob/2ef4ef003c2006df1458de6d47fbe3d2338edf69/haicrypt/hcrypt_sa.c#L69-L103
     pub fn generate_kek(&mut self) -> Result<&[u8], Error> {
         if !self.kek.is_empty() {
@@ -54,17 +86,14 @@ impl CryptoManager {
             return Ok(&self.kek[..]);
         }
 
-        // the reference implementation uses the last 8 (at max) bytes of the salt. Sources:
-        // https://github.com/Haivision/srt/blob/2ef4ef003c2006df1458de6d47fbe3d2338edf69/haicrypt/haicrypt.h#L72
-        // https://github.com/Haivision/srt/blob/2ef4ef003c2006df1458de6d47fbe3d2338edf69/haicrypt/hcrypt_sa.c#L77-L85
-        let salt_len = usize::min(8, self.salt.len());
+        let salt_len = usize::min(PBKDF2_SALT_LEN, self.salt.len());
 
         self.kek.resize(usize::from(self.size), 0);
 
         pbkdf2_hmac(
             self.passphrase.as_bytes(),
-            &self.salt[self.salt.len() - salt_len..], // last salt_len bytes
-            2048, // is what the reference implementation uses.https://github.com/Haivision/srt/blob/2ef4ef003c2006df1458de6d47fbe3d2338edf69/haicrypt/haicrypt.h#L73
+            &self.salt[self.salt.len() - salt_len..],
+            PBKDF2_ITERATIONS,
             MessageDigest::sha1(),
             &mut self.kek[..],
         )?;
@@ -89,32 +118,330 @@ impl CryptoManager {
         }
     }
 
-    pub fn wrap_key(&mut self) -> Result<Vec<u8>, Error> {
+    /// Wrap `keys`, one or more keys back to back, with the kek, using RFC 3394
+    /// AES key wrap with the default IV. The result is 8 bytes longer, the
+    /// integrity check value being first
+    fn wrap(&mut self, keys: &[u8]) -> Result<Vec<u8>, Error> {
         self.generate_kek()?;
 
-        let mut ret = Vec::new();
-        ret.resize(self.key.len() + 8, 0);
+        let mut ret = vec![0; keys.len() + 8];
 
         match aes::wrap_key(
             &AesKey::new_encrypt(&self.kek[..]).unwrap(),
             None,
             &mut ret[..],
-            &self.key[..],
+            keys,
         ) {
             Err(_) => bail!("Failed to wrap key"),
             Ok(_) => Ok(ret),
         }
     }
 
-    pub fn key(&self) -> &[u8] {
-        &self.key
+    /// The ciphers for the even and odd keys in `key_msg`, which were wrapped
+    /// with `passphrase`
+    ///
+    /// This fails if the keys can't be unwrapped, as when the passphrases differ
+    pub fn unwrap_key_message(
+        passphrase: String,
+        key_msg: &SrtKeyMessage,
+    ) -> Result<[Option<PayloadCipher>; 2], Error> {
+        if key_msg.cipher != CipherType::CTR {
+            bail!("Unsupported cipher {:?}", key_msg.cipher);
+        }
+        let salt: [u8; 16] = key_msg.salt[..]
+            .try_into()
+            .map_err(|_| format_err!("Salt is {} bytes, expected 16", key_msg.salt.len()))?;
+        let slots = [&key_msg.even_key, &key_msg.odd_key];
+        let key_len = match slots.iter().find_map(|key| key.as_ref()) {
+            Some(key) => key.len(),
+            None => bail!("Key material has no keys"),
+        };
+        if ![16, 24, 32].contains(&key_len) {
+            bail!("Invalid key length {}", key_len);
+        }
+
+        // the keys and wrap data are the wrapped keys split up
+        let mut wrapped: Vec<u8> = slots.iter().copied().flatten().flatten().copied().collect();
+        wrapped.extend_from_slice(&key_msg.wrap_data);
+
+        let mut manager =
+            CryptoManager::new_with_salt(key_len as u8, passphrase, &salt, Vec::new());
+        manager.unwrap_key(&wrapped)?;
+
+        let mut keys = manager.key.chunks(key_len);
+        let mut cipher = |slot: &Option<Vec<u8>>| {
+            slot.as_ref()
+                .map(|_| PayloadCipher::new(keys.next().unwrap().to_vec(), salt))
+        };
+        Ok([cipher(slots[0]), cipher(slots[1])])
+    }
+
+    /// The key material message announcing the key, wrapped with the passphrase
+    pub fn key_message(&mut self) -> Result<SrtKeyMessage, Error> {
+        let key = self.key.clone();
+        self.keys_message([Some(&key), None])
+    }
+
+    /// The key material message announcing the even and odd keys that are
+    /// given, wrapped with the passphrase
+    ///
+    /// The keys are wrapped together, the even one first. On the wire the
+    /// wrap follows the salt, so it's just split up over the key fields and
+    /// the wrap data, the last 8 bytes
+    fn keys_message(&mut self, keys: [Option<&[u8]>; 2]) -> Result<SrtKeyMessage, Error> {
+        let plain: Vec<u8> = keys
+            .iter()
+            .flatten()
+            .flat_map(|key| key.iter())
+            .copied()
+            .collect();
+        let wrapped = self.wrap(&plain)?;
+        let (wrapped_keys, wrap_data) = wrapped.split_at(wrapped.len() - 8);
+
+        let mut wrapped_keys = wrapped_keys.chunks(usize::from(self.size));
+        let mut wrapped_key =
+            |key: Option<&[u8]>| key.map(|_| wrapped_keys.next().unwrap().to_vec());
+        let even_key = wrapped_key(keys[0]);
+        let odd_key = wrapped_key(keys[1]);
+
+        Ok(SrtKeyMessage {
+            pt: KM_PT,
+            sign: KM_SIGN,
+            keki: 0,
+            cipher: CipherType::CTR,
+            auth: 0,
+            se: KM_SE,
+            salt: self.salt.to_vec(),
+            even_key,
+            odd_key,
+            wrap_data: wrap_data.try_into().unwrap(),
+        })
+    }
+
+    /// The cipher for payloads, with the key
+    pub fn cipher(&self) -> PayloadCipher {
+        PayloadCipher::new(self.key.clone(), self.salt)
+    }
+}
+
+/// When the sender replaces its key: after `rate` packets, or once it has
+/// been in use for `period` if that comes first, announcing the new key
+/// `pre_announce` packets before it's used and keeping the old one for as
+/// long after, so packets in flight can still be decrypted
+///
+/// When `period` runs out, the new key is announced right away and used
+/// `pre_announce` packets later
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyRefresh {
+    pub rate: u64,
+    pub pre_announce: u64,
+    pub period: Option<Duration>,
+}
+
+impl Default for KeyRefresh {
+    // the reference implementation's defaults
+    fn default() -> Self {
+        KeyRefresh {
+            rate: 1 << 24,
+            pre_announce: 1 << 12,
+            period: None,
+        }
+    }
+}
+
+/// The keys a sender encrypts with, replacing them as `KeyRefresh` says
+///
+/// There are two key slots, even and odd, and each packet is flagged with the
+/// one it was encrypted with. A new key goes in the slot that isn't in use, so
+/// the peer can have both while switching over
+pub struct SenderKeys {
+    manager: CryptoManager,
+    keys: [Option<PayloadCipher>; 2],
+    active: usize,
+    // packets sent with the active key, and when it came into use
+    sent: u64,
+    since: Instant,
+    // the packet count the announced key takes over at
+    switch_at: Option<u64>,
+}
+
+impl SenderKeys {
+    /// Start with `initial` as the even key, new keys being wrapped with
+    /// `passphrase`
+    pub fn new(passphrase: String, initial: PayloadCipher) -> Self {
+        SenderKeys {
+            manager: CryptoManager::new_with_salt(
+                initial.key_len(),
+                passphrase,
+                &initial.salt,
+                initial.key.clone(),
+            ),
+            keys: [Some(initial), None],
+            active: 0,
+            sent: 0,
+            since: Instant::now(),
+            switch_at: None,
+        }
+    }
+
+    /// Encrypt `payload`, the payload of packet `seq_number` sent at `now`,
+    /// with the key in use, returning which one that is
+    ///
+    /// When the keys change, the key material to announce them to the peer
+    /// is returned too
+    pub fn encrypt(
+        &mut self,
+        refresh: KeyRefresh,
+        seq_number: SeqNumber,
+        payload: &[u8],
+        now: Instant,
+    ) -> Result<(DataEncryption, Bytes, Option<SrtKeyMessage>), Error> {
+        let other = 1 - self.active;
+        let mut announce = None;
+
+        if self.keys[other].is_none() {
+            let expired = refresh.period.is_some_and(|p| now >= self.since + p);
+            if expired || self.sent >= refresh.rate - refresh.pre_announce {
+                let mut key = vec![0; self.manager.key.len()];
+                rand_bytes(&mut key)?;
+                self.keys[other] = Some(PayloadCipher::new(key, self.manager.salt));
+                self.switch_at = Some(self.sent + refresh.pre_announce);
+                announce = Some(self.announce()?);
+            }
+        } else if let Some(switch_at) = self.switch_at {
+            if self.sent >= switch_at {
+                self.active = other;
+                self.sent = 0;
+                self.since = now;
+                self.switch_at = None;
+            }
+        } else if self.sent >= refresh.pre_announce {
+            // this is the old key, packets with it have had time to arrive.
+            // Until the switch it's the new one, which is only there once
+            // `switch_at` is set
+            self.keys[other] = None;
+            announce = Some(self.announce()?);
+        }
+
+        let cipher = self.keys[self.active].as_ref().unwrap();
+        let encryption = if self.active == 0 {
+            DataEncryption::Even
+        } else {
+            DataEncryption::Odd
+        };
+        self.sent += 1;
+
+        Ok((encryption, cipher.apply(seq_number, payload), announce))
+    }
+
+    /// The key material with the keys there are now
+    fn announce(&mut self) -> Result<SrtKeyMessage, Error> {
+        let keys = self.keys.clone();
+        let [even, odd] = &keys;
+        self.manager.keys_message([
+            even.as_ref().map(|c| &c.key[..]),
+            odd.as_ref().map(|c| &c.key[..]),
+        ])
+    }
+}
+
+/// The keys a receiver decrypts with, replaced by the key material the
+/// sender announces
+pub struct ReceiverKeys {
+    passphrase: String,
+    keys: [Option<PayloadCipher>; 2],
+}
+
+impl ReceiverKeys {
+    /// Start with `initial` as the even key, if the handshake agreed on one
+    pub fn new(passphrase: String, initial: Option<PayloadCipher>) -> Self {
+        ReceiverKeys {
+            passphrase,
+            keys: [initial, None],
+        }
+    }
+
+    /// Decrypt `payload`, the payload of packet `seq_number`, with the key
+    /// `encryption` says, if there is one
+    pub fn decrypt(
+        &self,
+        encryption: DataEncryption,
+        seq_number: SeqNumber,
+        payload: &[u8],
+    ) -> Option<Bytes> {
+        let slot = match encryption {
+            DataEncryption::None => return Some(Bytes::copy_from_slice(payload)),
+            DataEncryption::Even => &self.keys[0],
+            DataEncryption::Odd => &self.keys[1],
+        };
+        slot.as_ref()
+            .map(|cipher| cipher.apply(seq_number, payload))
+    }
+
+    /// Take the keys in `key_msg`, which replace all of the ones there are
+    pub fn update(&mut self, key_msg: &SrtKeyMessage) -> Result<(), Error> {
+        self.keys = CryptoManager::unwrap_key_message(self.passphrase.clone(), key_msg)?;
+        Ok(())
+    }
+}
+
+/// Encrypts and decrypts data packet payloads with AES in counter mode
+///
+/// Like the reference implementation, the IV is the salt xored with the
+/// packet's sequence number, so each packet has its own keystream
+#[derive(Clone)]
+pub struct PayloadCipher {
+    cipher: Cipher,
+    key: Vec<u8>,
+    salt: [u8; 16],
+}
+
+impl PayloadCipher {
+    fn new(key: Vec<u8>, salt: [u8; 16]) -> Self {
+        PayloadCipher {
+            cipher: match key.len() {
+                16 => Cipher::aes_128_ctr(),
+                24 => Cipher::aes_192_ctr(),
+                32 => Cipher::aes_256_ctr(),
+                len => panic!("Invalid key length {}", len),
+            },
+            key,
+            salt,
+        }
+    }
+
+    /// Encrypt `payload`, the payload of packet `seq_number`. Decrypting is the
+    /// same in counter mode
+    pub fn apply(&self, seq_number: SeqNumber, payload: &[u8]) -> Bytes {
+        // https://github.com/Haivision/srt/blob/2ef4ef003c2006df1458de6d47fbe3d2338edf69/haicrypt/hcrypt.h#L146-L150
+        let mut iv = [0; 16];
+        iv[10..14].copy_from_slice(&seq_number.as_raw().to_be_bytes());
+        for (iv, salt) in iv[..14].iter_mut().zip(&self.salt[..]) {
+            *iv ^= salt;
+        }
+
+        // the key and IV are the right length for the cipher, so this can't fail
+        symm::encrypt(self.cipher, &self.key, Some(&iv), payload)
+            .unwrap()
+            .into()
+    }
+
+    /// The length of the key in bytes
+    pub fn key_len(&self) -> u8 {
+        self.key.len() as u8
+    }
+}
+
+impl Debug for PayloadCipher {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "PayloadCipher(AES-{}-CTR)", self.key.len() * 8)
     }
 }
 
 #[cfg(test)]
 mod test {
-
     use super::*;
+    use crate::packet::SrtControlPacket;
 
     #[test]
     fn kek_generate() {
@@ -135,7 +462,7 @@ mod test {
         let mut manager = CryptoManager::new_with_salt(
             16,
             "password123".into(),
-            &b"\x00\x00\x00\x00\x00\x00\x00\x00\x85\x2c\x3c\xcd\x02\x65\x1a\x22",
+            b"\x00\x00\x00\x00\x00\x00\x00\x00\x85\x2c\x3c\xcd\x02\x65\x1a\x22",
             b"\r\xab\xc8n/2\xb4\xa7\xb9\xbb\xa2\xf31*\xe4\"".to_vec(),
         );
         assert_eq!(
@@ -143,20 +470,19 @@ mod test {
             &b"\xe9\xa0\xa4\x30\x2f\x59\xd0\x63\xc8\x83\x32\xbe\x35\x88\x82\x08"[..]
         );
 
-        let _prev_key = Vec::from(manager.key());
-
-        let wrapped = manager.wrap_key().unwrap();
+        let key = manager.key.clone();
+        let wrapped = manager.wrap(&key).unwrap();
         assert_eq!(
             wrapped,
             &b"31ea\x11\xe8\xb0P\xfe\x99\x9f\xd5h\xc2b\xfb\x1a3\xcc\xc8\x9cNw\xca"[..]
         );
 
-        // manager.unwrap_key(&wrapped).unwrap();
-        // assert_eq!(&prev_key[..], manager.key());
+        manager.unwrap_key(&wrapped).unwrap();
+        assert_eq!(manager.key, key);
         let mut manager = CryptoManager::new_with_salt(
             16,
             "password123".into(),
-            &b"\x00\x00\x00\x00\x00\x00\x00\x00n\xd5+\x196\nq8",
+            b"\x00\x00\x00\x00\x00\x00\x00\x00n\xd5+\x196\nq8",
             vec![],
         );
         assert_eq!(
@@ -168,4 +494,176 @@ mod test {
             .unwrap_key(&b"U\x06\xe9\xfd\xdfd\xf1'nr\xf4\xe9f\x81#(\xb7\xb5D\x19{\x9b\xcdx"[..])
             .unwrap();
     }
+
+    #[test]
+    fn key_message_roundtrip() {
+        let mut manager = CryptoManager::new(16, "password123".into());
+        let key_msg = manager.key_message().unwrap();
+        assert_eq!(key_msg.salt, manager.salt);
+
+        let [even, odd] =
+            CryptoManager::unwrap_key_message("password123".into(), &key_msg).unwrap();
+        assert_eq!(even.unwrap().key, manager.key);
+        assert!(odd.is_none());
+
+        assert!(CryptoManager::unwrap_key_message("password456".into(), &key_msg).is_err());
+    }
+
+    // what the reference implementation sends and encrypts with this
+    // passphrase, salt and keys: PBKDF2-HMAC-SHA1 over the end of the salt
+    // for the kek, both keys wrapped together with RFC 3394, and AES-CTR with
+    // the IV from the salt and sequence number
+    #[test]
+    fn reference_key_material() {
+        let salt: [u8; 16] = (0..16).collect::<Vec<_>>()[..].try_into().unwrap();
+        let (even, odd): (Vec<u8>, Vec<u8>) = ((16..32).collect(), (32..48).collect());
+
+        let mut manager =
+            CryptoManager::new_with_salt(16, "passphrase1234".into(), &salt, even.clone());
+        assert_eq!(
+            hex::encode(manager.generate_kek().unwrap()),
+            "ab84d320ea30473fdc91dd280aa6f2e1"
+        );
+
+        let key_msg = manager.keys_message([Some(&even), Some(&odd)]).unwrap();
+        let mut buf = Vec::new();
+        SrtControlPacket::KeyManagerRequest(key_msg.clone()).serialize(&mut buf);
+        assert_eq!(
+            hex::encode(&buf),
+            "12202903000000000200020000000404\
+             000102030405060708090a0b0c0d0e0f\
+             f2678240bbc2ceef796b344627dabf62\
+             458ce3ebe26415789596c2c47e48db95\
+             f7f56f6adedc7467"
+        );
+
+        let [e, _] = CryptoManager::unwrap_key_message("passphrase1234".into(), &key_msg).unwrap();
+        assert_eq!(
+            hex::encode(
+                e.unwrap()
+                    .apply(SeqNumber::new_truncate(1234), b"Hello, SRT!")
+            ),
+            "90723a7f1ecba69d6f7b01"
+        );
+    }
+
+    #[test]
+    fn both_keys_roundtrip() {
+        let mut manager = CryptoManager::new(24, "password123".into());
+        let (even, odd) = ([1; 24], [2; 24]);
+        let key_msg = manager.keys_message([Some(&even), Some(&odd)]).unwrap();
+
+        let [e, o] = CryptoManager::unwrap_key_message("password123".into(), &key_msg).unwrap();
+        assert_eq!(e.unwrap().key, even);
+        assert_eq!(o.unwrap().key, odd);
+
+        // only the odd key, as when the even one is decommissioned
+        let key_msg = manager.keys_message([None, Some(&odd)]).unwrap();
+        assert!(key_msg.even_key.is_none());
+        let [e, o] = CryptoManager::unwrap_key_message("password123".into(), &key_msg).unwrap();
+        assert!(e.is_none());
+        assert_eq!(o.unwrap().key, odd);
+    }
+
+    #[test]
+    fn key_rotation() {
+        let refresh = KeyRefresh {
+            rate: 10,
+            pre_announce: 3,
+            period: None,
+        };
+        let initial = CryptoManager::new(16, "password123".into()).cipher();
+        let mut sender = SenderKeys::new("password123".into(), initial.clone());
+        let mut receiver = ReceiverKeys::new("password123".into(), Some(initial));
+
+        let mut flags = Vec::new();
+        let mut announced = Vec::new();
+        for i in 0..25 {
+            let seq = SeqNumber::new_truncate(i);
+            let (encryption, payload, key_msg) = sender
+                .encrypt(refresh, seq, b"Hello", Instant::now())
+                .unwrap();
+            if let Some(key_msg) = key_msg {
+                announced.push((i, key_msg.even_key.is_some(), key_msg.odd_key.is_some()));
+                receiver.update(&key_msg).unwrap();
+            }
+            flags.push(encryption);
+
+            assert_eq!(
+                receiver.decrypt(encryption, seq, &payload).as_deref(),
+                Some(&b"Hello"[..])
+            );
+        }
+
+        // switching over after 10 packets, announcing 3 before and
+        // decommissioning 3 after
+        let (even, odd) = (DataEncryption::Even, DataEncryption::Odd);
+        assert_eq!(flags[..10], [even; 10]);
+        assert_eq!(flags[10..20], [odd; 10]);
+        assert_eq!(flags[20..], [even; 5]);
+        assert_eq!(
+            announced,
+            [
+                (7, true, true),
+                (13, false, true),
+                (17, true, true),
+                (23, true, false)
+            ]
+        );
+
+        // a receiver with another passphrase can't take the new keys
+        let mut other = ReceiverKeys::new("password456".into(), None);
+        let key_msg = sender.announce().unwrap();
+        assert!(other.update(&key_msg).is_err());
+        assert_eq!(
+            other.decrypt(even, SeqNumber::new_truncate(0), b"Hello"),
+            None
+        );
+    }
+
+    #[test]
+    fn key_rotation_period() {
+        let refresh = KeyRefresh {
+            rate: 1000,
+            pre_announce: 2,
+            period: Some(Duration::from_secs(1)),
+        };
+        let initial = CryptoManager::new(16, "password123".into()).cipher();
+        let mut sender = SenderKeys::new("password123".into(), initial);
+        let start = sender.since;
+
+        let mut flags = Vec::new();
+        let mut announced = Vec::new();
+        for i in 0..8 {
+            // a packet every 400ms, so the key is due at the third
+            let now = start + Duration::from_millis(400 * i);
+            let seq = SeqNumber::new_truncate(i as u32);
+            let (encryption, _, key_msg) = sender.encrypt(refresh, seq, b"Hello", now).unwrap();
+            if key_msg.is_some() {
+                announced.push(i);
+            }
+            flags.push(encryption);
+        }
+
+        // announced once the second is up, switched to 2 packets later, and
+        // the old key kept for 2 packets after that
+        let (even, odd) = (DataEncryption::Even, DataEncryption::Odd);
+        assert_eq!(flags, [even, even, even, even, even, odd, odd, odd]);
+        assert_eq!(announced, [3, 7]);
+    }
+
+    #[test]
+    fn payload_roundtrip() {
+        let cipher = CryptoManager::new(16, "password123".into()).cipher();
+        let seq = SeqNumber::new_truncate(1234);
+
+        let encrypted = cipher.apply(seq, b"Hello, world!");
+        assert_eq!(encrypted.len(), 13);
+        assert_ne!(&encrypted[..], b"Hello, world!");
+        assert_eq!(&cipher.apply(seq, &encrypted)[..], b"Hello, world!");
+
+        // each packet has its own keystream
+        let other = cipher.apply(seq + 1, b"Hello, world!");
+        assert_ne!(encrypted, other);
+    }
 }
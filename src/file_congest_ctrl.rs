@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use crate::{CCData, CongestCtrl, SeqNumber};
+
+/// How often the rate is adjusted, UDT's SYN
+const RATE_CONTROL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The congestion window slow start starts with, and never goes below
+const MIN_WINDOW: f64 = 16.;
+
+/// The congestion window that ends slow start, the default flow window
+const MAX_WINDOW: f64 = 8192.;
+
+/// The least the send rate is increased by each ACK, in packets per SYN
+const MIN_INCREASE: f64 = 0.01;
+
+/// The congestion control for file transfer, UDT's native algorithm
+///
+/// It starts in slow start, where the congestion window grows with each ACK
+/// until there is loss or the window reaches the flow window. Then it paces
+/// packets at the rate they arrive at the receiver, speeding up a little each
+/// ACK depending on how much spare bandwidth there seems to be, and slowing
+/// down by 1/8 when newer packets are lost or the peer warns of congestion.
+#[derive(Debug, Clone)]
+pub struct FileCongestCtrl {
+    slow_start: bool,
+
+    /// The congestion window, in packets
+    window: f64,
+
+    /// The time between packets, in microseconds
+    send_interval: f64,
+
+    /// The latest packet sent at the last ACK, to tell how many have been sent since
+    last_ack_seq: Option<SeqNumber>,
+
+    /// The latest packet sent when the rate was last decreased, so one loss
+    /// event only slows sending down once
+    last_dec_seq: Option<SeqNumber>,
+}
+
+impl FileCongestCtrl {
+    /// Pace packets so at most `rate` bits/s are sent, assuming each packet is
+    /// `packet_size` bytes, until slow start ends
+    pub fn with_send_rate(rate: u64, packet_size: u32) -> FileCongestCtrl {
+        assert!(rate > 0, "Send rate must be nonzero");
+
+        let bits = u64::from(packet_size) * 8;
+
+        FileCongestCtrl {
+            send_interval: (bits * 1_000_000) as f64 / rate as f64,
+            ..FileCongestCtrl::default()
+        }
+    }
+
+    fn end_slow_start(&mut self, data: &CCData) {
+        self.slow_start = false;
+
+        // send at the rate the receiver gets packets, or a window per RTT
+        // if that's not known yet
+        self.send_interval = if data.packet_arr_rate > 0 {
+            1_000_000. / f64::from(data.packet_arr_rate)
+        } else {
+            (data.rtt + RATE_CONTROL_INTERVAL).as_micros() as f64 / self.window
+        };
+    }
+
+    fn slow_down(&mut self, data: &CCData) {
+        self.send_interval *= 1.125;
+        self.last_dec_seq = data.latest_seq_num;
+    }
+}
+
+impl Default for FileCongestCtrl {
+    fn default() -> Self {
+        FileCongestCtrl {
+            slow_start: true,
+            window: MIN_WINDOW,
+            send_interval: 0.,
+            last_ack_seq: None,
+            last_dec_seq: None,
+        }
+    }
+}
+
+impl CongestCtrl for FileCongestCtrl {
+    fn on_ack(&mut self, data: &CCData) {
+        let sent = match (data.latest_seq_num, self.last_ack_seq) {
+            (Some(latest), Some(last)) if latest > last => latest - last,
+            _ => 0,
+        };
+        self.last_ack_seq = data.latest_seq_num;
+
+        if self.slow_start {
+            // the window is full between ACKs, so what was sent since the
+            // last one has about been acknowledged by this one
+            self.window += f64::from(sent);
+            if self.window > MAX_WINDOW {
+                self.end_slow_start(data);
+            }
+            return;
+        }
+
+        let rtt = (data.rtt + RATE_CONTROL_INTERVAL).as_secs_f64();
+        self.window = f64::max(
+            f64::from(data.packet_arr_rate) * rtt + MIN_WINDOW,
+            MIN_WINDOW,
+        );
+
+        // increase by more when there's more bandwidth to spare, in bits/s
+        let mss = f64::from(data.max_segment_size);
+        let spare = f64::from(data.est_bandwidth) - 1_000_000. / self.send_interval;
+        let increase = if spare > 0. {
+            let bits = spare * mss * 8.;
+            f64::max(
+                10f64.powf(bits.log10().ceil()) * 0.000_001_5 / mss,
+                MIN_INCREASE,
+            )
+        } else {
+            MIN_INCREASE
+        };
+
+        let syn = RATE_CONTROL_INTERVAL.as_micros() as f64;
+        self.send_interval = self.send_interval * syn / (self.send_interval * increase + syn);
+    }
+
+    fn on_nak(&mut self, largest_seq_in_ll: SeqNumber, data: &CCData) {
+        if self.slow_start {
+            self.end_slow_start(data);
+        }
+
+        // only slow down for loss of packets sent since the last slow down
+        match self.last_dec_seq {
+            Some(last_dec) if largest_seq_in_ll <= last_dec => {}
+            _ => self.slow_down(data),
+        }
+    }
+
+    fn on_congestion_warning(&mut self, data: &CCData) {
+        if self.slow_start {
+            self.end_slow_start(data);
+        }
+        self.slow_down(data);
+    }
+
+    fn send_interval(&self) -> Duration {
+        Duration::from_nanos((self.send_interval * 1_000.) as u64)
+    }
+
+    fn window_size(&self) -> u32 {
+        self.window as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FileCongestCtrl;
+    use crate::{CCData, CongestCtrl, SeqNumber};
+
+    use std::time::Duration;
+
+    fn cc_data(latest_seq_num: u32) -> CCData {
+        CCData {
+            rtt: Duration::from_millis(10),
+            rtt_var: Duration::from_millis(5),
+            max_segment_size: 1500,
+            est_bandwidth: 10_000,
+            latest_seq_num: Some(SeqNumber::new_truncate(latest_seq_num)),
+            packet_arr_rate: 1_000,
+        }
+    }
+
+    #[test]
+    fn slow_start_until_loss() {
+        let mut cc = FileCongestCtrl::default();
+        assert_eq!(cc.window_size(), 16);
+        assert_eq!(cc.send_interval(), Duration::from_secs(0));
+
+        cc.on_ack(&cc_data(16));
+        cc.on_ack(&cc_data(48));
+        assert_eq!(cc.window_size(), 48);
+        assert_eq!(cc.send_interval(), Duration::from_secs(0));
+
+        // loss ends slow start, sending at the rate packets arrive
+        cc.on_nak(SeqNumber::new_truncate(40), &cc_data(60));
+        assert_eq!(cc.send_interval(), Duration::from_micros(1_125));
+
+        // loss of packets sent before that doesn't slow it down again
+        cc.on_nak(SeqNumber::new_truncate(50), &cc_data(70));
+        assert_eq!(cc.send_interval(), Duration::from_micros(1_125));
+
+        cc.on_nak(SeqNumber::new_truncate(65), &cc_data(80));
+        assert!(cc.send_interval() > Duration::from_micros(1_125));
+    }
+
+    #[test]
+    fn speeds_up_after_slow_start() {
+        let mut cc = FileCongestCtrl::with_send_rate(12_000_000, 1500);
+        assert_eq!(cc.send_interval(), Duration::from_millis(1));
+
+        cc.on_congestion_warning(&cc_data(0));
+        let slowed = cc.send_interval();
+        assert_eq!(slowed, Duration::from_micros(1_125));
+
+        cc.on_ack(&cc_data(10));
+        assert!(cc.send_interval() < slowed);
+        // a window of what arrives in an RTT and SYN, plus 16
+        assert_eq!(cc.window_size(), 36);
+    }
+}
@@ -0,0 +1,32 @@
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::net::SocketAddr;
+
+/// The peer encrypts with a key of another length than the one set with
+/// `SrtSocketBuilder::key_length`
+///
+/// Connecting fails with this error, it can be told apart from others by
+/// downcasting the `failure::Error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyLengthMismatch {
+    /// The peer that was being connected to
+    pub remote: SocketAddr,
+
+    /// The length in bytes of the key this end uses
+    pub local: u8,
+
+    /// The length in bytes of the key the peer uses
+    pub peer: u8,
+}
+
+impl Display for KeyLengthMismatch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} uses a {} byte key, but this end uses a {} byte key",
+            self.remote, self.peer, self.local
+        )
+    }
+}
+
+impl error::Error for KeyLengthMismatch {}
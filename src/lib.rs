@@ -1,40 +1,119 @@
+#![cfg_attr(not(feature = "std"), no_std)]
 #![deny(clippy::all)]
 #![forbid(unsafe_code)]
 
+//! Without the default `std` feature, only the packet codec (`Packet` and the
+//! types it's made of) is built, and the crate is `no_std` with `alloc`
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod access_control;
+#[cfg(feature = "std")]
 mod builder;
+#[cfg(feature = "std")]
 mod channel;
+#[cfg(feature = "std")]
 mod congest_ctrl;
+#[cfg(feature = "std")]
+mod connect_timeout;
+#[cfg(feature = "std")]
 mod connection;
+#[cfg(feature = "crypto")]
 mod crypto;
+#[cfg(feature = "std")]
+mod file_congest_ctrl;
+#[cfg(feature = "std")]
+mod key_length_mismatch;
 mod loss_compression;
 mod modular_num;
+#[cfg(feature = "std")]
+mod msg_ctrl;
 mod msg_number;
+#[cfg(feature = "std")]
 mod multiplex;
+#[cfg(feature = "std")]
+mod nat_traversal;
 mod packet;
+#[cfg(feature = "std")]
+mod peer_error;
+#[cfg(feature = "std")]
 mod pending_connection;
+#[cfg(feature = "std")]
 mod receiver;
+#[cfg(feature = "std")]
 mod sender;
 mod seq_number;
+#[cfg(feature = "std")]
 mod sink_send_wrapper;
+#[cfg(feature = "std")]
 mod socket;
 mod socket_id;
+#[cfg(feature = "std")]
 mod srt_congest_ctrl;
 mod srt_version;
+#[cfg(feature = "std")]
 mod stats;
+#[cfg(feature = "std")]
+mod stream_socket;
+#[cfg(feature = "std")]
+mod stream_writer;
+mod time_stamp;
+#[cfg(feature = "std")]
 mod util;
 
+#[cfg(feature = "std")]
+pub use crate::access_control::{AcceptParams, AccessDecision};
+#[cfg(feature = "std")]
 pub use crate::builder::{ConnInitMethod, SrtSocketBuilder, UnderlyingSocket};
-pub use crate::congest_ctrl::{CCData, CongestCtrl};
+#[cfg(feature = "std")]
+pub use crate::congest_ctrl::{CCData, CongestCtrl, CongestCtrlType};
+#[cfg(feature = "std")]
+pub use crate::connect_timeout::ConnectTimeout;
+#[cfg(feature = "std")]
 pub use crate::connection::{Connection, ConnectionSettings};
+#[cfg(feature = "crypto")]
+pub use crate::crypto::PayloadCipher;
+#[cfg(feature = "std")]
+pub use crate::file_congest_ctrl::FileCongestCtrl;
+#[cfg(feature = "std")]
+pub use crate::key_length_mismatch::KeyLengthMismatch;
+pub use crate::loss_compression::{
+    compress_loss_list, compress_loss_list_chunked, decompress_loss_list,
+};
+#[cfg(feature = "std")]
+pub use crate::msg_ctrl::MsgCtrl;
 pub use crate::msg_number::MsgNumber;
-pub use crate::multiplex::{MultiplexServer, StreamerServer};
-pub use crate::packet::{ControlPacket, DataPacket, Packet, PacketCodec};
-pub use crate::receiver::Receiver;
+#[cfg(feature = "std")]
+pub use crate::multiplex::{MultiplexServer, SrtConnector, SrtListener, StreamerServer};
+#[cfg(feature = "std")]
+pub use crate::nat_traversal::NatTraversal;
+pub use crate::packet::{
+    CipherType, ControlPacket, ControlTypes, DataEncryption, DataPacket, FilterControlHeader,
+    HandshakeControlInfo, HandshakeExtension, HandshakeVSInfo, KmState, Packet, PacketLocation,
+    PacketParseError, PeerStats, RejectReason, ShakeType, SocketType, SrtControlPacket,
+    SrtHandshake, SrtKeyMessage, SrtShakeFlags,
+};
+#[cfg(feature = "std")]
+pub use crate::packet::{PacketCodec, StrictPacketCodec, Udt4PacketCodec};
+#[cfg(feature = "std")]
+pub use crate::peer_error::PeerError;
+#[cfg(feature = "std")]
+pub use crate::receiver::{Receiver, UndecryptableEvent};
 // TODO: remove
-pub use crate::sender::Sender;
-pub use crate::seq_number::SeqNumber;
-pub use crate::socket::SrtSocket;
+#[cfg(feature = "std")]
+pub use crate::sender::{
+    InFlightEntry, RetransmitAlgorithm, RetransmitPriority, SendDropEvent, SendDropReason, Sender,
+};
+pub use crate::seq_number::{seq_num_range, SeqNumber};
+#[cfg(feature = "std")]
+pub use crate::socket::{SrtSocket, PACKET_TRACE_TARGET};
 pub use crate::socket_id::SocketID;
+#[cfg(feature = "std")]
 pub use crate::srt_congest_ctrl::SrtCongestCtrl;
 pub use crate::srt_version::SrtVersion;
-pub use crate::stats::Stats;
+#[cfg(feature = "std")]
+pub use crate::stats::{JitterHistogram, Stats, JITTER_BUCKETS};
+#[cfg(feature = "std")]
+pub use crate::stream_socket::StreamSocket;
+pub use crate::time_stamp::TimeStamp;
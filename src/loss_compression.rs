@@ -1,4 +1,4 @@
-use std::iter::Iterator;
+use alloc::{vec, vec::Vec};
 
 use crate::SeqNumber;
 
@@ -38,7 +38,8 @@ where
             // d) not in a loop and doesn't need to start one
 
             let this = self.next.unwrap();
-            self.next = match self.iterator.next() {
+            // skip duplicates, they're already covered by this
+            self.next = match self.iterator.find(|&next| next != this) {
                 Some(i) => Some(i),
                 None => {
                     // invalidate next, so it None will be returned next time
@@ -96,6 +97,48 @@ where
     }
 }
 
+/// Compress a loss list, splitting it into lists of at most `max_words` 32-bit words,
+/// so each can be sent in its own NAK packet
+///
+/// A range is never split between two lists, so each can be decompressed on its own
+pub fn compress_loss_list_chunked<I>(loss_list: I, max_words: usize) -> Vec<Vec<u32>>
+where
+    I: Iterator<Item = SeqNumber>,
+{
+    assert!(
+        max_words >= 2,
+        "A loss list chunk must be able to hold a range"
+    );
+
+    let mut chunks = vec![];
+    let mut current = Vec::with_capacity(max_words);
+    let mut compressed = compress_loss_list(loss_list);
+
+    while let Some(word) = compressed.next() {
+        // a range is two words, the start (with the high bit set) and the end
+        let range_end = if word & (1 << 31) != 0 {
+            compressed.next()
+        } else {
+            None
+        };
+        let len = if range_end.is_some() { 2 } else { 1 };
+
+        if current.len() + len > max_words {
+            chunks.push(current);
+            current = Vec::with_capacity(max_words);
+        }
+
+        current.push(word);
+        current.extend(range_end);
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
 pub struct DecompressLossList<I> {
     iterator: I,
 
@@ -155,8 +198,9 @@ pub fn decompress_loss_list<I: Iterator<Item = u32>>(loss_list: I) -> Decompress
 #[cfg(test)]
 mod test {
 
-    use super::{compress_loss_list, decompress_loss_list};
-    use crate::SeqNumber;
+    use super::{compress_loss_list, compress_loss_list_chunked, decompress_loss_list};
+    use crate::packet::{ControlPacket, ControlTypes};
+    use crate::{SeqNumber, TimeStamp};
 
     const ONE: u32 = 1 << 31;
 
@@ -196,6 +240,63 @@ mod test {
         );
     }
 
+    #[test]
+    fn duplicates_merged() {
+        assert_eq!(
+            compress_loss_list(
+                [1, 2, 2, 3, 5, 5]
+                    .iter()
+                    .cloned()
+                    .map(SeqNumber::new_truncate)
+            )
+            .collect::<Vec<_>>(),
+            vec![1 | ONE, 3, 5]
+        );
+    }
+
+    #[test]
+    fn adjacent_losses_in_one_mtu() {
+        // 1500 byte packets, minus IP/UDP and SRT header
+        let max_words = (1500 - 28 - 16) / 4;
+
+        let chunks =
+            compress_loss_list_chunked((1000..2000).map(SeqNumber::new_truncate), max_words);
+        assert_eq!(chunks, vec![vec![1000 | ONE, 1999]]);
+
+        let mut buf = vec![];
+        ControlPacket {
+            timestamp: TimeStamp(0),
+            dest_sockid: crate::SocketID(0),
+            control_type: ControlTypes::Nak(chunks[0].clone()),
+        }
+        .serialize(&mut buf);
+        assert!(buf.len() <= 1500 - 28);
+    }
+
+    #[test]
+    fn chunks_dont_split_ranges() {
+        // 0, 1, 3, 4, 6, 7..., which compresses to all two word ranges
+        let losses = (0..30)
+            .filter(|i| i % 3 != 2)
+            .map(SeqNumber::new_truncate)
+            .collect::<Vec<_>>();
+
+        let chunks = compress_loss_list_chunked(losses.iter().cloned(), 3);
+
+        for chunk in &chunks {
+            assert!(chunk.len() <= 3);
+            // each chunk can be decompressed on its own
+            let _ = decompress_loss_list(chunk.iter().cloned()).count();
+        }
+        assert_eq!(
+            chunks
+                .iter()
+                .flat_map(|c| decompress_loss_list(c.iter().cloned()))
+                .collect::<Vec<_>>(),
+            losses
+        );
+    }
+
     #[test]
     #[should_panic(expected = "error: 10!<1")]
     fn invalid_ordering() {
@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+
+/// Options for sending one message, like SRT_MSGCTRL, see `SrtSocket::send_msg`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsgCtrl {
+    in_order: bool,
+    ttl: Option<Duration>,
+    src_time: Option<Instant>,
+}
+
+impl Default for MsgCtrl {
+    fn default() -> Self {
+        MsgCtrl {
+            in_order: true,
+            ttl: None,
+            src_time: None,
+        }
+    }
+}
+
+impl MsgCtrl {
+    /// Send like any other message: it's delivered in order, retransmitted
+    /// until acknowledged, and timestamped with when it's given to the socket
+    #[must_use]
+    pub fn new() -> Self {
+        MsgCtrl::default()
+    }
+
+    /// Whether to deliver the message only after the messages sent before it,
+    /// true by default. Turned off, the peer delivers it as soon as it's
+    /// complete, even if earlier messages are still missing. That's only done
+    /// in message mode without TSBPD, as TSBPD delivers everything in order
+    #[must_use]
+    pub fn in_order(mut self, in_order: bool) -> Self {
+        self.in_order = in_order;
+
+        self
+    }
+
+    /// Drop the message if it isn't acknowledged within `ttl`, asking the
+    /// peer to drop it too
+    #[must_use]
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+
+        self
+    }
+
+    /// Timestamp the message with when its data was produced instead of when
+    /// it's given to the socket, so the peer delivers it the latency after that
+    #[must_use]
+    pub fn src_time(mut self, time: Instant) -> Self {
+        self.src_time = Some(time);
+
+        self
+    }
+
+    pub(crate) fn is_in_order(&self) -> bool {
+        self.in_order
+    }
+
+    /// When the message is dropped if it's given to the socket at `now`
+    pub(crate) fn expiry(&self, now: Instant) -> Option<Instant> {
+        self.ttl.map(|ttl| now + ttl)
+    }
+
+    /// The time to timestamp the message with if it's given to the socket at `now`
+    pub(crate) fn src_time_or(&self, now: Instant) -> Instant {
+        self.src_time.unwrap_or(now)
+    }
+}
@@ -1,5 +1,5 @@
 use crate::modular_num;
 
 modular_num! {
-    pub MsgNumber(u32, 29)
+    pub MsgNumber(u32, 26)
 }
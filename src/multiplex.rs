@@ -1,19 +1,25 @@
+mod connector;
+mod listener;
+mod rate_limit;
 mod streamer_server;
 
+pub use self::connector::SrtConnector;
+pub use self::listener::SrtListener;
 pub use self::streamer_server::StreamerServer;
 
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use futures::channel::mpsc;
 use futures::future::BoxFuture;
 use futures::ready;
 use futures::sink::Sink;
 use futures::stream::Stream;
 
-use log::{info, warn};
+use log::{debug, info, warn};
 
 use failure::{format_err, Error};
 
@@ -21,38 +27,144 @@ use tokio::net::UdpSocket;
 use tokio_util::udp::UdpFramed;
 
 use crate::channel::Channel;
-use crate::packet::{ControlPacket, ControlTypes};
-use crate::{pending_connection, Connection, Packet, PacketCodec, SocketID};
+use crate::packet::{ControlPacket, ControlTypes, ShakeType, SocketType};
+use crate::pending_connection::{self, HandshakeConfig, SynCookies};
+use crate::{Connection, Packet, PacketCodec, SocketID};
+
+use self::connector::Outgoing;
+use self::rate_limit::TokenBucket;
 
 type PackChan = Channel<(Packet, SocketAddr)>;
 
+/// How many handshakes can be in progress at once by default
+pub(crate) const DEFAULT_MAX_PENDING_HANDSHAKES: usize = 256;
+
+/// How many new handshakes are started a second by default
+pub(crate) const DEFAULT_HANDSHAKE_RATE: u32 = 500;
+
+/// How long a handshake can be in progress before it's given up on, to make
+/// room for others. Far longer than callers wait by default
+const PENDING_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct MultiplexServer {
     sock: UdpFramed<PacketCodec>,
 
-    // the socketid here is the remote socketid of the connecting party
+    // inductions are answered from this socketid, with cookies from here,
+    // without keeping anything about the caller
+    sockid: SocketID,
+    cookies: SynCookies,
+
+    // the socketid here is the remote socketid of a caller whose conclusion
+    // had a valid cookie
     initiators: HashMap<SocketID, InitMd>,
 
     // the socketid here is the local socketid
     connections: HashMap<SocketID, PackChan>,
 
-    latency: Duration,
+    // the local socketid of each rendezvous from this port, by the peer's address
+    rendezvous: HashMap<SocketAddr, SocketID>,
+
+    // connections made from this port by an `SrtConnector`
+    outgoing_tx: mpsc::UnboundedSender<Outgoing>,
+    outgoing_rx: mpsc::UnboundedReceiver<Outgoing>,
+
+    // shared by every handshake, so cloned into each
+    config: HandshakeConfig,
+
+    max_pending_handshakes: usize,
+    handshake_rate: TokenBucket,
 }
 
 struct InitMd {
+    started: Instant,
     chan: PackChan,
     future: BoxFuture<'static, Result<(Connection, PackChan), Error>>,
 }
 
 impl MultiplexServer {
     pub async fn bind(addr: &SocketAddr, latency: Duration) -> Result<Self, Error> {
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded();
+
         Ok(MultiplexServer {
             sock: UdpFramed::new(UdpSocket::bind(addr).await?, PacketCodec),
+            sockid: rand::random(),
+            cookies: SynCookies::new()?,
             initiators: HashMap::new(),
             connections: HashMap::new(),
-            latency,
+            rendezvous: HashMap::new(),
+            outgoing_tx,
+            outgoing_rx,
+            config: HandshakeConfig::new(latency),
+            max_pending_handshakes: DEFAULT_MAX_PENDING_HANDSHAKES,
+            handshake_rate: TokenBucket::new(DEFAULT_HANDSHAKE_RATE),
         })
     }
 
+    /// A handle to connect from this server's port with
+    pub fn connector(&self) -> SrtConnector {
+        SrtConnector::new(self.outgoing_tx.clone())
+    }
+
+    /// Do every handshake as `config` says, except that the connections are
+    /// yielded as message mode sockets, without peer stats or UDT4
+    pub(crate) fn with_handshake_config(mut self, config: HandshakeConfig) -> Self {
+        self.config = HandshakeConfig {
+            peer_stats: false,
+            udt4: false,
+            socket_type: SocketType::Datagram,
+            ..config
+        };
+
+        self
+    }
+
+    /// Bound the state a flood of handshakes can make this allocate: at most
+    /// `max_pending` handshakes are in progress at once, and at most `rate` new
+    /// ones are started a second. Handshakes over either limit are dropped
+    pub(crate) fn with_handshake_limits(mut self, max_pending: usize, rate: u32) -> Self {
+        self.max_pending_handshakes = max_pending;
+        self.handshake_rate = TokenBucket::new(rate);
+
+        self
+    }
+
+    /// If handshakes from `from` should be answered at all
+    fn allows(&self, from: SocketAddr) -> bool {
+        match &self.config.address_filter {
+            Some(filter) if !filter.allows(from) => {
+                debug!("Ignoring handshake from filtered address {}", from);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// If a handshake from a new caller should be started, making room for it
+    /// by forgetting ones that have been going on for too long
+    fn admit_handshake(&mut self, from: SocketAddr) -> bool {
+        let now = Instant::now();
+
+        if self.initiators.len() >= self.max_pending_handshakes {
+            self.initiators
+                .retain(|_, md| now - md.started < PENDING_HANDSHAKE_TIMEOUT);
+        }
+        if self.initiators.len() >= self.max_pending_handshakes {
+            debug!(
+                "{} handshakes in progress, dropping handshake from {}",
+                self.initiators.len(),
+                from
+            );
+            return false;
+        }
+
+        if !self.handshake_rate.take(now) {
+            debug!("Too many handshakes, dropping handshake from {}", from);
+            return false;
+        }
+
+        true
+    }
+
     fn sock(&mut self) -> Pin<&mut UdpFramed<PacketCodec>> {
         Pin::new(&mut self.sock)
     }
@@ -78,9 +190,18 @@ impl MultiplexServer {
             }
 
             if let Poll::Ready(conn) = listener_poll {
-                let (conn, chan) = conn?;
-
                 let md = self.initiators.remove(&sockid).unwrap();
+
+                // one caller failing to connect, or being refused, doesn't
+                // concern the others
+                let (conn, chan) = match conn {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        warn!("Handshake with socket {} failed: {}", sockid.0, e);
+                        continue;
+                    }
+                };
+
                 self.connections.insert(conn.settings.local_sockid, md.chan);
 
                 info!("Multiplexed connection to {} ready", conn.settings.remote);
@@ -101,6 +222,14 @@ impl Stream for MultiplexServer {
             // ok to discard, as we don't really care if it's finished or not
             let _ = pin.sock().poll_flush(cx)?;
 
+            // outgoing connections are routed like accepted ones from the start
+            while let Poll::Ready(Some(outgoing)) = Pin::new(&mut pin.outgoing_rx).poll_next(cx) {
+                if let Some(remote) = outgoing.rendezvous {
+                    pin.rendezvous.insert(remote, outgoing.local_sockid);
+                }
+                pin.connections.insert(outgoing.local_sockid, outgoing.chan);
+            }
+
             // poll send sides of channels
             for chan in pin
                 .initiators
@@ -138,6 +267,7 @@ impl Stream for MultiplexServer {
             }
             for r in to_remove {
                 pin.connections.remove(&r);
+                pin.rendezvous.retain(|_, sockid| *sockid != r);
             }
 
             // deal with incomming packets
@@ -151,7 +281,11 @@ impl Stream for MultiplexServer {
                     }
                 };
 
-                let dest_sockid = pack.dest_sockid();
+                // a rendezvous peer's waveahands aren't sent to a socket ID
+                let dest_sockid = match (pack.dest_sockid(), pin.rendezvous.get(&addr)) {
+                    (SocketID(0), Some(sockid)) => *sockid,
+                    (dest_sockid, _) => dest_sockid,
+                };
 
                 // is this an initator?
                 for (remote_sockid, md) in &mut pin.initiators {
@@ -192,11 +326,49 @@ impl Stream for MultiplexServer {
                 // if it is neither of these, then it is a new connection
                 if let Packet::Control(ControlPacket {
                     control_type: ControlTypes::Handshake(info),
+                    timestamp,
                     ..
                 }) = &pack
                 {
+                    if !pin.allows(addr) {
+                        continue 'outer;
+                    }
+
+                    // inductions are answered without anything being kept, so
+                    // only callers that got the answer take up room
+                    match info.shake_type {
+                        ShakeType::Induction => {
+                            let resp = pending_connection::induction_response(
+                                &pin.cookies,
+                                pin.sockid,
+                                false,
+                                info,
+                                *timestamp,
+                                addr,
+                            )?;
+                            ready!(pin.sock().poll_ready(cx))?;
+                            pin.sock().start_send((resp, addr))?;
+                            let _ = pin.sock().poll_flush(cx)?;
+
+                            continue 'outer;
+                        }
+                        ShakeType::Conclusion
+                            if pin.cookies.validate(&addr, info.syn_cookie)? => {}
+                        _ => {
+                            debug!(
+                                "Ignoring {:?} handshake from {} without a valid cookie",
+                                info.shake_type, addr
+                            );
+                            continue 'outer;
+                        }
+                    }
+
+                    if !pin.admit_handshake(addr) {
+                        continue 'outer;
+                    }
+
                     info!(
-                        "Got packet from an unrecognized socketid, starting new connection from {}",
+                        "Got conclusion from an unrecognized socketid, starting new connection from {}",
                         addr
                     );
 
@@ -205,11 +377,17 @@ impl Stream for MultiplexServer {
                     let (mut chan_a, mut chan_b) = PackChan::channel(1000); // TODO: what should this size be?
 
                     let listener = {
-                        let latency = pin.latency;
+                        let (cookies, config) = (pin.cookies.clone(), pin.config.clone());
                         Box::pin(async move {
                             Ok((
-                                pending_connection::listen(&mut chan_b, rand::random(), latency)
-                                    .await?,
+                                pending_connection::conclude(
+                                    &mut chan_b,
+                                    &cookies,
+                                    addr,
+                                    rand::random(),
+                                    &config,
+                                )
+                                .await?,
                                 chan_b,
                             ))
                         })
@@ -222,6 +400,7 @@ impl Stream for MultiplexServer {
                     pin.initiators.insert(
                         socket_id,
                         InitMd {
+                            started: Instant::now(),
                             chan: chan_a,
                             future: listener,
                         },
@@ -0,0 +1,63 @@
+use std::net::SocketAddr;
+
+use failure::{bail, format_err, Error};
+use futures::channel::mpsc;
+use futures::future;
+
+use crate::{ConnInitMethod, SocketID, SrtSocket, SrtSocketBuilder};
+
+use super::PackChan;
+
+/// A connection made from a listener's port, for the listener to route packets to
+pub(crate) struct Outgoing {
+    pub local_sockid: SocketID,
+
+    /// The peer, for rendezvous, whose waveahands aren't sent to a socket ID
+    pub rendezvous: Option<SocketAddr>,
+
+    pub chan: PackChan,
+}
+
+/// Connects or does rendezvous from the UDP port of a listener, so one port
+/// serves both incoming and outgoing connections. Got from
+/// `SrtListener::connector`
+///
+/// Packets for these connections are routed by the listener like those for
+/// the ones it accepted, so it has to keep being polled for them to make
+/// progress. Cloning gives another handle to the same listener
+#[derive(Clone)]
+pub struct SrtConnector {
+    register: mpsc::UnboundedSender<Outgoing>,
+}
+
+impl SrtConnector {
+    pub(crate) fn new(register: mpsc::UnboundedSender<Outgoing>) -> Self {
+        SrtConnector { register }
+    }
+
+    /// Connect as `builder` says, from the listener's port
+    ///
+    /// `builder` has to connect or do rendezvous. Its local address is
+    /// ignored, and so are the settings that only apply to binding a port
+    pub async fn connect(&self, builder: SrtSocketBuilder) -> Result<SrtSocket, Error> {
+        let rendezvous = match *builder.conn_type() {
+            ConnInitMethod::Connect(_) => None,
+            ConnInitMethod::Rendezvous(remote) => Some(remote),
+            ConnInitMethod::Listen => bail!("Cannot listen from a listener's port"),
+        };
+
+        let local_sockid = rand::random();
+        let (chan, theirs) = PackChan::channel(1000);
+        self.register
+            .unbounded_send(Outgoing {
+                local_sockid,
+                rendezvous,
+                chan: theirs,
+            })
+            .map_err(|_| format_err!("Listener was dropped"))?;
+
+        builder
+            .connect_as(chan, local_sockid, future::pending())
+            .await
+    }
+}
@@ -0,0 +1,50 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::ready;
+use futures::stream::Stream;
+
+use failure::Error;
+
+use crate::builder::SocketOptions;
+use crate::{MultiplexServer, SrtConnector, SrtSocket};
+
+/// Accepts any number of connections on one UDP socket, yielding each one as
+/// an `SrtSocket` once its handshake is done. Built with
+/// `SrtSocketBuilder::build_listener`
+///
+/// The listener routes the packets of all of its connections, so it has to
+/// keep being polled for them to make progress, for example by accepting in a
+/// loop and spawning a task for each connection
+pub struct SrtListener {
+    server: MultiplexServer,
+    options: SocketOptions,
+}
+
+impl SrtListener {
+    pub(crate) fn new(server: MultiplexServer, options: SocketOptions) -> Self {
+        SrtListener { server, options }
+    }
+
+    /// A handle to connect or do rendezvous from this listener's port with,
+    /// for when only one port can be used
+    pub fn connector(&self) -> SrtConnector {
+        self.server.connector()
+    }
+}
+
+impl Stream for SrtListener {
+    type Item = Result<SrtSocket, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let pin = self.get_mut();
+
+        let (conn, chan) = match ready!(Pin::new(&mut pin.server).poll_next(cx)) {
+            Some(Ok(conn)) => conn,
+            Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+            None => return Poll::Ready(None),
+        };
+
+        Poll::Ready(Some(Ok(pin.options.socket(chan, conn))))
+    }
+}
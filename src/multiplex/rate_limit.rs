@@ -0,0 +1,63 @@
+use std::time::{Duration, Instant};
+
+/// A token bucket, allowing `rate` events a second on average with bursts of
+/// up to a second's worth
+pub struct TokenBucket {
+    rate: u32,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// A bucket that starts full
+    pub fn new(rate: u32) -> TokenBucket {
+        TokenBucket {
+            rate,
+            tokens: f64::from(rate),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take a token if there is one, returning if one was taken
+    pub fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .checked_duration_since(self.last_refill)
+            .unwrap_or_else(|| Duration::from_secs(0));
+        self.last_refill = now;
+        self.tokens = f64::min(
+            self.tokens + elapsed.as_secs_f64() * f64::from(self.rate),
+            f64::from(self.rate),
+        );
+
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TokenBucket;
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn bursts_then_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(4);
+
+        assert_eq!((0..6).filter(|_| bucket.take(start)).count(), 4);
+
+        // a quarter second later there's one more
+        let later = start + Duration::from_millis(250);
+        assert!(bucket.take(later));
+        assert!(!bucket.take(later));
+
+        // and never more than a second's worth
+        let much_later = start + Duration::from_secs(10);
+        assert_eq!((0..6).filter(|_| bucket.take(much_later)).count(), 4);
+    }
+}
@@ -11,7 +11,9 @@ use bytes::Bytes;
 
 use failure::Error;
 
-use crate::{MultiplexServer, Sender, SrtCongestCtrl};
+#[cfg(feature = "crypto")]
+use crate::crypto::SenderKeys;
+use crate::{MultiplexServer, Sender};
 
 pub struct StreamerServer {
     server: MultiplexServer,
@@ -68,8 +70,15 @@ impl Sink<(Instant, Bytes)> for StreamerServer {
                 .expect("Multiplexer stream ended, strange")
                 .expect("Multiplex server return Err");
 
-            let mut sender =
-                Sender::new(chan, SrtCongestCtrl, conn.settings, Some(conn.hs_returner));
+            let congest_ctrl = conn
+                .settings
+                .congest_ctrl
+                .congest_ctrl(None, conn.settings.max_packet_size);
+            let mut sender = Sender::new(chan, congest_ctrl, conn.settings, Some(conn.hs_returner));
+            #[cfg(feature = "crypto")]
+            if let (Some(cipher), Some(passphrase)) = (conn.crypto, conn.passphrase) {
+                sender.set_keys(SenderKeys::new(passphrase, cipher));
+            }
 
             let (tx, rx) = mpsc::channel(100);
 
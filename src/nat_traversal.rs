@@ -0,0 +1,340 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use bytes::{Buf, BufMut};
+use failure::{bail, format_err, Error};
+use futures::channel::oneshot;
+use futures::{select, FutureExt};
+use log::{debug, info, warn};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout_at, Instant};
+use tokio_util::udp::UdpFramed;
+
+use crate::{ConnInitMethod, PacketCodec, SrtSocket, SrtSocketBuilder};
+
+/// Identifies STUN messages, and what the XOR-MAPPED-ADDRESS is xored with
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_RESPONSE: u16 = 0x0101;
+const BINDING_ERROR_RESPONSE: u16 = 0x0111;
+
+const MAPPED_ADDRESS: u16 = 0x0001;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// How long to wait for the first answer from the STUN server, doubled for
+/// each retry, the RTO RFC 5389 recommends
+const STUN_RTO: Duration = Duration::from_millis(500);
+
+/// How many binding requests to send before giving up on the STUN server
+const STUN_ATTEMPTS: usize = 4;
+
+/// How often to send a binding request to keep the NAT binding open. NATs
+/// keep UDP bindings open for at least 30 seconds without traffic
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A UDP socket to do rendezvous from behind a NAT, with the public address
+/// the NAT gave it
+///
+/// The public address is found by asking a STUN server, and has to be
+/// exchanged with the peer out of band. Until `rendezvous` is called, the
+/// socket sends a binding request to the STUN server every 15 seconds, so the
+/// NAT doesn't close the binding while waiting for the peer's address
+///
+/// This works through NATs that map each local port to the same public port
+/// whoever it sends to, not symmetric ones
+pub struct NatTraversal {
+    public_addr: SocketAddr,
+    stop_keepalive: oneshot::Sender<()>,
+    keepalive: JoinHandle<UdpSocket>,
+}
+
+impl NatTraversal {
+    /// Bind to `local_addr` and ask the STUN server at `stun_server` what
+    /// address that looks like from outside
+    pub async fn discover(
+        local_addr: SocketAddr,
+        stun_server: SocketAddr,
+    ) -> Result<NatTraversal, Error> {
+        let mut sock = UdpSocket::bind(local_addr).await?;
+        let public_addr = query(&mut sock, stun_server).await?;
+        info!("Public address of {} is {}", local_addr, public_addr);
+
+        let (stop_keepalive, stopped) = oneshot::channel();
+        let keepalive = tokio::spawn(keep_alive(sock, stun_server, stopped));
+
+        Ok(NatTraversal {
+            public_addr,
+            stop_keepalive,
+            keepalive,
+        })
+    }
+
+    /// The address the peer has to do rendezvous with
+    pub fn public_addr(&self) -> SocketAddr {
+        self.public_addr
+    }
+
+    /// Stop keeping the binding alive and do rendezvous from the socket as
+    /// `builder` says, which has to be `ConnInitMethod::Rendezvous` with the
+    /// peer's public address. Its local address is ignored, and so is
+    /// `verify_peer_address`: the peer sends to the public address, which
+    /// isn't one this end has
+    pub async fn rendezvous(self, builder: SrtSocketBuilder) -> Result<SrtSocket, Error> {
+        if !matches!(builder.conn_type(), ConnInitMethod::Rendezvous(_)) {
+            bail!("NAT traversal is only for rendezvous");
+        }
+
+        let _ = self.stop_keepalive.send(());
+        let sock = self
+            .keepalive
+            .await
+            .map_err(|e| format_err!("NAT keepalive failed: {}", e))?;
+
+        builder
+            .verify_peer_address(false)
+            .connect_with_sock(UdpFramed::new(sock, PacketCodec))
+            .await
+    }
+}
+
+/// Ask `stun_server` for the address `sock` has from outside, resending the
+/// request with exponential backoff
+async fn query(sock: &mut UdpSocket, stun_server: SocketAddr) -> Result<SocketAddr, Error> {
+    let transaction_id = rand::random();
+    let request = binding_request(transaction_id);
+
+    let mut buf = [0; 1500];
+    let mut rto = STUN_RTO;
+    for _ in 0..STUN_ATTEMPTS {
+        sock.send_to(&request, &stun_server).await?;
+
+        let deadline = Instant::now() + rto;
+        while let Ok(res) = timeout_at(deadline, sock.recv_from(&mut buf)).await {
+            let (len, from) = res?;
+            if from != stun_server {
+                continue;
+            }
+            if let Some(addr) = parse_binding_response(&buf[..len], transaction_id)? {
+                return Ok(addr);
+            }
+        }
+
+        debug!("No answer from STUN server {} after {:?}", stun_server, rto);
+        rto *= 2;
+    }
+
+    bail!("STUN server {} didn't answer", stun_server)
+}
+
+/// Send a binding request every `KEEPALIVE_INTERVAL` until told to stop,
+/// then hand back the socket
+async fn keep_alive(
+    mut sock: UdpSocket,
+    stun_server: SocketAddr,
+    mut stop: oneshot::Receiver<()>,
+) -> UdpSocket {
+    let mut buf = [0; 1500];
+    let mut next = Instant::now() + KEEPALIVE_INTERVAL;
+
+    loop {
+        // answers, and anything the peer sends too early, are discarded
+        let timed_out = select! {
+            _ = stop => return sock,
+            res = timeout_at(next, sock.recv_from(&mut buf)).fuse() => res.is_err(),
+        };
+
+        if timed_out {
+            let request = binding_request(rand::random());
+            if let Err(e) = sock.send_to(&request, &stun_server).await {
+                warn!("Failed to send keepalive to {}: {}", stun_server, e);
+            }
+            next += KEEPALIVE_INTERVAL;
+        }
+    }
+}
+
+/// A STUN binding request with no attributes
+fn binding_request(transaction_id: [u8; 12]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(20);
+    request.put_u16(BINDING_REQUEST);
+    request.put_u16(0);
+    request.put_u32(MAGIC_COOKIE);
+    request.put_slice(&transaction_id);
+
+    request
+}
+
+/// The mapped address in `msg`, or `None` if it isn't an answer to the
+/// binding request `transaction_id`
+fn parse_binding_response(
+    mut msg: &[u8],
+    transaction_id: [u8; 12],
+) -> Result<Option<SocketAddr>, Error> {
+    if msg.len() < 20 {
+        return Ok(None);
+    }
+
+    let msg_type = msg.get_u16();
+    let len = usize::from(msg.get_u16());
+    if msg.get_u32() != MAGIC_COOKIE || msg[..12] != transaction_id[..] {
+        return Ok(None);
+    }
+    msg.advance(12);
+
+    match msg_type {
+        BINDING_RESPONSE => {}
+        BINDING_ERROR_RESPONSE => bail!("STUN server refused the binding request"),
+        _ => return Ok(None),
+    }
+    if msg.len() < len {
+        bail!("STUN response is truncated");
+    }
+    let mut attrs = &msg[..len];
+
+    // servers from before RFC 5389 only send MAPPED-ADDRESS
+    let mut mapped = None;
+    while attrs.len() >= 4 {
+        let attr_type = attrs.get_u16();
+        let attr_len = usize::from(attrs.get_u16());
+        if attrs.len() < attr_len {
+            bail!("STUN attribute is truncated");
+        }
+        let value = &attrs[..attr_len];
+
+        match attr_type {
+            XOR_MAPPED_ADDRESS => return Ok(Some(parse_address(value, Some(transaction_id))?)),
+            MAPPED_ADDRESS => mapped = Some(parse_address(value, None)?),
+            _ => {}
+        }
+
+        // attributes are padded to 4 bytes
+        attrs.advance(usize::min((attr_len + 3) & !3, attrs.len()));
+    }
+
+    match mapped {
+        Some(addr) => Ok(Some(addr)),
+        None => bail!("STUN response has no mapped address"),
+    }
+}
+
+/// A (XOR-)MAPPED-ADDRESS attribute, unxored with the magic cookie and
+/// `transaction_id` if it's given
+fn parse_address(mut value: &[u8], transaction_id: Option<[u8; 12]>) -> Result<SocketAddr, Error> {
+    if value.len() < 4 {
+        bail!("STUN address attribute is truncated");
+    }
+
+    let mut mask = [0; 16];
+    if let Some(transaction_id) = transaction_id {
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(&transaction_id);
+    }
+
+    value.advance(1);
+    let family = value.get_u8();
+    let port = value.get_u16() ^ u16::from_be_bytes([mask[0], mask[1]]);
+
+    let ip = match (family, value.len()) {
+        (0x01, 4) => {
+            let mut octets = [0; 4];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value[i] ^ mask[i];
+            }
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        (0x02, 16) => {
+            let mut octets = [0; 16];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value[i] ^ mask[i];
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => bail!(
+            "Bad STUN address family {} with length {}",
+            family,
+            value.len()
+        ),
+    };
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{binding_request, parse_binding_response};
+
+    use std::net::SocketAddr;
+
+    // from RFC 5769
+    const TRANSACTION_ID: [u8; 12] = [
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+    ];
+
+    #[test]
+    fn request_format() {
+        assert_eq!(
+            hex::encode(binding_request(TRANSACTION_ID)),
+            "000100002112a442b7e7a701bc34d686fa87dfae"
+        );
+    }
+
+    #[test]
+    fn ipv4_response() {
+        // the RFC 5769 sample IPv4 response, with SOFTWARE, MESSAGE-INTEGRITY
+        // and FINGERPRINT attributes around the address
+        let response = hex::decode(
+            "0101003c2112a442b7e7a701bc34d686fa87dfae\
+             8022000b7465737420766563746f7220\
+             002000080001a147e112a643\
+             000800142b91f599fd9e90c38c7489f92af9ba53f06be7d7\
+             80280004c07d4c96",
+        )
+        .unwrap();
+
+        let addr: SocketAddr = "192.0.2.1:32853".parse().unwrap();
+        assert_eq!(
+            parse_binding_response(&response, TRANSACTION_ID).unwrap(),
+            Some(addr)
+        );
+
+        // an answer to some other request
+        let mut other_id = TRANSACTION_ID;
+        other_id[0] ^= 1;
+        assert_eq!(parse_binding_response(&response, other_id).unwrap(), None);
+    }
+
+    #[test]
+    fn ipv6_response() {
+        // the address from the RFC 5769 sample IPv6 response
+        let response = hex::decode(
+            "010100182112a442b7e7a701bc34d686fa87dfae\
+             002000140002a1470113a9faa5d3f179bc25f4b5bed2b9d9",
+        )
+        .unwrap();
+
+        let addr: SocketAddr = "[2001:db8:1234:5678:11:2233:4455:6677]:32853"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            parse_binding_response(&response, TRANSACTION_ID).unwrap(),
+            Some(addr)
+        );
+    }
+
+    #[test]
+    fn mapped_address_response() {
+        let response = hex::decode(
+            "0101000c2112a442b7e7a701bc34d686fa87dfae\
+             0001000800011f90c0000201",
+        )
+        .unwrap();
+
+        let addr: SocketAddr = "192.0.2.1:8080".parse().unwrap();
+        assert_eq!(
+            parse_binding_response(&response, TRANSACTION_ID).unwrap(),
+            Some(addr)
+        );
+    }
+}
@@ -1,32 +1,39 @@
 // Packet structures
 // see https://tools.ietf.org/html/draft-gg-udt-03#page-5
 
+use core::fmt;
+
 use bytes::{Buf, BufMut};
-use failure::{bail, Error};
+use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "std")]
 mod codec;
 mod control;
 mod data;
+mod error;
 
-pub use self::codec::PacketCodec;
+#[cfg(feature = "std")]
+pub use self::codec::{PacketCodec, StrictPacketCodec, Udt4PacketCodec};
 pub use self::control::{
-    CipherType, ControlPacket, ControlTypes, HandshakeControlInfo, HandshakeVSInfo, ShakeType,
-    SocketType, SrtControlPacket, SrtHandshake, SrtKeyMessage, SrtShakeFlags,
+    CipherType, ControlPacket, ControlTypes, HandshakeControlInfo, HandshakeExtension,
+    HandshakeVSInfo, KmState, PeerStats, RejectReason, ShakeType, SocketType, SrtControlPacket,
+    SrtHandshake, SrtKeyMessage, SrtShakeFlags,
 };
-pub use self::data::{DataPacket, PacketLocation};
+pub use self::data::{DataEncryption, DataPacket, FilterControlHeader, PacketLocation};
+pub use self::error::PacketParseError;
 
-use crate::SocketID;
+use crate::{SocketID, TimeStamp};
 
 /// Represents A UDT/SRT packet
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Packet {
     Data(DataPacket),
     Control(ControlPacket),
 }
 impl Packet {
     // TODO: should this be u32?
-    pub fn timestamp(&self) -> i32 {
+    pub fn timestamp(&self) -> TimeStamp {
         match *self {
             Packet::Data(DataPacket { timestamp, .. })
             | Packet::Control(ControlPacket { timestamp, .. }) => timestamp,
@@ -40,11 +47,46 @@ impl Packet {
         }
     }
 
-    pub fn parse<T: Buf>(buf: &mut T) -> Result<Packet, Error> {
+    /// If this packet is a Shutdown control packet
+    pub fn is_shutdown(&self) -> bool {
+        matches!(
+            self,
+            Packet::Control(ControlPacket {
+                control_type: ControlTypes::Shutdown,
+                ..
+            })
+        )
+    }
+
+    /// Parse a packet from the front of `buf`
+    ///
+    /// When `buf` is a `Bytes`, the payload of a data packet is a slice of it
+    /// instead of a copy, so parsing doesn't allocate
+    pub fn parse<T: Buf>(buf: &mut T) -> Result<Packet, PacketParseError> {
+        Packet::parse_with(buf, false)
+    }
+
+    /// Parse a packet from the front of `buf`, rejecting control packets that a
+    /// conforming peer wouldn't send. See `ControlPacket::parse_strict`
+    pub fn parse_strict<T: Buf>(buf: &mut T) -> Result<Packet, PacketParseError> {
+        Packet::parse_with(buf, true)
+    }
+
+    /// Parse a packet from the front of `buf`, with data packets laid out as
+    /// UDT4 sends them. See `DataPacket::parse_udt4`
+    pub fn parse_udt4<T: Buf>(buf: &mut T) -> Result<Packet, PacketParseError> {
+        if buf.remaining() >= 16 && (buf.bytes()[0] & 0x80) == 0 {
+            Ok(Packet::Data(DataPacket::parse_udt4(buf)?))
+        } else {
+            Packet::parse(buf)
+        }
+    }
+
+    fn parse_with<T: Buf>(buf: &mut T, strict: bool) -> Result<Packet, PacketParseError> {
         // Buffer must be at least 16 bytes,
         // the length of a header packet
         if buf.remaining() < 16 {
-            bail!("Packet not long enough to have a header");
+            return Err(PacketParseError::Truncated("packet header"));
         }
 
         // peek at the first byte to check if it's data or control
@@ -55,11 +97,47 @@ impl Packet {
         // if zero it's a data packet
         Ok(if (first & 0x80) == 0 {
             Packet::Data(DataPacket::parse(buf)?)
+        } else if strict {
+            Packet::Control(ControlPacket::parse_strict(buf)?)
         } else {
             Packet::Control(ControlPacket::parse(buf)?)
         })
     }
 
+    /// Parse a packet that starts `offset` bytes into `buf`, skipping whatever
+    /// prefix comes before it (for example, a tag added by a relay)
+    pub fn parse_at<T: Buf>(buf: &mut T, offset: usize) -> Result<Packet, PacketParseError> {
+        if buf.remaining() < offset {
+            return Err(PacketParseError::OffsetPastEnd {
+                offset,
+                len: buf.remaining(),
+            });
+        }
+        buf.advance(offset);
+
+        Packet::parse(buf)
+    }
+
+    /// The number of bytes `serialize` writes, for allocating an exact buffer
+    pub fn serialized_size(&self) -> usize {
+        match self {
+            Packet::Control(control) => control.serialized_size(),
+            Packet::Data(data) => data.serialized_size(),
+        }
+    }
+
+    /// Serialize into the front of `buf`, returning the number of bytes written,
+    /// or `None` (leaving `buf` untouched) if it is shorter than `serialized_size`
+    pub fn serialize_into(&self, buf: &mut [u8]) -> Option<usize> {
+        let size = self.serialized_size();
+        if buf.len() < size {
+            return None;
+        }
+
+        self.serialize(&mut &mut buf[..size]);
+        Some(size)
+    }
+
     pub fn serialize<T: BufMut>(&self, into: &mut T) {
         match *self {
             Packet::Control(ref control) => {
@@ -71,3 +149,380 @@ impl Packet {
         }
     }
 }
+
+/// A one line summary of the packet, for tracing the protocol
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Packet::Data(data) => write!(f, "{}", data),
+            Packet::Control(control) => write!(f, "{}", control),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        ControlPacket, ControlTypes, DataEncryption, DataPacket, FilterControlHeader,
+        HandshakeControlInfo, HandshakeVSInfo, Packet, PacketLocation, PacketParseError, PeerStats,
+        ShakeType, SrtControlPacket, SrtHandshake, SrtShakeFlags,
+    };
+    use crate::{MsgNumber, SeqNumber, SocketID, SrtVersion, TimeStamp};
+
+    use bytes::{Bytes, BytesMut};
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_at_offset() {
+        let pack = Packet::Data(DataPacket {
+            seq_number: SeqNumber::new_truncate(123),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            in_order_delivery: false,
+            retransmitted: false,
+            encryption: DataEncryption::None,
+            message_number: MsgNumber::new_truncate(4),
+            timestamp: TimeStamp(12_345),
+            dest_sockid: SocketID(81),
+            payload: Bytes::from("hello"),
+        });
+
+        let mut unprefixed = vec![];
+        pack.serialize(&mut unprefixed);
+
+        let mut prefixed = vec![0xAB, 0xCD, 0xEF, 0x01];
+        prefixed.extend_from_slice(&unprefixed);
+
+        let parsed = Packet::parse(&mut Cursor::new(&unprefixed[..])).unwrap();
+        let parsed_at = Packet::parse_at(&mut Cursor::new(&prefixed[..]), 4).unwrap();
+
+        assert_eq!(parsed, pack);
+        assert_eq!(parsed_at, parsed);
+
+        assert_eq!(
+            Packet::parse_at(&mut Cursor::new(&prefixed[..]), 100),
+            Err(PacketParseError::OffsetPastEnd {
+                offset: 100,
+                len: prefixed.len()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_bytes_zero_copy() {
+        let pack = Packet::Data(DataPacket {
+            seq_number: SeqNumber::new_truncate(123),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            in_order_delivery: false,
+            retransmitted: false,
+            encryption: DataEncryption::None,
+            message_number: MsgNumber::new_truncate(4),
+            timestamp: TimeStamp(12_345),
+            dest_sockid: SocketID(81),
+            payload: Bytes::from(vec![0x42; 1000]),
+        });
+
+        let mut buf = BytesMut::new();
+        pack.serialize(&mut buf);
+        let datagram = buf.freeze();
+
+        let parsed = Packet::parse(&mut datagram.clone()).unwrap();
+        assert_eq!(parsed, pack);
+
+        // the payload points into the datagram, right after the 16 byte header
+        match parsed {
+            Packet::Data(data) => assert_eq!(data.payload.as_ptr(), datagram[16..].as_ptr()),
+            p => panic!("Expected data packet, got {:?}", p),
+        }
+    }
+
+    #[test]
+    fn serde_json_roundtrip() {
+        let pack = Packet::Data(DataPacket {
+            seq_number: SeqNumber::new_truncate(123),
+            message_loc: PacketLocation::FIRST,
+            in_order_delivery: true,
+            retransmitted: false,
+            encryption: DataEncryption::None,
+            message_number: MsgNumber::new_truncate(4),
+            timestamp: TimeStamp(12_345),
+            dest_sockid: SocketID(81),
+            payload: Bytes::from("hello"),
+        });
+
+        let json = serde_json::to_value(&pack).unwrap();
+        assert_eq!(json["Data"]["seq_number"], 123);
+        assert_eq!(json["Data"]["dest_sockid"], 81);
+
+        assert_eq!(serde_json::from_value::<Packet>(json).unwrap(), pack);
+    }
+
+    #[test]
+    fn serialized_size_is_exact() {
+        let control = |control_type| {
+            Packet::Control(ControlPacket {
+                timestamp: TimeStamp(100),
+                dest_sockid: SocketID(81),
+                control_type,
+            })
+        };
+        let packets = vec![
+            Packet::Data(DataPacket {
+                seq_number: SeqNumber::new_truncate(123),
+                message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+                in_order_delivery: false,
+                retransmitted: false,
+                encryption: DataEncryption::None,
+                message_number: MsgNumber::new_truncate(4),
+                timestamp: TimeStamp(12_345),
+                dest_sockid: SocketID(81),
+                payload: Bytes::from("hello"),
+            }),
+            control(ControlTypes::Handshake(HandshakeControlInfo {
+                init_seq_num: SeqNumber::new_truncate(1_827_131),
+                max_packet_size: 1500,
+                max_flow_size: 25600,
+                shake_type: ShakeType::Conclusion,
+                socket_id: SocketID(1231),
+                syn_cookie: 0,
+                peer_addr: "127.0.0.1".parse().unwrap(),
+                info: HandshakeVSInfo::V5 {
+                    crypto_size: 0,
+                    ext_hs: Some(SrtControlPacket::HandshakeRequest(SrtHandshake {
+                        version: SrtVersion::CURRENT,
+                        flags: SrtShakeFlags::TSBPDSND,
+                        peer_latency: Duration::from_millis(0),
+                        latency: Duration::from_millis(120),
+                    })),
+                    ext_km: None,
+                    ext_config: Some(SrtControlPacket::StreamId("stream".to_string())),
+                    ext_other: Vec::new(),
+                },
+            })),
+            control(ControlTypes::Ack {
+                ack_seq_num: 1,
+                ack_number: SeqNumber::new_truncate(2),
+                rtt: None,
+                rtt_variance: None,
+                buffer_available: None,
+                packet_recv_rate: None,
+                est_link_cap: None,
+            }),
+            control(ControlTypes::Ack {
+                ack_seq_num: 1,
+                ack_number: SeqNumber::new_truncate(2),
+                rtt: Some(10_000),
+                rtt_variance: None,
+                buffer_available: None,
+                packet_recv_rate: None,
+                est_link_cap: None,
+            }),
+            control(ControlTypes::Nak(vec![1 | 1 << 31, 5, 9])),
+            control(ControlTypes::Ack2(3)),
+            control(ControlTypes::Shutdown),
+            control(ControlTypes::Srt(SrtControlPacket::PeerStats(PeerStats {
+                received_packets: 10,
+                lost_packets: 1,
+                rtt: 20_000,
+                buffer_available: 8000,
+            }))),
+        ];
+
+        for pack in packets {
+            let mut vec = vec![];
+            pack.serialize(&mut vec);
+            assert_eq!(pack.serialized_size(), vec.len(), "{:?}", pack);
+
+            let mut buf = [0xFF; 1500];
+            assert_eq!(pack.serialize_into(&mut buf), Some(vec.len()));
+            assert_eq!(&buf[..vec.len()], &vec[..]);
+
+            // one byte short, nothing is written
+            let mut short = vec![0; vec.len() - 1];
+            assert_eq!(pack.serialize_into(&mut short), None);
+            assert!(short.iter().all(|&b| b == 0));
+        }
+    }
+
+    #[test]
+    fn retransmitted_flag() {
+        let pack = Packet::Data(DataPacket {
+            seq_number: SeqNumber::new_truncate(123),
+            message_loc: PacketLocation::FIRST,
+            in_order_delivery: true,
+            retransmitted: true,
+            encryption: DataEncryption::None,
+            message_number: MsgNumber::new_truncate(MsgNumber::MAX - 1),
+            timestamp: TimeStamp(12_345),
+            dest_sockid: SocketID(81),
+            payload: Bytes::from("hello"),
+        });
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+
+        // FF, O, KK, then R, then the 26 bit message number
+        assert_eq!(&buf[4..8], &[0b1010_0111, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(Packet::parse(&mut Cursor::new(buf)).unwrap(), pack);
+    }
+
+    #[test]
+    fn encryption_key_flags() {
+        let pack = Packet::Data(DataPacket {
+            seq_number: SeqNumber::new_truncate(123),
+            message_loc: PacketLocation::empty(),
+            in_order_delivery: false,
+            retransmitted: false,
+            encryption: DataEncryption::Odd,
+            message_number: MsgNumber::new_truncate(4),
+            timestamp: TimeStamp(12_345),
+            dest_sockid: SocketID(81),
+            payload: Bytes::from("hello"),
+        });
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+        assert_eq!(buf[4], 0b0001_0000);
+        assert_eq!(Packet::parse(&mut Cursor::new(&buf[..])).unwrap(), pack);
+
+        // both keys at once
+        buf[4] = 0b0001_1000;
+        assert_eq!(
+            Packet::parse(&mut Cursor::new(&buf[..])),
+            Err(PacketParseError::BadEncryptionKeyFlags(0b11))
+        );
+    }
+
+    #[test]
+    fn udt4_data_layout() {
+        let pack = Packet::Data(DataPacket {
+            seq_number: SeqNumber::new_truncate(123),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            in_order_delivery: true,
+            retransmitted: false,
+            encryption: DataEncryption::None,
+            message_number: MsgNumber::new_truncate(4),
+            timestamp: TimeStamp(12_345),
+            dest_sockid: SocketID(81),
+            payload: Bytes::from("hello"),
+        });
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+        assert_eq!(
+            Packet::parse_udt4(&mut Cursor::new(&buf[..])).unwrap(),
+            pack
+        );
+
+        // a UDT4 peer past message number 2^26 sets the bits SRT has the
+        // flags in, which are taken as the message number instead
+        buf[4] |= 0b0001_1100;
+        assert_eq!(
+            Packet::parse_udt4(&mut Cursor::new(&buf[..])).unwrap(),
+            pack
+        );
+        assert_eq!(
+            Packet::parse(&mut Cursor::new(&buf[..])),
+            Err(PacketParseError::BadEncryptionKeyFlags(0b11))
+        );
+
+        // control packets are the same
+        let shutdown = Packet::Control(ControlPacket {
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(81),
+            control_type: ControlTypes::Shutdown,
+        });
+        let mut buf = vec![];
+        shutdown.serialize(&mut buf);
+        assert_eq!(
+            Packet::parse_udt4(&mut Cursor::new(&buf[..])).unwrap(),
+            shutdown
+        );
+    }
+
+    #[test]
+    fn display_trace_format() {
+        let data = Packet::Data(DataPacket {
+            seq_number: SeqNumber::new_truncate(123),
+            message_loc: PacketLocation::FIRST,
+            in_order_delivery: false,
+            retransmitted: true,
+            encryption: DataEncryption::Odd,
+            message_number: MsgNumber::new_truncate(4),
+            timestamp: TimeStamp(12_345),
+            dest_sockid: SocketID(0x51),
+            payload: Bytes::from("hello"),
+        });
+        assert_eq!(
+            data.to_string(),
+            "DATA seq=123 msg=4 loc=first len=5 ts=12345 dst=0x51 rexmit key=odd"
+        );
+
+        let ack = Packet::Control(ControlPacket {
+            timestamp: TimeStamp(100),
+            dest_sockid: SocketID(0x51),
+            control_type: ControlTypes::Ack {
+                ack_seq_num: 7,
+                ack_number: SeqNumber::new_truncate(124),
+                rtt: Some(10_000),
+                rtt_variance: Some(500),
+                buffer_available: None,
+                packet_recv_rate: Some(1_000),
+                est_link_cap: Some(2_000),
+            },
+        });
+        assert_eq!(
+            ack.to_string(),
+            "ACK ack_seq=7 ack_no=124 rtt=10000us rtt_var=500us recvd=1000 cap=2000 ts=100 dst=0x51"
+        );
+
+        let nak = Packet::Control(ControlPacket {
+            timestamp: TimeStamp(100),
+            dest_sockid: SocketID(0x51),
+            control_type: ControlTypes::Nak(vec![3, 5 | 1 << 31, 9]),
+        });
+        assert_eq!(nak.to_string(), "NAK lost=[3,5..=9] ts=100 dst=0x51");
+    }
+
+    #[test]
+    fn filter_control_packet() {
+        let header = FilterControlHeader {
+            group_index: FilterControlHeader::ROW,
+            flag_clip: 0b01,
+            length_clip: 1316 ^ 200,
+        };
+        let mut payload = BytesMut::new();
+        header.serialize(&mut payload);
+        payload.extend_from_slice(b"xored");
+
+        let pack = DataPacket {
+            seq_number: SeqNumber::new_truncate(123),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            in_order_delivery: false,
+            retransmitted: false,
+            encryption: DataEncryption::None,
+            message_number: DataPacket::FILTER_CONTROL_MSG_NUMBER,
+            timestamp: TimeStamp(12_345),
+            dest_sockid: SocketID(81),
+            payload: payload.freeze(),
+        };
+        assert!(pack.is_filter_control());
+
+        let mut buf = BytesMut::new();
+        pack.serialize(&mut buf);
+        let parsed = match Packet::parse(&mut buf.freeze()).unwrap() {
+            Packet::Data(data) => data,
+            p => panic!("Expected data, got {:?}", p),
+        };
+        assert!(parsed.is_filter_control());
+
+        let mut payload = parsed.payload;
+        assert_eq!(FilterControlHeader::parse(&mut payload), Ok(header));
+        assert_eq!(payload, "xored");
+
+        assert_eq!(
+            FilterControlHeader::parse(&mut Bytes::from_static(b"\xff\x00")),
+            Err(PacketParseError::Truncated("filter control header"))
+        );
+    }
+}
@@ -11,7 +11,10 @@ impl Decoder for PacketCodec {
     type Error = Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Packet>, Error> {
-        Packet::parse(&mut Cursor::new(buf)).map(Some)
+        // Copy the payload out instead of splitting `buf`: UdpFramed reuses one
+        // 64KiB buffer for every datagram, and a slice of it would keep a whole
+        // fresh buffer alive for each packet sitting in the send or receive buffers
+        Ok(Some(Packet::parse(&mut Cursor::new(buf))?))
     }
 }
 
@@ -20,6 +23,57 @@ impl Encoder for PacketCodec {
     type Error = Error;
 
     fn encode(&mut self, packet: Packet, buf: &mut BytesMut) -> Result<(), Error> {
+        buf.reserve(packet.serialized_size());
+        packet.serialize(buf);
+
+        Ok(())
+    }
+}
+
+/// A `PacketCodec` that parses with `Packet::parse_strict`, for listeners that
+/// should only accept packets exactly as a conforming peer sends them
+pub struct StrictPacketCodec;
+
+impl Decoder for StrictPacketCodec {
+    type Item = Packet;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Packet>, Error> {
+        Ok(Some(Packet::parse_strict(&mut Cursor::new(buf))?))
+    }
+}
+
+impl Encoder for StrictPacketCodec {
+    type Item = Packet;
+    type Error = Error;
+
+    fn encode(&mut self, packet: Packet, buf: &mut BytesMut) -> Result<(), Error> {
+        buf.reserve(packet.serialized_size());
+        packet.serialize(buf);
+
+        Ok(())
+    }
+}
+
+/// A `PacketCodec` that parses with `Packet::parse_udt4`, for sockets speaking
+/// UDT4, see `SrtSocketBuilder::udt4`
+pub struct Udt4PacketCodec;
+
+impl Decoder for Udt4PacketCodec {
+    type Item = Packet;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Packet>, Error> {
+        Ok(Some(Packet::parse_udt4(&mut Cursor::new(buf))?))
+    }
+}
+
+impl Encoder for Udt4PacketCodec {
+    type Item = Packet;
+    type Error = Error;
+
+    fn encode(&mut self, packet: Packet, buf: &mut BytesMut) -> Result<(), Error> {
+        buf.reserve(packet.serialized_size());
         packet.serialize(buf);
 
         Ok(())
@@ -1,15 +1,22 @@
-use std::net::{IpAddr, Ipv4Addr};
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr};
+use core::ops::RangeInclusive;
 
 use bitflags::bitflags;
-use bytes::{Buf, BufMut};
-use failure::{bail, format_err, Error};
+use bytes::{buf::BufExt, Buf, BufMut};
 use log::warn;
+use serde::{Deserialize, Serialize};
 
-use crate::{MsgNumber, SeqNumber, SocketID};
+use super::PacketParseError;
+use crate::{MsgNumber, SeqNumber, SocketID, TimeStamp};
 
 mod srt;
 
-pub use self::srt::{CipherType, SrtControlPacket, SrtHandshake, SrtKeyMessage, SrtShakeFlags};
+pub use self::srt::{
+    CipherType, HandshakeExtension, KmState, PeerStats, SrtControlPacket, SrtHandshake,
+    SrtKeyMessage, SrtShakeFlags,
+};
 
 /// A UDP packet carrying control information
 ///
@@ -31,10 +38,10 @@ pub use self::srt::{CipherType, SrtControlPacket, SrtHandshake, SrtKeyMessage, S
 ///  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 /// ```
 /// (from <https://tools.ietf.org/html/draft-gg-udt-03#page-5>)
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ControlPacket {
     /// The timestamp, relative to the socket start time
-    pub timestamp: i32,
+    pub timestamp: TimeStamp,
 
     /// The dest socket ID, used for multiplexing
     pub dest_sockid: SocketID,
@@ -44,7 +51,7 @@ pub struct ControlPacket {
 }
 
 /// The different kind of control packets
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[allow(clippy::large_enum_variant)]
 pub enum ControlTypes {
     /// The control packet for initiating connections, type 0x0
@@ -85,6 +92,11 @@ pub enum ControlTypes {
     /// The information is stored in the loss compression format, specified in the loss_compression module.
     Nak(Vec<u32>),
 
+    /// Congestion warning, type 0x4
+    /// Sent by a receiver that detects congestion, to slow the sender down
+    /// Does not use Additional Info or Control Info
+    CongestionWarning,
+
     /// Shutdown packet, type 0x5
     Shutdown,
 
@@ -105,6 +117,11 @@ pub enum ControlTypes {
         last: SeqNumber,
     },
 
+    /// Peer error, type 0x8
+    /// Sent when the peer hits an unrecoverable error, such as a failed file write
+    /// Additional Info (the u32) is the error code
+    PeerError(u32),
+
     /// Srt control packets
     /// These use the UDT extension type 0xFF
     Srt(SrtControlPacket),
@@ -117,13 +134,13 @@ bitflags! {
         const HS = 0b1;
         /// The packet has a kmreq extension
         const KM = 0b10;
-        /// The packet has a config extension (SID or smoother)
+        /// The packet has a config extension (SID, smoother or group)
         const CONFIG = 0b100;
     }
 }
 
 /// HS-version dependenent data
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[allow(clippy::large_enum_variant)]
 pub enum HandshakeVSInfo {
     V4(SocketType),
@@ -138,13 +155,17 @@ pub enum HandshakeVSInfo {
         /// The extension KMREQ/KMRESP
         ext_km: Option<SrtControlPacket>,
 
-        /// The extension config (SID, smoother)
+        /// The extension config (SID, smoother, filter, group)
         ext_config: Option<SrtControlPacket>,
+
+        /// Any other extension blocks, serialized after the rest so extensions
+        /// this crate doesn't handle are passed through
+        ext_other: Vec<HandshakeExtension>,
     },
 }
 
 /// The control info for handshake packets
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct HandshakeControlInfo {
     /// The initial sequence number, usually randomly initialized
     pub init_seq_num: SeqNumber,
@@ -176,7 +197,7 @@ pub struct HandshakeControlInfo {
 }
 
 /// The socket type for a handshake.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum SocketType {
     /// A stream socket, 1 when serialized
     Stream = 1,
@@ -206,20 +227,72 @@ pub enum SocketType {
 /// <-- CONCLUSION (without extensions, if RESPONDER, with extensions, if INITIATOR)
 /// --> CONCLUSION (with response extensions, if RESPONDER)
 /// <-- AGREEMENT (sent exclusively by INITIATOR upon reception of CONCLUSIOn with response extensions)
-#[derive(Debug, Clone, Copy, PartialEq)]
+///
+/// A refused connection is answered with a handshake whose type is the
+/// rejection reason code plus 1000.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ShakeType {
-    /// First handshake exchange in client-server connection
-    Induction = 1,
+    /// First handshake exchange in client-server connection, 1
+    Induction,
 
     /// A rendezvous connection, initial connect request, 0
-    Waveahand = 0,
+    Waveahand,
 
     /// A rendezvous connection, response to initial connect request, -1
     /// Also a regular connection client response to the second handshake
-    Conclusion = -1,
+    Conclusion,
 
     /// Final rendezvous check, -2
-    Agreement = -2,
+    Agreement,
+
+    /// The peer refused the connection, 1000 + the reason code
+    Rejection(RejectReason),
+}
+
+/// Why a peer refused a connection, sent in place of the handshake type
+///
+/// Codes below 1000 are defined by SRT (`SRT_REJ_*`), 1000 to 1999 are
+/// predefined server codes (`SRT_REJX_*`) and 2000 and up are for applications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    /// Unknown reason, 0
+    Unknown,
+    /// A system function reported a failure, 1
+    System,
+    /// The peer rejected the connection, 2
+    Peer,
+    /// A problem with resource allocation, 3
+    Resource,
+    /// Incorrect data in the handshake, 4
+    Rogue,
+    /// The listener's backlog is exceeded, 5
+    Backlog,
+    /// Internal program error, 6
+    Ipe,
+    /// The socket is closing, 7
+    Close,
+    /// The peer is older than the minimum version this side accepts, 8
+    Version,
+    /// Rendezvous cookie collision, 9
+    RdvCookie,
+    /// Wrong password, 10
+    BadSecret,
+    /// The connection was insecure when encryption was required, or the other way around, 11
+    Unsecure,
+    /// The message API settings don't match, 12
+    MessageApi,
+    /// The congestion controllers don't match, 13
+    Congestion,
+    /// The packet filters don't match, 14
+    Filter,
+    /// The group settings don't match, 15
+    Group,
+    /// The connection timed out, 16
+    Timeout,
+    /// A predefined server code, 1000 to 1999
+    Server(i32),
+    /// An application defined code, 2000 and up
+    User(i32),
 }
 
 impl HandshakeVSInfo {
@@ -233,9 +306,13 @@ impl HandshakeVSInfo {
                 ext_hs,
                 ext_km,
                 ext_config,
+                ext_other,
             } => {
                 if shake_type == ShakeType::Induction
-                    && (ext_hs.is_some() || ext_km.is_some() || ext_config.is_some())
+                    && (ext_hs.is_some()
+                        || ext_km.is_some()
+                        || ext_config.is_some()
+                        || !ext_other.is_empty())
                 {
                     // induction does not include any extensions, and instead has the
                     // magic code. this is an incompatialbe place to be.
@@ -273,6 +350,30 @@ impl HandshakeVSInfo {
             HandshakeVSInfo::V5 { .. } => 5,
         }
     }
+
+    /// The config extensions (stream ID, smoother, filter, group), which can
+    /// come in any number. The first is in `ext_config` and the rest in `ext_other`
+    pub fn config_extensions(&self) -> impl Iterator<Item = &SrtControlPacket> {
+        use SrtControlPacket::*;
+
+        let (config, other) = match self {
+            HandshakeVSInfo::V4(_) => (None, &[][..]),
+            HandshakeVSInfo::V5 {
+                ext_config,
+                ext_other,
+                ..
+            } => (ext_config.as_ref(), &ext_other[..]),
+        };
+
+        config
+            .into_iter()
+            .chain(other.iter().filter_map(|ext| match ext {
+                HandshakeExtension::Srt(
+                    ext @ (StreamId(_) | Smoother(_) | Filter(_) | Group(_)),
+                ) => Some(ext),
+                _ => None,
+            }))
+    }
 }
 
 impl SocketType {
@@ -287,23 +388,47 @@ impl SocketType {
 }
 
 impl ControlPacket {
-    pub fn parse(buf: &mut impl Buf) -> Result<ControlPacket, Error> {
+    pub fn parse(buf: &mut impl Buf) -> Result<ControlPacket, PacketParseError> {
+        ControlPacket::parse_with(buf, false)
+    }
+
+    /// Parse like `parse`, but reject anything a conforming peer wouldn't send:
+    /// reserved fields that aren't zero, control information that is the wrong
+    /// length for the type, and out of range handshake fields
+    pub fn parse_strict(buf: &mut impl Buf) -> Result<ControlPacket, PacketParseError> {
+        ControlPacket::parse_with(buf, true)
+    }
+
+    fn parse_with(buf: &mut impl Buf, strict: bool) -> Result<ControlPacket, PacketParseError> {
         let control_type = buf.get_u16() << 1 >> 1; // clear first bit
 
         // get reserved data, which is the last two bytes of the first four bytes
         let reserved = buf.get_u16();
         let add_info = buf.get_i32();
-        let timestamp = buf.get_i32();
+        let timestamp = TimeStamp::new_truncate(u64::from(buf.get_u32()));
         let dest_sockid = buf.get_u32();
 
+        let cif_len = buf.remaining();
+        // just match against the second byte, as everything is in that
+        let control_type =
+            ControlTypes::deserialize(control_type, reserved, add_info, &mut *buf, strict)?;
+
+        if strict {
+            control_type.validate(reserved, add_info, cif_len, buf)?;
+        }
+
         Ok(ControlPacket {
             timestamp,
             dest_sockid: SocketID(dest_sockid),
-            // just match against the second byte, as everything is in that
-            control_type: ControlTypes::deserialize(control_type, reserved, add_info, buf)?,
+            control_type,
         })
     }
 
+    /// The number of bytes `serialize` writes
+    pub fn serialized_size(&self) -> usize {
+        16 + self.control_type.serialized_size()
+    }
+
     pub fn serialize<T: BufMut>(&self, into: &mut T) {
         // first half of first row, the control type and the 1st bit which is a one
         into.put_u16(self.control_type.id_byte() | (0b1 << 15));
@@ -315,7 +440,7 @@ impl ControlPacket {
         into.put_i32(self.control_type.additional_info());
 
         // timestamp
-        into.put_i32(self.timestamp);
+        into.put_u32(self.timestamp.as_raw() as u32);
 
         // dest sock id
         into.put_u32(self.dest_sockid.0);
@@ -330,27 +455,47 @@ impl ControlPacket {
 // core.cpp:8176 (processConnectionRequest -> if INDUCTION)
 const SRT_MAGIC_CODE: u16 = 0x4A17;
 
+/// The size of the control information of a full ACK, in bytes
+const ACK_FULL_SIZE: usize = 6 * 4;
+
+/// Set on a NAK loss list entry that starts a range
+const NAK_RANGE_FLAG: u32 = 1 << 31;
+
+/// The max packet sizes a strictly parsed handshake may have, the same as the
+/// reference implementation allows for SRTO_MSS
+const HANDSHAKE_MSS_RANGE: RangeInclusive<u32> = 76..=1500;
+
+/// The smallest max flow size a strictly parsed handshake may have, the
+/// reference implementation's minimum for SRTO_FC
+const HANDSHAKE_MIN_FLOW_SIZE: u32 = 32;
+
+/// The control information lengths the reference implementation sends ACKs
+/// with: lite, small, full and full with the receive rate in bytes
+const ACK_LENGTHS: [usize; 4] = [4, 16, 24, 28];
+
 impl ControlTypes {
     /// Deserialize a control info
     /// * `packet_type` - The packet ID byte, the second byte in the first row
     /// * `reserved` - the second 16 bytes of the first row, reserved for custom packets
+    /// * `strict` - reject handshake fields that are out of range, instead of fixing them up
     fn deserialize<T: Buf>(
         packet_type: u16,
         reserved: u16,
         extra_info: i32,
         mut buf: T,
-    ) -> Result<ControlTypes, Error> {
+        strict: bool,
+    ) -> Result<ControlTypes, PacketParseError> {
         match packet_type {
             0x0 => {
                 // Handshake
                 // make sure the packet is large enough -- 8 32-bit words, 1 128 (ip)
                 if buf.remaining() < 8 * 4 + 16 {
-                    bail!("Packet not large enough to be a handshake");
+                    return Err(PacketParseError::Truncated("handshake"));
                 }
 
                 let udt_version = buf.get_i32();
                 if udt_version != 4 && udt_version != 5 {
-                    bail!("Incompatable UDT version: {}", udt_version);
+                    return Err(PacketParseError::BadUdtVersion(udt_version));
                 }
 
                 // the second 32 bit word is always socket type under UDT4
@@ -365,13 +510,26 @@ impl ControlTypes {
                 // it's ok to only have the lower 16 bits here for the socket type because socket types always have a zero upper 16 bits
                 let type_ext_socket_type = buf.get_u16();
 
-                let init_seq_num = SeqNumber::new_truncate(buf.get_u32()); // TODO: should this truncate?
+                let raw_init_seq_num = buf.get_u32();
+                let init_seq_num = SeqNumber::new_truncate(raw_init_seq_num); // TODO: should this truncate?
                 let max_packet_size = buf.get_u32();
                 let max_flow_size = buf.get_u32();
-                let shake_type = match ShakeType::from_i32(buf.get_i32()) {
-                    Ok(ct) => ct,
-                    Err(err_ct) => bail!("Invalid connection type {}", err_ct),
-                };
+
+                if strict {
+                    let bad_field =
+                        |field, value| PacketParseError::BadHandshakeField { field, value };
+                    if SeqNumber::new(raw_init_seq_num).is_err() {
+                        return Err(bad_field("initial sequence number", raw_init_seq_num));
+                    }
+                    if !HANDSHAKE_MSS_RANGE.contains(&max_packet_size) {
+                        return Err(bad_field("max packet size", max_packet_size));
+                    }
+                    if max_flow_size < HANDSHAKE_MIN_FLOW_SIZE {
+                        return Err(bad_field("max flow size", max_flow_size));
+                    }
+                }
+                let shake_type =
+                    ShakeType::from_i32(buf.get_i32()).map_err(PacketParseError::BadShakeType)?;
                 let socket_id = SocketID(buf.get_u32());
                 let syn_cookie = buf.get_i32();
 
@@ -387,16 +545,20 @@ impl ControlTypes {
                 };
 
                 let info = match udt_version {
-                    4 => HandshakeVSInfo::V4(match SocketType::from_u16(type_ext_socket_type) {
-                        Ok(t) => t,
-                        Err(e) => {
-                            bail!("Unrecognized socket type: {}", e);
-                        }
-                    }),
+                    4 => HandshakeVSInfo::V4(
+                        SocketType::from_u16(type_ext_socket_type)
+                            .map_err(PacketParseError::BadSocketType)?,
+                    ),
                     5 => {
                         // make sure crypto size is of a valid variant
                         let crypto_size = match crypto_size {
                             0 | 16 | 24 | 32 => crypto_size as u8,
+                            c if strict => {
+                                return Err(PacketParseError::BadHandshakeField {
+                                    field: "crypto size",
+                                    value: u32::from(c),
+                                })
+                            }
                             c => {
                                 warn!(
                                     "Unrecognized crypto key length: {}, disabling encryption. Should be 16, 24, or 32 bytes",
@@ -407,7 +569,12 @@ impl ControlTypes {
                         };
 
                         if shake_type == ShakeType::Induction {
-                            if type_ext_socket_type != SRT_MAGIC_CODE {
+                            if type_ext_socket_type != SRT_MAGIC_CODE && strict {
+                                return Err(PacketParseError::BadHandshakeField {
+                                    field: "SRT magic code",
+                                    value: u32::from(type_ext_socket_type),
+                                });
+                            } else if type_ext_socket_type != SRT_MAGIC_CODE {
                                 // TODO: should this bail? What does the reference implementation do?
                                 warn!("HSv5 induction response did not have SRT_MAGIC_CODE, which is suspicious")
                             }
@@ -417,11 +584,18 @@ impl ControlTypes {
                                 ext_hs: None,
                                 ext_km: None,
                                 ext_config: None,
+                                ext_other: Vec::new(),
                             }
                         } else {
                             // if this is not induction, this is the extension flags
                             let extensions = match ExtFlags::from_bits(type_ext_socket_type) {
                                 Some(i) => i,
+                                None if strict => {
+                                    return Err(PacketParseError::BadHandshakeField {
+                                        field: "extension flags",
+                                        value: u32::from(type_ext_socket_type),
+                                    })
+                                }
                                 None => {
                                     warn!(
                                         "Unnecessary bits in extensions flags: {:b}",
@@ -432,60 +606,72 @@ impl ControlTypes {
                                 }
                             };
 
-                            // parse out extensions
-                            let ext_hs = if extensions.contains(ExtFlags::HS) {
-                                if buf.remaining() < 4 {
-                                    bail!("Not enough room for declared exceptions")
-                                }
-                                let pack_type = buf.get_u16();
-                                let _pack_size = buf.get_u16(); // TODO: why exactly is this needed?
-                                match pack_type {
-                                    // 1 and 2 are handshake response and requests
-                                    1 | 2 => Some(SrtControlPacket::parse(pack_type, &mut buf)?),
-                                    e => bail!(
-                                    "Expected 1 or 2 (SRT handshake request or response), got {}",
-                                    e
-                                ),
-                                }
-                            } else {
-                                None
-                            };
-                            let ext_km = if extensions.contains(ExtFlags::KM) {
-                                if buf.remaining() < 4 {
-                                    bail!("Not enough room for declared exceptions")
+                            // sort the extension blocks into their slots, keeping
+                            // any that don't fit so they are sent back unchanged
+                            let (mut ext_hs, mut ext_km, mut ext_config) = (None, None, None);
+                            let mut ext_other = Vec::new();
+                            let mut blocks = Vec::new();
+                            while type_ext_socket_type != 0 && buf.has_remaining() {
+                                match HandshakeExtension::parse(&mut buf) {
+                                    Ok(block) => blocks.push(block),
+                                    // trailing bytes that aren't a whole block
+                                    Err(PacketParseError::Truncated(_)) if !strict => {
+                                        warn!("Ignoring incomplete handshake extension");
+                                        break;
+                                    }
+                                    Err(e) => return Err(e),
                                 }
-                                let pack_type = buf.get_u16();
-                                let _pack_size = buf.get_u16(); // TODO: why exactly is this needed?
-                                match pack_type {
-                                    // 3 and 4 are km packets
-                                    3 | 4 => Some(SrtControlPacket::parse(pack_type, &mut buf)?),
-                                    e => bail!(
-                                    "Exepcted 3 or 4 (SRT key manager request or response), got {}",
-                                    e
-                                ),
+                            }
+                            for block in blocks {
+                                use SrtControlPacket::*;
+
+                                let (slot, flag) = match &block {
+                                    HandshakeExtension::Srt(
+                                        HandshakeRequest(_) | HandshakeResponse(_),
+                                    ) => (&mut ext_hs, ExtFlags::HS),
+                                    HandshakeExtension::Srt(
+                                        KeyManagerRequest(_)
+                                        | KeyManagerResponse(_)
+                                        | KeyManagerError(_),
+                                    ) => (&mut ext_km, ExtFlags::KM),
+                                    HandshakeExtension::Srt(
+                                        StreamId(_) | Smoother(_) | Filter(_) | Group(_),
+                                    ) => (&mut ext_config, ExtFlags::CONFIG),
+                                    HandshakeExtension::Srt(_)
+                                    | HandshakeExtension::Unknown { .. } => {
+                                        ext_other.push(block);
+                                        continue;
+                                    }
+                                };
+
+                                if strict && !extensions.contains(flag) {
+                                    return Err(PacketParseError::BadHandshakeExtension {
+                                        expected: "an extension set in the extension flags",
+                                        found: block.type_id(),
+                                    });
                                 }
-                            } else {
-                                None
-                            };
-                            let ext_config = if extensions.contains(ExtFlags::CONFIG) {
-                                if buf.remaining() < 4 {
-                                    bail!("Not enough room for declared exceptions")
+                                match (slot.is_none(), block) {
+                                    (true, HandshakeExtension::Srt(ext)) => *slot = Some(ext),
+                                    (_, block) => ext_other.push(block),
                                 }
-                                let pack_type = buf.get_u16();
-                                let _pack_size = buf.get_u16(); // TODO: why exactly is this needed?
-                                match pack_type {
-                                    // 5 is sid 6 is smoother
-                                    5 | 6 => Some(SrtControlPacket::parse(pack_type, &mut buf)?),
-                                    e => bail!("Expected 5 or 6 (SRT SID or smoother), got {}", e),
+                            }
+
+                            for (flag, slot) in &[
+                                (ExtFlags::HS, &ext_hs),
+                                (ExtFlags::KM, &ext_km),
+                                (ExtFlags::CONFIG, &ext_config),
+                            ] {
+                                if extensions.contains(*flag) && slot.is_none() {
+                                    return Err(PacketParseError::Truncated("handshake extension"));
                                 }
-                            } else {
-                                None
-                            };
+                            }
+
                             HandshakeVSInfo::V5 {
                                 crypto_size,
                                 ext_hs,
                                 ext_km,
                                 ext_config,
+                                ext_other,
                             }
                         }
                     }
@@ -507,18 +693,21 @@ impl ControlTypes {
             0x2 => {
                 // ACK
 
-                // make sure there are enough bytes -- 6 32-bit words
-                if buf.remaining() < 6 * 4 {
-                    bail!("Not enough data for an ack packet");
+                // make sure there are enough bytes -- at least the ack number,
+                // which is all a lite ack has
+                if buf.remaining() < 4 {
+                    return Err(PacketParseError::Truncated("ack"));
                 }
 
                 // read control info
                 let ack_number = SeqNumber::new_truncate(buf.get_u32());
 
-                // if there is more data, use it. However, it's optional
+                // only read the fields that are part of the ack, anything after that
+                // is padding and shouldn't be interpreted
+                let mut fields = (&mut buf).take(ACK_FULL_SIZE - 4);
                 let mut opt_read_next = move || {
-                    if buf.remaining() >= 4 {
-                        Some(buf.get_i32())
+                    if fields.remaining() >= 4 {
+                        Some(fields.get_i32())
                     } else {
                         None
                     }
@@ -541,14 +730,33 @@ impl ControlTypes {
             }
             0x3 => {
                 // NAK
+                // The loss list is a list of sequence numbers, where a range is
+                // a start with the high bit set followed by an end without it
 
                 let mut loss_info = Vec::new();
                 while buf.remaining() >= 4 {
-                    loss_info.push(buf.get_u32());
+                    let word = buf.get_u32();
+                    loss_info.push(word);
+
+                    if word & NAK_RANGE_FLAG != 0 {
+                        if buf.remaining() < 4 {
+                            return Err(PacketParseError::BadLossList(
+                                "loss list ends in the middle of a range",
+                            ));
+                        }
+                        let end = buf.get_u32();
+                        if end & NAK_RANGE_FLAG != 0 {
+                            return Err(PacketParseError::BadLossList(
+                                "loss range end has the range bit set",
+                            ));
+                        }
+                        loss_info.push(end);
+                    }
                 }
 
                 Ok(ControlTypes::Nak(loss_info))
             }
+            0x4 => Ok(ControlTypes::CongestionWarning),
             0x5 => Ok(ControlTypes::Shutdown),
             0x6 => {
                 // ACK2
@@ -557,7 +765,7 @@ impl ControlTypes {
             0x7 => {
                 // Drop request
                 if buf.remaining() < 2 * 4 {
-                    bail!("Not enough data for a drop request");
+                    return Err(PacketParseError::Truncated("drop request"));
                 }
 
                 Ok(ControlTypes::DropRequest {
@@ -566,13 +774,94 @@ impl ControlTypes {
                     last: SeqNumber::new_truncate(buf.get_u32()),
                 })
             }
+            0x8 => {
+                // Peer error
+                Ok(ControlTypes::PeerError(extra_info as u32))
+            }
             0x7FFF => {
                 // Srt
                 Ok(ControlTypes::Srt(SrtControlPacket::parse(
                     reserved, &mut buf,
                 )?))
             }
-            x => Err(format_err!("Unrecognized control packet type: {:?}", x)),
+            x => Err(PacketParseError::UnknownControlType(x)),
+        }
+    }
+
+    /// The extra checks for strict parsing, once the control information has
+    /// been deserialized. `cif_len` is the length of the control information,
+    /// `rest` is what deserializing left over
+    fn validate(
+        &self,
+        reserved: u16,
+        add_info: i32,
+        cif_len: usize,
+        rest: &mut impl Buf,
+    ) -> Result<(), PacketParseError> {
+        if reserved != 0 && !matches!(self, ControlTypes::Srt(_)) {
+            return Err(PacketParseError::NonZeroReserved {
+                field: "reserved",
+                value: u32::from(reserved),
+            });
+        }
+        // the types with nothing to put in additional info leave it zero
+        if add_info != 0 && self.additional_info() == 0 && !matches!(self, ControlTypes::Srt(_)) {
+            return Err(PacketParseError::NonZeroReserved {
+                field: "additional info",
+                value: add_info as u32,
+            });
+        }
+
+        let bad_length = || PacketParseError::BadLength {
+            packet: self.name(),
+            len: cif_len,
+        };
+        match self {
+            // the reference implementation pads these with a single zero word
+            ControlTypes::KeepAlive
+            | ControlTypes::Shutdown
+            | ControlTypes::CongestionWarning
+            | ControlTypes::PeerError(_)
+            | ControlTypes::Ack2(_) => {
+                if cif_len > 4 {
+                    return Err(bad_length());
+                }
+                while rest.has_remaining() {
+                    let byte = rest.get_u8();
+                    if byte != 0 {
+                        return Err(PacketParseError::NonZeroReserved {
+                            field: "padding",
+                            value: u32::from(byte),
+                        });
+                    }
+                }
+            }
+            ControlTypes::Ack { .. } if !ACK_LENGTHS.contains(&cif_len) => return Err(bad_length()),
+            ControlTypes::Ack { .. } => {}
+            ControlTypes::Nak(loss_list) if loss_list.is_empty() || rest.has_remaining() => {
+                return Err(bad_length())
+            }
+            ControlTypes::DropRequest { .. } if cif_len != 8 => return Err(bad_length()),
+            _ if rest.has_remaining() => return Err(bad_length()),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// A name for the type, for errors
+    fn name(&self) -> &'static str {
+        match self {
+            ControlTypes::Handshake(_) => "handshake",
+            ControlTypes::KeepAlive => "keepalive",
+            ControlTypes::Ack { .. } => "ack",
+            ControlTypes::Nak(_) => "nak",
+            ControlTypes::CongestionWarning => "congestion warning",
+            ControlTypes::Shutdown => "shutdown",
+            ControlTypes::Ack2(_) => "ack2",
+            ControlTypes::DropRequest { .. } => "drop request",
+            ControlTypes::PeerError(_) => "peer error",
+            ControlTypes::Srt(_) => "SRT control packet",
         }
     }
 
@@ -582,9 +871,11 @@ impl ControlTypes {
             ControlTypes::KeepAlive => 0x1,
             ControlTypes::Ack { .. } => 0x2,
             ControlTypes::Nak(_) => 0x3,
+            ControlTypes::CongestionWarning => 0x4,
             ControlTypes::Shutdown => 0x5,
             ControlTypes::Ack2(_) => 0x6,
             ControlTypes::DropRequest { .. } => 0x7,
+            ControlTypes::PeerError(_) => 0x8,
             ControlTypes::Srt(_) => 0x7FFF,
         }
     }
@@ -594,6 +885,7 @@ impl ControlTypes {
             // These types have additional info
             ControlTypes::DropRequest { msg_to_drop: a, .. } => a.as_raw() as i32,
             ControlTypes::Ack2(a) | ControlTypes::Ack { ack_seq_num: a, .. } => *a,
+            ControlTypes::PeerError(code) => *code as i32,
             // These do not, just use zero
             _ => 0,
         }
@@ -606,6 +898,49 @@ impl ControlTypes {
         }
     }
 
+    /// The size of the control information, after the header
+    fn serialized_size(&self) -> usize {
+        match self {
+            ControlTypes::Handshake(c) => {
+                // 8 words of fields, and the 16 byte address
+                let extensions = match &c.info {
+                    HandshakeVSInfo::V5 {
+                        ext_hs,
+                        ext_km,
+                        ext_config,
+                        ext_other,
+                        ..
+                    } => [ext_hs, ext_km, ext_config]
+                        .iter()
+                        .filter_map(|&s| s.as_ref())
+                        .map(SrtControlPacket::size_words)
+                        .chain(ext_other.iter().map(HandshakeExtension::size_words))
+                        .map(|words| 4 + usize::from(words) * 4)
+                        .sum(),
+                    HandshakeVSInfo::V4(_) => 0,
+                };
+                48 + extensions
+            }
+            ControlTypes::Ack {
+                rtt: None,
+                rtt_variance: None,
+                buffer_available: None,
+                packet_recv_rate: None,
+                est_link_cap: None,
+                ..
+            } => 4,
+            ControlTypes::Ack { .. } => 24,
+            ControlTypes::Nak(n) => n.len() * 4,
+            ControlTypes::DropRequest { .. } => 8,
+            ControlTypes::Ack2(_) => 4,
+            ControlTypes::Shutdown
+            | ControlTypes::KeepAlive
+            | ControlTypes::CongestionWarning
+            | ControlTypes::PeerError(_) => 0,
+            ControlTypes::Srt(srt) => usize::from(srt.size_words()) * 4,
+        }
+    }
+
     fn serialize<T: BufMut>(&self, into: &mut T) {
         match self {
             ControlTypes::Handshake(ref c) => {
@@ -614,7 +949,7 @@ impl ControlTypes {
                 into.put_u32(c.init_seq_num.as_raw());
                 into.put_u32(c.max_packet_size);
                 into.put_u32(c.max_flow_size);
-                into.put_i32(c.shake_type as i32);
+                into.put_i32(c.shake_type.to_i32());
                 into.put_u32(c.socket_id.0);
                 into.put_i32(c.syn_cookie);
 
@@ -640,6 +975,7 @@ impl ControlTypes {
                     ref ext_hs,
                     ref ext_km,
                     ref ext_config,
+                    ref ext_other,
                     ..
                 } = c.info
                 {
@@ -652,6 +988,9 @@ impl ControlTypes {
                         into.put_u16(ext.size_words());
                         ext.serialize(into);
                     }
+                    for ext in ext_other {
+                        ext.serialize(into);
+                    }
                 }
             }
             ControlTypes::Ack {
@@ -664,6 +1003,17 @@ impl ControlTypes {
                 ..
             } => {
                 into.put_u32(ack_number.as_raw());
+
+                // a lite ack only has the ack number
+                if rtt.is_none()
+                    && rtt_variance.is_none()
+                    && buffer_available.is_none()
+                    && packet_recv_rate.is_none()
+                    && est_link_cap.is_none()
+                {
+                    return;
+                }
+
                 into.put_i32(rtt.unwrap_or(10_000));
                 into.put_i32(rtt_variance.unwrap_or(50_000));
                 into.put_i32(buffer_available.unwrap_or(8175)); // TODO: better defaults
@@ -675,14 +1025,20 @@ impl ControlTypes {
                     into.put_u32(loss);
                 }
             }
-            ControlTypes::DropRequest { .. } => unimplemented!(),
+            ControlTypes::DropRequest { first, last, .. } => {
+                into.put_u32(first.as_raw());
+                into.put_u32(last.as_raw());
+            }
             ControlTypes::Ack2(_) => {
                 // The reference implementation appends one (4 byte) word at the end of the ack2 packet, which wireshark labels as 'Unused'
                 // I have no idea why, but wireshark reports it as a "malformed packet" without it. For the record,
                 // this is NOT in the UDT specification. I wonder if this was carried over from the original UDT implementation.
                 into.put_u32(0x0);
             }
-            ControlTypes::Shutdown | ControlTypes::KeepAlive => {}
+            ControlTypes::Shutdown
+            | ControlTypes::KeepAlive
+            | ControlTypes::CongestionWarning
+            | ControlTypes::PeerError(_) => {}
             ControlTypes::Srt(srt) => {
                 srt.serialize(into);
             }
@@ -691,6 +1047,9 @@ impl ControlTypes {
 }
 
 impl ShakeType {
+    /// Offset added to a rejection reason code when it is sent as a handshake type
+    const REJECTION_BASE: i32 = 1000;
+
     /// Turns an i32 into a `ConnectionType`, returning Err(num) if no valid one was passed.
     pub fn from_i32(num: i32) -> Result<ShakeType, i32> {
         match num {
@@ -698,23 +1057,214 @@ impl ShakeType {
             0 => Ok(ShakeType::Waveahand),
             -1 => Ok(ShakeType::Conclusion),
             -2 => Ok(ShakeType::Agreement),
+            i if i >= Self::REJECTION_BASE => RejectReason::from_code(i - Self::REJECTION_BASE)
+                .map(ShakeType::Rejection)
+                .map_err(|_| i),
             i => Err(i),
         }
     }
+
+    /// The value of the handshake type field
+    pub fn to_i32(self) -> i32 {
+        match self {
+            ShakeType::Induction => 1,
+            ShakeType::Waveahand => 0,
+            ShakeType::Conclusion => -1,
+            ShakeType::Agreement => -2,
+            ShakeType::Rejection(reason) => Self::REJECTION_BASE + reason.code(),
+        }
+    }
 }
 
+impl RejectReason {
+    /// Turns a reason code into a `RejectReason`, returning Err(code) for
+    /// codes SRT hasn't defined.
+    pub fn from_code(code: i32) -> Result<RejectReason, i32> {
+        use RejectReason::*;
+
+        Ok(match code {
+            0 => Unknown,
+            1 => System,
+            2 => Peer,
+            3 => Resource,
+            4 => Rogue,
+            5 => Backlog,
+            6 => Ipe,
+            7 => Close,
+            8 => Version,
+            9 => RdvCookie,
+            10 => BadSecret,
+            11 => Unsecure,
+            12 => MessageApi,
+            13 => Congestion,
+            14 => Filter,
+            15 => Group,
+            16 => Timeout,
+            1000..=1999 => Server(code),
+            c if c >= 2000 => User(c),
+            c => return Err(c),
+        })
+    }
+
+    /// The reason code, as sent in the handshake
+    pub fn code(self) -> i32 {
+        use RejectReason::*;
+
+        match self {
+            Unknown => 0,
+            System => 1,
+            Peer => 2,
+            Resource => 3,
+            Rogue => 4,
+            Backlog => 5,
+            Ipe => 6,
+            Close => 7,
+            Version => 8,
+            RdvCookie => 9,
+            BadSecret => 10,
+            Unsecure => 11,
+            MessageApi => 12,
+            Congestion => 13,
+            Filter => 14,
+            Group => 15,
+            Timeout => 16,
+            Server(c) | User(c) => c,
+        }
+    }
+}
+
+impl fmt::Display for ControlPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ts={} dst={:#x}",
+            self.control_type, self.timestamp, self.dest_sockid.0
+        )
+    }
+}
+
+impl fmt::Display for ControlTypes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ControlTypes::*;
+
+        match self {
+            Handshake(hs) => write!(
+                f,
+                "HANDSHAKE {:?} v{} seq={} sockid={:#x} cookie={:#x}",
+                hs.shake_type,
+                hs.info.version(),
+                hs.init_seq_num,
+                hs.socket_id.0,
+                hs.syn_cookie
+            ),
+            KeepAlive => write!(f, "KEEPALIVE"),
+            Ack {
+                ack_seq_num,
+                ack_number,
+                rtt,
+                rtt_variance,
+                buffer_available,
+                packet_recv_rate,
+                est_link_cap,
+            } => {
+                write!(f, "ACK ack_seq={} ack_no={}", ack_seq_num, ack_number)?;
+                // lite ACKs only have the ACK number
+                if let Some(rtt) = rtt {
+                    write!(f, " rtt={}us", rtt)?;
+                }
+                if let Some(rtt_variance) = rtt_variance {
+                    write!(f, " rtt_var={}us", rtt_variance)?;
+                }
+                if let Some(buffer_available) = buffer_available {
+                    write!(f, " buf={}", buffer_available)?;
+                }
+                if let Some(packet_recv_rate) = packet_recv_rate {
+                    write!(f, " recvd={}", packet_recv_rate)?;
+                }
+                if let Some(est_link_cap) = est_link_cap {
+                    write!(f, " cap={}", est_link_cap)?;
+                }
+                Ok(())
+            }
+            Nak(loss_info) => {
+                write!(f, "NAK lost=[")?;
+                // a set high bit starts a range, ended by the next entry
+                let mut entries = loss_info.iter();
+                let mut separator = "";
+                while let Some(&entry) = entries.next() {
+                    write!(f, "{}", separator)?;
+                    separator = ",";
+
+                    if entry & (1 << 31) == 0 {
+                        write!(f, "{}", entry)?;
+                    } else if let Some(last) = entries.next() {
+                        write!(f, "{}..={}", entry & !(1 << 31), last)?;
+                    } else {
+                        write!(f, "{}..", entry & !(1 << 31))?;
+                    }
+                }
+                write!(f, "]")
+            }
+            CongestionWarning => write!(f, "CONGESTION_WARNING"),
+            Shutdown => write!(f, "SHUTDOWN"),
+            Ack2(ack_seq_num) => write!(f, "ACK2 ack_seq={}", ack_seq_num),
+            DropRequest {
+                msg_to_drop,
+                first,
+                last,
+            } => write!(f, "DROPREQ msg={} seq={}..={}", msg_to_drop, first, last),
+            PeerError(code) => write!(f, "PEERERROR code={}", code),
+            Srt(srt) => write!(f, "{}", srt),
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use RejectReason::*;
+
+        let why = match self {
+            Unknown => "unknown reason",
+            System => "system function error",
+            Peer => "rejected by peer",
+            Resource => "resource allocation failure",
+            Rogue => "incorrect data in handshake",
+            Backlog => "listener backlog exceeded",
+            Ipe => "internal program error",
+            Close => "socket is closing",
+            Version => "peer version too old",
+            RdvCookie => "rendezvous cookie collision",
+            BadSecret => "wrong password",
+            Unsecure => "password required or unexpected",
+            MessageApi => "message API mismatch",
+            Congestion => "congestion controller mismatch",
+            Filter => "packet filter mismatch",
+            Group => "group settings mismatch",
+            Timeout => "connection timed out",
+            Server(c) => return write!(f, "Connection rejected: server code {}", c),
+            User(c) => return write!(f, "Connection rejected: user code {}", c),
+        };
+        write!(f, "Connection rejected: {}", why)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RejectReason {}
+
 #[cfg(test)]
 mod test {
 
     use super::*;
-    use crate::{SeqNumber, SocketID, SrtVersion};
+    use crate::loss_compression::{compress_loss_list, decompress_loss_list};
+    use crate::{MsgNumber, SeqNumber, SocketID, SrtVersion};
+    use bytes::Bytes;
     use std::io::Cursor;
     use std::time::Duration;
 
     #[test]
     fn handshake_ser_des_test() {
         let pack = ControlPacket {
-            timestamp: 0,
+            timestamp: TimeStamp(0),
             dest_sockid: SocketID(0),
             control_type: ControlTypes::Handshake(HandshakeControlInfo {
                 init_seq_num: SeqNumber::new_truncate(1_827_131),
@@ -734,10 +1284,171 @@ mod test {
                     })),
                     ext_km: None,
                     ext_config: None,
+                    ext_other: Vec::new(),
+                },
+            }),
+        };
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+
+        let des = ControlPacket::parse(&mut Cursor::new(buf)).unwrap();
+
+        assert_eq!(pack, des);
+    }
+
+    #[test]
+    fn handshake_rejection_ser_des_test() {
+        let pack = ControlPacket {
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(1231),
+            control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                init_seq_num: SeqNumber::new_truncate(1_827_131),
+                max_packet_size: 1500,
+                max_flow_size: 25600,
+                shake_type: ShakeType::Rejection(RejectReason::Version),
+                socket_id: SocketID(4321),
+                syn_cookie: 0,
+                peer_addr: "127.0.0.1".parse().unwrap(),
+                info: HandshakeVSInfo::V5 {
+                    crypto_size: 0,
+                    ext_hs: None,
+                    ext_km: None,
+                    ext_config: None,
+                    ext_other: Vec::new(),
+                },
+            }),
+        };
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+
+        // the handshake type is 1000 + SRT_REJ_VERSION
+        assert_eq!(&buf[36..40], &1008_i32.to_be_bytes());
+
+        let des = ControlPacket::parse_strict(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(pack, des);
+    }
+
+    #[test]
+    fn reject_reason_codes() {
+        for code in (0..=16).chain(vec![1000, 1999, 2000, 2500]) {
+            assert_eq!(RejectReason::from_code(code).unwrap().code(), code);
+        }
+        assert_eq!(
+            RejectReason::from_code(1234),
+            Ok(RejectReason::Server(1234))
+        );
+        assert_eq!(RejectReason::from_code(2001), Ok(RejectReason::User(2001)));
+        assert_eq!(RejectReason::from_code(17), Err(17));
+        assert_eq!(RejectReason::from_code(-1), Err(-1));
+
+        assert_eq!(
+            ShakeType::from_i32(3000),
+            Ok(ShakeType::Rejection(RejectReason::User(2000)))
+        );
+        assert_eq!(ShakeType::from_i32(1017), Err(1017));
+    }
+
+    #[test]
+    fn handshake_config_ext_ser_des_test() {
+        let pack = ControlPacket {
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(0),
+            control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                init_seq_num: SeqNumber::new_truncate(1_827_131),
+                max_packet_size: 1500,
+                max_flow_size: 25600,
+                shake_type: ShakeType::Conclusion,
+                socket_id: SocketID(1231),
+                syn_cookie: 0,
+                peer_addr: "127.0.0.1".parse().unwrap(),
+                info: HandshakeVSInfo::V5 {
+                    crypto_size: 0,
+                    ext_hs: Some(SrtControlPacket::HandshakeRequest(SrtHandshake {
+                        version: SrtVersion::CURRENT,
+                        flags: SrtShakeFlags::TSBPDSND | SrtShakeFlags::TSBPDRCV,
+                        peer_latency: Duration::from_millis(0),
+                        latency: Duration::from_millis(120),
+                    })),
+                    ext_km: None,
+                    ext_config: Some(SrtControlPacket::StreamId("stream 1".to_string())),
+                    ext_other: Vec::new(),
+                },
+            }),
+        };
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+
+        // the extension flags go where the socket type is in HSv4
+        assert_eq!(&buf[16 + 4..16 + 8], &[0, 0, 0, 0b101]);
+
+        let des = ControlPacket::parse(&mut Cursor::new(buf)).unwrap();
+
+        assert_eq!(pack, des);
+    }
+
+    #[test]
+    fn handshake_unknown_ext_ser_des_test() {
+        let pack = ControlPacket {
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(0),
+            control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                init_seq_num: SeqNumber::new_truncate(1_827_131),
+                max_packet_size: 1500,
+                max_flow_size: 25600,
+                shake_type: ShakeType::Conclusion,
+                socket_id: SocketID(1231),
+                syn_cookie: 0,
+                peer_addr: "127.0.0.1".parse().unwrap(),
+                info: HandshakeVSInfo::V5 {
+                    crypto_size: 0,
+                    ext_hs: Some(SrtControlPacket::HandshakeRequest(SrtHandshake {
+                        version: SrtVersion::CURRENT,
+                        flags: SrtShakeFlags::TSBPDSND | SrtShakeFlags::TSBPDRCV,
+                        peer_latency: Duration::from_millis(0),
+                        latency: Duration::from_millis(120),
+                    })),
+                    ext_km: None,
+                    ext_config: Some(SrtControlPacket::StreamId("stream 1".to_string())),
+                    ext_other: vec![
+                        // a second config block
+                        HandshakeExtension::Srt(SrtControlPacket::Smoother("live".to_string())),
+                        HandshakeExtension::Unknown {
+                            type_id: 0x77,
+                            data: Bytes::from(&[1, 2, 3, 4][..]),
+                        },
+                    ],
                 },
             }),
         };
 
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+        assert_eq!(buf.len(), pack.serialized_size());
+
+        let des = ControlPacket::parse_strict(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(pack, des);
+    }
+
+    #[test]
+    fn handshake_v4_ser_des_test() {
+        let pack = ControlPacket {
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(0),
+            control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                init_seq_num: SeqNumber::new_truncate(1_827_131),
+                max_packet_size: 1500,
+                max_flow_size: 25600,
+                shake_type: ShakeType::Induction,
+                socket_id: SocketID(1231),
+                syn_cookie: 0,
+                peer_addr: "127.0.0.1".parse().unwrap(),
+                info: HandshakeVSInfo::V4(SocketType::Datagram),
+            }),
+        };
+
         let mut buf = vec![];
         pack.serialize(&mut buf);
 
@@ -749,7 +1460,88 @@ mod test {
     #[test]
     fn ack_ser_des_test() {
         let pack = ControlPacket {
-            timestamp: 113_703,
+            timestamp: TimeStamp(113_703),
+            dest_sockid: SocketID(2_453_706_529),
+            control_type: ControlTypes::Ack {
+                ack_seq_num: 1,
+                ack_number: SeqNumber::new_truncate(282_049_186),
+                rtt: Some(10_002),
+                rtt_variance: Some(1000),
+                buffer_available: Some(1314),
+                packet_recv_rate: Some(0),
+                est_link_cap: Some(0),
+            },
+        };
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+
+        let des = ControlPacket::parse(&mut Cursor::new(buf)).unwrap();
+
+        assert_eq!(pack, des);
+    }
+
+    #[test]
+    fn lite_ack_ser_des_test() {
+        let pack = ControlPacket {
+            timestamp: TimeStamp(113_703),
+            dest_sockid: SocketID(2_453_706_529),
+            control_type: ControlTypes::Ack {
+                ack_seq_num: 0,
+                ack_number: SeqNumber::new_truncate(282_049_186),
+                rtt: None,
+                rtt_variance: None,
+                buffer_available: None,
+                packet_recv_rate: None,
+                est_link_cap: None,
+            },
+        };
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+        assert_eq!(buf.len(), 16 + 4);
+
+        let des = ControlPacket::parse(&mut Cursor::new(buf)).unwrap();
+
+        assert_eq!(pack, des);
+    }
+
+    #[test]
+    fn partial_ack_defaults() {
+        let pack = ControlPacket {
+            timestamp: TimeStamp(113_703),
+            dest_sockid: SocketID(2_453_706_529),
+            control_type: ControlTypes::Ack {
+                ack_seq_num: 1,
+                ack_number: SeqNumber::new_truncate(282_049_186),
+                rtt: Some(10_002),
+                rtt_variance: Some(1000),
+                buffer_available: None,
+                packet_recv_rate: Some(0),
+                est_link_cap: Some(0),
+            },
+        };
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+        assert_eq!(buf.len(), 16 + ACK_FULL_SIZE);
+
+        // missing fields are filled in, so a full ack is always sent
+        match ControlPacket::parse(&mut Cursor::new(buf))
+            .unwrap()
+            .control_type
+        {
+            ControlTypes::Ack {
+                buffer_available, ..
+            } => assert_eq!(buffer_available, Some(8175)),
+            ct => panic!("Expected ack, got {:?}", ct),
+        }
+    }
+
+    #[test]
+    fn ack_trailing_garbage() {
+        let pack = ControlPacket {
+            timestamp: TimeStamp(113_703),
             dest_sockid: SocketID(2_453_706_529),
             control_type: ControlTypes::Ack {
                 ack_seq_num: 1,
@@ -764,16 +1556,202 @@ mod test {
 
         let mut buf = vec![];
         pack.serialize(&mut buf);
+        buf.extend_from_slice(&[0xFF; 8]);
 
         let des = ControlPacket::parse(&mut Cursor::new(buf)).unwrap();
 
         assert_eq!(pack, des);
     }
 
+    #[test]
+    fn handshake_extension_trailing_garbage() {
+        let pack = ControlPacket {
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(0),
+            control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                init_seq_num: SeqNumber::new_truncate(1_827_131),
+                max_packet_size: 1500,
+                max_flow_size: 25600,
+                shake_type: ShakeType::Conclusion,
+                socket_id: SocketID(1231),
+                syn_cookie: 0,
+                peer_addr: "127.0.0.1".parse().unwrap(),
+                info: HandshakeVSInfo::V5 {
+                    crypto_size: 0,
+                    ext_hs: Some(SrtControlPacket::HandshakeRequest(SrtHandshake {
+                        version: SrtVersion::CURRENT,
+                        flags: SrtShakeFlags::TSBPDSND | SrtShakeFlags::TSBPDRCV,
+                        peer_latency: Duration::from_millis(120),
+                        latency: Duration::from_millis(120),
+                    })),
+                    ext_km: None,
+                    ext_config: None,
+                    ext_other: Vec::new(),
+                },
+            }),
+        };
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+
+        // pad the HS extension by one word, and bump its declared size to match
+        // the extension header starts after the 16 byte header and 48 byte handshake
+        let size_idx = 16 + 48 + 2;
+        buf[size_idx + 1] += 1;
+        buf.extend_from_slice(&[0xAB; 4]);
+        // and add some garbage after the end of the packet
+        buf.extend_from_slice(&[0xCD; 8]);
+
+        let des = ControlPacket::parse(&mut Cursor::new(buf)).unwrap();
+
+        assert_eq!(pack, des);
+    }
+
+    #[test]
+    fn handshake_extension_too_long() {
+        let packet_data = hex::decode("8000000000000000000F9EC400000000000000050000000144BEA60D000005DC00002000FFFFFFFF3D6936B6E3E405DD0100007F00000000000000000000000000010004000103010000002F00780000").unwrap();
+
+        assert_eq!(
+            ControlPacket::parse(&mut Cursor::new(&packet_data[..])),
+            Err(PacketParseError::Truncated("handshake extension"))
+        );
+    }
+
+    #[test]
+    fn nak_ser_des_test() {
+        let lost = [5, 6, 7, 9, 12, 13, 100, 101, 102, 103]
+            .iter()
+            .cloned()
+            .map(SeqNumber::new_truncate);
+        let loss_list: Vec<_> = compress_loss_list(lost.clone()).collect();
+        assert_eq!(
+            loss_list,
+            [
+                5 | NAK_RANGE_FLAG,
+                7,
+                9,
+                12 | NAK_RANGE_FLAG,
+                13,
+                100 | NAK_RANGE_FLAG,
+                103
+            ]
+        );
+
+        let pack = ControlPacket {
+            timestamp: TimeStamp(1234),
+            dest_sockid: SocketID(5678),
+            control_type: ControlTypes::Nak(loss_list),
+        };
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+        assert_eq!(buf.len(), 16 + 7 * 4);
+
+        let des = ControlPacket::parse(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(pack, des);
+
+        match des.control_type {
+            ControlTypes::Nak(loss_list) => {
+                assert!(decompress_loss_list(loss_list.into_iter()).eq(lost))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn nak_unterminated_range() {
+        // header, then a range start with no end
+        let packet_data =
+            hex::decode("80030000000000000000000100000002800000050000000780000009").unwrap();
+
+        assert!(matches!(
+            ControlPacket::parse(&mut Cursor::new(&packet_data[..])),
+            Err(PacketParseError::BadLossList(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_control_type() {
+        let packet_data = hex::decode("8009000000000000000F424000000005").unwrap();
+
+        let err = ControlPacket::parse(&mut Cursor::new(&packet_data[..])).unwrap_err();
+        assert_eq!(err, PacketParseError::UnknownControlType(0x9));
+        assert_eq!(err.to_string(), "Unrecognized control packet type: 0x9");
+    }
+
+    #[test]
+    fn nak_range_end_flagged() {
+        let packet_data = hex::decode("800300000000000000000001000000028000000580000007").unwrap();
+
+        assert!(matches!(
+            ControlPacket::parse(&mut Cursor::new(&packet_data[..])),
+            Err(PacketParseError::BadLossList(_))
+        ));
+    }
+
+    #[test]
+    fn drop_request_ser_des_test() {
+        let pack = ControlPacket {
+            timestamp: TimeStamp(1234),
+            dest_sockid: SocketID(5678),
+            control_type: ControlTypes::DropRequest {
+                msg_to_drop: MsgNumber::new_truncate(42),
+                first: SeqNumber::new_truncate(100),
+                last: SeqNumber::new_truncate(103),
+            },
+        };
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+        assert_eq!(buf.len(), 16 + 8);
+
+        let des = ControlPacket::parse(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(pack, des);
+    }
+
+    #[test]
+    fn congestion_warning_ser_des_test() {
+        let packet_data = hex::decode("8004000000000000000F424000000005").unwrap();
+
+        let pack = ControlPacket::parse(&mut Cursor::new(&packet_data[..])).unwrap();
+        assert_eq!(
+            pack,
+            ControlPacket {
+                timestamp: TimeStamp(1_000_000),
+                dest_sockid: SocketID(5),
+                control_type: ControlTypes::CongestionWarning,
+            }
+        );
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+        assert_eq!(&buf[..], &packet_data[..]);
+    }
+
+    #[test]
+    fn peer_error_ser_des_test() {
+        let pack = ControlPacket {
+            timestamp: TimeStamp(1234),
+            dest_sockid: SocketID(81),
+            control_type: ControlTypes::PeerError(4000),
+        };
+        assert_eq!(pack.control_type.additional_info(), 4000);
+
+        let mut buf = vec![];
+        pack.serialize(&mut buf);
+
+        // just the header, the code is in the additional info
+        assert_eq!(buf.len(), 16);
+        assert_eq!(&buf[4..8], &4000_u32.to_be_bytes()[..]);
+
+        let des = ControlPacket::parse(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(pack, des);
+    }
+
     #[test]
     fn ack2_ser_des_test() {
         let pack = ControlPacket {
-            timestamp: 125_812,
+            timestamp: TimeStamp(125_812),
             dest_sockid: SocketID(8313),
             control_type: ControlTypes::Ack2(831),
         };
@@ -802,7 +1780,7 @@ mod test {
         assert_eq!(
             packet,
             ControlPacket {
-                timestamp: 100_720,
+                timestamp: TimeStamp(100_720),
                 dest_sockid: SocketID(738_193_394),
                 control_type: ControlTypes::Srt(SrtControlPacket::Reject)
             }
@@ -814,10 +1792,14 @@ mod test {
         // this is a example HSv5 conclusion packet from the reference implementation
         let packet_data = hex::decode("8000000000000000000F9EC400000000000000050000000144BEA60D000005DC00002000FFFFFFFF3D6936B6E3E405DD0100007F00000000000000000000000000010003000103010000002F00780000").unwrap();
         let packet = ControlPacket::parse(&mut Cursor::new(&packet_data[..])).unwrap();
+        assert_eq!(
+            ControlPacket::parse_strict(&mut Cursor::new(&packet_data[..])).unwrap(),
+            packet
+        );
         assert_eq!(
             packet,
             ControlPacket {
-                timestamp: 1_023_684,
+                timestamp: TimeStamp(1_023_684),
                 dest_sockid: SocketID(0),
                 control_type: ControlTypes::Handshake(HandshakeControlInfo {
                     init_seq_num: SeqNumber(1_153_345_037),
@@ -840,7 +1822,8 @@ mod test {
                             latency: Duration::new(0, 0)
                         })),
                         ext_km: None,
-                        ext_config: None
+                        ext_config: None,
+                        ext_other: Vec::new()
                     }
                 })
             }
@@ -858,11 +1841,15 @@ mod test {
         // this is an example HSv5 conclusion packet from the reference implementation that has crypto data embedded.
         let packet_data = hex::decode("800000000000000000175E8A0000000000000005000000036FEFB8D8000005DC00002000FFFFFFFF35E790ED5D16CCEA0100007F00000000000000000000000000010003000103010000002F01F401F40003000E122029010000000002000200000004049D75B0AC924C6E4C9EC40FEB4FE973DB1D215D426C18A2871EBF77E2646D9BAB15DBD7689AEF60EC").unwrap();
         let packet = ControlPacket::parse(&mut Cursor::new(&packet_data[..])).unwrap();
+        assert_eq!(
+            ControlPacket::parse_strict(&mut Cursor::new(&packet_data[..])).unwrap(),
+            packet
+        );
 
         assert_eq!(
             packet,
             ControlPacket {
-                timestamp: 1_531_530,
+                timestamp: TimeStamp(1_531_530),
                 dest_sockid: SocketID(0),
                 control_type: ControlTypes::Handshake(HandshakeControlInfo {
                     init_seq_num: SeqNumber(1_877_981_400),
@@ -898,7 +1885,8 @@ mod test {
                             odd_key: None,
                             wrap_data: *b"\x15\xDB\xD7\x68\x9A\xEF\x60\xEC",
                         })),
-                        ext_config: None
+                        ext_config: None,
+                        ext_other: Vec::new()
                     }
                 })
             }
@@ -907,12 +1895,120 @@ mod test {
         let mut buf = vec![];
         packet.serialize(&mut buf);
 
-        assert_eq!(&buf[..], &packet_data[..])
+        assert_eq!(&buf[..], &packet_data[..]);
+
+        // and it survives a trip through JSON, for dumping traces
+        let json = serde_json::to_string(&packet).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ControlPacket>(&json).unwrap(),
+            packet
+        );
     }
 
     #[test]
     fn raw_handshake_crypto_pt2() {
         let packet_data = hex::decode("8000000000000000000000000C110D94000000050000000374B7526E000005DC00002000FFFFFFFF18C1CED1F3819B720100007F00000000000000000000000000020003000103010000003F03E803E80004000E12202901000000000200020000000404D3B3D84BE1188A4EBDA4DA16EA65D522D82DE544E1BE06B6ED8128BF15AA4E18EC50EAA95546B101").unwrap();
-        let _packet = ControlPacket::parse(&mut Cursor::new(&packet_data[..])).unwrap();
+        let _packet = ControlPacket::parse_strict(&mut Cursor::new(&packet_data[..])).unwrap();
+    }
+
+    #[test]
+    fn strict_roundtrip() {
+        let types = vec![
+            ControlTypes::KeepAlive,
+            ControlTypes::Ack {
+                ack_seq_num: 1,
+                ack_number: SeqNumber::new_truncate(2),
+                rtt: None,
+                rtt_variance: None,
+                buffer_available: None,
+                packet_recv_rate: None,
+                est_link_cap: None,
+            },
+            ControlTypes::Ack {
+                ack_seq_num: 1,
+                ack_number: SeqNumber::new_truncate(2),
+                rtt: Some(10_000),
+                rtt_variance: Some(1_000),
+                buffer_available: Some(8192),
+                packet_recv_rate: Some(100),
+                est_link_cap: Some(1_000),
+            },
+            ControlTypes::Nak(vec![5, 0x8000_0007, 9]),
+            ControlTypes::CongestionWarning,
+            ControlTypes::Shutdown,
+            ControlTypes::Ack2(3),
+            ControlTypes::DropRequest {
+                msg_to_drop: MsgNumber::new_truncate(4),
+                first: SeqNumber::new_truncate(5),
+                last: SeqNumber::new_truncate(6),
+            },
+            ControlTypes::PeerError(4000),
+        ];
+
+        for control_type in types {
+            let pack = ControlPacket {
+                timestamp: TimeStamp(0),
+                dest_sockid: SocketID(1),
+                control_type,
+            };
+            let mut buf = vec![];
+            pack.serialize(&mut buf);
+
+            assert_eq!(
+                ControlPacket::parse_strict(&mut Cursor::new(buf)).unwrap(),
+                pack
+            );
+        }
+    }
+
+    #[test]
+    fn strict_rejects() {
+        let strict = |hex_data: &str| {
+            let packet_data = hex::decode(hex_data).unwrap();
+            // all of these are fine when not strict
+            ControlPacket::parse(&mut Cursor::new(&packet_data[..])).unwrap();
+            ControlPacket::parse_strict(&mut Cursor::new(&packet_data[..])).unwrap_err()
+        };
+
+        // keepalive with the reserved field set
+        assert_eq!(
+            strict("80010001000000000000000000000001"),
+            PacketParseError::NonZeroReserved {
+                field: "reserved",
+                value: 1
+            }
+        );
+        // keepalive with additional info set
+        assert_eq!(
+            strict("80010000000000070000000000000001"),
+            PacketParseError::NonZeroReserved {
+                field: "additional info",
+                value: 7
+            }
+        );
+        // shutdown with two words of padding
+        assert_eq!(
+            strict("800500000000000000000000000000010000000000000000"),
+            PacketParseError::BadLength {
+                packet: "shutdown",
+                len: 8
+            }
+        );
+        // ack with a 20 byte control information field
+        assert_eq!(
+            strict("800200000000000100000000000000010000000200002710000003E80000200000000064"),
+            PacketParseError::BadLength {
+                packet: "ack",
+                len: 20
+            }
+        );
+        // handshake with a max packet size of 9000
+        assert_eq!(
+            strict("8000000000000000000F9EC400000000000000050000000144BEA60D00002328000020000000000100000001000000007F00000100000000000000000000000000000000"),
+            PacketParseError::BadHandshakeField {
+                field: "max packet size",
+                value: 9000
+            }
+        );
     }
 }
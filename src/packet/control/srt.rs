@@ -1,15 +1,19 @@
-use std::time::Duration;
+use alloc::string::String;
+use alloc::{vec, vec::Vec};
+use core::fmt;
+use core::time::Duration;
 
 use bitflags::bitflags;
-use bytes::{Buf, BufMut};
-use failure::{bail, Error};
+use bytes::{buf::BufExt, Buf, BufMut, Bytes};
 use log::warn;
+use serde::{Deserialize, Serialize};
 
-use crate::SrtVersion;
+use crate::packet::PacketParseError;
+use crate::{SocketID, SrtVersion};
 
 /// The SRT-specific control packets
 /// These are `Packet::Custom` types
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum SrtControlPacket {
     /// SRT handshake reject
     /// ID = 0
@@ -31,13 +35,119 @@ pub enum SrtControlPacket {
     /// ID = 4
     KeyManagerResponse(SrtKeyMessage),
 
-    /// StreamID(?) // TODO: research
+    /// Key manager response when the key material couldn't be used, which
+    /// only has the responder's state
+    /// ID = 4
+    KeyManagerError(KmState),
+
+    /// The stream ID the caller wants to connect to, sent in the config
+    /// extension of a handshake
     /// ID = 5
-    StreamId,
+    StreamId(String),
 
-    /// Smoother? // TODO: research
+    /// The name of the congestion control (smoother) to use, like "live" or
+    /// "file", sent in the config extension of a handshake
     /// ID = 6
-    Smoother,
+    Smoother(String),
+
+    /// The packet filter configuration, like "fec,cols:10,rows:5", sent in the
+    /// config extension of a handshake. The listener answers with the
+    /// configuration both sides agreed on
+    /// ID = 7
+    Filter(String),
+
+    /// The group the caller's socket belongs to, sent in the config extension
+    /// of a handshake when connecting a member of a bonded group
+    /// ID = 8
+    Group(GroupMembership),
+
+    /// Statistics summary from the peer's receiver, only sent if both sides
+    /// set `SrtShakeFlags::PEERSTATS` during the handshake
+    /// This is an extension, the reference implementation does not have it
+    /// ID = 0x4000
+    PeerStats(PeerStats),
+}
+
+/// A block from the extension area of a HSv5 handshake
+///
+/// Each block is a 16-bit type, a 16-bit length in 32-bit words, and that
+/// many words of contents. Blocks of a type this crate doesn't know are kept
+/// as they are, so they survive being parsed and serialized again.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum HandshakeExtension {
+    /// An extension this crate understands
+    Srt(SrtControlPacket),
+
+    /// An extension this crate doesn't understand
+    Unknown {
+        /// The type of the block
+        type_id: u16,
+        /// The contents of the block, padded with zeros to a whole number of
+        /// words when serialized
+        data: Bytes,
+    },
+}
+
+/// The group membership handshake extension
+///
+/// ```ignore,
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+///  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///  |                           Group ID                            |
+///  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///  |     Type      |     Flags     |            Weight             |
+///  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+///
+/// see SRT_CMD_GROUP in srtcore/core.cpp in the reference implementation
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GroupMembership {
+    /// The ID of the group, which always has `GroupMembership::GROUP_ID_BIT` set
+    pub group_id: SocketID,
+
+    /// How the members of the group are used
+    pub group_type: GroupType,
+
+    /// Group flags, 0x1 is "synchronize on message numbers"
+    pub flags: u8,
+
+    /// The priority of this member, for backup groups
+    pub weight: u16,
+}
+
+/// How a bonded group uses its member connections
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum GroupType {
+    Undefined = 0,
+
+    /// Every packet is sent over every member
+    Broadcast = 1,
+
+    /// One member is active, the others take over when it fails
+    Backup = 2,
+
+    /// Packets are spread over the members
+    Balancing = 3,
+
+    Multicast = 4,
+}
+
+/// A compact summary of a receiver's statistics, sent back to the sender
+/// so it has more feedback than just what's in ACK packets
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PeerStats {
+    /// The number of data packets the receiver has received
+    pub received_packets: u32,
+
+    /// The number of packets the receiver gave up on, as they were too late
+    pub lost_packets: u32,
+
+    /// The receiver's round trip time, in us
+    pub rtt: i32,
+
+    /// The number of packets the receive buffer can still hold
+    pub buffer_available: u32,
 }
 
 /// from https://github.com/Haivision/srt/blob/2ef4ef003c2006df1458de6d47fbe3d2338edf69/haicrypt/hcrypt_msg.h#L76-L96
@@ -64,7 +174,7 @@ pub enum SrtControlPacket {
 ///       +-+-+-+-+-+-+-+-|-+-+-+-+-+-+-+-|-+-+-+-+-+-+-+-|-+-+-+-+-+-+-+-+
 /// ```
 ///
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct SrtKeyMessage {
     pub pt: u8,
     pub sign: u16,
@@ -73,16 +183,20 @@ pub struct SrtKeyMessage {
     pub auth: u8,
     pub se: u8,
     pub salt: Vec<u8>,
+    /// The keys, wrapped together with RFC 3394 AES key wrap. On the wire the
+    /// wrap is one field, the 8 byte integrity check value first, so these
+    /// and `wrap_data` are where it's split up: the even key field has its
+    /// first key length bytes and so on
     pub even_key: Option<Vec<u8>>,
     pub odd_key: Option<Vec<u8>>,
 
-    /// The data for unwrapping the key. TODO: research more
-    /// RFC 3394
+    /// The last 8 bytes of the wrap
     pub wrap_data: [u8; 8],
 }
 
 /// from https://github.com/Haivision/srt/blob/2ef4ef003c2006df1458de6d47fbe3d2338edf69/haicrypt/hcrypt_msg.h#L121-L124
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum CipherType {
     None = 0,
     ECB = 1,
@@ -90,8 +204,30 @@ pub enum CipherType {
     CBC = 3,
 }
 
+/// The state of a key material exchange, sent alone in a key manager response
+/// when it fails
+///
+/// from https://github.com/Haivision/srt/blob/2ef4ef003c2006df1458de6d47fbe3d2338edf69/srtcore/srt.h#L495-L503
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum KmState {
+    /// No encryption
+    Unsecured = 0,
+
+    /// The key material exchange hasn't finished
+    Securing = 1,
+
+    /// The key material exchange succeeded
+    Secured = 2,
+
+    /// The peer is encrypting, but no passphrase was set on this end
+    NoSecret = 3,
+
+    /// The passphrases don't match
+    BadSecret = 4,
+}
+
 /// The SRT handshake object
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
 pub struct SrtHandshake {
     /// The SRT version
     /// Serialized just as the u32 that SrtVersion serialized to
@@ -112,6 +248,7 @@ pub struct SrtHandshake {
 }
 
 bitflags! {
+    #[derive(Serialize, Deserialize)]
     pub struct SrtShakeFlags: u32 {
         /// Timestamp-based Packet delivery real-time data sender
         const TSBPDSND = 0x1;
@@ -136,11 +273,19 @@ bitflags! {
 
         /// Again not sure... TODO:
         const FILTERCAP = 0x80;
+
+        /// Exchange `SrtControlPacket::PeerStats`. This is an extension that
+        /// the reference implementation doesn't set, so it's kept away from the
+        /// bits it uses
+        const PEERSTATS = 0x8000_0000;
     }
 }
 
 impl SrtControlPacket {
-    pub fn parse<T: Buf>(packet_type: u16, buf: &mut T) -> Result<SrtControlPacket, Error> {
+    pub fn parse<T: Buf>(
+        packet_type: u16,
+        buf: &mut T,
+    ) -> Result<SrtControlPacket, PacketParseError> {
         use self::SrtControlPacket::*;
 
         match packet_type {
@@ -148,8 +293,20 @@ impl SrtControlPacket {
             1 => Ok(HandshakeRequest(SrtHandshake::parse(buf)?)),
             2 => Ok(HandshakeResponse(SrtHandshake::parse(buf)?)),
             3 => Ok(KeyManagerRequest(SrtKeyMessage::parse(buf)?)),
+            5 => Ok(StreamId(parse_string(buf)?)),
+            6 => Ok(Smoother(parse_string(buf)?)),
+            7 => Ok(Filter(parse_string(buf)?)),
+            8 => Ok(Group(GroupMembership::parse(buf)?)),
+            // an error is a single word, too short to be a key message
+            4 if buf.remaining() < 4 * 4 => {
+                if buf.remaining() < 4 {
+                    return Err(PacketParseError::Truncated("key manager response"));
+                }
+                Ok(KeyManagerError(KmState::from_u32(buf.get_u32())?))
+            }
             4 => Ok(KeyManagerResponse(SrtKeyMessage::parse(buf)?)),
-            _ => bail!("Unrecognized custom packet type {}", packet_type),
+            0x4000 => Ok(PeerStats(self::PeerStats::parse(buf)?)),
+            _ => Err(PacketParseError::UnknownSrtControlType(packet_type)),
         }
     }
 
@@ -162,9 +319,12 @@ impl SrtControlPacket {
             HandshakeRequest(_) => 1,
             HandshakeResponse(_) => 2,
             KeyManagerRequest(_) => 3,
-            KeyManagerResponse(_) => 4,
-            StreamId => 5,
-            Smoother => 6,
+            KeyManagerResponse(_) | KeyManagerError(_) => 4,
+            StreamId(_) => 5,
+            Smoother(_) => 6,
+            Filter(_) => 7,
+            Group(_) => 8,
+            PeerStats(_) => 0x4000,
         }
     }
     pub fn serialize<T: BufMut>(&self, into: &mut T) {
@@ -177,6 +337,10 @@ impl SrtControlPacket {
             KeyManagerRequest(ref k) | KeyManagerResponse(ref k) => {
                 k.serialize(into);
             }
+            KeyManagerError(state) => into.put_u32(state as u32),
+            StreamId(ref s) | Smoother(ref s) | Filter(ref s) => serialize_string(s, into),
+            Group(ref g) => g.serialize(into),
+            PeerStats(ref s) => s.serialize(into),
             _ => unimplemented!(),
         }
     }
@@ -194,15 +358,162 @@ impl SrtControlPacket {
                     + k.even_key.as_ref().map(Vec::len).unwrap_or(0) as u16 / 4
                     + 2
             }
+            KeyManagerError(_) => 1,
+            // padded to a whole number of words
+            StreamId(ref s) | Smoother(ref s) | Filter(ref s) => s.len().div_ceil(4) as u16,
+            // group id, then type, flags and weight
+            Group(_) => 2,
+            // received, lost, rtt, buffer
+            PeerStats(_) => 4,
             _ => unimplemented!(),
         }
     }
 }
 
+impl fmt::Display for SrtControlPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SrtControlPacket::*;
+
+        match self {
+            Reject => write!(f, "SRT_REJECT"),
+            HandshakeRequest(hs) | HandshakeResponse(hs) => write!(
+                f,
+                "{} ver={} latency={}ms peer_latency={}ms flags={:#x}",
+                if let HandshakeRequest(_) = self {
+                    "HSREQ"
+                } else {
+                    "HSRSP"
+                },
+                hs.version,
+                hs.latency.as_millis(),
+                hs.peer_latency.as_millis(),
+                hs.flags.bits()
+            ),
+            KeyManagerRequest(km) | KeyManagerResponse(km) => write!(
+                f,
+                "{} cipher={:?} keys={}",
+                if let KeyManagerRequest(_) = self {
+                    "KMREQ"
+                } else {
+                    "KMRSP"
+                },
+                km.cipher,
+                match (&km.even_key, &km.odd_key) {
+                    (Some(_), Some(_)) => "even+odd",
+                    (Some(_), None) => "even",
+                    (None, Some(_)) => "odd",
+                    (None, None) => "none",
+                }
+            ),
+            KeyManagerError(state) => write!(f, "KMRSP state={:?}", state),
+            StreamId(sid) => write!(f, "SID {:?}", sid),
+            Smoother(name) => write!(f, "SMOOTHER {:?}", name),
+            Filter(config) => write!(f, "FILTER {:?}", config),
+            Group(group) => write!(
+                f,
+                "GROUP id={:#x} type={:?} weight={}",
+                group.group_id.0, group.group_type, group.weight
+            ),
+            PeerStats(stats) => write!(
+                f,
+                "PEERSTATS recvd={} lost={} rtt={}us buf={}",
+                stats.received_packets, stats.lost_packets, stats.rtt, stats.buffer_available
+            ),
+        }
+    }
+}
+
+impl HandshakeExtension {
+    /// Parse extension blocks until `buf` is empty
+    pub fn parse_blocks<T: Buf>(buf: &mut T) -> Result<Vec<HandshakeExtension>, PacketParseError> {
+        let mut blocks = Vec::new();
+        while buf.has_remaining() {
+            blocks.push(HandshakeExtension::parse(buf)?);
+        }
+
+        Ok(blocks)
+    }
+
+    /// Parse one extension block from the front of `buf`
+    pub fn parse<T: Buf>(buf: &mut T) -> Result<HandshakeExtension, PacketParseError> {
+        if buf.remaining() < 4 {
+            return Err(PacketParseError::Truncated("handshake extension header"));
+        }
+        let type_id = buf.get_u16();
+
+        // the size is in 32-bit words. Split the contents off of `buf`, so
+        // parsing them cannot read into the next block and any bytes the
+        // parser doesn't understand are skipped
+        let size = usize::from(buf.get_u16()) * 4;
+        if buf.remaining() < size {
+            return Err(PacketParseError::Truncated("handshake extension"));
+        }
+        let mut data = buf.take(size).to_bytes();
+
+        Ok(match type_id {
+            // handshake, key manager and config (stream ID, smoother, filter, group) extensions
+            1..=8 => HandshakeExtension::Srt(SrtControlPacket::parse(type_id, &mut data)?),
+            _ => HandshakeExtension::Unknown { type_id, data },
+        })
+    }
+
+    pub fn type_id(&self) -> u16 {
+        match self {
+            HandshakeExtension::Srt(srt) => srt.type_id(),
+            HandshakeExtension::Unknown { type_id, .. } => *type_id,
+        }
+    }
+
+    /// The size of the contents in 32-bit words, not including the type and length
+    pub fn size_words(&self) -> u16 {
+        match self {
+            HandshakeExtension::Srt(srt) => srt.size_words(),
+            HandshakeExtension::Unknown { data, .. } => data.len().div_ceil(4) as u16,
+        }
+    }
+
+    /// Serialize the whole block, including the type and length
+    pub fn serialize<T: BufMut>(&self, into: &mut T) {
+        into.put_u16(self.type_id());
+        into.put_u16(self.size_words());
+
+        match self {
+            HandshakeExtension::Srt(srt) => srt.serialize(into),
+            HandshakeExtension::Unknown { data, .. } => {
+                into.put(&data[..]);
+                into.put(&[0; 3][..usize::from(self.size_words()) * 4 - data.len()]);
+            }
+        }
+    }
+}
+
+// Strings in SRT control packets are padded with zeros to a whole number of
+// 32-bit words, and each word has its bytes reversed
+// see srtcore/utilities.h:hton_string in the reference implementation
+fn parse_string<T: Buf>(buf: &mut T) -> Result<String, PacketParseError> {
+    let mut bytes = Vec::with_capacity(buf.remaining());
+    while buf.remaining() >= 4 {
+        bytes.extend_from_slice(&buf.get_u32_le().to_be_bytes()[..]);
+    }
+    while bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn serialize_string<T: BufMut>(s: &str, into: &mut T) {
+    for chunk in s.as_bytes().chunks(4) {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        into.put_u32_le(u32::from_be_bytes(word));
+    }
+}
+
 impl SrtHandshake {
-    pub fn parse<T: Buf>(buf: &mut T) -> Result<SrtHandshake, Error> {
+    pub fn parse<T: Buf>(buf: &mut T) -> Result<SrtHandshake, PacketParseError> {
         if buf.remaining() < 12 {
-            bail!("Unexpected EOF in SRT handshake packet");
+            return Err(PacketParseError::Truncated("SRT handshake"));
         }
 
         let version = SrtVersion::parse(buf.get_u32());
@@ -237,8 +548,88 @@ impl SrtHandshake {
     }
 }
 
+impl GroupMembership {
+    /// Set in every group ID, which keeps them apart from socket IDs
+    pub const GROUP_ID_BIT: u32 = 1 << 30;
+
+    pub fn parse<T: Buf>(buf: &mut T) -> Result<GroupMembership, PacketParseError> {
+        if buf.remaining() < 8 {
+            return Err(PacketParseError::Truncated("group membership"));
+        }
+
+        let group_id = buf.get_u32();
+        if group_id & GroupMembership::GROUP_ID_BIT == 0 {
+            return Err(PacketParseError::BadGroupId(group_id));
+        }
+
+        let group_type = GroupType::from_u8(buf.get_u8())?;
+        let flags = buf.get_u8();
+        let weight = buf.get_u16();
+
+        Ok(GroupMembership {
+            group_id: SocketID(group_id),
+            group_type,
+            flags,
+            weight,
+        })
+    }
+
+    pub fn serialize<T: BufMut>(&self, into: &mut T) {
+        into.put_u32(self.group_id.0);
+        into.put_u8(self.group_type as u8);
+        into.put_u8(self.flags);
+        into.put_u16(self.weight);
+    }
+}
+
+impl GroupType {
+    fn from_u8(num: u8) -> Result<GroupType, PacketParseError> {
+        match num {
+            0 => Ok(GroupType::Undefined),
+            1 => Ok(GroupType::Broadcast),
+            2 => Ok(GroupType::Backup),
+            3 => Ok(GroupType::Balancing),
+            4 => Ok(GroupType::Multicast),
+            t => Err(PacketParseError::BadGroupType(t)),
+        }
+    }
+}
+
+impl PeerStats {
+    pub fn parse<T: Buf>(buf: &mut T) -> Result<PeerStats, PacketParseError> {
+        if buf.remaining() < 16 {
+            return Err(PacketParseError::Truncated("peer stats"));
+        }
+
+        Ok(PeerStats {
+            received_packets: buf.get_u32(),
+            lost_packets: buf.get_u32(),
+            rtt: buf.get_i32(),
+            buffer_available: buf.get_u32(),
+        })
+    }
+
+    pub fn serialize<T: BufMut>(&self, into: &mut T) {
+        into.put_u32(self.received_packets);
+        into.put_u32(self.lost_packets);
+        into.put_i32(self.rtt);
+        into.put_u32(self.buffer_available);
+    }
+
+    /// The fraction of packets that were lost, between 0 and 1
+    pub fn loss_rate(&self) -> f64 {
+        let total = u64::from(self.received_packets) + u64::from(self.lost_packets);
+
+        if total == 0 {
+            0.0
+        } else {
+            self.lost_packets as f64 / total as f64
+        }
+    }
+}
+
 impl SrtKeyMessage {
-    pub fn parse(buf: &mut impl Buf) -> Result<SrtKeyMessage, Error> {
+    pub fn parse(buf: &mut impl Buf) -> Result<SrtKeyMessage, PacketParseError> {
         // first 32-bit word:
         //
         //  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
@@ -248,21 +639,21 @@ impl SrtKeyMessage {
         // make sure there is enough data left in the buffer to at least get to the key flags and length, which tells us how long the packet will be
         // that's 4x32bit words
         if buf.remaining() < 4 * 4 {
-            bail!("Not enough data for SrtKeyMessage");
+            return Err(PacketParseError::Truncated("SRT key message"));
         }
 
         let vers_pt = buf.get_u8();
 
         // make sure the first bit is zero
         if (vers_pt & 0b1000_0000) != 0 {
-            bail!("First bit of SRT key message must be zero");
+            return Err(PacketParseError::BadKeyMessage("first bit must be zero"));
         }
 
         // upper 4 bits are version
         let version = vers_pt >> 4;
 
         if version != 1 {
-            bail!("Invalid SRT key message version: {} must be 1.", version);
+            return Err(PacketParseError::BadKeyMessageVersion(version));
         }
 
         // lower 4 bits are pt
@@ -303,16 +694,14 @@ impl SrtKeyMessage {
             // OK
             16 | 24 | 32 => {}
             // not
-            e => {
-                bail!("Invalid key length: {}. Expected 16, 24, or 32", e);
-            }
+            e => return Err(PacketParseError::BadKeyLength(e)),
         }
 
         // get the size of the packet to make sure that there is enough space
 
         // salt + keys (there's a 1 for each in key flags, it's already been anded with 0b11 so max is 2), wrap data is 8 long
         if buf.remaining() < salt_len + key_len * (key_flags.count_ones() as usize) + 8 {
-            bail!("Not enough data for SrtKeyMessage");
+            return Err(PacketParseError::Truncated("SRT key message"));
         }
 
         // the reference implmentation converts the whole thing to network order (bit endian) (in 32-bit words)
@@ -442,19 +831,48 @@ impl SrtKeyMessage {
     }
 }
 
+impl KmState {
+    fn from_u32(from: u32) -> Result<KmState, PacketParseError> {
+        match from {
+            0 => Ok(KmState::Unsecured),
+            1 => Ok(KmState::Securing),
+            2 => Ok(KmState::Secured),
+            3 => Ok(KmState::NoSecret),
+            4 => Ok(KmState::BadSecret),
+            e => Err(PacketParseError::BadKmState(e)),
+        }
+    }
+}
+
+impl CipherType {
+    fn from_u8(from: u8) -> Result<CipherType, PacketParseError> {
+        match from {
+            0 => Ok(CipherType::None),
+            1 => Ok(CipherType::ECB),
+            2 => Ok(CipherType::CTR),
+            3 => Ok(CipherType::CBC),
+            e => Err(PacketParseError::BadCipherType(e)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{SrtControlPacket, SrtHandshake, SrtShakeFlags};
-    use crate::packet::ControlTypes;
-    use crate::{ControlPacket, Packet, SocketID, SrtVersion};
-
+    use super::{
+        CipherType, GroupMembership, GroupType, HandshakeExtension, KmState, PeerStats,
+        SrtControlPacket, SrtHandshake, SrtKeyMessage, SrtShakeFlags,
+    };
+    use crate::packet::{ControlTypes, PacketParseError};
+    use crate::{ControlPacket, Packet, SocketID, SrtVersion, TimeStamp};
+
+    use bytes::Bytes;
     use std::io::Cursor;
     use std::time::Duration;
 
     #[test]
     fn deser_ser_shake() {
         let handshake = Packet::Control(ControlPacket {
-            timestamp: 123_141,
+            timestamp: TimeStamp(123_141),
             dest_sockid: SocketID(123),
             control_type: ControlTypes::Srt(SrtControlPacket::HandshakeRequest(SrtHandshake {
                 version: SrtVersion::CURRENT,
@@ -471,19 +889,272 @@ mod tests {
 
         assert_eq!(handshake, deserialized);
     }
-}
 
-impl CipherType {
-    fn from_u8(from: u8) -> Result<CipherType, Error> {
-        match from {
-            0 => Ok(CipherType::None),
-            1 => Ok(CipherType::ECB),
-            2 => Ok(CipherType::CTR),
-            3 => Ok(CipherType::CBC),
-            e => bail!(
-                "Unexpected cipher type in key message: {}. Must be 0, 1, 2, or 3",
-                e
-            ),
+    #[test]
+    fn deser_ser_peer_stats() {
+        let stats = Packet::Control(ControlPacket {
+            timestamp: TimeStamp(1_000_000),
+            dest_sockid: SocketID(5),
+            control_type: ControlTypes::Srt(SrtControlPacket::PeerStats(PeerStats {
+                received_packets: 1000,
+                lost_packets: 10,
+                rtt: 12_345,
+                buffer_available: 8000,
+            })),
+        });
+
+        let mut buf = Vec::new();
+        stats.serialize(&mut buf);
+
+        let deserialized = Packet::parse(&mut Cursor::new(buf)).unwrap();
+
+        assert_eq!(stats, deserialized);
+    }
+
+    #[test]
+    fn raw_hsreq() {
+        // the SRT handshake request the reference implementation (libsrt
+        // 1.3.1, by the version it sends) put in the conclusion captured for
+        // `raw_handshake_crypto`, with that packet's timestamp and
+        // destination, in its own HSv4 style packet
+        let packet_data =
+            hex::decode("FFFF00010000000000175E8A00000000000103010000002F01F401F4").unwrap();
+
+        let packet = Packet::parse(&mut Cursor::new(&packet_data[..])).unwrap();
+        assert_eq!(
+            packet,
+            Packet::Control(ControlPacket {
+                timestamp: TimeStamp(1_531_530),
+                dest_sockid: SocketID(0),
+                control_type: ControlTypes::Srt(SrtControlPacket::HandshakeRequest(SrtHandshake {
+                    version: SrtVersion::new(1, 3, 1),
+                    flags: SrtShakeFlags::TSBPDSND
+                        | SrtShakeFlags::TSBPDRCV
+                        | SrtShakeFlags::HAICRYPT
+                        | SrtShakeFlags::TLPKTDROP
+                        | SrtShakeFlags::REXMITFLG,
+                    peer_latency: Duration::from_millis(500),
+                    latency: Duration::from_millis(500),
+                })),
+            })
+        );
+
+        let mut buf = Vec::new();
+        packet.serialize(&mut buf);
+        assert_eq!(&buf[..], &packet_data[..]);
+    }
+
+    #[test]
+    fn raw_hsrsp() {
+        // the SRT handshake response libsrt 1.3.1 put in the conclusion
+        // response captured for `raw_handshake_crypto_pt2`, with that packet's
+        // timestamp and destination, in its own packet
+        let packet_data =
+            hex::decode("FFFF000200000000000000000C110D94000103010000003F03E803E8").unwrap();
+
+        let packet = Packet::parse(&mut Cursor::new(&packet_data[..])).unwrap();
+        assert_eq!(
+            packet,
+            Packet::Control(ControlPacket {
+                timestamp: TimeStamp(0),
+                dest_sockid: SocketID(0x0C11_0D94),
+                control_type: ControlTypes::Srt(SrtControlPacket::HandshakeResponse(
+                    SrtHandshake {
+                        version: SrtVersion::new(1, 3, 1),
+                        flags: SrtShakeFlags::TSBPDSND
+                            | SrtShakeFlags::TSBPDRCV
+                            | SrtShakeFlags::HAICRYPT
+                            | SrtShakeFlags::TLPKTDROP
+                            | SrtShakeFlags::NAKREPORT
+                            | SrtShakeFlags::REXMITFLG,
+                        peer_latency: Duration::from_millis(1000),
+                        latency: Duration::from_millis(1000),
+                    }
+                )),
+            })
+        );
+
+        let mut buf = Vec::new();
+        packet.serialize(&mut buf);
+        assert_eq!(&buf[..], &packet_data[..]);
+    }
+
+    #[test]
+    fn hsreq_too_short() {
+        // the latency word is missing
+        let packet_data = hex::decode("FFFF00010000000000175E8A00000000000103010000002F").unwrap();
+
+        assert_eq!(
+            Packet::parse(&mut Cursor::new(&packet_data[..])),
+            Err(PacketParseError::Truncated("SRT handshake"))
+        );
+    }
+
+    #[test]
+    fn deser_ser_km_error() {
+        let packet_data = hex::decode("FFFF000400000000000F42401234567800000004").unwrap();
+
+        let packet = Packet::parse(&mut Cursor::new(&packet_data[..])).unwrap();
+        assert_eq!(
+            packet,
+            Packet::Control(ControlPacket {
+                timestamp: TimeStamp(1_000_000),
+                dest_sockid: SocketID(0x1234_5678),
+                control_type: ControlTypes::Srt(SrtControlPacket::KeyManagerError(
+                    KmState::BadSecret
+                )),
+            })
+        );
+
+        let mut buf = Vec::new();
+        packet.serialize(&mut buf);
+        assert_eq!(&buf[..], &packet_data[..]);
+    }
+
+    #[test]
+    fn km_error_unknown_state() {
+        let packet_data = hex::decode("FFFF000400000000000F42401234567800000009").unwrap();
+
+        assert_eq!(
+            Packet::parse(&mut Cursor::new(&packet_data[..])),
+            Err(PacketParseError::BadKmState(9))
+        );
+    }
+
+    #[test]
+    fn deser_ser_km_request() {
+        let km = Packet::Control(ControlPacket {
+            timestamp: TimeStamp(1_000_000),
+            dest_sockid: SocketID(5),
+            control_type: ControlTypes::Srt(SrtControlPacket::KeyManagerRequest(SrtKeyMessage {
+                pt: 2,
+                sign: 0x2029,
+                keki: 0,
+                cipher: CipherType::CTR,
+                auth: 0,
+                se: 2,
+                salt: (0..16).collect(),
+                even_key: Some((16..32).collect()),
+                odd_key: Some((32..48).collect()),
+                wrap_data: [1, 2, 3, 4, 5, 6, 7, 8],
+            })),
+        });
+
+        let mut buf = Vec::new();
+        km.serialize(&mut buf);
+        // header, 4 words of fields, salt, two keys and the wrap data
+        assert_eq!(buf.len(), 16 + 16 + 16 + 2 * 16 + 8);
+
+        let deserialized = Packet::parse(&mut Cursor::new(buf)).unwrap();
+
+        assert_eq!(km, deserialized);
+    }
+
+    #[test]
+    fn deser_ser_stream_id() {
+        let stream_id = SrtControlPacket::StreamId("#!::r=live".to_string());
+        assert_eq!(stream_id.size_words(), 3);
+
+        let mut buf = Vec::new();
+        stream_id.serialize(&mut buf);
+        // each word is byte swapped, and the last one padded
+        assert_eq!(&buf[..], &b"::!#il=r\0\0ev"[..]);
+
+        assert_eq!(
+            SrtControlPacket::parse(5, &mut Cursor::new(&buf[..])).unwrap(),
+            stream_id
+        );
+    }
+
+    #[test]
+    fn filter_config_extension() {
+        // a filter config block
+        let raw = hex::decode("000700022c636566326c6f63").unwrap();
+
+        let blocks = HandshakeExtension::parse_blocks(&mut Cursor::new(&raw[..])).unwrap();
+        let filter = SrtControlPacket::Filter("fec,col2".to_string());
+        assert_eq!(blocks, [HandshakeExtension::Srt(filter.clone())]);
+        assert_eq!(filter.type_id(), 7);
+
+        let mut buf = Vec::new();
+        blocks[0].serialize(&mut buf);
+        assert_eq!(buf, raw);
+    }
+
+    #[test]
+    fn handshake_extension_blocks() {
+        // a stream ID, then an unknown type 0x77 block of 2 words
+        let raw = hex::decode("000500026162636400000065007700020102030405060708").unwrap();
+
+        let blocks = HandshakeExtension::parse_blocks(&mut Cursor::new(&raw[..])).unwrap();
+        assert_eq!(
+            blocks,
+            vec![
+                HandshakeExtension::Srt(SrtControlPacket::StreamId("dcbae".to_string())),
+                HandshakeExtension::Unknown {
+                    type_id: 0x77,
+                    data: Bytes::from(&[1, 2, 3, 4, 5, 6, 7, 8][..]),
+                },
+            ]
+        );
+
+        let mut buf = Vec::new();
+        for block in &blocks {
+            block.serialize(&mut buf);
         }
+        assert_eq!(buf, raw);
+
+        // the length runs past the end
+        assert_eq!(
+            HandshakeExtension::parse_blocks(&mut Cursor::new(&raw[..raw.len() - 1])),
+            Err(PacketParseError::Truncated("handshake extension"))
+        );
+    }
+
+    #[test]
+    fn unknown_extension_padded() {
+        let block = HandshakeExtension::Unknown {
+            type_id: 0x77,
+            data: Bytes::from("hello"),
+        };
+        assert_eq!(block.size_words(), 2);
+
+        let mut buf = Vec::new();
+        block.serialize(&mut buf);
+        assert_eq!(buf, b"\x00\x77\x00\x02hello\x00\x00\x00");
+    }
+
+    #[test]
+    fn group_membership() {
+        let raw = hex::decode("000800024000012302010005").unwrap();
+
+        let block = HandshakeExtension::parse(&mut Cursor::new(&raw[..])).unwrap();
+        assert_eq!(
+            block,
+            HandshakeExtension::Srt(SrtControlPacket::Group(GroupMembership {
+                group_id: SocketID(0x4000_0123),
+                group_type: GroupType::Backup,
+                flags: 0x1,
+                weight: 5,
+            }))
+        );
+
+        let mut buf = Vec::new();
+        block.serialize(&mut buf);
+        assert_eq!(buf, raw);
+
+        // a socket ID, not a group ID
+        assert_eq!(
+            GroupMembership::parse(&mut Cursor::new(&hex::decode("0000012302010005").unwrap())),
+            Err(PacketParseError::BadGroupId(0x123))
+        );
+        assert_eq!(
+            GroupMembership::parse(&mut Cursor::new(&hex::decode("4000012309010005").unwrap())),
+            Err(PacketParseError::BadGroupType(9))
+        );
+        assert_eq!(
+            GroupMembership::parse(&mut Cursor::new(&raw[4..10])),
+            Err(PacketParseError::Truncated("group membership"))
+        );
     }
 }
@@ -1,8 +1,11 @@
+use core::fmt;
+
 use bitflags::bitflags;
 use bytes::{Buf, BufMut, Bytes};
-use failure::Error;
+use serde::{Deserialize, Serialize};
 
-use crate::{MsgNumber, SeqNumber, SocketID};
+use super::PacketParseError;
+use crate::{MsgNumber, SeqNumber, SocketID, TimeStamp};
 
 /// A UDT packet carrying data
 ///
@@ -12,15 +15,16 @@ use crate::{MsgNumber, SeqNumber, SocketID};
 ///  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 ///  |0|                     Packet Sequence Number                  |
 ///  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
-///  |FF |O|                     Message Number                      |
+///  |FF |O|KK |R|               Message Number                      |
 ///  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 ///  |                          Time Stamp                           |
 ///  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 ///  |                    Destination Socket ID                      |
 ///  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 /// ```
-/// (from <https://tools.ietf.org/html/draft-gg-udt-03>)
-#[derive(Debug, Clone, PartialEq)]
+/// (from <https://tools.ietf.org/html/draft-gg-udt-03>, with the encryption
+/// key (KK) and retransmission (R) flags SRT adds)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DataPacket {
     /// The sequence number is packet based, so if packet n has
     /// sequence number `i`, the next would have `i + 1`
@@ -36,13 +40,21 @@ pub struct DataPacket {
     /// In order delivery, the third bit in the second row of 4 bytes
     pub in_order_delivery: bool,
 
+    /// Which key the payload is encrypted with, if any
+    /// Represented by the fourth and fifth bits in the second row of 4 bytes
+    pub encryption: DataEncryption,
+
+    /// Set when this packet is being sent again, after it was reported lost
+    /// Represented by the sixth bit in the second row of 4 bytes
+    pub retransmitted: bool,
+
     /// The message number, is the ID of the message being passed
-    /// Represented by the final 29 bits of the third row
-    /// It's only 29 bits long, so it's wrapped after 2^29 - 1
+    /// Represented by the final 26 bits of the second row
+    /// It's only 26 bits long, so it's wrapped after 2^26 - 1
     pub message_number: MsgNumber,
 
     /// The timestamp, relative to when the connection was created.
-    pub timestamp: i32,
+    pub timestamp: TimeStamp,
 
     /// The dest socket id, used for UDP multiplexing
     pub dest_sockid: SocketID,
@@ -51,12 +63,43 @@ pub struct DataPacket {
     pub payload: Bytes,
 }
 
+/// The key used to encrypt a data packet's payload, the KK bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DataEncryption {
+    /// Not encrypted, 0b00
+    None = 0b00,
+
+    /// Encrypted with the even key, 0b01
+    Even = 0b01,
+
+    /// Encrypted with the odd key, 0b10
+    Odd = 0b10,
+}
+
+/// The header at the front of the payload of a packet filter control packet,
+/// like a FEC packet
+///
+/// The rest of the payload is the XOR of the (zero padded) payloads of the
+/// packets in the group, and the packet's timestamp is the XOR of theirs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilterControlHeader {
+    /// The column of the group, or `FilterControlHeader::ROW` for a row
+    pub group_index: i8,
+
+    /// The XOR of the encryption key flags of the packets in the group
+    pub flag_clip: u8,
+
+    /// The XOR of the payload lengths of the packets in the group
+    pub length_clip: u16,
+}
+
 bitflags! {
     /// Signifies the packet location in a message for a data packet
     /// The bitflag just represents the first byte in the second line
     /// FIRST | LAST means it's the only one
     /// FIRST means it's the beginning of a longer message
     /// 0 means it's the middle of a longer message
+    #[derive(Serialize, Deserialize)]
     pub struct PacketLocation: u8 {
         const FIRST    = 0b1000_0000;
         const LAST     = 0b0100_0000;
@@ -64,7 +107,34 @@ bitflags! {
 }
 
 impl DataPacket {
-    pub fn parse(buf: &mut impl Buf) -> Result<DataPacket, Error> {
+    /// The message number of packet filter control packets, which is never
+    /// used for data
+    pub const FILTER_CONTROL_MSG_NUMBER: MsgNumber = MsgNumber(0);
+
+    /// If this is a packet filter control packet (like FEC) instead of data,
+    /// in which case the payload starts with a `FilterControlHeader`
+    pub fn is_filter_control(&self) -> bool {
+        self.message_number == DataPacket::FILTER_CONTROL_MSG_NUMBER
+    }
+
+    pub fn parse(buf: &mut impl Buf) -> Result<DataPacket, PacketParseError> {
+        DataPacket::parse_with(buf, false)
+    }
+
+    /// Parse a data packet laid out as UDT4 sends it, with a 29 bit message
+    /// number where SRT has the encryption key and retransmission flags
+    ///
+    /// The message number is truncated to the 26 bits used here. It only
+    /// tells messages apart, which the low bits do
+    pub fn parse_udt4(buf: &mut impl Buf) -> Result<DataPacket, PacketParseError> {
+        DataPacket::parse_with(buf, true)
+    }
+
+    fn parse_with(buf: &mut impl Buf, udt4: bool) -> Result<DataPacket, PacketParseError> {
+        if buf.remaining() < 16 {
+            return Err(PacketParseError::Truncated("data packet header"));
+        }
+
         // get the sequence number, which is the last 31 bits of the header
         let seq_number = SeqNumber::new_truncate(buf.get_u32());
 
@@ -74,14 +144,27 @@ impl DataPacket {
         // in order delivery is the third bit
         let in_order_delivery = (buf.bytes()[0] & 0b0010_0000) != 0;
 
+        // the two bits after that are the encryption key, then the retransmission
+        // flag, except in UDT4 where they're part of the message number
+        let (encryption, retransmitted) = if udt4 {
+            (DataEncryption::None, false)
+        } else {
+            (
+                DataEncryption::from_u8((buf.bytes()[0] >> 3) & 0b11)?,
+                (buf.bytes()[0] & 0b0000_0100) != 0,
+            )
+        };
+
         let message_number = MsgNumber::new_truncate(buf.get_u32());
-        let timestamp = buf.get_i32();
+        let timestamp = TimeStamp::new_truncate(u64::from(buf.get_u32()));
         let dest_sockid = SocketID(buf.get_u32());
 
         Ok(DataPacket {
             seq_number,
             message_loc,
             in_order_delivery,
+            encryption,
+            retransmitted,
             message_number,
             timestamp,
             dest_sockid,
@@ -89,20 +172,103 @@ impl DataPacket {
         })
     }
 
+    /// The number of bytes `serialize` writes
+    pub fn serialized_size(&self) -> usize {
+        16 + self.payload.len()
+    }
+
     pub fn serialize(&self, into: &mut impl BufMut) {
         assert!(self.seq_number.as_raw() & (1 << 31) == 0);
 
         into.put_u32(self.seq_number.as_raw());
 
-        // the format is first two bits are the message location, third is in order delivery, and the rest is message number
-        // message number is garunteed have it's first three bits as zero
+        // the format is first two bits are the message location, third is in order delivery,
+        // then the encryption key, the retransmission flag, and the rest is message number
+        // message number is garunteed have it's first six bits as zero
         into.put_u32(
             self.message_number.as_raw()
-                | ((u32::from(self.message_loc.bits() | (self.in_order_delivery as u8) << 5))
-                    << 24),
+                | ((u32::from(
+                    self.message_loc.bits()
+                        | (self.in_order_delivery as u8) << 5
+                        | (self.encryption as u8) << 3
+                        | (self.retransmitted as u8) << 2,
+                )) << 24),
         );
-        into.put_i32(self.timestamp);
+        into.put_u32(self.timestamp.as_raw() as u32);
         into.put_u32(self.dest_sockid.0);
         into.put(&self.payload[..]);
     }
 }
+
+impl fmt::Display for DataPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let loc = match (
+            self.message_loc.contains(PacketLocation::FIRST),
+            self.message_loc.contains(PacketLocation::LAST),
+        ) {
+            (true, true) => "solo",
+            (true, false) => "first",
+            (false, true) => "last",
+            (false, false) => "middle",
+        };
+
+        write!(
+            f,
+            "DATA seq={} msg={} loc={} len={} ts={} dst={:#x}",
+            self.seq_number,
+            self.message_number,
+            loc,
+            self.payload.len(),
+            self.timestamp,
+            self.dest_sockid.0
+        )?;
+        if self.retransmitted {
+            write!(f, " rexmit")?;
+        }
+        match self.encryption {
+            DataEncryption::None => Ok(()),
+            DataEncryption::Even => write!(f, " key=even"),
+            DataEncryption::Odd => write!(f, " key=odd"),
+        }
+    }
+}
+
+impl FilterControlHeader {
+    /// The group index of a row group
+    pub const ROW: i8 = -1;
+
+    /// The size of the header, in bytes
+    pub const SIZE: usize = 4;
+
+    /// Parse the header from the front of a filter control packet's payload,
+    /// leaving the rest of the payload in `buf`
+    pub fn parse(buf: &mut impl Buf) -> Result<FilterControlHeader, PacketParseError> {
+        if buf.remaining() < FilterControlHeader::SIZE {
+            return Err(PacketParseError::Truncated("filter control header"));
+        }
+
+        Ok(FilterControlHeader {
+            group_index: buf.get_i8(),
+            flag_clip: buf.get_u8(),
+            length_clip: buf.get_u16(),
+        })
+    }
+
+    pub fn serialize(&self, into: &mut impl BufMut) {
+        into.put_i8(self.group_index);
+        into.put_u8(self.flag_clip);
+        into.put_u16(self.length_clip);
+    }
+}
+
+impl DataEncryption {
+    fn from_u8(bits: u8) -> Result<DataEncryption, PacketParseError> {
+        match bits {
+            0b00 => Ok(DataEncryption::None),
+            0b01 => Ok(DataEncryption::Even),
+            0b10 => Ok(DataEncryption::Odd),
+            // both keys is only valid in key material messages
+            k => Err(PacketParseError::BadEncryptionKeyFlags(k)),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use alloc::string::FromUtf8Error;
+use core::fmt::{self, Display, Formatter};
+
+/// Why a packet couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketParseError {
+    /// The buffer ended before the named part of the packet
+    Truncated(&'static str),
+
+    /// The offset given to `Packet::parse_at` is past the end of the buffer
+    OffsetPastEnd { offset: usize, len: usize },
+
+    /// The control type in the header is not one this crate knows
+    UnknownControlType(u16),
+
+    /// The SRT control type, from the reserved field, is not one this crate knows
+    UnknownSrtControlType(u16),
+
+    /// A handshake with a UDT version other than 4 or 5
+    BadUdtVersion(i32),
+
+    /// A handshake type that isn't induction, waveahand, conclusion or agreement
+    BadShakeType(i32),
+
+    /// A HSv4 socket type that isn't stream or datagram
+    BadSocketType(u16),
+
+    /// A handshake field that is out of range, only checked when parsing strictly
+    BadHandshakeField { field: &'static str, value: u32 },
+
+    /// A reserved or unused field that isn't zero, only checked when parsing strictly
+    NonZeroReserved { field: &'static str, value: u32 },
+
+    /// Control information that is the wrong length for the type of packet,
+    /// only checked when parsing strictly
+    BadLength { packet: &'static str, len: usize },
+
+    /// A handshake extension block of a kind the extension flags don't announce,
+    /// only checked when parsing strictly
+    BadHandshakeExtension {
+        /// The kinds that are allowed
+        expected: &'static str,
+        /// The SRT control type that was found
+        found: u16,
+    },
+
+    /// A NAK loss list that isn't in the loss compression format
+    BadLossList(&'static str),
+
+    /// A key material message that doesn't start with a zero bit
+    BadKeyMessage(&'static str),
+
+    /// A key material message with a version other than 1
+    BadKeyMessageVersion(u8),
+
+    /// A data packet that claims to be encrypted with both the even and odd keys
+    BadEncryptionKeyFlags(u8),
+
+    /// A key length that isn't 16, 24 or 32 bytes
+    BadKeyLength(usize),
+
+    /// A cipher type that isn't 0 to 3
+    BadCipherType(u8),
+
+    /// A key material state that isn't 0 to 4
+    BadKmState(u32),
+
+    /// A group ID without `GroupMembership::GROUP_ID_BIT` set
+    BadGroupId(u32),
+
+    /// A group type that isn't 0 to 4
+    BadGroupType(u8),
+
+    /// A string (stream ID or smoother) that isn't valid UTF-8
+    InvalidUtf8(FromUtf8Error),
+}
+
+impl Display for PacketParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use PacketParseError::*;
+
+        match self {
+            Truncated(what) => write!(f, "Not enough data for {}", what),
+            OffsetPastEnd { offset, len } => write!(
+                f,
+                "Packet offset {} is past the end of the buffer ({} bytes)",
+                offset, len
+            ),
+            UnknownControlType(t) => write!(f, "Unrecognized control packet type: {:#x}", t),
+            UnknownSrtControlType(t) => write!(f, "Unrecognized custom packet type {}", t),
+            BadUdtVersion(v) => write!(f, "Incompatable UDT version: {}", v),
+            BadShakeType(t) => write!(f, "Invalid connection type {}", t),
+            BadSocketType(t) => write!(f, "Unrecognized socket type: {}", t),
+            BadHandshakeField { field, value } => {
+                write!(f, "Handshake {} out of range: {}", field, value)
+            }
+            NonZeroReserved { field, value } => {
+                write!(f, "Expected {} to be zero, got {:#x}", field, value)
+            }
+            BadLength { packet, len } => write!(
+                f,
+                "Control information of {} bytes is the wrong length for a {} packet",
+                len, packet
+            ),
+            BadHandshakeExtension { expected, found } => write!(
+                f,
+                "Expected {} in handshake extension, got {}",
+                expected, found
+            ),
+            BadLossList(why) => write!(f, "Invalid NAK loss list: {}", why),
+            BadKeyMessage(why) => write!(f, "Invalid SRT key message: {}", why),
+            BadKeyMessageVersion(v) => {
+                write!(f, "Invalid SRT key message version: {} must be 1.", v)
+            }
+            BadEncryptionKeyFlags(k) => {
+                write!(f, "Invalid encryption key flags in data packet: {:#b}", k)
+            }
+            BadKeyLength(l) => write!(f, "Invalid key length: {}. Expected 16, 24, or 32", l),
+            BadCipherType(c) => write!(
+                f,
+                "Unexpected cipher type in key message: {}. Must be 0, 1, 2, or 3",
+                c
+            ),
+            BadKmState(s) => write!(f, "Unexpected key material state: {}. Must be 0 to 4", s),
+            BadGroupId(id) => write!(f, "Invalid group ID: {:#x}", id),
+            BadGroupType(t) => write!(f, "Unrecognized group type: {}. Must be 0 to 4", t),
+            InvalidUtf8(e) => write!(f, "Invalid string in control packet: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PacketParseError {}
+
+impl From<FromUtf8Error> for PacketParseError {
+    fn from(e: FromUtf8Error) -> Self {
+        PacketParseError::InvalidUtf8(e)
+    }
+}
@@ -0,0 +1,20 @@
+use std::error;
+use std::fmt::{self, Display, Formatter};
+
+/// An unrecoverable error reported by the peer in a Peer Error control packet
+///
+/// The sender and receiver fail with this error when one arrives, the code
+/// can be found by downcasting the `failure::Error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerError {
+    /// The error code sent by the peer
+    pub code: u32,
+}
+
+impl Display for PeerError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Peer reported error code {}", self.code)
+    }
+}
+
+impl error::Error for PeerError {}
@@ -1,7 +1,236 @@
 mod connect;
+mod cookie;
+mod key_exchange;
 mod listen;
+mod race;
 mod rendezvous;
 
 pub use self::connect::connect;
-pub use self::listen::listen;
+pub use self::cookie::SynCookies;
+pub use self::listen::{conclude, induction_response, listen};
+pub use self::race::staggered_race;
 pub use self::rendezvous::rendezvous;
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use tokio::time::{delay_until, Instant};
+
+use crate::access_control::{AccessControl, AddressFilter};
+use crate::packet::{
+    HandshakeControlInfo, HandshakeVSInfo, SocketType, SrtControlPacket, SrtShakeFlags,
+};
+use crate::{CongestCtrlType, SrtVersion};
+
+/// What to ask the peer for during the handshake, and what to accept from it
+///
+/// Listeners, callers and rendezvous each use the parts that apply to them
+#[derive(Debug, Clone)]
+pub struct HandshakeConfig {
+    /// The TSBPD latency this end receives with, at least
+    pub recv_latency: Duration,
+    /// The TSBPD latency the peer receives with, at least
+    pub peer_latency: Duration,
+    /// Exchange receiver statistics if the peer wants to as well
+    pub peer_stats: bool,
+    /// Speak UDT4 instead of SRT
+    pub udt4: bool,
+    /// The address this end is bound to, to refuse handshakes addressed to
+    /// another one. Not checked when connecting
+    pub verify_local_addr: Option<IpAddr>,
+    /// Refuse peers older than this
+    pub min_peer_version: Option<SrtVersion>,
+    pub congest_ctrl: CongestCtrlType,
+    pub socket_type: SocketType,
+    /// The passphrase keys are wrapped with, if encrypting
+    pub passphrase: Option<String>,
+    /// The key length to use, or to insist on when listening
+    pub key_length: Option<u8>,
+    /// Refuse peers unless both ends have the same passphrase or neither has one
+    pub enforced_encryption: bool,
+    /// Sent to the listener when connecting
+    pub stream_id: Option<String>,
+    /// Which callers a listener answers at all
+    pub address_filter: Option<AddressFilter>,
+    /// Which callers a listener accepts
+    pub access_control: Option<AccessControl>,
+}
+
+impl HandshakeConfig {
+    /// Unencrypted SRT message mode with live congestion control, with
+    /// `latency` both ways
+    pub fn new(latency: Duration) -> HandshakeConfig {
+        HandshakeConfig {
+            recv_latency: latency,
+            peer_latency: latency,
+            peer_stats: false,
+            udt4: false,
+            verify_local_addr: None,
+            min_peer_version: None,
+            congest_ctrl: CongestCtrlType::Live,
+            socket_type: SocketType::Datagram,
+            passphrase: None,
+            key_length: None,
+            enforced_encryption: false,
+            stream_id: None,
+            address_filter: None,
+            access_control: None,
+        }
+    }
+}
+
+/// The socket type used when speaking UDT4, the one UDT uses for file transfer
+const UDT4_SOCKET_TYPE: SocketType = SocketType::Stream;
+
+/// The largest packet this end will send or accept, the maximum segment size
+/// offered in the handshake
+const MAX_PACKET_SIZE: u32 = 1500;
+
+/// The flow window offered in the handshake, in packets
+const MAX_FLOW_SIZE: u32 = 8192;
+
+/// If `shake` is addressed to this end's `local_addr`
+///
+/// Like in the reference implementation, the address in a handshake is the
+/// one its sender sent it to. One that doesn't say isn't let through
+fn peer_addr_matches(shake: &HandshakeControlInfo, local_addr: IpAddr) -> bool {
+    canonical(shake.peer_addr) == canonical(local_addr)
+}
+
+/// IPv4 addresses a dual stack socket sees as mapped into IPv6, as IPv4
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        IpAddr::V4(_) => addr,
+    }
+}
+
+/// The congestion control a handshake names, live if it doesn't name one
+///
+/// `None` if it names one this crate doesn't have
+fn congest_ctrl_of(info: &HandshakeVSInfo) -> Option<CongestCtrlType> {
+    let name = info.config_extensions().find_map(|ext| match ext {
+        SrtControlPacket::Smoother(name) => Some(name),
+        _ => None,
+    });
+
+    match name {
+        Some(name) => CongestCtrlType::from_name(name),
+        None => Some(CongestCtrlType::Live),
+    }
+}
+
+/// The config extension to name `congest_ctrl` with. Like the reference
+/// implementation, live isn't named
+fn congest_ctrl_extension(congest_ctrl: CongestCtrlType) -> Option<SrtControlPacket> {
+    match congest_ctrl {
+        CongestCtrlType::Live => None,
+        _ => Some(SrtControlPacket::Smoother(congest_ctrl.name().to_string())),
+    }
+}
+
+/// The socket type HSv5 handshake `flags` announce: stream (buffer mode) if
+/// they have STREAM, datagram (message mode) otherwise
+fn socket_type_of(flags: SrtShakeFlags) -> SocketType {
+    if flags.contains(SrtShakeFlags::STREAM) {
+        SocketType::Stream
+    } else {
+        SocketType::Datagram
+    }
+}
+
+/// The HSv5 handshake flags that announce `socket_type`
+fn socket_type_flags(socket_type: SocketType) -> SrtShakeFlags {
+    match socket_type {
+        SocketType::Stream => SrtShakeFlags::STREAM,
+        SocketType::Datagram => SrtShakeFlags::empty(),
+    }
+}
+
+/// How long to wait before resending an unanswered handshake the first time
+const RESEND_START: Duration = Duration::from_millis(100);
+
+/// The longest the wait between handshake resends grows to
+const RESEND_MAX: Duration = Duration::from_secs(1);
+
+/// Paces the resending of a handshake the peer hasn't answered, doubling the
+/// wait after each resend so a peer that isn't there yet isn't flooded
+struct ResendTimer {
+    next: Instant,
+    interval: Duration,
+}
+
+impl ResendTimer {
+    /// Start timing from a handshake that was just sent
+    fn new() -> ResendTimer {
+        ResendTimer {
+            next: Instant::now() + RESEND_START,
+            interval: RESEND_START,
+        }
+    }
+
+    /// Wait until the handshake should be resent, and schedule the resend
+    /// after that
+    ///
+    /// Dropping the future before it finishes leaves the schedule as it was
+    async fn tick(&mut self) {
+        delay_until(self.next).await;
+
+        self.interval = Duration::min(self.interval * 2, RESEND_MAX);
+        self.next = Instant::now() + self.interval;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{peer_addr_matches, ResendTimer};
+    use crate::packet::{HandshakeControlInfo, HandshakeVSInfo, ShakeType, SocketType};
+    use crate::SocketID;
+
+    use std::net::IpAddr;
+    use tokio::time::{self, Instant};
+
+    fn shake_to(peer_addr: &str) -> HandshakeControlInfo {
+        HandshakeControlInfo {
+            init_seq_num: rand::random(),
+            max_packet_size: 1500,
+            max_flow_size: 8192,
+            shake_type: ShakeType::Conclusion,
+            socket_id: SocketID(1234),
+            syn_cookie: 0,
+            peer_addr: peer_addr.parse().unwrap(),
+            info: HandshakeVSInfo::V4(SocketType::Datagram),
+        }
+    }
+
+    #[tokio::test]
+    async fn resend_backs_off() {
+        time::pause();
+
+        let start = Instant::now();
+        let mut timer = ResendTimer::new();
+
+        let mut resends = Vec::new();
+        for _ in 0..7 {
+            timer.tick().await;
+            resends.push((Instant::now() - start).as_millis());
+        }
+
+        assert_eq!(resends, [100, 300, 700, 1500, 2500, 3500, 4500]);
+    }
+
+    #[test]
+    fn peer_addr_verification() {
+        let local: IpAddr = "192.168.1.10".parse().unwrap();
+
+        assert!(peer_addr_matches(&shake_to("192.168.1.10"), local));
+        assert!(!peer_addr_matches(&shake_to("10.0.0.1"), local));
+        // a handshake that doesn't say where it was sent isn't let through
+        assert!(!peer_addr_matches(&shake_to("0.0.0.0"), local));
+        // IPv4 seen by a dual stack socket
+        assert!(peer_addr_matches(
+            &shake_to("192.168.1.10"),
+            "::ffff:192.168.1.10".parse().unwrap()
+        ));
+    }
+}
@@ -1,39 +1,83 @@
-use std::net::{IpAddr, SocketAddr};
-use std::time::{Duration, Instant};
+use std::net::SocketAddr;
+use std::time::Instant;
 
-use failure::Error;
+use failure::{bail, Error};
 
 use futures::prelude::*;
-use futures::select;
+use futures::{pin_mut, select};
 
 use log::{debug, info, warn};
-use tokio::time::interval;
 
+use super::key_exchange::{self, KeyRequest, PayloadCipher, DEFAULT_KEY_SIZE};
+use super::{
+    congest_ctrl_extension, congest_ctrl_of, socket_type_flags, socket_type_of, HandshakeConfig,
+    ResendTimer,
+};
+use super::{MAX_FLOW_SIZE, MAX_PACKET_SIZE, UDT4_SOCKET_TYPE};
 use crate::packet::{
-    ControlPacket, ControlTypes, HandshakeControlInfo, HandshakeVSInfo, Packet, ShakeType,
-    SocketType, SrtControlPacket, SrtHandshake, SrtShakeFlags,
+    ControlPacket, ControlTypes, HandshakeControlInfo, HandshakeExtension, HandshakeVSInfo,
+    KmState, Packet, RejectReason, ShakeType, SocketType, SrtControlPacket, SrtHandshake,
+    SrtShakeFlags,
 };
 use crate::util::get_packet;
-use crate::{Connection, ConnectionSettings, SocketID, SrtVersion};
+use crate::{Connection, ConnectionSettings, KeyLengthMismatch, SocketID, SrtVersion, TimeStamp};
 
-pub async fn connect<T>(
+/// Do the caller's side of the handshake with the listener at `remote`, as
+/// `config` says
+///
+/// Connecting fails with the error `give_up` resolves to if that comes first.
+/// A listener that was already sent the conclusion is told with a shutdown,
+/// as it may think it's connected
+pub async fn connect<T, G>(
     sock: &mut T,
     remote: SocketAddr,
     local_sockid: SocketID,
-    local_addr: IpAddr,
-    tsbpd_latency: Duration,
-    _crypto: Option<(u8, String)>,
+    config: &HandshakeConfig,
+    give_up: G,
 ) -> Result<Connection, Error>
 where
     T: Stream<Item = Result<(Packet, SocketAddr), Error>>
         + Sink<(Packet, SocketAddr), Error = Error>
         + Unpin,
+    G: Future<Output = Error>,
 {
     info!("Got hanshake from {}", remote);
-    let mut send_interval = interval(Duration::from_millis(100));
 
-    let (timestamp, hs_info) =
-        get_initial_handshake(sock, local_addr, local_sockid, remote).await?;
+    let HandshakeConfig {
+        recv_latency,
+        peer_latency,
+        peer_stats,
+        udt4,
+        min_peer_version,
+        congest_ctrl,
+        socket_type,
+        enforced_encryption,
+        ..
+    } = *config;
+    let (passphrase, stream_id) = (config.passphrase.clone(), config.stream_id.clone());
+    let key_size = config.key_length.unwrap_or(DEFAULT_KEY_SIZE);
+
+    let give_up = give_up.fuse();
+    pin_mut!(give_up);
+
+    let (timestamp, hs_info) = select! {
+        res = get_initial_handshake(sock, local_sockid, remote, udt4).fuse() => res?,
+        e = give_up => return Err(e),
+    };
+
+    // a new key for this connection, sent to the listener wrapped with the
+    // passphrase
+    let mut key_request = match &passphrase {
+        Some(passphrase) => Some(KeyRequest::new(passphrase.clone(), key_size)?),
+        None => None,
+    };
+
+    // the stream ID and congestion control, if they're sent
+    let mut config = stream_id
+        .clone()
+        .map(SrtControlPacket::StreamId)
+        .into_iter()
+        .chain(congest_ctrl_extension(congest_ctrl));
 
     // send back a packet with the same syn cookie
     let pack = Packet::Control(ControlPacket {
@@ -42,44 +86,74 @@ where
         control_type: ControlTypes::Handshake(HandshakeControlInfo {
             shake_type: ShakeType::Conclusion,
             socket_id: local_sockid,
-            info: HandshakeVSInfo::V5 {
-                crypto_size: 0, // TODO: implement
-                ext_hs: Some(SrtControlPacket::HandshakeRequest(SrtHandshake {
-                    version: SrtVersion::CURRENT,
-                    // TODO: this is hyper bad, don't blindly set send flag
-                    // if you don't pass TSBPDRCV, it doens't set the latency correctly for some reason. Requires more research
-                    peer_latency: Duration::from_secs(0), // TODO: research
-                    flags: SrtShakeFlags::TSBPDSND | SrtShakeFlags::TSBPDRCV, // TODO: the reference implementation sets a lot more of these, research
-                    latency: tsbpd_latency,
-                })),
-                ext_km: None,
-                // ext_km: self.crypto.as_mut().map(|manager| {
-                //     SrtControlPacket::KeyManagerRequest(SrtKeyMessage {
-                //         pt: 2,       // TODO: what is this
-                //         sign: 8_233, // TODO: again
-                //         keki: 0,
-                //         cipher: CipherType::CTR,
-                //         auth: 0,
-                //         se: 2,
-                //         salt: Vec::from(manager.salt()),
-                //         even_key: Some(manager.wrap_key().unwrap()),
-                //         odd_key: None,
-                //         wrap_data: [0; 8],
-                //     })
-                // }),
-                ext_config: None,
+            info: if udt4 {
+                HandshakeVSInfo::V4(UDT4_SOCKET_TYPE)
+            } else {
+                HandshakeVSInfo::V5 {
+                    crypto_size: key_request.as_ref().map_or(0, KeyRequest::key_size),
+                    ext_hs: Some(SrtControlPacket::HandshakeRequest(SrtHandshake {
+                        version: SrtVersion::CURRENT,
+                        // TODO: this is hyper bad, don't blindly set send flag
+                        // if you don't pass TSBPDRCV, it doens't set the latency correctly for some reason. Requires more research
+                        peer_latency,
+                        // the receiver always sends periodic NAK reports
+                        flags: (if peer_stats {
+                            SrtShakeFlags::TSBPDSND
+                                | SrtShakeFlags::TSBPDRCV
+                                | SrtShakeFlags::NAKREPORT
+                                | SrtShakeFlags::REXMITFLG
+                                | SrtShakeFlags::PEERSTATS
+                        } else {
+                            SrtShakeFlags::TSBPDSND
+                                | SrtShakeFlags::TSBPDRCV
+                                | SrtShakeFlags::NAKREPORT
+                                | SrtShakeFlags::REXMITFLG
+                            // TODO: the reference implementation sets a lot more of these, research
+                        }) | socket_type_flags(socket_type),
+                        latency: recv_latency,
+                    })),
+                    ext_km: key_request.as_ref().map(KeyRequest::extension),
+                    ext_config: config.next(),
+                    ext_other: config.map(HandshakeExtension::Srt).collect(),
+                }
             },
-            ..hs_info
+            // the induction response has this end's address in it, but like
+            // the request this is addressed to the listener
+            peer_addr: remote.ip(),
+            ..hs_info.clone()
         }),
     });
 
     sock.send((pack.clone(), remote)).await?;
+    // the listener starts the connection when the first conclusion arrives,
+    // and only answers resends with the same response
+    let sent_at = Instant::now();
+    let mut resend = ResendTimer::new();
 
     loop {
         let (packet, from) = select! {
-            _ = send_interval.tick().fuse() => {sock.send((pack.clone(), remote)).await?; continue},
-            res = get_packet(sock).fuse() => res?
+            _ = resend.tick().fuse() => {
+                sock.send((pack.clone(), remote)).await?;
+                continue
+            },
+            res = get_packet(sock).fuse() => res?,
+            e = give_up => {
+                let shutdown = Packet::Control(ControlPacket {
+                    dest_sockid: hs_info.socket_id,
+                    timestamp,
+                    control_type: ControlTypes::Shutdown,
+                });
+                sock.send((shutdown, remote)).await?;
+
+                return Err(e);
+            }
         };
+        if from == remote && packet.is_shutdown() {
+            bail!("Peer {} aborted during handshake", remote);
+        }
+        if let (true, Some(reason)) = (from == remote, rejection(&packet)) {
+            return Err(reason.into());
+        }
         if let Packet::Control(ControlPacket {
             dest_sockid,
             control_type:
@@ -102,16 +176,111 @@ where
                 );
                 continue;
             }
-            let latency = if let HandshakeVSInfo::V5 {
+            let (peer_version, peer_socket_type) = match info.info {
+                HandshakeVSInfo::V5 {
+                    ext_hs: Some(SrtControlPacket::HandshakeResponse(hs)),
+                    ..
+                } => (Some(hs.version), socket_type_of(hs.flags)),
+                _ => (None, SocketType::Datagram),
+            };
+            let peer_congest_ctrl = congest_ctrl_of(&info.info);
+            // the listener says which key length it uses if it won't take the
+            // one that was sent
+            let (key_size, peer_key_size) = match (&key_request, &info.info) {
+                (Some(request), HandshakeVSInfo::V5 { crypto_size, .. }) => {
+                    (request.key_size(), *crypto_size)
+                }
+                _ => (0, 0),
+            };
+            let (km_state, crypto) = match key_request.take() {
+                Some(request) => {
+                    let response = match &info.info {
+                        HandshakeVSInfo::V5 { ext_km, .. } => ext_km.as_ref(),
+                        HandshakeVSInfo::V4(_) => None,
+                    };
+                    let (km_state, cipher) = request.conclude(response);
+                    (km_state, Some(cipher))
+                }
+                None => (KmState::Unsecured, None),
+            };
+
+            let refusal: Option<Error> = match min_peer_version {
+                Some(min) if !matches!(peer_version, Some(v) if v >= min) => {
+                    warn!(
+                        "{} has SRT version {:?}, {} is required",
+                        remote, peer_version, min
+                    );
+                    Some(RejectReason::Version.into())
+                }
+                _ if !udt4 && peer_congest_ctrl != Some(congest_ctrl) => {
+                    warn!(
+                        "{} uses congestion control {:?}, expected {:?}",
+                        remote, peer_congest_ctrl, congest_ctrl
+                    );
+                    Some(RejectReason::Congestion.into())
+                }
+                _ if !udt4 && peer_socket_type != socket_type => {
+                    warn!(
+                        "{} uses socket type {:?}, expected {:?}",
+                        remote, peer_socket_type, socket_type
+                    );
+                    Some(RejectReason::MessageApi.into())
+                }
+                _ if peer_key_size != 0 && peer_key_size != key_size => {
+                    warn!(
+                        "{} uses a {} byte key, {} was sent",
+                        remote, peer_key_size, key_size
+                    );
+                    Some(
+                        KeyLengthMismatch {
+                            remote,
+                            local: key_size,
+                            peer: peer_key_size,
+                        }
+                        .into(),
+                    )
+                }
+                _ if enforced_encryption && crypto.is_some() && km_state != KmState::Secured => {
+                    warn!("Key exchange with {} failed with {:?}", remote, km_state);
+                    Some(key_exchange::rejection(km_state).into())
+                }
+                _ => None,
+            };
+            if let Some(reason) = refusal {
+                // the listener already thinks it's connected
+                let shutdown = Packet::Control(ControlPacket {
+                    dest_sockid: info.socket_id,
+                    timestamp,
+                    control_type: ControlTypes::Shutdown,
+                });
+                sock.send((shutdown, remote)).await?;
+
+                return Err(reason);
+            }
+
+            // the response's peer latency is what this end receives with,
+            // and its latency is what the listener receives with. UDT4 has
+            // nothing to negotiate, so it keeps the configured latency
+            let (latency, peer_tsbpd_latency, srt_flags) = if udt4 {
+                (recv_latency, peer_latency, SrtShakeFlags::empty())
+            } else if let HandshakeVSInfo::V5 {
                 ext_hs: Some(SrtControlPacket::HandshakeResponse(hs)),
                 ..
             } = info.info
             {
-                hs.latency
+                (hs.peer_latency, hs.latency, hs.flags)
             } else {
                 warn!("Did not get SRT handhsake in conclusion handshake packet, using latency from connector's end");
-                tsbpd_latency
+                (recv_latency, peer_latency, SrtShakeFlags::empty())
             };
+            let peer_stats = peer_stats && srt_flags.contains(SrtShakeFlags::PEERSTATS);
+
+            if crypto.is_some() && km_state != KmState::Secured {
+                warn!(
+                    "Key exchange with {} failed with {:?}, it won't be able to decrypt anything",
+                    remote, km_state
+                );
+            }
 
             info!(
                 "Got second handshake, connection established to {} with latency {:?}",
@@ -120,52 +289,134 @@ where
             return Ok(Connection {
                 settings: ConnectionSettings {
                     remote,
-                    max_flow_size: info.max_flow_size,
-                    max_packet_size: info.max_packet_size,
+                    max_flow_size: u32::min(info.max_flow_size, MAX_FLOW_SIZE),
+                    max_packet_size: u32::min(info.max_packet_size, MAX_PACKET_SIZE),
+                    peer_version,
+                    srt_flags,
+                    key_size: crypto.as_ref().map_or(0, PayloadCipher::key_len),
+                    km_state,
                     init_seq_num: info.init_seq_num,
-                    socket_start_time: Instant::now(), // restamp the socket start time, so TSBPD works correctly. TODO: technically it would be 1 rtt off....
+                    socket_start_time: listener_start_time(sent_at),
                     local_sockid,
                     remote_sockid: info.socket_id,
                     tsbpd_latency: latency,
+                    peer_tsbpd_latency,
+                    peer_stats,
+                    congest_ctrl,
+                    socket_type: if udt4 { UDT4_SOCKET_TYPE } else { socket_type },
+                    udt4,
                 },
                 // TODO: is this right? Needs testing.
                 hs_returner: Box::new(move |_| None),
+                early_data: Vec::new(),
+                stream_id,
+                #[cfg(feature = "crypto")]
+                crypto,
+                #[cfg(feature = "crypto")]
+                passphrase,
+            });
+        }
+
+        // If our conclusion response was lost, the listener thinks it's
+        // connected and starts sending data. That data is confirmation
+        // of the handshake, so finish connecting with what the induction
+        // response told us instead of waiting forever.
+        //
+        // The peer's version and whether it took the key are only in the
+        // conclusion response though, so wait for a resend of that if there's
+        // a minimum to check or a key was sent.
+        if let Packet::Data(ref data) = packet {
+            if from != remote
+                || data.dest_sockid != local_sockid
+                || min_peer_version.is_some()
+                || key_request.is_some()
+            {
+                continue;
+            }
+
+            warn!(
+                "Got data from {} before the conclusion handshake, assuming it was lost. Using latency {:?} from this end",
+                remote, recv_latency
+            );
+            return Ok(Connection {
+                settings: ConnectionSettings {
+                    remote,
+                    max_flow_size: u32::min(hs_info.max_flow_size, MAX_FLOW_SIZE),
+                    max_packet_size: u32::min(hs_info.max_packet_size, MAX_PACKET_SIZE),
+                    peer_version: None,
+                    srt_flags: SrtShakeFlags::empty(),
+                    key_size: 0,
+                    km_state: KmState::Unsecured,
+                    init_seq_num: hs_info.init_seq_num,
+                    socket_start_time: listener_start_time(sent_at),
+                    local_sockid,
+                    remote_sockid: hs_info.socket_id,
+                    tsbpd_latency: recv_latency,
+                    peer_tsbpd_latency: peer_latency,
+                    peer_stats: false,
+                    congest_ctrl,
+                    socket_type: if udt4 { UDT4_SOCKET_TYPE } else { socket_type },
+                    udt4,
+                },
+                hs_returner: Box::new(move |_| None),
+                early_data: vec![(packet, from)],
+                stream_id,
+                #[cfg(feature = "crypto")]
+                crypto: None,
+                #[cfg(feature = "crypto")]
+                passphrase: None,
             });
         }
     }
 }
 
+/// When the listener started the connection, for a first conclusion sent at
+/// `sent_at` that's just been answered
+///
+/// The listener starts it as the conclusion arrives, which is about halfway
+/// through the round trip. Starting at the same time means both ends stamp
+/// packets from the same origin, so each delivers the other's packets the
+/// latency after they were sent with TSBPD, rather than half a round trip
+/// early or late
+fn listener_start_time(sent_at: Instant) -> Instant {
+    sent_at + (Instant::now() - sent_at) / 2
+}
+
 async fn get_initial_handshake<T>(
     sock: &mut T,
-    local_addr: IpAddr,
     local_sockid: SocketID,
     remote: SocketAddr,
-) -> Result<(i32, HandshakeControlInfo), Error>
+    udt4: bool,
+) -> Result<(TimeStamp, HandshakeControlInfo), Error>
 where
     T: Stream<Item = Result<(Packet, SocketAddr), Error>>
         + Sink<(Packet, SocketAddr), Error = Error>
         + Unpin,
 {
-    let mut send_interval = interval(Duration::from_millis(100));
-
     info!("Connecting to {}...", remote);
 
     let request_packet = Packet::Control(ControlPacket {
         dest_sockid: SocketID(0),
-        timestamp: 0, // TODO: this is not zero in the reference implementation
+        timestamp: TimeStamp(0), // TODO: this is not zero in the reference implementation
         control_type: ControlTypes::Handshake(HandshakeControlInfo {
             init_seq_num: rand::random(),
-            max_packet_size: 1500, // TODO: take as a parameter
-            max_flow_size: 8192,   // TODO: take as a parameter
+            max_packet_size: MAX_PACKET_SIZE,
+            max_flow_size: MAX_FLOW_SIZE,
             socket_id: local_sockid,
             shake_type: ShakeType::Induction,
-            peer_addr: local_addr,
+            // like the reference implementation, the address it's sent to
+            peer_addr: remote.ip(),
             syn_cookie: 0,
-            info: HandshakeVSInfo::V4(SocketType::Datagram),
+            info: HandshakeVSInfo::V4(if udt4 {
+                UDT4_SOCKET_TYPE
+            } else {
+                SocketType::Datagram
+            }),
         }),
     });
 
     sock.send((request_packet.clone(), remote)).await?;
+    let mut resend = ResendTimer::new();
 
     info!("Sent first packet to {}", remote);
 
@@ -173,11 +424,17 @@ where
     let (timestamp, hs_info) = loop {
         // just drop the future that didn't finish first
         let (packet, addr) = select! {
-            _ = send_interval.tick().fuse() => {sock.send((request_packet.clone(), remote)).await?; continue},
+            _ = resend.tick().fuse() => {sock.send((request_packet.clone(), remote)).await?; continue},
             res = get_packet(sock).fuse() => res?
         };
 
         debug!("Got packet from {}", addr);
+        if addr == remote && packet.is_shutdown() {
+            bail!("Peer {} aborted during handshake", remote);
+        }
+        if let (true, Some(reason)) = (addr == remote, rejection(&packet)) {
+            return Err(reason.into());
+        }
         // make sure the socket id and packet type match
         if let Packet::Control(ControlPacket {
             timestamp,
@@ -188,7 +445,8 @@ where
             if info.shake_type != ShakeType::Induction {
                 info!(
                     "Expected Induction (1) packet, got {:?} ({})",
-                    info.shake_type, info.shake_type as u32
+                    info.shake_type,
+                    info.shake_type.to_i32()
                 );
                 continue;
             }
@@ -196,9 +454,10 @@ where
                 warn!("Expected packet from {}, got {}", remote, addr);
                 continue;
             }
-            if info.info.version() != 5 {
-                warn!("Handshake was HSv4, expected HSv5");
-                continue;
+            // a UDT4 listener answers with HSv4, an SRT one with HSv5, and
+            // either takes a HSv4 conclusion
+            if !udt4 && info.info.version() != 5 {
+                bail!("{} only speaks HSv4 (UDT4), expected HSv5", remote);
             }
             break (timestamp, info);
         }
@@ -206,3 +465,409 @@ where
 
     Ok((timestamp, hs_info))
 }
+
+/// The reason the peer gave if `packet` is a handshake refusing the connection
+fn rejection(packet: &Packet) -> Option<RejectReason> {
+    match packet {
+        Packet::Control(ControlPacket {
+            control_type:
+                ControlTypes::Handshake(HandshakeControlInfo {
+                    shake_type: ShakeType::Rejection(reason),
+                    ..
+                }),
+            ..
+        }) => Some(*reason),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::connect;
+    use crate::channel::Channel;
+    use crate::packet::{
+        ControlPacket, ControlTypes, DataEncryption, DataPacket, HandshakeControlInfo,
+        HandshakeVSInfo, Packet, PacketLocation, RejectReason, ShakeType,
+    };
+    use crate::pending_connection::HandshakeConfig;
+    use crate::socket::create_bidrectional_srt;
+    use crate::{MsgNumber, SocketID, SrtCongestCtrl, TimeStamp};
+
+    use bytes::Bytes;
+    use failure::format_err;
+    use futures::future::{pending, FutureExt};
+    use futures::{SinkExt, StreamExt, TryStreamExt};
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+    use tokio::sync::oneshot;
+    use tokio::time::{delay_for, timeout};
+
+    // respond to the connector's induction like a listener would
+    async fn respond_to_induction(
+        listen_side: &mut Channel<(Packet, SocketAddr)>,
+        remote: SocketAddr,
+    ) -> HandshakeControlInfo {
+        let (induction, _) = listen_side.try_next().await.unwrap().unwrap();
+        let info = match induction {
+            Packet::Control(ControlPacket {
+                control_type: ControlTypes::Handshake(info),
+                ..
+            }) => info,
+            p => panic!("Expected handshake, got {:?}", p),
+        };
+        listen_side
+            .send((
+                Packet::Control(ControlPacket {
+                    timestamp: TimeStamp(0),
+                    dest_sockid: info.socket_id,
+                    control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                        socket_id: SocketID(1234),
+                        syn_cookie: 5678,
+                        info: HandshakeVSInfo::V5 {
+                            crypto_size: 0,
+                            ext_hs: None,
+                            ext_km: None,
+                            ext_config: None,
+                            ext_other: Vec::new(),
+                        },
+                        ..info.clone()
+                    }),
+                }),
+                remote,
+            ))
+            .await
+            .unwrap();
+
+        info
+    }
+
+    #[tokio::test]
+    async fn data_completes_lost_conclusion() {
+        let remote: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let (mut conn_side, mut listen_side) = Channel::<(Packet, SocketAddr)>::channel(10);
+
+        // a listener whose conclusion response never arrives
+        tokio::spawn(async move {
+            let info = respond_to_induction(&mut listen_side, remote).await;
+
+            // wait for the conclusion, and "lose" the response
+            let (conclusion, _) = listen_side.try_next().await.unwrap().unwrap();
+            match conclusion {
+                Packet::Control(ControlPacket {
+                    control_type:
+                        ControlTypes::Handshake(HandshakeControlInfo {
+                            shake_type: ShakeType::Conclusion,
+                            ..
+                        }),
+                    ..
+                }) => {}
+                p => panic!("Expected conclusion, got {:?}", p),
+            }
+
+            listen_side
+                .send((
+                    Packet::Data(DataPacket {
+                        seq_number: info.init_seq_num,
+                        message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+                        in_order_delivery: false,
+                        retransmitted: false,
+                        encryption: DataEncryption::None,
+                        message_number: MsgNumber::new_truncate(0),
+                        timestamp: TimeStamp(0),
+                        dest_sockid: info.socket_id,
+                        payload: Bytes::from("hello"),
+                    }),
+                    remote,
+                ))
+                .await
+                .unwrap();
+
+            // keep the channel open, ignoring whatever else comes in
+            while listen_side.next().await.is_some() {}
+        });
+
+        let conn = connect(
+            &mut conn_side,
+            remote,
+            SocketID(4321),
+            &HandshakeConfig::new(Duration::from_millis(20)),
+            pending(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(conn.settings.remote_sockid, SocketID(1234));
+        assert_eq!(conn.settings.local_sockid, SocketID(4321));
+        assert_eq!(conn.early_data.len(), 1);
+
+        let mut sock = create_bidrectional_srt(
+            conn_side,
+            conn,
+            Box::new(SrtCongestCtrl::default()),
+            None,
+            false,
+        );
+
+        let (_, payload) = sock.try_next().await.unwrap().unwrap();
+        assert_eq!(payload, Bytes::from("hello"));
+    }
+
+    #[tokio::test]
+    async fn connects_behind_nat() {
+        let remote: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let (mut conn_side, mut listen_side) = Channel::<(Packet, SocketAddr)>::channel(10);
+
+        // the listener sees the caller at the NAT's public address
+        tokio::spawn(async move {
+            let info = respond_to_induction(&mut listen_side, remote).await;
+            listen_side.try_next().await.unwrap().unwrap();
+
+            listen_side
+                .send((
+                    Packet::Control(ControlPacket {
+                        timestamp: TimeStamp(0),
+                        dest_sockid: info.socket_id,
+                        control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                            shake_type: ShakeType::Conclusion,
+                            socket_id: SocketID(1234),
+                            peer_addr: "203.0.113.7".parse().unwrap(),
+                            info: HandshakeVSInfo::V5 {
+                                crypto_size: 0,
+                                ext_hs: None,
+                                ext_km: None,
+                                ext_config: None,
+                                ext_other: Vec::new(),
+                            },
+                            ..info
+                        }),
+                    }),
+                    remote,
+                ))
+                .await
+                .unwrap();
+
+            while listen_side.next().await.is_some() {}
+        });
+
+        let conn = timeout(
+            Duration::from_secs(1),
+            connect(
+                &mut conn_side,
+                remote,
+                SocketID(4321),
+                &HandshakeConfig::new(Duration::from_millis(20)),
+                pending(),
+            ),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(conn.settings.remote_sockid, SocketID(1234));
+    }
+
+    // how far apart the connector's start and that of a listener `one_way`
+    // away are
+    async fn listener_start_offset(one_way: Duration) -> Duration {
+        let remote: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let (mut conn_side, mut listen_side) = Channel::<(Packet, SocketAddr)>::channel(10);
+        let (started_tx, started_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let info = respond_to_induction(&mut listen_side, remote).await;
+            listen_side.try_next().await.unwrap().unwrap();
+
+            delay_for(one_way).await;
+            started_tx.send(Instant::now()).unwrap();
+            delay_for(one_way).await;
+
+            listen_side
+                .send((
+                    Packet::Control(ControlPacket {
+                        timestamp: TimeStamp(0),
+                        dest_sockid: info.socket_id,
+                        control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                            shake_type: ShakeType::Conclusion,
+                            socket_id: SocketID(1234),
+                            info: HandshakeVSInfo::V5 {
+                                crypto_size: 0,
+                                ext_hs: None,
+                                ext_km: None,
+                                ext_config: None,
+                                ext_other: Vec::new(),
+                            },
+                            ..info
+                        }),
+                    }),
+                    remote,
+                ))
+                .await
+                .unwrap();
+
+            while listen_side.next().await.is_some() {}
+        });
+
+        let conn = connect(
+            &mut conn_side,
+            remote,
+            SocketID(4321),
+            &HandshakeConfig::new(Duration::from_millis(20)),
+            pending(),
+        )
+        .await
+        .unwrap();
+
+        let listener_start = started_rx.await.unwrap();
+        let start = conn.settings.socket_start_time;
+        if start > listener_start {
+            start - listener_start
+        } else {
+            listener_start - start
+        }
+    }
+
+    #[tokio::test]
+    async fn starts_with_listener() {
+        // so both ends stamp packets from the same origin
+        let offset = listener_start_offset(Duration::from_millis(30)).await;
+        assert!(offset < Duration::from_millis(10), "{:?}", offset);
+    }
+
+    #[tokio::test]
+    async fn starts_with_listener_after_resend() {
+        // the conclusion is resent before the response to the first arrives
+        let offset = listener_start_offset(Duration::from_millis(80)).await;
+        assert!(offset < Duration::from_millis(10), "{:?}", offset);
+    }
+
+    #[tokio::test]
+    async fn shutdown_during_handshake() {
+        let remote: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let (mut conn_side, mut listen_side) = Channel::<(Packet, SocketAddr)>::channel(10);
+
+        // a listener that gives up after getting the conclusion
+        tokio::spawn(async move {
+            let info = respond_to_induction(&mut listen_side, remote).await;
+            listen_side.try_next().await.unwrap().unwrap();
+
+            listen_side
+                .send((
+                    Packet::Control(ControlPacket {
+                        timestamp: TimeStamp(0),
+                        dest_sockid: info.socket_id,
+                        control_type: ControlTypes::Shutdown,
+                    }),
+                    remote,
+                ))
+                .await
+                .unwrap();
+
+            while listen_side.next().await.is_some() {}
+        });
+
+        let res = timeout(
+            Duration::from_millis(50),
+            connect(
+                &mut conn_side,
+                remote,
+                SocketID(4321),
+                &HandshakeConfig::new(Duration::from_millis(20)),
+                pending(),
+            ),
+        )
+        .await
+        .expect("Connecting didn't fail promptly");
+
+        let err = res.err().expect("Connected despite the shutdown");
+        assert_eq!(
+            err.to_string(),
+            "Peer 127.0.0.1:2000 aborted during handshake"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_during_handshake() {
+        let remote: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let (mut conn_side, mut listen_side) = Channel::<(Packet, SocketAddr)>::channel(10);
+
+        // a listener that refuses the conclusion
+        tokio::spawn(async move {
+            let info = respond_to_induction(&mut listen_side, remote).await;
+            listen_side.try_next().await.unwrap().unwrap();
+
+            listen_side
+                .send((
+                    Packet::Control(ControlPacket {
+                        timestamp: TimeStamp(0),
+                        dest_sockid: info.socket_id,
+                        control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                            shake_type: ShakeType::Rejection(RejectReason::User(2001)),
+                            socket_id: SocketID(1234),
+                            ..info
+                        }),
+                    }),
+                    remote,
+                ))
+                .await
+                .unwrap();
+
+            while listen_side.next().await.is_some() {}
+        });
+
+        let res = timeout(
+            Duration::from_millis(50),
+            connect(
+                &mut conn_side,
+                remote,
+                SocketID(4321),
+                &HandshakeConfig::new(Duration::from_millis(20)),
+                pending(),
+            ),
+        )
+        .await
+        .expect("Connecting didn't fail promptly");
+
+        let err = res.err().expect("Connected despite the rejection");
+        assert_eq!(
+            err.downcast_ref::<RejectReason>(),
+            Some(&RejectReason::User(2001))
+        );
+    }
+
+    #[tokio::test]
+    async fn giving_up_shuts_down() {
+        let remote: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let (mut conn_side, mut listen_side) = Channel::<(Packet, SocketAddr)>::channel(10);
+
+        // a listener that never answers the conclusion
+        let listener = tokio::spawn(async move {
+            respond_to_induction(&mut listen_side, remote).await;
+            loop {
+                match listen_side.try_next().await.unwrap().unwrap() {
+                    (
+                        Packet::Control(ControlPacket {
+                            dest_sockid,
+                            control_type: ControlTypes::Shutdown,
+                            ..
+                        }),
+                        _,
+                    ) => break dest_sockid,
+                    _ => continue,
+                }
+            }
+        });
+
+        let res = connect(
+            &mut conn_side,
+            remote,
+            SocketID(4321),
+            &HandshakeConfig::new(Duration::from_millis(20)),
+            delay_for(Duration::from_millis(50)).map(|_| format_err!("Gave up")),
+        )
+        .await;
+        assert_eq!(res.err().unwrap().to_string(), "Gave up");
+
+        let shut_down = timeout(Duration::from_millis(50), listener).await;
+        assert_eq!(shut_down.unwrap().unwrap(), SocketID(1234));
+    }
+}
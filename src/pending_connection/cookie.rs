@@ -0,0 +1,138 @@
+#[cfg(not(feature = "crypto"))]
+use std::collections::hash_map::RandomState;
+#[cfg(not(feature = "crypto"))]
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use failure::Error;
+#[cfg(feature = "crypto")]
+use openssl::hash::MessageDigest;
+#[cfg(feature = "crypto")]
+use openssl::pkey::PKey;
+#[cfg(feature = "crypto")]
+use openssl::rand::rand_bytes;
+#[cfg(feature = "crypto")]
+use openssl::sign::Signer;
+
+/// Makes and checks the SYN cookies a listener hands out in its induction response
+///
+/// Nothing is stored per peer: the cookie is a HMAC of the peer's address and
+/// the current minute, keyed with a secret that never leaves this struct, so a
+/// caller can only get a valid one by actually receiving the induction response.
+/// Like the reference implementation, a cookie from the previous minute is still
+/// accepted, so a handshake that straddles the minute boundary isn't refused.
+///
+/// Without the `crypto` feature there's no HMAC, and SipHash with random keys
+/// is used instead
+#[derive(Clone)]
+pub struct SynCookies {
+    #[cfg(feature = "crypto")]
+    secret: [u8; 32],
+    #[cfg(not(feature = "crypto"))]
+    secret: RandomState,
+}
+
+impl SynCookies {
+    #[cfg(feature = "crypto")]
+    pub fn new() -> Result<SynCookies, Error> {
+        let mut secret = [0; 32];
+        rand_bytes(&mut secret[..])?;
+
+        Ok(SynCookies { secret })
+    }
+
+    #[cfg(not(feature = "crypto"))]
+    pub fn new() -> Result<SynCookies, Error> {
+        Ok(SynCookies {
+            secret: RandomState::new(),
+        })
+    }
+
+    /// The cookie to send to `peer` right now
+    pub fn generate(&self, peer: &SocketAddr) -> Result<i32, Error> {
+        self.generate_at(peer, current_minute())
+    }
+
+    /// If `cookie` is one that was sent to `peer` in the last minute or two
+    pub fn validate(&self, peer: &SocketAddr, cookie: i32) -> Result<bool, Error> {
+        self.validate_at(peer, cookie, current_minute())
+    }
+
+    fn validate_at(&self, peer: &SocketAddr, cookie: i32, minute: u64) -> Result<bool, Error> {
+        Ok(cookie == self.generate_at(peer, minute)?
+            || cookie == self.generate_at(peer, minute.wrapping_sub(1))?)
+    }
+
+    fn generate_at(&self, peer: &SocketAddr, minute: u64) -> Result<i32, Error> {
+        let mut message = match peer.ip() {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+        message.extend_from_slice(&peer.port().to_be_bytes());
+        message.extend_from_slice(&minute.to_be_bytes());
+
+        let mac = self.mac(&message)?;
+        Ok(i32::from_be_bytes([mac[0], mac[1], mac[2], mac[3]]))
+    }
+
+    #[cfg(feature = "crypto")]
+    fn mac(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let key = PKey::hmac(&self.secret)?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(message)?;
+
+        Ok(signer.sign_to_vec()?)
+    }
+
+    #[cfg(not(feature = "crypto"))]
+    fn mac(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut hasher = self.secret.build_hasher();
+        hasher.write(message);
+
+        Ok(hasher.finish().to_be_bytes().to_vec())
+    }
+}
+
+fn current_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() / 60)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::SynCookies;
+
+    use std::net::SocketAddr;
+
+    #[test]
+    fn cookie_expires() {
+        let cookies = SynCookies::new().unwrap();
+        let peer: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+
+        let cookie = cookies.generate_at(&peer, 100).unwrap();
+        assert_eq!(cookie, cookies.generate_at(&peer, 100).unwrap());
+
+        assert!(cookies.validate_at(&peer, cookie, 100).unwrap());
+        assert!(cookies.validate_at(&peer, cookie, 101).unwrap());
+        assert!(!cookies.validate_at(&peer, cookie, 102).unwrap());
+        assert!(!cookies.validate_at(&peer, cookie, 99).unwrap());
+    }
+
+    #[test]
+    fn cookie_depends_on_peer_and_secret() {
+        let cookies = SynCookies::new().unwrap();
+        let peer: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let cookie = cookies.generate_at(&peer, 100).unwrap();
+
+        let other_port: SocketAddr = "127.0.0.1:2001".parse().unwrap();
+        let other_ip: SocketAddr = "127.0.0.2:2000".parse().unwrap();
+        assert!(!cookies.validate_at(&other_port, cookie, 100).unwrap());
+        assert!(!cookies.validate_at(&other_ip, cookie, 100).unwrap());
+
+        let other_secret = SynCookies::new().unwrap();
+        assert!(!other_secret.validate_at(&peer, cookie, 100).unwrap());
+    }
+}
@@ -0,0 +1,330 @@
+#[cfg(not(feature = "crypto"))]
+use failure::bail;
+use failure::Error;
+use log::warn;
+
+#[cfg(feature = "crypto")]
+use crate::crypto::CryptoManager;
+#[cfg(feature = "crypto")]
+pub use crate::crypto::{PayloadCipher, DEFAULT_KEY_SIZE};
+#[cfg(feature = "crypto")]
+use crate::packet::SrtKeyMessage;
+use crate::packet::{KmState, RejectReason, SrtControlPacket};
+
+/// Without the `crypto` feature nothing is encrypted, so there's never a
+/// cipher to send with
+#[cfg(not(feature = "crypto"))]
+pub enum PayloadCipher {}
+
+#[cfg(not(feature = "crypto"))]
+impl PayloadCipher {
+    pub fn key_len(&self) -> u8 {
+        match *self {}
+    }
+}
+
+/// There's no key without the `crypto` feature, so its length is never used
+#[cfg(not(feature = "crypto"))]
+pub const DEFAULT_KEY_SIZE: u8 = 0;
+
+/// The key material the initiator of a handshake sends, wrapped with its
+/// passphrase, and the cipher for it
+#[cfg(feature = "crypto")]
+pub struct KeyRequest {
+    key_msg: SrtKeyMessage,
+    cipher: PayloadCipher,
+}
+
+#[cfg(feature = "crypto")]
+impl KeyRequest {
+    /// Generate a new key of `key_size` bytes, wrapped with `passphrase`
+    pub fn new(passphrase: String, key_size: u8) -> Result<KeyRequest, Error> {
+        let mut manager = CryptoManager::new(key_size, passphrase);
+
+        Ok(KeyRequest {
+            key_msg: manager.key_message()?,
+            cipher: manager.cipher(),
+        })
+    }
+
+    /// The KMREQ to put in the conclusion
+    pub fn extension(&self) -> SrtControlPacket {
+        SrtControlPacket::KeyManagerRequest(self.key_msg.clone())
+    }
+
+    /// The size of the key in bytes
+    pub fn key_size(&self) -> u8 {
+        self.cipher.key_len()
+    }
+
+    /// The state of the exchange, given the `response` in the responder's
+    /// conclusion, and the cipher to send with
+    ///
+    /// The responder sends the key material back if it could unwrap the key,
+    /// so anything else it sends back means the key isn't agreed on. Payloads
+    /// are encrypted with the key anyway, so they're never sent in the clear
+    pub fn conclude(self, response: Option<&SrtControlPacket>) -> (KmState, PayloadCipher) {
+        let state = match response {
+            Some(SrtControlPacket::KeyManagerResponse(key_msg)) if *key_msg == self.key_msg => {
+                KmState::Secured
+            }
+            Some(SrtControlPacket::KeyManagerResponse(_)) => {
+                warn!("Peer sent back other key material than was sent");
+                KmState::BadSecret
+            }
+            Some(SrtControlPacket::KeyManagerError(state)) => *state,
+            // it doesn't know about encryption at all
+            _ => KmState::NoSecret,
+        };
+
+        (state, self.cipher)
+    }
+}
+
+/// Without the `crypto` feature there's no passphrase to wrap a key with, so
+/// no key material is ever sent
+#[cfg(not(feature = "crypto"))]
+pub enum KeyRequest {}
+
+#[cfg(not(feature = "crypto"))]
+impl KeyRequest {
+    pub fn new(_passphrase: String, _key_size: u8) -> Result<KeyRequest, Error> {
+        bail!("Encryption needs the crypto feature")
+    }
+
+    pub fn extension(&self) -> SrtControlPacket {
+        match *self {}
+    }
+
+    pub fn key_size(&self) -> u8 {
+        match *self {}
+    }
+
+    pub fn conclude(self, _response: Option<&SrtControlPacket>) -> (KmState, PayloadCipher) {
+        match self {}
+    }
+}
+
+/// Why a connection is refused when encryption is enforced and the key
+/// exchange ended in `state`
+pub fn rejection(state: KmState) -> RejectReason {
+    match state {
+        KmState::BadSecret => RejectReason::BadSecret,
+        _ => RejectReason::Unsecure,
+    }
+}
+
+/// The length in bytes of the key in the key material `request`, if there is any
+pub fn key_size(request: Option<&SrtControlPacket>) -> Option<u8> {
+    match request {
+        Some(SrtControlPacket::KeyManagerRequest(key_msg)) => key_msg
+            .even_key
+            .as_ref()
+            .or(key_msg.odd_key.as_ref())
+            .map(|key| key.len() as u8),
+        _ => None,
+    }
+}
+
+/// Answer the key material `request` from the initiator, if it sent any
+///
+/// Returns the state of the exchange, what to put in the conclusion response
+/// and the cipher to send with. The key material is sent back if the key
+/// could be unwrapped with `passphrase`, and the state if it couldn't
+///
+/// With a passphrase, payloads are always encrypted, with a key of this end's
+/// own of `key_size` bytes if the peer's couldn't be unwrapped, so they're
+/// never sent in the clear
+#[cfg(feature = "crypto")]
+pub fn respond(
+    passphrase: Option<&str>,
+    key_size: u8,
+    request: Option<&SrtControlPacket>,
+) -> (KmState, Option<SrtControlPacket>, Option<PayloadCipher>) {
+    let own_cipher = || passphrase.map(|p| CryptoManager::new(key_size, p.into()).cipher());
+
+    let key_msg = match request {
+        Some(SrtControlPacket::KeyManagerRequest(key_msg)) => key_msg,
+        _ => {
+            if passphrase.is_some() {
+                warn!("Peer didn't send a key, it won't be able to decrypt anything");
+            }
+            return (KmState::Unsecured, None, own_cipher());
+        }
+    };
+
+    let (state, cipher) = match passphrase {
+        None => {
+            warn!("Peer sent a key, but no passphrase is set to unwrap it");
+            (KmState::NoSecret, None)
+        }
+        Some(passphrase) => match CryptoManager::unwrap_key_message(passphrase.into(), key_msg) {
+            // the handshake only announces the even key
+            Ok([Some(cipher), _]) => (KmState::Secured, Some(cipher)),
+            Ok(_) => {
+                warn!("Peer's key material has no even key");
+                (KmState::BadSecret, own_cipher())
+            }
+            Err(e) => {
+                warn!("Failed to unwrap the peer's key: {}", e);
+                (KmState::BadSecret, own_cipher())
+            }
+        },
+    };
+
+    let response = match state {
+        KmState::Secured => SrtControlPacket::KeyManagerResponse(key_msg.clone()),
+        state => SrtControlPacket::KeyManagerError(state),
+    };
+
+    (state, Some(response), cipher)
+}
+
+/// Answer the key material `request` from the initiator, if it sent any
+///
+/// Without the `crypto` feature there's no passphrase to unwrap it with, so
+/// it's refused like by an end that has none
+#[cfg(not(feature = "crypto"))]
+pub fn respond(
+    _passphrase: Option<&str>,
+    _key_size: u8,
+    request: Option<&SrtControlPacket>,
+) -> (KmState, Option<SrtControlPacket>, Option<PayloadCipher>) {
+    match request {
+        Some(SrtControlPacket::KeyManagerRequest(_)) => {
+            warn!("Peer sent a key, but encryption isn't available without the crypto feature");
+            (
+                KmState::NoSecret,
+                Some(SrtControlPacket::KeyManagerError(KmState::NoSecret)),
+                None,
+            )
+        }
+        _ => (KmState::Unsecured, None, None),
+    }
+}
+
+#[cfg(all(test, feature = "crypto"))]
+mod test {
+    use super::{respond, KeyRequest};
+    use crate::packet::{KmState, SrtControlPacket};
+
+    fn exchange(
+        initiator: &str,
+        responder: Option<&str>,
+    ) -> (KmState, KmState, Option<SrtControlPacket>) {
+        let request = KeyRequest::new(initiator.into(), 16).unwrap();
+        let (responder_state, response, _) = respond(responder, 16, Some(&request.extension()));
+        let (initiator_state, _) = request.conclude(response.as_ref());
+
+        (initiator_state, responder_state, response)
+    }
+
+    #[test]
+    fn secured() {
+        let request = KeyRequest::new("password123".into(), 16).unwrap();
+        let (state, response, responder_cipher) =
+            respond(Some("password123"), 16, Some(&request.extension()));
+        assert_eq!(state, KmState::Secured);
+        assert_eq!(
+            response,
+            Some(SrtControlPacket::KeyManagerResponse(
+                request.key_msg.clone()
+            ))
+        );
+
+        let (state, initiator_cipher) = request.conclude(response.as_ref());
+        assert_eq!(state, KmState::Secured);
+
+        // both ends have the same key
+        let seq = rand::random();
+        assert_eq!(
+            initiator_cipher.apply(seq, b"Hello"),
+            responder_cipher.unwrap().apply(seq, b"Hello")
+        );
+    }
+
+    #[test]
+    fn bad_secret() {
+        let (initiator, responder, response) = exchange("password123", Some("password456"));
+        assert_eq!(initiator, KmState::BadSecret);
+        assert_eq!(responder, KmState::BadSecret);
+        assert_eq!(
+            response,
+            Some(SrtControlPacket::KeyManagerError(KmState::BadSecret))
+        );
+
+        // the responder still encrypts, with a key of its own
+        let request = KeyRequest::new("password123".into(), 16).unwrap();
+        let (_, _, cipher) = respond(Some("password456"), 16, Some(&request.extension()));
+        let seq = rand::random();
+        assert_ne!(
+            cipher.unwrap().apply(seq, b"Hello"),
+            request.cipher.apply(seq, b"Hello")
+        );
+    }
+
+    #[test]
+    fn no_secret() {
+        let (initiator, responder, response) = exchange("password123", None);
+        assert_eq!(initiator, KmState::NoSecret);
+        assert_eq!(responder, KmState::NoSecret);
+        assert_eq!(
+            response,
+            Some(SrtControlPacket::KeyManagerError(KmState::NoSecret))
+        );
+
+        // a peer that doesn't send anything back can't have taken the key
+        let request = KeyRequest::new("password123".into(), 16).unwrap();
+        assert_eq!(request.conclude(None).0, KmState::NoSecret);
+    }
+
+    #[test]
+    fn unsecured() {
+        let (state, response, cipher) = respond(Some("password123"), 16, None);
+        assert_eq!(state, KmState::Unsecured);
+        assert_eq!(response, None);
+        assert!(cipher.is_some());
+
+        let (state, _, cipher) = respond(None, 16, None);
+        assert_eq!(state, KmState::Unsecured);
+        assert!(cipher.is_none());
+    }
+
+    #[test]
+    fn responder_verified() {
+        // a response with another key is as bad as a refusal
+        let request = KeyRequest::new("password123".into(), 16).unwrap();
+        let other = KeyRequest::new("password123".into(), 16).unwrap();
+        let response = SrtControlPacket::KeyManagerResponse(other.key_msg);
+        assert_eq!(request.conclude(Some(&response)).0, KmState::BadSecret);
+    }
+}
+
+#[cfg(all(test, not(feature = "crypto")))]
+mod no_crypto_test {
+    use super::respond;
+    use crate::packet::{CipherType, KmState, SrtControlPacket, SrtKeyMessage};
+
+    #[test]
+    fn key_refused() {
+        let request = SrtControlPacket::KeyManagerRequest(SrtKeyMessage {
+            pt: 2,
+            sign: 0x2029,
+            keki: 0,
+            cipher: CipherType::CTR,
+            auth: 0,
+            se: 2,
+            salt: vec![0; 16],
+            even_key: Some(vec![0; 16]),
+            odd_key: None,
+            wrap_data: [0; 8],
+        });
+        let (state, response, cipher) = respond(None, 0, Some(&request));
+        assert_eq!(state, KmState::NoSecret);
+        assert_eq!(
+            response,
+            Some(SrtControlPacket::KeyManagerError(KmState::NoSecret))
+        );
+        assert!(cipher.is_none());
+    }
+}
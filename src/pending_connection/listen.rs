@@ -1,5 +1,3 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 
@@ -7,17 +5,39 @@ use failure::{bail, Error};
 use futures::prelude::*;
 use log::{debug, info, warn};
 
+use super::cookie::SynCookies;
+use super::key_exchange::{self, PayloadCipher, DEFAULT_KEY_SIZE};
+use super::HandshakeConfig;
+use super::{congest_ctrl_extension, congest_ctrl_of, peer_addr_matches, socket_type_of};
+use super::{MAX_FLOW_SIZE, MAX_PACKET_SIZE, UDT4_SOCKET_TYPE};
+use crate::access_control::{AccessDecision, AddressFilter};
 use crate::packet::{
-    ControlPacket, ControlTypes, HandshakeControlInfo, HandshakeVSInfo, Packet, ShakeType,
-    SrtControlPacket, SrtHandshake,
+    ControlPacket, ControlTypes, HandshakeControlInfo, HandshakeVSInfo, KmState, Packet,
+    RejectReason, ShakeType, SrtControlPacket, SrtHandshake, SrtShakeFlags,
 };
 use crate::util::get_packet;
-use crate::{Connection, ConnectionSettings, SocketID};
+use crate::{Connection, ConnectionSettings, KeyLengthMismatch, SocketID, SrtVersion, TimeStamp};
 
+/// Wait for a caller and do the listener's side of the handshake with it,
+/// as `config` says
+///
+/// Handshakes from addresses the address filter doesn't allow are ignored.
+/// With `verify_local_addr`, the address this end is bound to, conclusions the
+/// caller addressed to another one are refused as rogue. If the access
+/// control refuses the caller, its SRT version is older than
+/// `min_peer_version` or it doesn't use `congest_ctrl` and `socket_type`, it's
+/// told so and this fails with the `RejectReason`
+///
+/// With a `passphrase`, or one the access control accepted the caller with,
+/// the key the caller sends is unwrapped with it and the connection is
+/// encrypted with that key. If it isn't `key_length` bytes long, the caller
+/// is told the length to use and this fails with `KeyLengthMismatch`. With
+/// `enforced_encryption`, a caller is refused unless both ends have the same
+/// passphrase or neither has one
 pub async fn listen<T>(
     sock: &mut T,
     local_sockid: SocketID,
-    tsbpd_latency: Duration,
+    config: &HandshakeConfig,
 ) -> Result<Connection, Error>
 where
     T: Stream<Item = Result<(Packet, SocketAddr), Error>>
@@ -26,23 +46,56 @@ where
 {
     info!("Listening...");
 
-    // keep on retrying
-    let (cookie, from, induction_pkt) = get_handshake(sock, local_sockid).await?;
-
-    info!("Got induction shake from {}", from);
+    let cookies = SynCookies::new()?;
 
-    let (latency, shake, resp_handshake) = get_conclusion(
+    // keep on retrying
+    let from = get_handshake(
         sock,
-        &induction_pkt,
-        cookie,
+        &cookies,
         local_sockid,
-        tsbpd_latency,
-        &from,
+        config.udt4,
+        config.address_filter.as_ref(),
     )
     .await?;
-    // select the smaller packet size and max window size
-    // TODO: allow configuration of these parameters, for now just
-    // use the remote ones
+
+    info!("Got induction shake from {}", from);
+
+    conclude(sock, &cookies, from, local_sockid, config).await
+}
+
+/// Do the rest of the listener's side of the handshake with the caller at
+/// `from`, which has been handed a cookie from `cookies`, as `listen` does
+///
+/// Until its conclusion comes, inductions it resends are answered again
+pub async fn conclude<T>(
+    sock: &mut T,
+    cookies: &SynCookies,
+    from: SocketAddr,
+    local_sockid: SocketID,
+    config: &HandshakeConfig,
+) -> Result<Connection, Error>
+where
+    T: Stream<Item = Result<(Packet, SocketAddr), Error>>
+        + Sink<(Packet, SocketAddr), Error = Error>
+        + Unpin,
+{
+    // the passphrase is only kept for refreshing keys
+    #[cfg_attr(not(feature = "crypto"), allow(unused_variables))]
+    let ((latency, peer_latency), srt_flags, shake, resp_handshake, (km_state, crypto, passphrase)) =
+        get_conclusion(sock, cookies, local_sockid, config, &from).await?;
+
+    let stream_id = stream_id(&shake.info);
+    if let Some(id) = &stream_id {
+        info!("{} asked for stream {:?}", from, id);
+    }
+
+    let peer_version = match shake.info {
+        HandshakeVSInfo::V5 {
+            ext_hs: Some(SrtControlPacket::HandshakeRequest(hs)),
+            ..
+        } => Some(hs.version),
+        _ => None,
+    };
 
     // finish the connection
     Ok(Connection {
@@ -50,13 +103,32 @@ where
             init_seq_num: shake.init_seq_num,
             remote_sockid: shake.socket_id,
             remote: from,
-            max_flow_size: 16000, // TODO: what is this?
+            max_flow_size: shake.max_flow_size,
             max_packet_size: shake.max_packet_size,
+            peer_version,
+            srt_flags,
+            key_size: crypto.as_ref().map_or(0, PayloadCipher::key_len),
+            km_state,
             local_sockid,
             socket_start_time: Instant::now(), // restamp the socket start time, so TSBPD works correctly
             tsbpd_latency: latency,
+            peer_tsbpd_latency: peer_latency,
+            peer_stats: srt_flags.contains(SrtShakeFlags::PEERSTATS),
+            congest_ctrl: config.congest_ctrl,
+            socket_type: if config.udt4 {
+                UDT4_SOCKET_TYPE
+            } else {
+                config.socket_type
+            },
+            udt4: config.udt4,
         },
         hs_returner: Box::new(move |_| Some(resp_handshake.clone())),
+        early_data: Vec::new(),
+        stream_id,
+        #[cfg(feature = "crypto")]
+        crypto,
+        #[cfg(feature = "crypto")]
+        passphrase,
     })
 }
 
@@ -66,11 +138,21 @@ async fn get_handshake<
         + Unpin,
 >(
     sock: &mut T,
+    cookies: &SynCookies,
     local_sockid: SocketID,
-) -> Result<(i32, SocketAddr, Packet), Error> {
+    udt4: bool,
+    address_filter: Option<&AddressFilter>,
+) -> Result<SocketAddr, Error> {
     loop {
         let (packet, from) = get_packet(sock).await?;
 
+        if let Some(filter) = address_filter {
+            if !filter.allows(from) {
+                debug!("Ignoring packet from filtered address {}", from);
+                continue;
+            }
+        }
+
         if let Packet::Control(ControlPacket {
             control_type: ControlTypes::Handshake(shake),
             timestamp,
@@ -80,66 +162,92 @@ async fn get_handshake<
             if shake.shake_type != ShakeType::Induction {
                 info!(
                     "Expected Induction (1), got {:?} ({})",
-                    shake.shake_type, shake.shake_type as u32
+                    shake.shake_type,
+                    shake.shake_type.to_i32()
                 );
                 continue;
             }
 
-            // https://tools.ietf.org/html/draft-gg-udt-03#page-9
-            // When the server first receives the connection request from a client,
-            // it generates a cookie value according to the client address and a
-            // secret key and sends it back to the client. The client must then send
-            // back the same cookie to the server.
-
-            // generate the cookie, which is just a hash of the address
-            // TODO: the reference impl uses the time, maybe we should here
-            let cookie = {
-                let mut hasher = DefaultHasher::new();
-                shake.peer_addr.hash(&mut hasher);
-                hasher.finish() as i32 // this will truncate, which is fine
-            };
-
-            // we expect HSv5, so upgrade it
-
-            // construct a packet to send back
-            let resp_handshake = Packet::Control(ControlPacket {
-                timestamp,
-                dest_sockid: shake.socket_id,
-                control_type: ControlTypes::Handshake(HandshakeControlInfo {
-                    syn_cookie: cookie,
-                    socket_id: local_sockid,
-                    info: HandshakeVSInfo::V5 {
-                        crypto_size: 0,
-                        ext_hs: None,
-                        ext_km: None,
-                        ext_config: None,
-                    },
-                    ..shake
-                }),
-            });
+            let resp_handshake =
+                induction_response(cookies, local_sockid, udt4, &shake, timestamp, from)?;
 
-            sock.send((resp_handshake.clone(), from)).await?;
+            sock.send((resp_handshake, from)).await?;
             debug!("Sending induction to {}", from);
 
-            return Ok((cookie, from, resp_handshake));
+            return Ok(from);
         } else {
             continue; // try again
         };
     }
 }
 
+/// The answer to the induction `shake` from `from`, with a cookie from
+/// `cookies`. Nothing has to be kept to check the cookie that comes back,
+/// so this can be sent to anyone
+pub fn induction_response(
+    cookies: &SynCookies,
+    local_sockid: SocketID,
+    udt4: bool,
+    shake: &HandshakeControlInfo,
+    timestamp: TimeStamp,
+    from: SocketAddr,
+) -> Result<Packet, Error> {
+    // https://tools.ietf.org/html/draft-gg-udt-03#page-9
+    // When the server first receives the connection request from a client,
+    // it generates a cookie value according to the client address and a
+    // secret key and sends it back to the client. The client must then send
+    // back the same cookie to the server.
+
+    let cookie = cookies.generate(&from)?;
+
+    // we expect HSv5, so upgrade it, unless speaking UDT4
+    let info = if udt4 {
+        HandshakeVSInfo::V4(UDT4_SOCKET_TYPE)
+    } else {
+        HandshakeVSInfo::V5 {
+            crypto_size: 0,
+            ext_hs: None,
+            ext_km: None,
+            ext_config: None,
+            ext_other: Vec::new(),
+        }
+    };
+
+    // construct a packet to send back, telling the caller the
+    // address it's seen at so it can put that in its conclusion
+    Ok(Packet::Control(ControlPacket {
+        timestamp,
+        dest_sockid: shake.socket_id,
+        control_type: ControlTypes::Handshake(HandshakeControlInfo {
+            syn_cookie: cookie,
+            socket_id: local_sockid,
+            peer_addr: from.ip(),
+            info,
+            ..shake.clone()
+        }),
+    }))
+}
+
 async fn get_conclusion<
     T: Stream<Item = Result<(Packet, SocketAddr), Error>>
         + Sink<(Packet, SocketAddr), Error = Error>
         + Unpin,
 >(
     sock: &mut T,
-    induction_hs: &Packet,
-    cookie: i32,
+    cookies: &SynCookies,
     local_socket_id: SocketID,
-    tsbpd_latency: Duration,
+    config: &HandshakeConfig,
     from: &SocketAddr,
-) -> Result<(Duration, HandshakeControlInfo, Packet), Error> {
+) -> Result<
+    (
+        (Duration, Duration),
+        SrtShakeFlags,
+        HandshakeControlInfo,
+        Packet,
+        (KmState, Option<PayloadCipher>, Option<String>),
+    ),
+    Error,
+> {
     // https://tools.ietf.org/html/draft-gg-udt-03#page-10
     // The server, when receiving a handshake packet and the correct cookie,
     // compares the packet size and maximum window size with its own values
@@ -150,9 +258,26 @@ async fn get_conclusion<
     // However, it must send back response packet as long as it receives any
     // further handshakes from the same client.
 
+    let HandshakeConfig {
+        recv_latency,
+        peer_latency,
+        peer_stats,
+        udt4,
+        verify_local_addr,
+        min_peer_version,
+        congest_ctrl,
+        socket_type,
+        key_length,
+        enforced_encryption,
+        ..
+    } = *config;
+
     // first packet received, wait for response (with cookie)
     loop {
         match get_packet(sock).await? {
+            (pack, from_second) if from_second == *from && pack.is_shutdown() => {
+                bail!("Peer {} aborted during handshake", from);
+            }
             (
                 Packet::Control(ControlPacket {
                     control_type: ControlTypes::Handshake(ref shake),
@@ -163,28 +288,152 @@ async fn get_conclusion<
             ) if from_second == *from => {
                 if shake.shake_type == ShakeType::Induction {
                     // it maybe missed our induction packet, so send it again
-                    sock.send((induction_hs.clone(), *from)).await?;
+                    let resp_handshake = induction_response(
+                        cookies,
+                        local_socket_id,
+                        udt4,
+                        shake,
+                        timestamp,
+                        *from,
+                    )?;
+                    sock.send((resp_handshake, *from)).await?;
                     continue;
                 } else if shake.shake_type != ShakeType::Conclusion {
                     // discard
                     info!(
                         "Expected Conclusion (-1) packet, got {:?} ({}). Discarding handshake.",
-                        shake.shake_type, shake.shake_type as i32
+                        shake.shake_type,
+                        shake.shake_type.to_i32()
                     );
                     continue;
                 }
 
-                // check that the cookie matches
-                if shake.syn_cookie != cookie {
+                // check that the cookie is one we gave this peer, recently
+                if !cookies.validate(from, shake.syn_cookie)? {
                     // wait for the next one
                     warn!(
-                        "Received invalid cookie handshake from {:?}: {}, should be {}",
-                        from, shake.syn_cookie, cookie
+                        "Received invalid or stale cookie handshake from {:?}: {}",
+                        from, shake.syn_cookie
                     );
+                    reject(
+                        sock,
+                        shake,
+                        timestamp,
+                        local_socket_id,
+                        *from,
+                        RejectReason::Rogue,
+                    )
+                    .await?;
                     continue;
                 }
 
+                // the caller puts the address it sent the conclusion to in it
+                let misaddressed = verify_local_addr
+                    .is_some_and(|local_addr| !peer_addr_matches(shake, local_addr));
+                if misaddressed {
+                    warn!(
+                        "Conclusion from {} is addressed to {}, refusing it",
+                        from, shake.peer_addr
+                    );
+                    reject(
+                        sock,
+                        shake,
+                        timestamp,
+                        local_socket_id,
+                        *from,
+                        RejectReason::Rogue,
+                    )
+                    .await?;
+                    continue;
+                }
+
+                // the caller is real, let the application decide if it's welcome
+                let listener_passphrase = config.passphrase.as_deref();
+                let (recv_latency, peer_latency, passphrase) = match &config.access_control {
+                    Some(access_control) => {
+                        match access_control.check(*from, stream_id(&shake.info)).await {
+                            AccessDecision::Accept(params) => (
+                                params.latency_or(recv_latency),
+                                params.latency_or(peer_latency),
+                                params.passphrase_or(listener_passphrase),
+                            ),
+                            AccessDecision::Reject(reason) => {
+                                reject(sock, shake, timestamp, local_socket_id, *from, reason)
+                                    .await?;
+                                return Err(reason.into());
+                            }
+                        }
+                    }
+                    None => (
+                        recv_latency,
+                        peer_latency,
+                        listener_passphrase.map(String::from),
+                    ),
+                };
+
+                // select the smaller packet size and max window size, and
+                // tell the caller about them in the response
+                let shake = &HandshakeControlInfo {
+                    max_packet_size: u32::min(shake.max_packet_size, MAX_PACKET_SIZE),
+                    max_flow_size: u32::min(shake.max_flow_size, MAX_FLOW_SIZE),
+                    ..shake.clone()
+                };
+
+                if udt4 {
+                    if shake.info != HandshakeVSInfo::V4(UDT4_SOCKET_TYPE) {
+                        reject(
+                            sock,
+                            shake,
+                            timestamp,
+                            local_socket_id,
+                            *from,
+                            RejectReason::Rogue,
+                        )
+                        .await?;
+                        bail!(
+                            "Conclusion was {:?}, expected a UDT4 {:?} socket",
+                            shake.info,
+                            UDT4_SOCKET_TYPE
+                        );
+                    }
+
+                    info!(
+                        "Cookie was correct, UDT4 connection established to {:?}",
+                        from
+                    );
+
+                    // UDT4 has no extensions, so just confirm what the peer sent
+                    let resp_handshake = Packet::Control(ControlPacket {
+                        timestamp,
+                        dest_sockid: shake.socket_id,
+                        control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                            socket_id: local_socket_id,
+                            peer_addr: from.ip(),
+                            ..shake.clone()
+                        }),
+                    });
+                    sock.send((resp_handshake.clone(), *from)).await?;
+
+                    // nothing to negotiate, so keep the configured latency
+                    return Ok((
+                        (recv_latency, peer_latency),
+                        SrtShakeFlags::empty(),
+                        shake.clone(),
+                        resp_handshake,
+                        (KmState::Unsecured, None, None),
+                    ));
+                }
+
                 if shake.info.version() != 5 {
+                    reject(
+                        sock,
+                        shake,
+                        timestamp,
+                        local_socket_id,
+                        *from,
+                        RejectReason::Version,
+                    )
+                    .await?;
                     bail!("Conclusion was HSv4, not HSv5, terminating connection");
                 }
 
@@ -201,23 +450,133 @@ async fn get_conclusion<
                     bail!("Did not get SRT handshake request in conclusion handshake packet, using latency from this end");
                 };
 
-                let latency = Duration::max(srt_handshake.latency, tsbpd_latency);
+                if let Some(min) = min_peer_version {
+                    if srt_handshake.version < min {
+                        warn!(
+                            "{} has SRT version {}, {} is required",
+                            from, srt_handshake.version, min
+                        );
+                        reject(
+                            sock,
+                            shake,
+                            timestamp,
+                            local_socket_id,
+                            *from,
+                            RejectReason::Version,
+                        )
+                        .await?;
+                        return Err(RejectReason::Version.into());
+                    }
+                }
+
+                let peer_congest_ctrl = congest_ctrl_of(&shake.info);
+                if peer_congest_ctrl != Some(congest_ctrl) {
+                    warn!(
+                        "{} uses congestion control {:?}, expected {:?}",
+                        from, peer_congest_ctrl, congest_ctrl
+                    );
+                    reject(
+                        sock,
+                        shake,
+                        timestamp,
+                        local_socket_id,
+                        *from,
+                        RejectReason::Congestion,
+                    )
+                    .await?;
+                    return Err(RejectReason::Congestion.into());
+                }
+
+                let peer_socket_type = socket_type_of(srt_handshake.flags);
+                if peer_socket_type != socket_type {
+                    warn!(
+                        "{} uses socket type {:?}, expected {:?}",
+                        from, peer_socket_type, socket_type
+                    );
+                    reject(
+                        sock,
+                        shake,
+                        timestamp,
+                        local_socket_id,
+                        *from,
+                        RejectReason::MessageApi,
+                    )
+                    .await?;
+                    return Err(RejectReason::MessageApi.into());
+                }
+
+                // each receiver gets the larger of its own latency and the
+                // latency the other end asks for it to have
+                let latency = Duration::max(recv_latency, srt_handshake.peer_latency);
+                let peer_latency = Duration::max(peer_latency, srt_handshake.latency);
+
+                // only agree to exchange stats if both sides want to
+                let mut flags = srt_handshake.flags;
+                flags.set(
+                    SrtShakeFlags::PEERSTATS,
+                    peer_stats && flags.contains(SrtShakeFlags::PEERSTATS),
+                );
+
+                // unwrap the caller's key, and tell it if that worked
+                let request = match &shake.info {
+                    HandshakeVSInfo::V5 { ext_km, .. } => ext_km.as_ref(),
+                    HandshakeVSInfo::V4(_) => None,
+                };
+                // a key of another length than the one set is refused, telling
+                // the caller the length this end uses
+                let mismatch = match (&passphrase, key_length, key_exchange::key_size(request)) {
+                    (Some(_), Some(local), Some(peer)) if local != peer => {
+                        warn!("{} sent a {} byte key, {} is required", from, peer, local);
+                        Some(KeyLengthMismatch {
+                            remote: *from,
+                            local,
+                            peer,
+                        })
+                    }
+                    _ => None,
+                };
+                let (km_state, ext_km, crypto) = match mismatch {
+                    Some(_) => (
+                        KmState::BadSecret,
+                        Some(SrtControlPacket::KeyManagerError(KmState::BadSecret)),
+                        None,
+                    ),
+                    None => key_exchange::respond(
+                        passphrase.as_deref(),
+                        key_length.unwrap_or(DEFAULT_KEY_SIZE),
+                        request,
+                    ),
+                };
+                if enforced_encryption
+                    && (passphrase.is_some() || request.is_some())
+                    && km_state != KmState::Secured
+                {
+                    warn!("Key exchange with {} failed with {:?}", from, km_state);
+                    let reason = key_exchange::rejection(km_state);
+                    reject(sock, shake, timestamp, local_socket_id, *from, reason).await?;
+                    return Err(reason.into());
+                }
 
-                // construct a packet to send back
+                // construct a packet to send back, addressed to the caller
+                // like the reference implementation does
                 let resp_handshake = Packet::Control(ControlPacket {
                     timestamp,
                     dest_sockid: shake.socket_id,
                     control_type: ControlTypes::Handshake(HandshakeControlInfo {
-                        syn_cookie: cookie,
                         socket_id: local_socket_id,
+                        peer_addr: from.ip(),
                         info: HandshakeVSInfo::V5 {
                             ext_hs: Some(SrtControlPacket::HandshakeResponse(SrtHandshake {
+                                version: SrtVersion::CURRENT,
+                                // the receiver always sends periodic NAK reports
+                                flags: flags | SrtShakeFlags::NAKREPORT,
+                                peer_latency,
                                 latency,
-                                ..srt_handshake
                             })),
-                            ext_km: None,
-                            ext_config: None,
-                            crypto_size,
+                            ext_km,
+                            ext_config: congest_ctrl_extension(congest_ctrl),
+                            ext_other: Vec::new(),
+                            crypto_size: mismatch.map_or(crypto_size, |m| m.local),
                         },
                         ..*shake
                     }),
@@ -225,10 +584,317 @@ async fn get_conclusion<
 
                 // send the packet
                 sock.send((resp_handshake.clone(), *from)).await?;
+                if let Some(mismatch) = mismatch {
+                    return Err(mismatch.into());
+                }
 
-                return Ok((latency, shake.clone(), resp_handshake));
+                return Ok((
+                    (latency, peer_latency),
+                    flags,
+                    shake.clone(),
+                    resp_handshake,
+                    (km_state, crypto, passphrase),
+                ));
             }
             _ => continue,
         }
     }
 }
+
+/// The stream ID a caller asked for in its conclusion
+fn stream_id(info: &HandshakeVSInfo) -> Option<String> {
+    info.config_extensions().find_map(|ext| match ext {
+        SrtControlPacket::StreamId(id) => Some(id.clone()),
+        _ => None,
+    })
+}
+
+/// Tell the peer that sent `shake` that its connection was refused, and why
+async fn reject<T>(
+    sock: &mut T,
+    shake: &HandshakeControlInfo,
+    timestamp: TimeStamp,
+    local_socket_id: SocketID,
+    to: SocketAddr,
+    reason: RejectReason,
+) -> Result<(), Error>
+where
+    T: Sink<(Packet, SocketAddr), Error = Error> + Unpin,
+{
+    info!("Rejecting connection from {}: {}", to, reason);
+
+    let rejection = Packet::Control(ControlPacket {
+        timestamp,
+        dest_sockid: shake.socket_id,
+        control_type: ControlTypes::Handshake(HandshakeControlInfo {
+            shake_type: ShakeType::Rejection(reason),
+            socket_id: local_socket_id,
+            info: HandshakeVSInfo::V5 {
+                crypto_size: 0,
+                ext_hs: None,
+                ext_km: None,
+                ext_config: None,
+                ext_other: Vec::new(),
+            },
+            ..shake.clone()
+        }),
+    });
+
+    sock.send((rejection, to)).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::listen;
+    use crate::channel::Channel;
+    use crate::packet::{
+        ControlPacket, ControlTypes, HandshakeControlInfo, HandshakeVSInfo, Packet, RejectReason,
+        ShakeType, SocketType, SrtControlPacket, SrtHandshake, SrtShakeFlags,
+    };
+    use crate::pending_connection::HandshakeConfig;
+    use crate::socket::create_bidrectional_srt;
+    use crate::{SeqNumber, SocketID, SrtCongestCtrl, SrtVersion, TimeStamp};
+
+    use futures::{join, SinkExt, TryStreamExt};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    // the address the listener is bound to
+    const LISTENER_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+
+    // checking conclusions are addressed to the listener
+    fn config() -> HandshakeConfig {
+        HandshakeConfig {
+            verify_local_addr: Some(LISTENER_IP),
+            ..HandshakeConfig::new(Duration::from_millis(20))
+        }
+    }
+
+    fn handshake(info: HandshakeControlInfo) -> Packet {
+        Packet::Control(ControlPacket {
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(0),
+            control_type: ControlTypes::Handshake(info),
+        })
+    }
+
+    // the induction a libsrt caller sends, addressed to the listener
+    fn induction() -> HandshakeControlInfo {
+        HandshakeControlInfo {
+            init_seq_num: SeqNumber::new_truncate(1000),
+            max_packet_size: 1316,
+            max_flow_size: 25600,
+            shake_type: ShakeType::Induction,
+            socket_id: SocketID(4321),
+            syn_cookie: 0,
+            peer_addr: LISTENER_IP,
+            info: HandshakeVSInfo::V4(SocketType::Datagram),
+        }
+    }
+
+    // the conclusion a libsrt caller sends, once it has a cookie
+    fn conclusion(syn_cookie: i32) -> HandshakeControlInfo {
+        HandshakeControlInfo {
+            shake_type: ShakeType::Conclusion,
+            syn_cookie,
+            info: HandshakeVSInfo::V5 {
+                crypto_size: 0,
+                ext_hs: Some(SrtControlPacket::HandshakeRequest(SrtHandshake {
+                    version: SrtVersion::new(1, 3, 1),
+                    flags: SrtShakeFlags::TSBPDSND
+                        | SrtShakeFlags::TSBPDRCV
+                        | SrtShakeFlags::REXMITFLG,
+                    peer_latency: Duration::from_millis(80),
+                    latency: Duration::from_millis(50),
+                })),
+                ext_km: None,
+                ext_config: None,
+                ext_other: Vec::new(),
+            },
+            ..induction()
+        }
+    }
+
+    async fn next_handshake(sock: &mut Channel<(Packet, SocketAddr)>) -> HandshakeControlInfo {
+        match sock.try_next().await.unwrap().unwrap() {
+            (
+                Packet::Control(ControlPacket {
+                    control_type: ControlTypes::Handshake(info),
+                    dest_sockid: SocketID(4321),
+                    ..
+                }),
+                _,
+            ) => info,
+            (p, _) => panic!("Expected handshake to the caller, got {:?}", p),
+        }
+    }
+
+    #[tokio::test]
+    async fn induction_then_conclusion() {
+        let remote: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let (mut listen_side, mut conn_side) = Channel::<(Packet, SocketAddr)>::channel(10);
+
+        // play the caller, which like libsrt puts the listener's address in
+        // its handshakes rather than its own
+        let caller = async move {
+            conn_side
+                .send((handshake(induction()), remote))
+                .await
+                .unwrap();
+
+            // the listener upgrades to HSv5 and hands out a cookie
+            let resp = next_handshake(&mut conn_side).await;
+            assert_eq!(resp.shake_type, ShakeType::Induction);
+            assert_eq!(resp.info.version(), 5);
+            assert_eq!(resp.socket_id, SocketID(1234));
+
+            conn_side
+                .send((handshake(conclusion(resp.syn_cookie)), remote))
+                .await
+                .unwrap();
+
+            next_handshake(&mut conn_side).await
+        };
+
+        let config = config();
+        let (conn, resp) = join!(listen(&mut listen_side, SocketID(1234), &config), caller);
+        let conn = conn.unwrap();
+
+        // the response has the smaller sizes, this end's SRT version, and
+        // the larger latency in each direction
+        assert_eq!(resp.shake_type, ShakeType::Conclusion);
+        assert_eq!(resp.max_packet_size, 1316);
+        assert_eq!(resp.max_flow_size, 8192);
+        match resp.info {
+            HandshakeVSInfo::V5 {
+                ext_hs: Some(SrtControlPacket::HandshakeResponse(hs)),
+                ..
+            } => {
+                assert_eq!(hs.version, SrtVersion::CURRENT);
+                assert_eq!(hs.latency, Duration::from_millis(80));
+                assert_eq!(hs.peer_latency, Duration::from_millis(50));
+            }
+            info => panic!("Expected a HSRSP, got {:?}", info),
+        }
+
+        assert_eq!(conn.settings.remote_sockid, SocketID(4321));
+        assert_eq!(conn.settings.init_seq_num, SeqNumber::new_truncate(1000));
+        assert_eq!(conn.settings.max_packet_size, 1316);
+        assert_eq!(conn.settings.max_flow_size, 8192);
+        assert_eq!(conn.settings.tsbpd_latency, Duration::from_millis(80));
+        assert_eq!(conn.settings.peer_tsbpd_latency, Duration::from_millis(50));
+        assert_eq!(conn.settings.peer_version, Some(SrtVersion::new(1, 3, 1)));
+        assert_eq!(
+            conn.settings.srt_flags,
+            SrtShakeFlags::TSBPDSND | SrtShakeFlags::TSBPDRCV | SrtShakeFlags::REXMITFLG
+        );
+    }
+
+    #[tokio::test]
+    async fn bad_cookie_rejected() {
+        let remote: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let (mut listen_side, mut conn_side) = Channel::<(Packet, SocketAddr)>::channel(10);
+
+        tokio::spawn(async move {
+            let _ = listen(&mut listen_side, SocketID(1234), &config()).await;
+        });
+
+        conn_side
+            .send((handshake(induction()), remote))
+            .await
+            .unwrap();
+        let resp = next_handshake(&mut conn_side).await;
+
+        // a conclusion with a cookie the listener never handed out
+        conn_side
+            .send((
+                handshake(HandshakeControlInfo {
+                    shake_type: ShakeType::Conclusion,
+                    syn_cookie: resp.syn_cookie.wrapping_add(1),
+                    info: resp.info,
+                    ..induction()
+                }),
+                remote,
+            ))
+            .await
+            .unwrap();
+
+        let rejection = next_handshake(&mut conn_side).await;
+        assert_eq!(
+            rejection.shake_type,
+            ShakeType::Rejection(RejectReason::Rogue)
+        );
+    }
+
+    #[tokio::test]
+    async fn misaddressed_conclusion_rejected() {
+        let remote: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let (mut listen_side, mut conn_side) = Channel::<(Packet, SocketAddr)>::channel(10);
+
+        tokio::spawn(async move {
+            let _ = listen(&mut listen_side, SocketID(1234), &config()).await;
+        });
+
+        conn_side
+            .send((handshake(induction()), remote))
+            .await
+            .unwrap();
+        // the response is addressed to the caller
+        let resp = next_handshake(&mut conn_side).await;
+        assert_eq!(resp.peer_addr, remote.ip());
+
+        // the right cookie, but a conclusion addressed to another listener
+        conn_side
+            .send((
+                handshake(HandshakeControlInfo {
+                    peer_addr: "10.0.0.1".parse().unwrap(),
+                    ..conclusion(resp.syn_cookie)
+                }),
+                remote,
+            ))
+            .await
+            .unwrap();
+
+        let rejection = next_handshake(&mut conn_side).await;
+        assert_eq!(
+            rejection.shake_type,
+            ShakeType::Rejection(RejectReason::Rogue)
+        );
+    }
+
+    #[tokio::test]
+    async fn conclusion_answered_after_connecting() {
+        let remote: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let (mut listen_side, mut conn_side) = Channel::<(Packet, SocketAddr)>::channel(10);
+
+        let listener = tokio::spawn(async move {
+            let conn = listen(&mut listen_side, SocketID(1234), &config())
+                .await
+                .unwrap();
+            create_bidrectional_srt(
+                listen_side,
+                conn,
+                Box::new(SrtCongestCtrl::default()),
+                None,
+                false,
+            )
+        });
+
+        conn_side
+            .send((handshake(induction()), remote))
+            .await
+            .unwrap();
+        let resp = next_handshake(&mut conn_side).await;
+
+        let conclusion = handshake(conclusion(resp.syn_cookie));
+        conn_side.send((conclusion.clone(), remote)).await.unwrap();
+        let first = next_handshake(&mut conn_side).await;
+
+        // pretend that response was lost. The listener is connected, but
+        // nothing is polling its socket, which still has to answer
+        let _sock = listener.await.unwrap();
+
+        conn_side.send((conclusion, remote)).await.unwrap();
+        assert_eq!(next_handshake(&mut conn_side).await, first);
+    }
+}
@@ -0,0 +1,122 @@
+use std::future::Future;
+use std::time::Duration;
+
+use failure::{format_err, Error};
+use futures::stream::FuturesUnordered;
+use futures::{future, select, FutureExt, StreamExt};
+use log::warn;
+use tokio::time::delay_for;
+
+/// Race connection attempts happy eyeballs style, returning the first to
+/// succeed
+///
+/// The attempts start in order, each one `delay` after the one before, or
+/// straight away if all the ones before have failed. If they all fail, the
+/// last error is returned
+///
+/// The ones still running when one succeeds are left to finish in the
+/// background, so they can tell their peer they gave up. They should give
+/// up once the race is over, the ones yet to start are dropped
+pub async fn staggered_race<F, T>(attempts: Vec<F>, delay: Duration) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>> + Send + 'static,
+    T: Send + 'static,
+{
+    let mut waiting = attempts.into_iter();
+    let mut running = FuturesUnordered::new();
+    let mut last_err = None;
+
+    loop {
+        if running.is_empty() {
+            match waiting.next() {
+                Some(attempt) => running.push(attempt),
+                None => {
+                    return Err(last_err.unwrap_or_else(|| format_err!("Nothing to connect to")))
+                }
+            }
+        }
+
+        let res = if waiting.len() == 0 {
+            running.next().await
+        } else {
+            select! {
+                res = running.next() => res,
+                _ = delay_for(delay).fuse() => {
+                    running.extend(waiting.next());
+                    continue;
+                }
+            }
+        };
+
+        match res {
+            Some(Ok(conn)) => {
+                tokio::spawn(running.for_each(|_| future::ready(())));
+                return Ok(conn);
+            }
+            Some(Err(e)) => {
+                warn!("Connection attempt failed: {}", e);
+                last_err = Some(e);
+
+                // don't wait for the delay when one has failed
+                running.extend(waiting.next());
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::staggered_race;
+
+    use failure::{bail, Error};
+    use futures::future::{pending, BoxFuture, FutureExt};
+    use std::time::Duration;
+    use tokio::time::{self, delay_for, Instant};
+
+    fn attempt(after: u64, succeeds: bool) -> BoxFuture<'static, Result<(u64, Instant), Error>> {
+        async move {
+            delay_for(Duration::from_millis(after)).await;
+            if !succeeds {
+                bail!("Attempt failed");
+            }
+            Ok((after, Instant::now()))
+        }
+        .boxed()
+    }
+
+    #[tokio::test]
+    async fn later_attempt_wins() {
+        time::pause();
+        let start = Instant::now();
+
+        // the first never finishes, the second starts 250ms in
+        let attempts = vec![pending().boxed(), attempt(100, true)];
+        let (_, done) = staggered_race(attempts, Duration::from_millis(250))
+            .await
+            .unwrap();
+        assert_eq!((done - start).as_millis(), 350);
+    }
+
+    #[tokio::test]
+    async fn failure_starts_next_attempt() {
+        time::pause();
+        let start = Instant::now();
+
+        let attempts = vec![attempt(50, false), attempt(50, false), attempt(10, true)];
+        let (after, done) = staggered_race(attempts, Duration::from_millis(250))
+            .await
+            .unwrap();
+        assert_eq!(after, 10);
+        assert_eq!((done - start).as_millis(), 110);
+    }
+
+    #[tokio::test]
+    async fn all_fail() {
+        time::pause();
+
+        let attempts = vec![attempt(50, false), attempt(400, false)];
+        let res = staggered_race(attempts, Duration::from_millis(250)).await;
+        assert_eq!(res.unwrap_err().to_string(), "Attempt failed");
+    }
+}
@@ -1,167 +1,643 @@
-use std::cmp;
+use std::cmp::Ordering;
 use std::net::{IpAddr, SocketAddr};
 use std::time::{Duration, Instant};
 
 use failure::{bail, Error};
-use futures::{select, FutureExt, Sink, SinkExt, Stream, TryStreamExt};
-use log::warn;
-use tokio::time::interval;
+use futures::{select, FutureExt, Sink, SinkExt, Stream};
+use log::{debug, info, warn};
 
-use crate::packet::{ControlTypes, HandshakeControlInfo, HandshakeVSInfo, ShakeType, SocketType};
-use crate::{Connection, ConnectionSettings, ControlPacket, Packet, SeqNumber, SocketID};
+use super::{peer_addr_matches, HandshakeConfig, ResendTimer, MAX_FLOW_SIZE, MAX_PACKET_SIZE};
+use crate::packet::{
+    ControlTypes, HandshakeControlInfo, HandshakeVSInfo, KmState, ShakeType, SocketType,
+    SrtControlPacket, SrtHandshake, SrtShakeFlags,
+};
+use crate::util::get_packet;
+use crate::{
+    CongestCtrlType, Connection, ConnectionSettings, ControlPacket, Packet, SeqNumber, SocketID,
+    SrtVersion, TimeStamp,
+};
 
+/// Which side of the handshake this end takes, decided by the cookie contest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    /// Sends the conclusion with the HSREQ, and confirms the HSRSP with an agreement
+    Initiator,
+
+    /// Answers the initiator's conclusion with the HSRSP
+    Responder,
+}
+
+/// Both ends wave with a random cookie, and the one with the larger cookie
+/// initiates. `None` on a draw, where a new cookie has to be picked
+fn cookie_contest(ours: i32, theirs: i32) -> Option<Role> {
+    match ours.cmp(&theirs) {
+        Ordering::Greater => Some(Role::Initiator),
+        Ordering::Less => Some(Role::Responder),
+        Ordering::Equal => None,
+    }
+}
+
+/// Do a HSv5 rendezvous with `remote_public`, which must be doing the same
+///
+/// Both ends send waveahands until they have each other's cookie, and the
+/// cookie contest decides which of them initiates, so the two never both
+/// think they've finished with different settings. With `verify_local_addr`,
+/// handshakes the peer addressed to another address are ignored
+///
+/// Only the latencies and `verify_local_addr` of `config` apply
 pub async fn rendezvous<T>(
     sock: &mut T,
     local_socket_id: SocketID,
-    local_addr: IpAddr,
     remote_public: SocketAddr,
-    tsbpd_latency: Duration,
+    config: &HandshakeConfig,
 ) -> Result<Connection, Error>
 where
     T: Stream<Item = Result<(Packet, SocketAddr), Error>>
         + Sink<(Packet, SocketAddr), Error = Error>
         + Unpin,
 {
-    let mut snd_interval = interval(Duration::from_millis(100));
-    let mut init_seq_num = rand::random();
-
-    let (info, packet) = loop {
-        let opt_pack_addr = select! {
-            _ = snd_interval.tick().fuse() => {
-                send_packet(
-                    sock,
-                    init_seq_num,
-                    local_socket_id,
-                    local_addr,
-                    remote_public,
-                )
-                .await?;
+    let (recv_latency, peer_latency) = (config.recv_latency, config.peer_latency);
+
+    let init_seq_num = rand::random();
+    let mut cookie = rand::random();
+    let mut role = None;
+
+    // resent until the peer answers: the waveahand, then for the initiator
+    // the conclusion
+    let remote_addr = remote_public.ip();
+    let mut pending = waveahand(init_seq_num, local_socket_id, remote_addr, cookie);
+    sock.send((pending.clone(), remote_public)).await?;
+    let mut resend = ResendTimer::new();
+
+    // data the responder sent after it finished, before the HSRSP arrived
+    let mut early_data = Vec::new();
+
+    loop {
+        let (packet, from) = select! {
+            _ = resend.tick().fuse() => {
+                sock.send((pending.clone(), remote_public)).await?;
                 continue
             },
-            res = sock.try_next().fuse() => res?
+            res = get_packet(sock).fuse() => res?
         };
-        if let Some((packet, from_addr)) = opt_pack_addr {
-            if from_addr != remote_public {
+        if from != remote_public {
+            warn!(
+                "Received handshake packet from unrecognized location: {}",
+                from
+            );
+            continue;
+        }
+        if packet.is_shutdown() {
+            bail!("Peer {} aborted during handshake", remote_public);
+        }
+
+        let info = match packet {
+            Packet::Control(ControlPacket {
+                control_type: ControlTypes::Handshake(info),
+                ..
+            }) => info,
+            Packet::Data(ref data) if data.dest_sockid == local_socket_id => {
+                early_data.push((packet, from));
+                continue;
+            }
+            _ => {
+                warn!("Received non-handshake packet when negotiating rendezvous");
+                continue;
+            }
+        };
+
+        // behind a NAT the peer sends to this end's public address, so this
+        // may need turning off
+        let misaddressed = config
+            .verify_local_addr
+            .is_some_and(|local_addr| !peer_addr_matches(&info, local_addr));
+        if misaddressed {
+            warn!(
+                "Handshake from {} is addressed to {}, ignoring it",
+                from, info.peer_addr
+            );
+            continue;
+        }
+
+        match info.shake_type {
+            ShakeType::Rejection(reason) => return Err(reason.into()),
+            ShakeType::Induction => {
+                warn!("Received induction handshake while initiating a rendezvous connection. Maybe you tried to pair connect with rendezvous?");
+                continue;
+            }
+            _ if info.info.version() != 5 => {
                 warn!(
-                    "Received handshake packet from unrecognized location: {}",
-                    from_addr
+                    "Received HSv4 handshake from {}, rendezvous needs HSv5",
+                    from
                 );
                 continue;
             }
+            _ => {}
+        }
 
-            let info = match packet {
-                Packet::Control(ControlPacket {
-                    control_type: ControlTypes::Handshake(info),
-                    ..
-                }) => info,
-                _ => {
-                    warn!("Received non-handshake packet when negotiating rendezvous");
+        // every handshake carries the sender's cookie, so whichever arrives
+        // first decides
+        let role = match role {
+            Some(role) => role,
+            None => match cookie_contest(cookie, info.syn_cookie) {
+                Some(decided) => {
+                    debug!("Cookie contest with {} made this end {:?}", from, decided);
+                    role = Some(decided);
+                    if decided == Role::Initiator {
+                        pending = initiator_conclusion(
+                            init_seq_num,
+                            local_socket_id,
+                            remote_addr,
+                            cookie,
+                            info.socket_id,
+                            (recv_latency, peer_latency),
+                        );
+                    }
+                    decided
+                }
+                None => {
+                    debug!("Cookie contest with {} was a draw, picking another", from);
+                    cookie = rand::random();
+                    pending = waveahand(init_seq_num, local_socket_id, remote_addr, cookie);
+                    sock.send((pending.clone(), remote_public)).await?;
                     continue;
                 }
-            };
+            },
+        };
 
-            // update our init seq num
-            init_seq_num = cmp::max(info.init_seq_num, init_seq_num);
-
-            match info.shake_type {
-                ShakeType::Waveahand => {
-                    // we now respond with a Conclusion packet
-                    let new_packet = Packet::Control(ControlPacket {
-                        dest_sockid: info.socket_id,
-                        timestamp: 0, // TODO: deal with timestamp
-                        control_type: ControlTypes::Handshake(HandshakeControlInfo {
-                            shake_type: ShakeType::Conclusion,
-                            socket_id: local_socket_id,
-                            peer_addr: local_addr,
-                            init_seq_num,
-                            ..info
-                        }),
-                    });
+        let ext_hs = match &info.info {
+            HandshakeVSInfo::V5 { ext_hs, .. } => ext_hs.clone(),
+            HandshakeVSInfo::V4(_) => None,
+        };
+        match (role, info.shake_type, ext_hs) {
+            // the peer may not have this end's cookie yet
+            (_, ShakeType::Waveahand, _) => {
+                sock.send((pending.clone(), remote_public)).await?;
+            }
+            (
+                Role::Initiator,
+                ShakeType::Conclusion,
+                Some(SrtControlPacket::HandshakeResponse(hs)),
+            ) => {
+                let agreement = Packet::Control(ControlPacket {
+                    dest_sockid: info.socket_id,
+                    timestamp: TimeStamp(0),
+                    control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                        shake_type: ShakeType::Agreement,
+                        socket_id: local_socket_id,
+                        peer_addr: remote_addr,
+                        syn_cookie: cookie,
+                        info: HandshakeVSInfo::V5 {
+                            crypto_size: 0,
+                            ext_hs: None,
+                            ext_km: None,
+                            ext_config: None,
+                            ext_other: Vec::new(),
+                        },
+                        ..info.clone()
+                    }),
+                });
+                sock.send((agreement.clone(), remote_public)).await?;
 
-                    sock.send((new_packet, remote_public)).await?;
-                }
-                ShakeType::Conclusion => {
-                    // connection is created, send Agreement back
-                    // TODO: if this packet gets dropped, this connection will never init. This is a pretty big bug.
-                    let new_packet = Packet::Control(ControlPacket {
-                        dest_sockid: info.socket_id,
-                        timestamp: 0, // TODO: deal with timestamp,
-                        control_type: ControlTypes::Handshake(HandshakeControlInfo {
-                            shake_type: ShakeType::Agreement,
-                            socket_id: local_socket_id,
-                            peer_addr: local_addr,
-                            ..info.clone()
-                        }),
-                    });
-
-                    sock.send((new_packet.clone(), remote_public)).await?;
-
-                    break (info, Some(new_packet));
-                }
-                ShakeType::Agreement => {
-                    // connection is established
-                    break (info, None);
-                }
-                ShakeType::Induction => {
-                    warn!("Received induction handshake while initiating a rendezvous connection. Maybe you tried to pair connect with rendezvous?");
-                }
+                info!(
+                    "Rendezvous with {} established as initiator, latency {:?}",
+                    remote_public, hs.peer_latency
+                );
+
+                // the HSRSP's peer latency is what this end receives with
+                return Ok(connection(
+                    &info,
+                    remote_public,
+                    local_socket_id,
+                    init_seq_num,
+                    (hs.peer_latency, hs.latency),
+                    &hs,
+                    hs.flags,
+                    agreement,
+                    early_data,
+                ));
+            }
+            (
+                Role::Responder,
+                ShakeType::Conclusion,
+                Some(SrtControlPacket::HandshakeRequest(hs)),
+            ) => {
+                // like a listener, each receiver gets the larger of its own
+                // latency and what the other end asks for
+                let latency = Duration::max(recv_latency, hs.peer_latency);
+                let peer_latency = Duration::max(peer_latency, hs.latency);
+
+                let mut flags = hs.flags;
+                flags.remove(SrtShakeFlags::PEERSTATS);
+
+                let response = Packet::Control(ControlPacket {
+                    dest_sockid: info.socket_id,
+                    timestamp: TimeStamp(0),
+                    control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                        max_packet_size: u32::min(info.max_packet_size, MAX_PACKET_SIZE),
+                        max_flow_size: u32::min(info.max_flow_size, MAX_FLOW_SIZE),
+                        socket_id: local_socket_id,
+                        peer_addr: remote_addr,
+                        syn_cookie: cookie,
+                        info: HandshakeVSInfo::V5 {
+                            crypto_size: 0,
+                            ext_hs: Some(SrtControlPacket::HandshakeResponse(SrtHandshake {
+                                version: SrtVersion::CURRENT,
+                                // the receiver always sends periodic NAK reports
+                                flags: flags | SrtShakeFlags::NAKREPORT,
+                                peer_latency,
+                                latency,
+                            })),
+                            ext_km: None,
+                            ext_config: None,
+                            ext_other: Vec::new(),
+                        },
+                        ..info.clone()
+                    }),
+                });
+                sock.send((response.clone(), remote_public)).await?;
+
+                info!(
+                    "Rendezvous with {} established as responder, latency {:?}",
+                    remote_public, latency
+                );
+
+                // the initiator's sequence number is used for the connection
+                return Ok(connection(
+                    &info,
+                    remote_public,
+                    local_socket_id,
+                    info.init_seq_num,
+                    (latency, peer_latency),
+                    &hs,
+                    flags,
+                    response,
+                    early_data,
+                ));
             }
-        } else {
-            bail!("Underlying stream ended");
+            (role, shake_type, _) => debug!(
+                "Ignoring {:?} from {} as {:?}",
+                shake_type, remote_public, role
+            ),
         }
-    };
-    Ok(Connection {
+    }
+}
+
+/// The connection once the handshake is done, which answers the peer's
+/// conclusion with `answer` in case the last one was lost
+#[allow(clippy::too_many_arguments)]
+fn connection(
+    info: &HandshakeControlInfo,
+    remote: SocketAddr,
+    local_socket_id: SocketID,
+    init_seq_num: SeqNumber,
+    (tsbpd_latency, peer_tsbpd_latency): (Duration, Duration),
+    peer_hs: &SrtHandshake,
+    srt_flags: SrtShakeFlags,
+    answer: Packet,
+    early_data: Vec<(Packet, SocketAddr)>,
+) -> Connection {
+    Connection {
         settings: ConnectionSettings {
-            remote: remote_public,
-            max_flow_size: info.max_flow_size,
-            max_packet_size: info.max_packet_size,
-            init_seq_num: info.init_seq_num,
+            remote,
+            max_flow_size: u32::min(info.max_flow_size, MAX_FLOW_SIZE),
+            max_packet_size: u32::min(info.max_packet_size, MAX_PACKET_SIZE),
+            peer_version: Some(peer_hs.version),
+            srt_flags,
+            key_size: 0,
+            km_state: KmState::Unsecured,
+            init_seq_num,
             socket_start_time: Instant::now(), // restamp the socket start time, so TSBPD works correctly
             local_sockid: local_socket_id,
             remote_sockid: info.socket_id,
-            tsbpd_latency, // TODO: needs to be send in the handshakes
+            tsbpd_latency,
+            peer_tsbpd_latency,
+            peer_stats: false,
+            congest_ctrl: CongestCtrlType::Live,
+            socket_type: SocketType::Datagram,
+            udt4: false,
         },
-        hs_returner: Box::new(move |pack| {
-            if let Packet::Control(ControlPacket {
-                control_type: ControlTypes::Handshake(info),
+        hs_returner: Box::new(move |pack| match pack {
+            Packet::Control(ControlPacket {
+                control_type:
+                    ControlTypes::Handshake(HandshakeControlInfo {
+                        shake_type: ShakeType::Conclusion,
+                        ..
+                    }),
                 ..
-            }) = pack
-            {
-                match info.shake_type {
-                    ShakeType::Conclusion => packet.clone(),
-                    _ => None,
-                }
-            } else {
-                None
-            }
+            }) => Some(answer.clone()),
+            _ => None,
         }),
-    })
+        early_data,
+        stream_id: None,
+        #[cfg(feature = "crypto")]
+        crypto: None,
+        #[cfg(feature = "crypto")]
+        passphrase: None,
+    }
 }
 
-async fn send_packet<T>(
-    sock: &mut T,
+/// The waveahand that starts the rendezvous, with this end's cookie
+fn waveahand(
     init_seq_num: SeqNumber,
     local_socket_id: SocketID,
-    local_addr: IpAddr,
-    remote_public: SocketAddr,
-) -> Result<(), Error>
-where
-    T: Sink<(Packet, SocketAddr), Error = Error> + Unpin,
-{
-    let pack = Packet::Control(ControlPacket {
-        timestamp: 0, // TODO: is this right?
+    remote_addr: IpAddr,
+    cookie: i32,
+) -> Packet {
+    Packet::Control(ControlPacket {
+        timestamp: TimeStamp(0),
         dest_sockid: SocketID(0),
         control_type: ControlTypes::Handshake(HandshakeControlInfo {
             init_seq_num,
-            max_packet_size: 1500, // TODO: take as a parameter
-            max_flow_size: 8192,   // TODO: take as a parameter
+            max_packet_size: MAX_PACKET_SIZE,
+            max_flow_size: MAX_FLOW_SIZE,
             socket_id: local_socket_id,
-            shake_type: ShakeType::Waveahand, // as per the spec, the first packet is waveahand
-            peer_addr: local_addr,
-            syn_cookie: 0,
-            info: HandshakeVSInfo::V4(SocketType::Datagram),
+            shake_type: ShakeType::Waveahand,
+            peer_addr: remote_addr,
+            syn_cookie: cookie,
+            info: HandshakeVSInfo::V5 {
+                crypto_size: 0,
+                ext_hs: None,
+                ext_km: None,
+                ext_config: None,
+                ext_other: Vec::new(),
+            },
         }),
-    });
-    sock.send((pack, remote_public)).await?;
-    Ok(())
+    })
+}
+
+/// The initiator's conclusion, with the HSREQ
+fn initiator_conclusion(
+    init_seq_num: SeqNumber,
+    local_socket_id: SocketID,
+    remote_addr: IpAddr,
+    cookie: i32,
+    remote_socket_id: SocketID,
+    (recv_latency, peer_latency): (Duration, Duration),
+) -> Packet {
+    Packet::Control(ControlPacket {
+        timestamp: TimeStamp(0),
+        dest_sockid: remote_socket_id,
+        control_type: ControlTypes::Handshake(HandshakeControlInfo {
+            init_seq_num,
+            max_packet_size: MAX_PACKET_SIZE,
+            max_flow_size: MAX_FLOW_SIZE,
+            socket_id: local_socket_id,
+            shake_type: ShakeType::Conclusion,
+            peer_addr: remote_addr,
+            syn_cookie: cookie,
+            info: HandshakeVSInfo::V5 {
+                crypto_size: 0,
+                ext_hs: Some(SrtControlPacket::HandshakeRequest(SrtHandshake {
+                    version: SrtVersion::CURRENT,
+                    // the receiver always sends periodic NAK reports
+                    flags: SrtShakeFlags::TSBPDSND
+                        | SrtShakeFlags::TSBPDRCV
+                        | SrtShakeFlags::NAKREPORT
+                        | SrtShakeFlags::REXMITFLG,
+                    peer_latency,
+                    latency: recv_latency,
+                })),
+                ext_km: None,
+                ext_config: None,
+                ext_other: Vec::new(),
+            },
+        }),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cookie_contest, rendezvous, Role};
+    use crate::channel::Channel;
+    use crate::packet::{
+        ControlPacket, ControlTypes, HandshakeControlInfo, HandshakeVSInfo, Packet, ShakeType,
+        SrtControlPacket, SrtHandshake, SrtShakeFlags,
+    };
+    use crate::pending_connection::HandshakeConfig;
+    use crate::{SeqNumber, SocketID, SrtVersion, TimeStamp};
+
+    use futures::{join, SinkExt, TryStreamExt};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    fn handshake(info: HandshakeControlInfo) -> Packet {
+        Packet::Control(ControlPacket {
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(0),
+            control_type: ControlTypes::Handshake(info),
+        })
+    }
+
+    // the waveahand the scripted peer sends
+    fn waveahand(remote: SocketAddr, syn_cookie: i32) -> HandshakeControlInfo {
+        HandshakeControlInfo {
+            init_seq_num: SeqNumber::new_truncate(1000),
+            max_packet_size: 1316,
+            max_flow_size: 25600,
+            shake_type: ShakeType::Waveahand,
+            socket_id: SocketID(4321),
+            syn_cookie,
+            peer_addr: remote.ip(),
+            info: HandshakeVSInfo::V5 {
+                crypto_size: 0,
+                ext_hs: None,
+                ext_km: None,
+                ext_config: None,
+                ext_other: Vec::new(),
+            },
+        }
+    }
+
+    fn with_ext_hs(info: HandshakeControlInfo, ext_hs: SrtControlPacket) -> HandshakeControlInfo {
+        HandshakeControlInfo {
+            shake_type: ShakeType::Conclusion,
+            info: HandshakeVSInfo::V5 {
+                crypto_size: 0,
+                ext_hs: Some(ext_hs),
+                ext_km: None,
+                ext_config: None,
+                ext_other: Vec::new(),
+            },
+            ..info
+        }
+    }
+
+    // the next handshake of `shake_type`, skipping resends of earlier ones
+    async fn next_handshake(
+        sock: &mut Channel<(Packet, SocketAddr)>,
+        shake_type: ShakeType,
+    ) -> HandshakeControlInfo {
+        loop {
+            match sock.try_next().await.unwrap().unwrap() {
+                (
+                    Packet::Control(ControlPacket {
+                        control_type: ControlTypes::Handshake(info),
+                        ..
+                    }),
+                    _,
+                ) if info.shake_type == shake_type => return info,
+                (
+                    Packet::Control(ControlPacket {
+                        control_type: ControlTypes::Handshake(_),
+                        ..
+                    }),
+                    _,
+                ) => {}
+                (p, _) => panic!("Expected handshake, got {:?}", p),
+            }
+        }
+    }
+
+    fn ext_hs(info: &HandshakeControlInfo) -> SrtControlPacket {
+        match &info.info {
+            HandshakeVSInfo::V5 {
+                ext_hs: Some(ext_hs),
+                ..
+            } => ext_hs.clone(),
+            info => panic!("Expected a handshake extension, got {:?}", info),
+        }
+    }
+
+    #[test]
+    fn larger_cookie_initiates() {
+        assert_eq!(cookie_contest(5, 3), Some(Role::Initiator));
+        assert_eq!(cookie_contest(-5, 3), Some(Role::Responder));
+        assert_eq!(cookie_contest(i32::MIN, i32::MAX), Some(Role::Responder));
+        assert_eq!(cookie_contest(7, 7), None);
+    }
+
+    #[tokio::test]
+    async fn initiates_against_smaller_cookie() {
+        let remote: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let (mut rdv_side, mut peer_side) = Channel::<(Packet, SocketAddr)>::channel(10);
+
+        // play a peer that loses the contest
+        let peer = async move {
+            let wave = next_handshake(&mut peer_side, ShakeType::Waveahand).await;
+            assert_eq!(wave.info.version(), 5);
+            assert_eq!(wave.socket_id, SocketID(1234));
+
+            peer_side
+                .send((handshake(waveahand(remote, i32::MIN)), remote))
+                .await
+                .unwrap();
+
+            let conclusion = next_handshake(&mut peer_side, ShakeType::Conclusion).await;
+            let hs = match ext_hs(&conclusion) {
+                SrtControlPacket::HandshakeRequest(hs) => hs,
+                ext => panic!("Expected a HSREQ, got {:?}", ext),
+            };
+            assert_eq!(hs.latency, Duration::from_millis(20));
+            assert_eq!(hs.peer_latency, Duration::from_millis(30));
+
+            // answer like a responder with higher latencies
+            let response = SrtHandshake {
+                version: SrtVersion::new(1, 3, 1),
+                flags: hs.flags,
+                peer_latency: Duration::from_millis(80),
+                latency: Duration::from_millis(50),
+            };
+            peer_side
+                .send((
+                    handshake(HandshakeControlInfo {
+                        init_seq_num: conclusion.init_seq_num,
+                        ..with_ext_hs(
+                            waveahand(remote, i32::MIN),
+                            SrtControlPacket::HandshakeResponse(response),
+                        )
+                    }),
+                    remote,
+                ))
+                .await
+                .unwrap();
+
+            let agreement = next_handshake(&mut peer_side, ShakeType::Agreement).await;
+            (conclusion.init_seq_num, agreement)
+        };
+
+        let config = HandshakeConfig {
+            peer_latency: Duration::from_millis(30),
+            ..HandshakeConfig::new(Duration::from_millis(20))
+        };
+        let (conn, (init_seq_num, agreement)) = join!(
+            rendezvous(&mut rdv_side, SocketID(1234), remote, &config,),
+            peer
+        );
+        let conn = conn.unwrap();
+
+        assert_eq!(agreement.socket_id, SocketID(1234));
+        assert_eq!(conn.settings.remote, remote);
+        assert_eq!(conn.settings.remote_sockid, SocketID(4321));
+        assert_eq!(conn.settings.init_seq_num, init_seq_num);
+        assert_eq!(conn.settings.max_packet_size, 1316);
+        assert_eq!(conn.settings.tsbpd_latency, Duration::from_millis(80));
+        assert_eq!(conn.settings.peer_tsbpd_latency, Duration::from_millis(50));
+        assert_eq!(conn.settings.peer_version, Some(SrtVersion::new(1, 3, 1)));
+    }
+
+    #[tokio::test]
+    async fn responds_to_larger_cookie() {
+        let remote: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let (mut rdv_side, mut peer_side) = Channel::<(Packet, SocketAddr)>::channel(10);
+
+        // play a peer that wins the contest
+        let peer = async move {
+            peer_side
+                .send((handshake(waveahand(remote, i32::MAX)), remote))
+                .await
+                .unwrap();
+
+            // the responder waves back so the peer knows it won
+            let wave = next_handshake(&mut peer_side, ShakeType::Waveahand).await;
+            assert_eq!(wave.info.version(), 5);
+
+            let request = SrtHandshake {
+                version: SrtVersion::new(1, 3, 1),
+                flags: SrtShakeFlags::TSBPDSND | SrtShakeFlags::TSBPDRCV | SrtShakeFlags::REXMITFLG,
+                peer_latency: Duration::from_millis(80),
+                latency: Duration::from_millis(10),
+            };
+            peer_side
+                .send((
+                    handshake(with_ext_hs(
+                        waveahand(remote, i32::MAX),
+                        SrtControlPacket::HandshakeRequest(request),
+                    )),
+                    remote,
+                ))
+                .await
+                .unwrap();
+
+            next_handshake(&mut peer_side, ShakeType::Conclusion).await
+        };
+
+        let config = HandshakeConfig {
+            peer_latency: Duration::from_millis(30),
+            ..HandshakeConfig::new(Duration::from_millis(20))
+        };
+        let (conn, response) = join!(
+            rendezvous(&mut rdv_side, SocketID(1234), remote, &config,),
+            peer
+        );
+        let conn = conn.unwrap();
+
+        // the larger latency each way, as a listener would pick
+        match ext_hs(&response) {
+            SrtControlPacket::HandshakeResponse(hs) => {
+                assert_eq!(hs.version, SrtVersion::CURRENT);
+                assert_eq!(hs.latency, Duration::from_millis(80));
+                assert_eq!(hs.peer_latency, Duration::from_millis(30));
+            }
+            ext => panic!("Expected a HSRSP, got {:?}", ext),
+        }
+
+        // the initiator's sequence number is used
+        assert_eq!(conn.settings.init_seq_num, SeqNumber::new_truncate(1000));
+        assert_eq!(conn.settings.remote, remote);
+        assert_eq!(conn.settings.remote_sockid, SocketID(4321));
+        assert_eq!(conn.settings.tsbpd_latency, Duration::from_millis(80));
+        assert_eq!(conn.settings.peer_tsbpd_latency, Duration::from_millis(30));
+        assert_eq!(conn.settings.peer_version, Some(SrtVersion::new(1, 3, 1)));
+    }
 }
@@ -6,30 +6,95 @@ use log::{debug, info, trace, warn};
 use tokio::time::{self, delay_for, interval, Delay, Interval};
 
 use crate::connection::HandshakeReturner;
-use crate::loss_compression::compress_loss_list;
-use crate::packet::{ControlPacket, ControlTypes, DataPacket, Packet, SrtControlPacket};
+#[cfg(feature = "crypto")]
+use crate::crypto::ReceiverKeys;
+use crate::loss_compression::compress_loss_list_chunked;
+use crate::packet::{
+    ControlPacket, ControlTypes, DataEncryption, DataPacket, KmState, Packet, PeerStats,
+    SocketType, SrtControlPacket, SrtKeyMessage, SrtShakeFlags,
+};
 use crate::sink_send_wrapper::SinkSendWrapper;
-use crate::{seq_number::seq_num_range, ConnectionSettings, SeqNumber};
+use crate::stats::JitterHistogram;
+use crate::{
+    seq_number::seq_num_range, CongestCtrlType, ConnectionSettings, MsgNumber, PeerError,
+    SeqNumber, TimeStamp,
+};
 
 use std::cmp;
 use std::cmp::Ordering;
+use std::fmt::{self, Debug, Formatter};
 use std::iter::Iterator;
 use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 
 mod buffer;
+mod drift;
 use self::buffer::RecvBuffer;
+use self::drift::DriftTracer;
+
+/// Encrypted packets from the peer started being dropped, as there's no key
+/// to decrypt them with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UndecryptableEvent {
+    /// The peer that sent them
+    pub remote: SocketAddr,
+
+    /// The state of the keys for receiving from the peer: `BadSecret` if the
+    /// passphrases don't match, `NoSecret` if this end has none
+    pub km_state: KmState,
+
+    /// The number of packets dropped so far, counting the one that started it
+    pub undecryptable_packets: u32,
+}
+
+/// What to call when encrypted packets start being dropped
+#[derive(Clone)]
+pub(crate) struct UndecryptableHandler(Arc<dyn Fn(UndecryptableEvent) + Send + Sync>);
+
+impl UndecryptableHandler {
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(UndecryptableEvent) + Send + Sync + 'static,
+    {
+        UndecryptableHandler(Arc::new(handler))
+    }
+}
+
+impl Debug for UndecryptableHandler {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "UndecryptableHandler")
+    }
+}
+
+/// The size of the IPv4 and UDP headers, which count towards the max packet size
+const UDP_HEADER_SIZE: usize = 28;
+
+/// The size of the header of a control packet
+const CONTROL_HEADER_SIZE: usize = 16;
+
+/// How many data packets can arrive between full ACKs before a light ACK is
+/// sent, so at high rates the sender's buffer is released sooner
+const LIGHT_ACK_PACKETS: u32 = 64;
+
+/// How many one way delays are looked at each time the receiver checks if
+/// they're growing
+const DELAY_TREND_PACKETS: usize = 16;
 
 struct LossListEntry {
     seq_num: SeqNumber,
 
     // last time it was feed into NAK
-    feedback_time: i32,
+    feedback_time: TimeStamp,
 
     // the number of times this entry has been fed back into NAK
     k: i32,
+
+    // how many more packets can arrive after it before it's reported lost,
+    // as it could just be reordered. Zero once it's been reported
+    reorder_ttl: u32,
 }
 
 struct AckHistoryEntry {
@@ -40,7 +105,7 @@ struct AckHistoryEntry {
     ack_seq_num: i32,
 
     /// timestamp that it was sent at
-    timestamp: i32,
+    timestamp: TimeStamp,
 }
 
 pub struct Receiver<T> {
@@ -82,7 +147,7 @@ pub struct Receiver<T> {
     /// of each data packet.
     ///
     /// First is sequence number, second is timestamp
-    packet_history_window: Vec<(SeqNumber, i32)>,
+    packet_history_window: Vec<(SeqNumber, TimeStamp)>,
 
     /// https://tools.ietf.org/html/draft-gg-udt-03#page-12
     /// Packet Pair Window: A circular array that records the time
@@ -108,7 +173,7 @@ pub struct Receiver<T> {
 
     /// The timestamp of the probe time
     /// Used to see duration between packets
-    probe_time: Option<i32>,
+    probe_time: Option<TimeStamp>,
 
     timeout_timer: Delay,
 
@@ -127,6 +192,72 @@ pub struct Receiver<T> {
 
     /// A buffer of packets to send to the underlying sink
     send_wrapper: SinkSendWrapper<(Packet, SocketAddr)>,
+
+    /// Wakes the thread when stats are to be sent to the peer, if they were negotiated
+    peer_stats_interval: Interval,
+
+    /// The number of data packets received
+    received_packets: u32,
+
+    /// The number of those data packets that were marked as retransmissions
+    retransmitted_packets: u32,
+
+    /// The number of payload bytes received
+    received_bytes: u64,
+
+    /// The number of packets dropped because they were too late
+    lost_packets: u32,
+
+    /// The number of packets given up on as their TSBPD deadline passed,
+    /// counting what arrived of their messages, or as their message could
+    /// never be completed
+    dropped_packets: u32,
+
+    /// What payloads are decrypted with, if the connection is encrypted
+    #[cfg(feature = "crypto")]
+    keys: Option<ReceiverKeys>,
+
+    /// The number of encrypted packets dropped for there being no key
+    undecryptable_packets: u32,
+
+    /// If the last encrypted packet was dropped, so each run of them is only
+    /// reported once
+    dropping_undecryptable: bool,
+
+    /// Called when encrypted packets start being dropped
+    undecryptable_handler: Option<UndecryptableHandler>,
+
+    /// The state of the keys the peer encrypts with
+    km_state: KmState,
+
+    /// What packet timestamps are counted from for TSBPD, the socket's start
+    /// time moved along with the peer's clock as it drifts
+    tsbpd_base: Instant,
+
+    /// Follows the drift of the peer's clock from ACK2 timestamps
+    drift_tracer: DriftTracer,
+
+    /// How many packets can arrive after a missing one before it's reported
+    /// lost, see `set_reorder_tolerance`
+    reorder_tolerance: u32,
+
+    /// The number of data packets received since the last ACK, full or light
+    packets_since_ack: u32,
+
+    /// The one way delays of the latest original data packets, in
+    /// microseconds, to tell if queues are building up on the path
+    delay_window: Vec<i32>,
+
+    /// When the last congestion warning was sent, so at most one is sent
+    /// each RTT
+    last_congestion_warning: Option<TimeStamp>,
+
+    /// The largest ACK number sent, in a full or light ACK
+    last_ack_number: SeqNumber,
+
+    /// How often full ACKs are sent, if it's set instead of following the
+    /// RTT, see `set_ack_interval`
+    fixed_ack_interval: Option<Duration>,
 }
 
 impl<T> Receiver<T>
@@ -135,6 +266,14 @@ where
         + Sink<(Packet, SocketAddr), Error = Error>
         + Unpin,
 {
+    /// Create a receiver for an already established connection
+    ///
+    /// This doesn't do any handshaking, so it can be used to drive a receiver
+    /// directly, for example with an `UnderlyingSocket` and hand-made settings.
+    ///
+    /// * `sock` - The transport to receive data and send ACKs/NAKs on
+    /// * `settings` - The connection parameters, `remote` and `local_sockid` are used to filter incoming packets
+    /// * `hs_returner` - What to reply to handshakes with, pass `None` to ignore them
     pub fn new(
         sock: T,
         settings: ConnectionSettings,
@@ -142,6 +281,15 @@ where
     ) -> Receiver<T> {
         let init_seq_num = settings.init_seq_num;
 
+        // with a passphrase, the peer's key is known only if the handshake
+        // agreed on it
+        let km_state = match (settings.key_size, settings.km_state) {
+            (0, KmState::NoSecret) => KmState::NoSecret,
+            (0, _) => KmState::Unsecured,
+            (_, state @ KmState::Secured) | (_, state @ KmState::BadSecret) => state,
+            (_, _) => KmState::Unsecured,
+        };
+
         info!(
             "Receiving started from {:?}, with latency={:?}",
             settings.remote, settings.tsbpd_latency
@@ -170,6 +318,26 @@ where
             shutdown_flag: false,
             release_delay: delay_for(Duration::from_secs(0)), // start with an empty delay
             send_wrapper: SinkSendWrapper::new(),
+            peer_stats_interval: interval(Duration::from_secs(1)),
+            received_packets: 0,
+            retransmitted_packets: 0,
+            received_bytes: 0,
+            lost_packets: 0,
+            dropped_packets: 0,
+            #[cfg(feature = "crypto")]
+            keys: None,
+            undecryptable_packets: 0,
+            dropping_undecryptable: false,
+            undecryptable_handler: None,
+            km_state,
+            tsbpd_base: settings.socket_start_time,
+            drift_tracer: DriftTracer::default(),
+            reorder_tolerance: 0,
+            packets_since_ack: 0,
+            delay_window: Vec::with_capacity(DELAY_TREND_PACKETS),
+            last_congestion_warning: None,
+            last_ack_number: init_seq_num,
+            fixed_ack_interval: None,
         }
     }
 
@@ -181,6 +349,117 @@ where
         self.settings.remote
     }
 
+    /// The number of payload bytes received, including duplicates
+    pub fn received_bytes(&self) -> u64 {
+        self.received_bytes
+    }
+
+    /// The number of data packets received on their first transmission
+    pub fn received_original_packets(&self) -> u32 {
+        self.received_packets - self.retransmitted_packets
+    }
+
+    /// The number of data packets received that the sender marked as retransmissions,
+    /// including duplicates of packets that had already arrived
+    pub fn received_retransmitted_packets(&self) -> u32 {
+        self.retransmitted_packets
+    }
+
+    /// The number of data packets given up on as their TSBPD deadline
+    /// passed, whether they never arrived or the rest of their message didn't,
+    /// or as what arrived of their message can never be put together
+    pub fn dropped_packets(&self) -> u32 {
+        self.dropped_packets
+    }
+
+    /// The number of encrypted data packets that were dropped instead of
+    /// delivered, as the key exchange failed so there's no key to decrypt them
+    pub fn undecryptable_packets(&self) -> u32 {
+        self.undecryptable_packets
+    }
+
+    /// The state of the keys the peer encrypts with: `Secured` once there's
+    /// a key to decrypt with, `BadSecret` if the peer's key couldn't be
+    /// unwrapped with the passphrase, `NoSecret` if the peer encrypts but this
+    /// end has no passphrase, and `Unsecured` if the peer doesn't encrypt
+    pub fn km_state(&self) -> KmState {
+        self.km_state
+    }
+
+    /// How far the TSBPD time base has been moved to follow the peer's
+    /// clock as it drifts, in microseconds
+    pub fn tsbpd_drift(&self) -> i32 {
+        self.drift_tracer.drift()
+    }
+
+    /// How long each released packet waited in the receive buffer before TSBPD released it
+    pub fn jitter_histogram(&self) -> &JitterHistogram {
+        self.buffer.jitter_histogram()
+    }
+
+    /// Set the bucket width of the jitter histogram, clearing it
+    /// Defaults to one millisecond
+    pub fn set_jitter_bucket_width(&mut self, width: Duration) {
+        self.buffer.set_jitter_bucket_width(width);
+    }
+
+    /// Decrypt payloads with `keys`, taking the new ones the peer announces
+    #[cfg(feature = "crypto")]
+    pub fn set_keys(&mut self, keys: ReceiverKeys) {
+        self.keys = Some(keys);
+    }
+
+    /// Call `handler` each time encrypted packets start being dropped for
+    /// there being no key to decrypt them with
+    pub(crate) fn set_undecryptable_handler(&mut self, handler: UndecryptableHandler) {
+        self.undecryptable_handler = Some(handler);
+    }
+
+    /// Wait for up to `packets` more packets to arrive after a missing one
+    /// before reporting it lost, in case it was only reordered on the way
+    /// Losses are still reported in the next periodic NAK report if the gap
+    /// isn't filled by then. Defaults to 0, reporting losses right away
+    pub fn set_reorder_tolerance(&mut self, packets: u32) {
+        self.reorder_tolerance = packets;
+    }
+
+    /// Send full ACKs every `period`, instead of every 4 * RTT + RTTVar +
+    /// 10ms as the RTT is measured from their ACK2s
+    pub fn set_ack_interval(&mut self, period: Duration) {
+        self.fixed_ack_interval = Some(period);
+        self.ack_interval = interval(period);
+    }
+
+    /// Tell the application that encrypted packets are being dropped, and why
+    fn report_undecryptable(&self) {
+        warn!(
+            "Received encrypted data from {}, but there's no key to decrypt it ({:?})",
+            self.settings.remote, self.km_state
+        );
+
+        if let Some(handler) = &self.undecryptable_handler {
+            (handler.0)(UndecryptableEvent {
+                remote: self.settings.remote,
+                km_state: self.km_state,
+                undecryptable_packets: self.undecryptable_packets,
+            });
+        }
+    }
+
+    /// If messages wait for their TSBPD time and are dropped once they're too
+    /// late. Not in UDT4, and not in buffer mode, where the whole stream has
+    /// to arrive
+    fn tsbpd(&self) -> bool {
+        !self.settings.udt4 && self.settings.socket_type == SocketType::Datagram
+    }
+
+    /// If messages not marked for in order delivery can be delivered before
+    /// missing ones ahead of them. Like in the reference implementation, only
+    /// in message mode without TSBPD, which delivers in order
+    fn unordered_delivery(&self) -> bool {
+        self.settings.socket_type == SocketType::Datagram && !self.tsbpd()
+    }
+
     fn timeout_timer(&mut self) -> Pin<&mut Delay> {
         Pin::new(&mut self.timeout_timer)
     }
@@ -243,14 +522,14 @@ where
             ack_number: last_ack_number,
             timestamp: last_timestamp,
             ..
-        }) = self.ack_history_window.first()
+        }) = self.ack_history_window.last()
         {
             // or, (b) it is equal to the ACK number in the
             // last ACK
             if last_ack_number == ack_number &&
                     // and the time interval between this two ACK packets is
                     // less than 2 RTTs,
-                    (self.get_timestamp_now() - last_timestamp) < (self.rtt * 2)
+                    self.get_timestamp_now().diff(last_timestamp) < (self.rtt * 2)
             {
                 // stop (do not send this ACK).
                 return Ok(());
@@ -264,19 +543,19 @@ where
         // 4) Calculate the packet arrival speed according to the following
         // algorithm:
         let packet_recv_rate = {
-            if self.packet_history_window.len() < 16 {
+            if self.packet_history_window.len() < 17 {
                 0
             } else {
                 // Calculate the median value of the last 16 packet arrival
                 // intervals (AI) using the values stored in PKT History Window.
                 let mut last_16: Vec<_> = self.packet_history_window
-                    [self.packet_history_window.len() - 16..]
-                    .iter()
-                    .map(|&(_, ts)| ts)
+                    [self.packet_history_window.len() - 17..]
+                    .windows(2)
+                    .map(|w| w[1].1.diff(w[0].1))
                     .collect();
                 last_16.sort();
 
-                // the median timestamp
+                // the median interval
                 let ai = last_16[last_16.len() / 2];
 
                 // In these 16 values, remove those either greater than AI*8 or
@@ -331,7 +610,7 @@ where
             ack_number,
             rtt: Some(self.rtt),
             rtt_variance: Some(self.rtt_variance),
-            buffer_available: None, // TODO: add this
+            buffer_available: Some(self.buffer_available() as i32),
             packet_recv_rate: Some(packet_recv_rate),
             est_link_cap: Some(est_link_cap),
         });
@@ -343,20 +622,57 @@ where
             ack_seq_num,
             timestamp: now,
         });
+        self.packets_since_ack = 0;
+        self.last_ack_number = ack_number;
         self.send_to_remote(cx, ack)?;
 
         Ok(())
     }
 
+    /// Send a light ACK, which only has the ACK number, if there's anything
+    /// new to acknowledge. It isn't answered with an ACK2, so it's not put
+    /// in the ACK history.
+    fn send_light_ack(&mut self, cx: &mut Context) -> Result<(), Error> {
+        self.packets_since_ack = 0;
+
+        let ack_number = match self.loss_list.first() {
+            Some(i) => i.seq_num,
+            None => self.lrsn,
+        };
+        if ack_number <= self.last_ack_number {
+            return Ok(());
+        }
+        self.last_ack_number = ack_number;
+
+        trace!("Sending light ACK; ack_num={:?}", ack_number);
+        let ack = self.make_control_packet(ControlTypes::Ack {
+            ack_seq_num: 0,
+            ack_number,
+            rtt: None,
+            rtt_variance: None,
+            buffer_available: None,
+            packet_recv_rate: None,
+            est_link_cap: None,
+        });
+        self.send_to_remote(cx, ack)
+    }
+
     fn on_nak_event(&mut self, cx: &mut Context) -> Result<(), Error> {
         // reset NAK timer, rtt and variance are in us, so convert to ns
 
         // NAK is used to trigger a negative acknowledgement (NAK). Its period
         // is dynamically updated to 4 * RTT_+ RTTVar + SYN, where RTTVar is the
         // variance of RTT samples.
-        let nak_interval_us = 4 * self.rtt as u64 + self.rtt_variance as u64 + 10_000;
+        // Periodic NAK reports, like the reference implementation's, come
+        // every (RTT + 4 * RTTVar) / 2 instead, but no more than every 20ms
+        let nak_report = self.settings.srt_flags.contains(SrtShakeFlags::NAKREPORT);
+        let nak_interval_us = if nak_report {
+            cmp::max((self.rtt + 4 * self.rtt_variance) / 2, 20_000)
+        } else {
+            4 * self.rtt + self.rtt_variance + 10_000
+        };
         self.nak_interval.reset(time::Instant::from_std(
-            Instant::now() + Duration::from_micros(nak_interval_us),
+            Instant::now() + Duration::from_micros(nak_interval_us as u64),
         ));
 
         // Search the receiver's loss list, find out all those sequence numbers
@@ -364,7 +680,9 @@ where
         // and increased by 1 each time the number is fed back. Compress
         // (according to section 6.4) and send these numbers back to the sender
         // in an NAK packet.
-
+        // With NAK reports, every loss that wasn't just reported is reported
+        // again each period, until it arrives or is too late, so the reports
+        // don't get further and further apart
         let now = self.get_timestamp_now();
 
         // increment k and change feedback time, returning sequence numbers
@@ -372,13 +690,17 @@ where
             let mut ret = Vec::new();
 
             let rtt = self.rtt;
-            for pak in self
-                .loss_list
-                .iter_mut()
-                .filter(|lle| lle.feedback_time < now - lle.k * rtt)
-            {
+            for pak in self.loss_list.iter_mut().filter(|lle| {
+                let since = now.diff(lle.feedback_time);
+                if nak_report {
+                    since >= nak_interval_us / 2
+                } else {
+                    since > lle.k * rtt
+                }
+            }) {
                 pak.k += 1;
                 pak.feedback_time = now;
+                pak.reorder_ttl = 0;
 
                 ret.push(pak.seq_num);
             }
@@ -405,25 +727,62 @@ where
             self.on_ack_event(cx)?;
         }
 
-        if let Poll::Ready(_) = self.nak_interval().poll(cx) {
+        if self.nak_interval().poll(cx).is_ready() {
             self.on_nak_event(cx)?;
         }
 
+        if self.settings.peer_stats {
+            if let Poll::Ready(Some(_)) = Pin::new(&mut self.peer_stats_interval).poll_next(cx) {
+                self.send_peer_stats(cx)?;
+            }
+        }
+
         // no need to do anything specific
         let _ = self.release_delay().poll(cx);
 
         Ok(())
     }
 
+    /// The packets there's room for in the buffer, out of the max flow size
+    fn buffer_available(&self) -> u32 {
+        self.settings
+            .max_flow_size
+            .saturating_sub(self.buffer.buffered_packets() as u32)
+    }
+
+    fn send_peer_stats(&mut self, cx: &mut Context) -> Result<(), Error> {
+        let stats =
+            self.make_control_packet(ControlTypes::Srt(SrtControlPacket::PeerStats(PeerStats {
+                received_packets: self.received_packets,
+                lost_packets: self.lost_packets,
+                rtt: self.rtt,
+                buffer_available: self.buffer_available(),
+            })));
+
+        self.send_to_remote(cx, stats)
+    }
+
     // handles a SRT control packet
-    fn handle_srt_control_packet(&mut self, pack: &SrtControlPacket) -> Result<(), Error> {
+    fn handle_srt_control_packet(
+        &mut self,
+        cx: &mut Context,
+        pack: &SrtControlPacket,
+    ) -> Result<(), Error> {
         use self::SrtControlPacket::*;
 
         match pack {
             HandshakeRequest(_) | HandshakeResponse(_) => {
                 warn!("Received handshake SRT packet, HSv5 expected");
             }
-            _ => unimplemented!(),
+            KeyManagerRequest(key_msg) => {
+                let response = self.take_keys(key_msg);
+                let packet = self.make_control_packet(ControlTypes::Srt(response));
+                self.send_to_remote(cx, packet)?;
+            }
+            other => warn!(
+                "Received {:?} from {}, which the receiver doesn't handle, ignoring it",
+                other, self.settings.remote
+            ),
         }
 
         Ok(())
@@ -442,7 +801,7 @@ where
             return Ok(());
         }
 
-        if self.settings.local_sockid != packet.dest_sockid() {
+        if !self.settings.accepts_dest_sockid(packet) {
             // packet isn't applicable
             info!(
                 "Packet send to socket id ({}) that does not match local ({})",
@@ -460,27 +819,38 @@ where
 
                 match &ctrl.control_type {
                     ControlTypes::Ack { .. } => warn!("Receiver received ACK packet, unusual"),
-                    ControlTypes::Ack2(seq_num) => self.handle_ack2(*seq_num)?,
-                    ControlTypes::DropRequest { .. } => unimplemented!(),
+                    ControlTypes::Ack2(seq_num) => {
+                        self.handle_ack2(*seq_num)?;
+                        self.trace_drift(ctrl.timestamp);
+                    }
+                    ControlTypes::DropRequest {
+                        msg_to_drop,
+                        first,
+                        last,
+                    } => self.handle_drop_request(*msg_to_drop, *first, *last),
                     ControlTypes::Handshake(_) => {
                         if let Some(ret) = self.hs_returner.as_ref() {
-                            if let Some(pack) = (*ret)(&packet) {
+                            if let Some(pack) = (*ret)(packet) {
                                 self.send_to_remote(cx, pack)?;
                             }
                         }
                     }
                     ControlTypes::KeepAlive => {} // TODO: actually reset EXP etc
                     ControlTypes::Nak { .. } => warn!("Receiver received NAK packet, unusual"),
+                    ControlTypes::CongestionWarning => {
+                        warn!("Receiver received congestion warning, unusual")
+                    }
+                    ControlTypes::PeerError(code) => return Err(PeerError { code: *code }.into()),
                     ControlTypes::Shutdown => {
                         info!("Shutdown packet received, flushing receiver...");
                         self.shutdown_flag = true;
                     } // end of stream
                     ControlTypes::Srt(srt_packet) => {
-                        self.handle_srt_control_packet(srt_packet)?;
+                        self.handle_srt_control_packet(cx, srt_packet)?;
                     }
                 }
             }
-            Packet::Data(data) => self.handle_data_packet(cx, &data)?,
+            Packet::Data(data) => self.handle_data_packet(cx, data)?,
         };
 
         Ok(())
@@ -489,14 +859,11 @@ where
     fn handle_ack2(&mut self, seq_num: i32) -> Result<(), Error> {
         // 1) Locate the related ACK in the ACK History Window according to the
         //    ACK sequence number in this ACK2.
-        let id_in_wnd = match self
+        let id_in_wnd = self
             .ack_history_window
             .as_slice()
             .binary_search_by(|entry| entry.ack_seq_num.cmp(&seq_num))
-        {
-            Ok(i) => Some(i),
-            Err(_) => None,
-        };
+            .ok();
 
         if let Some(id) = id_in_wnd {
             let AckHistoryEntry {
@@ -511,16 +878,21 @@ where
             // 3) Calculate new rtt according to the ACK2 arrival time and the ACK
             //    departure time, and update the RTT value as: RTT = (RTT * 7 +
             //    rtt) / 8
-            let immediate_rtt = self.get_timestamp_now() - send_timestamp;
-            self.rtt = (self.rtt * 7 + immediate_rtt) / 8;
-
             // 4) Update RTTVar by: RTTVar = (RTTVar * 3 + abs(RTT - rtt)) / 4.
-            self.rtt_variance =
-                (self.rtt_variance * 3 + (self.rtt_variance - immediate_rtt).abs()) / 4;
+            // RTTVar is updated first, as it uses RTT from before this sample
+            let immediate_rtt = self.get_timestamp_now().diff(send_timestamp);
+            self.rtt_variance = (self.rtt_variance * 3 + (self.rtt - immediate_rtt).abs()) / 4;
+            self.rtt = (self.rtt * 7 + immediate_rtt) / 8;
 
             // 5) Update both ACK and NAK period to 4 * RTT + RTTVar + SYN.
-            let ack_us = 4 * self.rtt as u64 + self.rtt_variance as u64 + 10_000;
-            self.ack_interval = interval(Duration::from_micros(ack_us));
+            // UDT4 keeps ACKing every SYN, and a set ACK period is kept
+            if !self.settings.udt4 && self.fixed_ack_interval.is_none() {
+                let ack_us = 4 * self.rtt as u64 + self.rtt_variance as u64 + 10_000;
+                self.ack_interval = interval(Duration::from_micros(ack_us));
+            }
+
+            // this ACK, and any sent before it, won't be acknowledged again
+            self.ack_history_window.drain(..=id);
         } else {
             warn!(
                 "ACK sequence number in ACK2 packet not found in ACK history: {}",
@@ -531,9 +903,69 @@ where
         Ok(())
     }
 
+    fn handle_drop_request(&mut self, msg_to_drop: MsgNumber, first: SeqNumber, last: SeqNumber) {
+        info!(
+            "Sender dropped message {}, packets {}..={}",
+            msg_to_drop, first, last
+        );
+
+        // a message can't be wider than the flow window, so this can't be a
+        // real drop request
+        if first > last || last - first >= self.settings.max_flow_size {
+            warn!(
+                "Ignoring drop request for message {}, packets {}..={} aren't a valid range",
+                msg_to_drop, first, last
+            );
+            return;
+        }
+
+        // stop asking for the packets, the buffer makes sure they aren't
+        // noticed missing later
+        self.loss_list
+            .retain(|ll| ll.seq_num < first || ll.seq_num > last);
+
+        let dropped = self.buffer.drop_message(first, last);
+        debug!("Dropped {} buffered packets", dropped);
+    }
+
+    /// Follow the drift of the peer's clock, from when a packet it stamped
+    /// with `timestamp` arrived. Without drift it arrives the one way delay,
+    /// half the RTT, after that
+    fn trace_drift(&mut self, timestamp: TimeStamp) {
+        if self.settings.udt4 {
+            return;
+        }
+
+        let arrival = TimeStamp::from_instant(self.tsbpd_base, Instant::now());
+        let drift = match self
+            .drift_tracer
+            .sample(arrival.diff(timestamp) - self.rtt / 2)
+        {
+            Some(drift) => drift,
+            None => return,
+        };
+
+        info!(
+            "Clock of {} drifted {}us, moving the TSBPD time base",
+            self.settings.remote, drift
+        );
+        let offset = Duration::from_micros(u64::from(drift.unsigned_abs()));
+        self.tsbpd_base = if drift > 0 {
+            self.tsbpd_base + offset
+        } else {
+            self.tsbpd_base - offset
+        };
+    }
+
     fn handle_data_packet(&mut self, cx: &mut Context, data: &DataPacket) -> Result<(), Error> {
         let now = self.get_timestamp_now();
 
+        self.received_packets += 1;
+        if data.retransmitted {
+            self.retransmitted_packets += 1;
+        }
+        self.received_bytes += data.payload.len() as u64;
+
         // 1) Reset the ExpCount to 1. If there is no unacknowledged data
         //     packet, or if this is an ACK or NAK control packet, reset the EXP
         //     timer.
@@ -549,7 +981,8 @@ where
             // if there is an entry
             if let Some(pt) = self.probe_time {
                 // calculate and insert
-                self.packet_pair_window.push((data.seq_number, now - pt));
+                self.packet_pair_window
+                    .push((data.seq_number, now.diff(pt)));
 
                 // reset
                 self.probe_time = None;
@@ -558,24 +991,51 @@ where
         // 5) Record the packet arrival time in PKT History Window.
         self.packet_history_window.push((data.seq_number, now));
 
+        // retransmissions were held up by the loss, not the queues on the path
+        if !data.retransmitted {
+            self.check_delay_trend(cx, now, now.diff(data.timestamp))?;
+        }
+
         // 6)
         // a. If the sequence number of the current data packet is greater
         //    than LRSN, put all the sequence numbers between (but
         //    excluding) these two values into the receiver's loss list and
         //    send them to the sender in an NAK packet.
+        //    Losses that could just be reordered are held back until enough
+        //    packets arrived after them, see `set_reorder_tolerance`
+        if data.seq_number >= self.lrsn {
+            let mut lost = Vec::new();
+            for lle in self.loss_list.iter_mut().filter(|lle| lle.reorder_ttl > 0) {
+                lle.reorder_ttl -= 1;
+                if lle.reorder_ttl == 0 {
+                    lle.feedback_time = now;
+                    lost.push(lle.seq_num);
+                }
+            }
+            if !lost.is_empty() {
+                self.send_nak(cx, lost.into_iter())?;
+            }
+        }
         match data.seq_number.cmp(&self.lrsn) {
             Ordering::Greater => {
                 // lrsn is the latest packet received, so nak the one after that
-                for i in seq_num_range(self.lrsn, data.seq_number) {
+                // packets the sender has dropped aren't coming, so don't ask for them
+                let lost: Vec<_> = seq_num_range(self.lrsn, data.seq_number)
+                    .filter(|&i| !self.buffer.is_dropped(i))
+                    .collect();
+                for &i in &lost {
                     self.loss_list.push(LossListEntry {
                         seq_num: i,
                         feedback_time: now,
                         // k is initialized at 2, as stated on page 12 (very end)
                         k: 2,
+                        reorder_ttl: self.reorder_tolerance,
                     })
                 }
 
-                self.send_nak(cx, seq_num_range(self.lrsn, data.seq_number))?;
+                if self.reorder_tolerance == 0 {
+                    self.send_nak(cx, lost.into_iter())?;
+                }
             }
             // b. If the sequence number is less than LRSN, remove it from the
             //    receiver's loss list.
@@ -602,13 +1062,37 @@ where
         // record that we got this packet
         self.lrsn = cmp::max(data.seq_number + 1, self.lrsn);
 
+        self.packets_since_ack += 1;
+        if self.packets_since_ack >= LIGHT_ACK_PACKETS {
+            self.send_light_ack(cx)?;
+        }
+
         // we've already gotten this packet, drop it
         if self.buffer.next_release() > data.seq_number {
             debug!("Received packet {:?} twice", data.seq_number);
             return Ok(());
         }
 
-        self.buffer.add(data.clone());
+        match self.decrypt(data) {
+            Some(payload) => {
+                self.dropping_undecryptable = false;
+                self.buffer.add(DataPacket {
+                    encryption: DataEncryption::None,
+                    payload,
+                    ..data.clone()
+                })
+            }
+            // it can never be delivered, so skip it like a dropped message
+            // instead of waiting for it
+            None => {
+                self.undecryptable_packets += 1;
+                if !self.dropping_undecryptable {
+                    self.dropping_undecryptable = true;
+                    self.report_undecryptable();
+                }
+                self.buffer.drop_message(data.seq_number, data.seq_number);
+            }
+        }
 
         trace!(
             "Received data packet seq_num={}, loc={:?}, buffer={:?}",
@@ -620,6 +1104,76 @@ where
         Ok(())
     }
 
+    /// Take the new keys in the key material the peer announced, returning
+    /// what to answer with
+    ///
+    /// The key material is sent back if it was taken, so the peer stops
+    /// resending it
+    #[cfg(feature = "crypto")]
+    fn take_keys(&mut self, key_msg: &SrtKeyMessage) -> SrtControlPacket {
+        match &mut self.keys {
+            Some(keys) => match keys.update(key_msg) {
+                Ok(()) => {
+                    self.km_state = KmState::Secured;
+                    SrtControlPacket::KeyManagerResponse(key_msg.clone())
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to take the new keys from {}: {}",
+                        self.settings.remote, e
+                    );
+                    self.km_state = KmState::BadSecret;
+                    SrtControlPacket::KeyManagerError(KmState::BadSecret)
+                }
+            },
+            None => self.refuse_keys(),
+        }
+    }
+
+    /// Without the `crypto` feature there's never a passphrase to take the
+    /// peer's keys with
+    #[cfg(not(feature = "crypto"))]
+    fn take_keys(&mut self, _key_msg: &SrtKeyMessage) -> SrtControlPacket {
+        self.refuse_keys()
+    }
+
+    fn refuse_keys(&mut self) -> SrtControlPacket {
+        warn!(
+            "Received key material from {}, but encryption isn't set up",
+            self.settings.remote
+        );
+        self.km_state = KmState::NoSecret;
+        SrtControlPacket::KeyManagerError(KmState::NoSecret)
+    }
+
+    /// The payload of `data` in the clear, or `None` if there's no key to
+    /// decrypt it with
+    #[cfg(feature = "crypto")]
+    fn decrypt(&mut self, data: &DataPacket) -> Option<Bytes> {
+        match (&self.keys, data.encryption) {
+            (_, DataEncryption::None) => Some(data.payload.clone()),
+            (Some(keys), encryption) => keys.decrypt(encryption, data.seq_number, &data.payload),
+            (None, _) => {
+                // the peer encrypting means it has a passphrase
+                self.km_state = KmState::NoSecret;
+                None
+            }
+        }
+    }
+
+    /// Without the `crypto` feature nothing encrypted can be decrypted
+    #[cfg(not(feature = "crypto"))]
+    fn decrypt(&mut self, data: &DataPacket) -> Option<Bytes> {
+        match data.encryption {
+            DataEncryption::None => Some(data.payload.clone()),
+            _ => {
+                // the peer encrypting means it has a passphrase
+                self.km_state = KmState::NoSecret;
+                None
+            }
+        }
+    }
+
     // send a NAK, and return the future
     fn send_nak<I>(&mut self, cx: &mut Context, lost_seq_nums: I) -> Result<(), Error>
     where
@@ -628,11 +1182,58 @@ where
         let vec: Vec<_> = lost_seq_nums.collect();
         debug!("Sending NAK for={:?}", vec);
 
-        let pack = self.make_control_packet(ControlTypes::Nak(
-            compress_loss_list(vec.iter().cloned()).collect(),
-        ));
+        for pack in self.make_nak_packets(vec.into_iter()) {
+            self.send_to_remote(cx, pack)?;
+        }
+
+        Ok(())
+    }
+
+    // build the NAKs for a loss list, splitting it into as many packets as it
+    // takes for each to fit in the negotiated packet size
+    fn make_nak_packets<I>(&self, lost_seq_nums: I) -> Vec<Packet>
+    where
+        I: Iterator<Item = SeqNumber>,
+    {
+        let max_words = (self.settings.max_packet_size as usize)
+            .saturating_sub(UDP_HEADER_SIZE + CONTROL_HEADER_SIZE)
+            / 4;
+
+        compress_loss_list_chunked(lost_seq_nums, max_words.max(2))
+            .into_iter()
+            .map(|loss_list| self.make_control_packet(ControlTypes::Nak(loss_list)))
+            .collect()
+    }
+
+    // warn the sender when the one way delay keeps growing, as the queues on
+    // the path filling up comes before them overflowing into losses. Only file
+    // transfers are warned, as a live sender's rate is set by its input
+    fn check_delay_trend(
+        &mut self,
+        cx: &mut Context,
+        now: TimeStamp,
+        delay: i32,
+    ) -> Result<(), Error> {
+        if self.settings.congest_ctrl != CongestCtrlType::File {
+            return Ok(());
+        }
 
-        self.send_to_remote(cx, pack)?;
+        self.delay_window.push(delay);
+        if self.delay_window.len() < DELAY_TREND_PACKETS {
+            return Ok(());
+        }
+        let increasing = delay_increasing(&self.delay_window);
+        self.delay_window.clear();
+
+        let warned_recently = self
+            .last_congestion_warning
+            .is_some_and(|last| now.diff(last) < self.rtt);
+        if increasing && !warned_recently {
+            debug!("One way delay increasing, sending congestion warning");
+            self.last_congestion_warning = Some(now);
+            let pack = self.make_control_packet(ControlTypes::CongestionWarning);
+            self.send_to_remote(cx, pack)?;
+        }
 
         Ok(())
     }
@@ -646,11 +1247,15 @@ where
     }
 
     /// Timestamp in us
-    fn get_timestamp_now(&self) -> i32 {
+    fn get_timestamp_now(&self) -> TimeStamp {
         self.settings.get_timestamp_now()
     }
 }
 
+/// Received messages, in the order they're released, with when their source
+/// sent them in this side's clock. With TSBPD, each is released the latency
+/// after that. Ends once the peer shuts the connection down and everything
+/// before that was released
 impl<T> Stream for Receiver<T>
 where
     T: Stream<Item = Result<(Packet, SocketAddr), Error>>
@@ -672,36 +1277,74 @@ where
         let _ = pin.sock().poll_flush(cx)?;
 
         loop {
-            // try to release packets
-            if let Some((ts, p)) = pin
-                .buffer
-                .next_msg_tsbpd(pin.settings.tsbpd_latency, pin.settings.socket_start_time)
-            {
-                return Poll::Ready(Some(Ok((
-                    pin.settings.socket_start_time + Duration::from_micros(ts as u64),
-                    p,
-                ))));
+            // give up on partial messages that can never be completed, so
+            // they don't hold back the ones after them
+            pin.dropped_packets += pin.buffer.drop_broken_messages() as u32;
+
+            // try to release packets, without waiting for the latency in UDT4
+            // or buffer mode
+            let next_msg = if pin.tsbpd() {
+                let (latency, base) = (pin.settings.tsbpd_latency, pin.tsbpd_base);
+                pin.buffer.next_msg_tsbpd(latency, base)
+            } else if pin.unordered_delivery() {
+                pin.buffer
+                    .next_msg()
+                    .or_else(|| pin.buffer.next_unordered_msg())
+            } else {
+                pin.buffer.next_msg()
+            };
+            if let Some((ts, p)) = next_msg {
+                return Poll::Ready(Some(Ok((ts.instant(pin.tsbpd_base), p))));
             }
 
-            // drop packets
-            // TODO: do something with this
-            let _dropped = pin
-                .buffer
-                .drop_too_late_packets(pin.settings.tsbpd_latency, pin.settings.socket_start_time);
+            // drop packets, UDT4 and buffer mode wait for them instead
+            let dropped = if pin.tsbpd() {
+                pin.buffer
+                    .drop_too_late_packets(pin.settings.tsbpd_latency, pin.tsbpd_base)
+            } else {
+                0
+            };
+            pin.lost_packets += dropped as u32;
+            pin.dropped_packets += dropped as u32;
 
-            if let Poll::Ready(_) = pin.timeout_timer().poll(cx) {
+            // stop asking for packets that have been given up on
+            if dropped > 0 {
+                let next_release = pin.buffer.next_release();
+                pin.loss_list.retain(|ll| ll.seq_num >= next_release);
+            }
+
+            if pin.timeout_timer().poll(cx).is_ready() {
                 pin.exp_count += 1;
                 pin.reset_timeout();
             }
 
             // if there is a packet ready, set the timeout timer for it
-            if let Some(release_time) = pin.buffer.next_message_release_time(
-                pin.settings.socket_start_time,
-                pin.settings.tsbpd_latency,
-            ) {
+            // or to give up on a missing packet, so it doesn't stall the
+            // stream until something else arrives
+            let latency = if pin.tsbpd() {
+                pin.settings.tsbpd_latency
+            } else {
+                Duration::from_secs(0)
+            };
+            let release_time = pin
+                .buffer
+                .next_message_release_time(pin.tsbpd_base, latency);
+            let drop_time = if pin.tsbpd() {
+                pin.buffer
+                    .next_drop_time(pin.tsbpd_base, pin.settings.tsbpd_latency)
+            } else {
+                None
+            };
+            if let Some(release_time) = match (release_time, drop_time) {
+                (Some(release), Some(drop)) => Some(cmp::min(release, drop)),
+                (release, drop) => release.or(drop),
+            } {
                 pin.release_delay
                     .reset(time::Instant::from_std(release_time));
-                let _ = Pin::new(&mut pin.release_delay).poll(cx);
+                // the time may have come since the checks above
+                if Pin::new(&mut pin.release_delay).poll(cx).is_ready() {
+                    continue;
+                }
 
                 // if we are setup to shutdown, then the internal socket
                 // returned None, so we shouldn't poll it again, as it may panic
@@ -746,3 +1389,609 @@ where
         }
     }
 }
+
+/// The pairwise comparison and pairwise difference tests from pathload, which
+/// UDT detected delay increase with. Both have to agree the delays are growing
+fn delay_increasing(delays: &[i32]) -> bool {
+    let (first, last) = match (delays.first(), delays.last()) {
+        (Some(&first), Some(&last)) if delays.len() > 1 => (first, last),
+        _ => return false,
+    };
+
+    let increases = delays.windows(2).filter(|w| w[1] > w[0]).count();
+    let variation: i64 = delays
+        .windows(2)
+        .map(|w| (i64::from(w[1]) - i64::from(w[0])).abs())
+        .sum();
+
+    // PCT: most of the samples are larger than the one before
+    let pct = increases as f64 / (delays.len() - 1) as f64 > 0.66;
+    // PDT: the overall increase makes up most of the variation
+    let pdt =
+        variation > 0 && (i64::from(last) - i64::from(first)) as f64 / variation as f64 > 0.45;
+
+    pct && pdt
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        delay_increasing, AckHistoryEntry, LossListEntry, Receiver, DELAY_TREND_PACKETS,
+        UDP_HEADER_SIZE,
+    };
+    use crate::channel::Channel;
+    use crate::loss_compression::decompress_loss_list;
+    use crate::packet::{
+        ControlPacket, ControlTypes, DataEncryption, DataPacket, KmState, Packet, PacketLocation,
+        SocketType, SrtControlPacket, SrtShakeFlags,
+    };
+    use crate::{
+        CongestCtrlType, ConnectionSettings, MsgNumber, PeerError, SeqNumber, SocketID, TimeStamp,
+    };
+
+    use bytes::{Bytes, BytesMut};
+    use futures::task::noop_waker_ref;
+    use futures::{FutureExt, StreamExt};
+    use std::net::SocketAddr;
+    use std::task::Context;
+    use std::time::{Duration, Instant};
+    use tokio::time::delay_for;
+
+    type PackChan = Channel<(Packet, SocketAddr)>;
+
+    fn new_receiver(max_packet_size: u32) -> Receiver<PackChan> {
+        new_receiver_with_remote(max_packet_size, SrtShakeFlags::empty()).0
+    }
+
+    fn new_receiver_with_remote(
+        max_packet_size: u32,
+        srt_flags: SrtShakeFlags,
+    ) -> (Receiver<PackChan>, PackChan) {
+        let (chan, remote) = Channel::channel(10);
+        let recvr = Receiver::new(
+            chan,
+            ConnectionSettings {
+                remote: "127.0.0.1:1234".parse().unwrap(),
+                remote_sockid: SocketID(1),
+                local_sockid: SocketID(2),
+                socket_start_time: Instant::now() - Duration::from_secs(1),
+                init_seq_num: SeqNumber::new_truncate(0),
+                max_packet_size,
+                max_flow_size: 8192,
+                peer_version: None,
+                srt_flags,
+                key_size: 0,
+                km_state: KmState::Unsecured,
+                tsbpd_latency: Duration::from_millis(50),
+                peer_tsbpd_latency: Duration::from_millis(50),
+                peer_stats: false,
+                congest_ctrl: CongestCtrlType::Live,
+                socket_type: SocketType::Datagram,
+                udt4: false,
+            },
+            None,
+        );
+
+        (recvr, remote)
+    }
+
+    fn data_packet(seq: u32) -> Packet {
+        Packet::Data(DataPacket {
+            seq_number: SeqNumber::new_truncate(seq),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            in_order_delivery: false,
+            retransmitted: false,
+            encryption: DataEncryption::None,
+            message_number: MsgNumber::new_truncate(seq),
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(2),
+            payload: Bytes::from("hello"),
+        })
+    }
+
+    /// The losses in each NAK the receiver sent since last time
+    fn naks_sent(remote: &mut PackChan) -> Vec<Vec<u32>> {
+        let mut naks = Vec::new();
+        while let Some(Some(Ok((packet, _)))) = remote.next().now_or_never() {
+            if let Packet::Control(ControlPacket {
+                control_type: ControlTypes::Nak(loss_list),
+                ..
+            }) = packet
+            {
+                naks.push(
+                    decompress_loss_list(loss_list.iter().cloned())
+                        .map(|seq| seq.as_raw())
+                        .collect(),
+                );
+            }
+        }
+        naks
+    }
+
+    #[tokio::test]
+    async fn nak_reports() {
+        let (mut recvr, mut remote) = new_receiver_with_remote(1316, SrtShakeFlags::NAKREPORT);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let from = "127.0.0.1:1234".parse().unwrap();
+
+        // 1..=3 are reported lost right away, compressed into a range
+        recvr
+            .handle_packet(&mut cx, &data_packet(0), &from)
+            .unwrap();
+        recvr
+            .handle_packet(&mut cx, &data_packet(4), &from)
+            .unwrap();
+        assert_eq!(naks_sent(&mut remote), vec![vec![1, 2, 3]]);
+        assert_eq!(recvr.loss_list.len(), 3);
+
+        // not reported again right after
+        recvr.on_nak_event(&mut cx).unwrap();
+        assert_eq!(naks_sent(&mut remote), Vec::<Vec<u32>>::new());
+
+        // then every period, without backing off, until they arrive
+        for _ in 0..3 {
+            delay_for(Duration::from_millis(25)).await;
+            recvr.on_nak_event(&mut cx).unwrap();
+            assert_eq!(naks_sent(&mut remote), vec![vec![1, 2, 3]]);
+        }
+
+        recvr
+            .handle_packet(&mut cx, &data_packet(2), &from)
+            .unwrap();
+        delay_for(Duration::from_millis(25)).await;
+        recvr.on_nak_event(&mut cx).unwrap();
+        assert_eq!(naks_sent(&mut remote), vec![vec![1, 3]]);
+    }
+
+    #[tokio::test]
+    async fn nak_split_across_packets() {
+        const MAX_PACKET_SIZE: u32 = 100;
+
+        let recvr = new_receiver(MAX_PACKET_SIZE);
+
+        // every third loss is a range of three, so a split in the wrong
+        // place would break one up
+        let lost: Vec<_> = (0..30)
+            .flat_map(|i| {
+                let len = if i % 3 == 0 { 3 } else { 1 };
+                (10 * i..10 * i + len).map(SeqNumber::new_truncate)
+            })
+            .collect();
+
+        let naks = recvr.make_nak_packets(lost.iter().cloned());
+        assert_eq!(naks.len(), 3);
+
+        let mut covered = Vec::new();
+        for nak in &naks {
+            let mut buf = BytesMut::new();
+            nak.serialize(&mut buf);
+            assert!(buf.len() + UDP_HEADER_SIZE <= MAX_PACKET_SIZE as usize);
+
+            match nak {
+                Packet::Control(ControlPacket {
+                    control_type: ControlTypes::Nak(loss_list),
+                    ..
+                }) => covered.extend(decompress_loss_list(loss_list.iter().cloned())),
+                p => panic!("Expected NAK, got {:?}", p),
+            }
+        }
+        assert_eq!(covered, lost);
+    }
+
+    #[tokio::test]
+    async fn ack2_updates_rtt() {
+        let mut recvr = new_receiver(1316);
+        let now = recvr.get_timestamp_now();
+        for (ack_seq_num, sent_ago) in [(1, 50_000), (2, 30_000), (3, 10_000)].iter() {
+            recvr.ack_history_window.push(AckHistoryEntry {
+                ack_number: SeqNumber::new_truncate(*ack_seq_num as u32 * 10),
+                ack_seq_num: *ack_seq_num,
+                timestamp: now - *sent_ago as u64,
+            });
+        }
+
+        // unknown ACK sequence numbers are ignored
+        recvr.handle_ack2(7).unwrap();
+        assert_eq!((recvr.rtt, recvr.rtt_variance), (10_000, 1_000));
+
+        recvr.handle_ack2(2).unwrap();
+
+        // rtt sample is ~30ms: RTTVar = (1000 * 3 + 20000) / 4, RTT = (10000 * 7 + 30000) / 8
+        let tolerance = 1_000;
+        assert!(
+            (recvr.rtt_variance - 5_750).abs() < tolerance,
+            "{}",
+            recvr.rtt_variance
+        );
+        assert!((recvr.rtt - 12_500).abs() < tolerance, "{}", recvr.rtt);
+        assert_eq!(recvr.lr_ack_acked, (2, SeqNumber::new_truncate(20)));
+
+        // the acknowledged entry and those before it are done with
+        assert_eq!(
+            recvr
+                .ack_history_window
+                .iter()
+                .map(|e| e.ack_seq_num)
+                .collect::<Vec<_>>(),
+            [3]
+        );
+    }
+
+    #[tokio::test]
+    async fn drift_moves_tsbpd_base() {
+        let mut recvr = new_receiver(1316);
+        recvr.rtt = 20_000;
+        let base = recvr.tsbpd_base;
+
+        // the peer's clock is 10ms behind, on top of the 10ms one way delay
+        for _ in 0..1_000 {
+            let sent = Instant::now() - Duration::from_millis(20);
+            recvr.trace_drift(TimeStamp::from_instant(base, sent));
+        }
+
+        let drift = recvr.tsbpd_drift();
+        assert!((10_000..10_500).contains(&drift), "{}", drift);
+        assert_eq!(recvr.tsbpd_base, base + Duration::from_micros(drift as u64));
+    }
+
+    #[tokio::test]
+    async fn reorder_tolerance() {
+        let (mut recvr, mut remote) = new_receiver_with_remote(1316, SrtShakeFlags::NAKREPORT);
+        recvr.set_reorder_tolerance(2);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let from = "127.0.0.1:1234".parse().unwrap();
+
+        // 1 is only reordered, 3 is lost
+        for seq in &[0, 2, 1, 4] {
+            recvr
+                .handle_packet(&mut cx, &data_packet(*seq), &from)
+                .unwrap();
+        }
+        assert_eq!(naks_sent(&mut remote), Vec::<Vec<u32>>::new());
+
+        // reported once two packets arrived after it
+        recvr
+            .handle_packet(&mut cx, &data_packet(5), &from)
+            .unwrap();
+        assert_eq!(naks_sent(&mut remote), Vec::<Vec<u32>>::new());
+        recvr
+            .handle_packet(&mut cx, &data_packet(6), &from)
+            .unwrap();
+        assert_eq!(naks_sent(&mut remote), vec![vec![3]]);
+
+        // a gap that isn't filled is reported in the next periodic report
+        recvr
+            .handle_packet(&mut cx, &data_packet(8), &from)
+            .unwrap();
+        delay_for(Duration::from_millis(25)).await;
+        recvr.on_nak_event(&mut cx).unwrap();
+        assert_eq!(naks_sent(&mut remote), vec![vec![3, 7]]);
+    }
+
+    #[tokio::test]
+    async fn light_acks() {
+        let (mut recvr, mut remote) = new_receiver_with_remote(1316, SrtShakeFlags::empty());
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let from = "127.0.0.1:1234".parse().unwrap();
+        let acks_sent = |remote: &mut PackChan| {
+            let mut acks = Vec::new();
+            while let Some(Some(Ok((packet, _)))) = remote.next().now_or_never() {
+                if let Packet::Control(ControlPacket {
+                    control_type:
+                        ControlTypes::Ack {
+                            ack_seq_num,
+                            ack_number,
+                            rtt,
+                            ..
+                        },
+                    ..
+                }) = packet
+                {
+                    acks.push((ack_seq_num, ack_number.as_raw(), rtt.is_some()));
+                }
+            }
+            acks
+        };
+
+        // one every 64 packets, with only the ACK number
+        for seq in 0..63 {
+            recvr
+                .handle_packet(&mut cx, &data_packet(seq), &from)
+                .unwrap();
+        }
+        assert_eq!(acks_sent(&mut remote), vec![]);
+        recvr
+            .handle_packet(&mut cx, &data_packet(63), &from)
+            .unwrap();
+        assert_eq!(acks_sent(&mut remote), vec![(0, 64, false)]);
+
+        // a full ACK starts the count again
+        for seq in 64..100 {
+            recvr
+                .handle_packet(&mut cx, &data_packet(seq), &from)
+                .unwrap();
+        }
+        recvr.on_ack_event(&mut cx).unwrap();
+        assert_eq!(acks_sent(&mut remote), vec![(1, 100, true)]);
+        for seq in 100..163 {
+            recvr
+                .handle_packet(&mut cx, &data_packet(seq), &from)
+                .unwrap();
+        }
+        assert_eq!(acks_sent(&mut remote), vec![]);
+
+        // nothing is sent if a loss holds the ACK number back
+        for seq in 165..229 {
+            recvr
+                .handle_packet(&mut cx, &data_packet(seq), &from)
+                .unwrap();
+        }
+        assert_eq!(acks_sent(&mut remote), vec![(0, 163, false)]);
+        for seq in 229..293 {
+            recvr
+                .handle_packet(&mut cx, &data_packet(seq), &from)
+                .unwrap();
+        }
+        assert_eq!(acks_sent(&mut remote), vec![]);
+    }
+
+    #[tokio::test]
+    async fn drop_request_clears_losses() {
+        let mut recvr = new_receiver(1316);
+
+        // 0 arrived, then 5, so 1..=4 are lost
+        recvr.lrsn = SeqNumber::new_truncate(6);
+        recvr.loss_list = (1..5)
+            .map(|i| LossListEntry {
+                seq_num: SeqNumber::new_truncate(i),
+                feedback_time: TimeStamp(0),
+                k: 2,
+                reorder_ttl: 0,
+            })
+            .collect();
+
+        // the sender gives up on 2..=3, and 7..=8 before they're sent
+        recvr.handle_drop_request(
+            MsgNumber::new_truncate(1),
+            SeqNumber::new_truncate(2),
+            SeqNumber::new_truncate(3),
+        );
+        recvr.handle_drop_request(
+            MsgNumber::new_truncate(3),
+            SeqNumber::new_truncate(7),
+            SeqNumber::new_truncate(8),
+        );
+
+        assert_eq!(
+            recvr
+                .loss_list
+                .iter()
+                .map(|ll| ll.seq_num.as_raw())
+                .collect::<Vec<_>>(),
+            [1, 4]
+        );
+        // 7..=8 won't be reported lost when later packets arrive, but 6 will
+        assert!(recvr.buffer.is_dropped(SeqNumber::new_truncate(7)));
+        assert!(!recvr.buffer.is_dropped(SeqNumber::new_truncate(6)));
+    }
+
+    #[tokio::test]
+    async fn invalid_drop_request_ignored() {
+        let mut recvr = new_receiver(1316);
+
+        // backwards
+        recvr.handle_drop_request(
+            MsgNumber::new_truncate(1),
+            SeqNumber::new_truncate(8),
+            SeqNumber::new_truncate(7),
+        );
+        // wider than the flow window
+        recvr.handle_drop_request(
+            MsgNumber::new_truncate(2),
+            SeqNumber::new_truncate(10),
+            SeqNumber::new_truncate(10 + 8192),
+        );
+
+        assert!(!recvr.buffer.is_dropped(SeqNumber::new_truncate(7)));
+        assert!(!recvr.buffer.is_dropped(SeqNumber::new_truncate(11)));
+    }
+
+    #[tokio::test]
+    async fn peer_error() {
+        let mut recvr = new_receiver(1316);
+        let pack = Packet::Control(ControlPacket {
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(2),
+            control_type: ControlTypes::PeerError(4000),
+        });
+
+        let err = recvr
+            .handle_packet(
+                &mut Context::from_waker(noop_waker_ref()),
+                &pack,
+                &"127.0.0.1:1234".parse().unwrap(),
+            )
+            .unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&PeerError { code: 4000 }));
+    }
+
+    #[tokio::test]
+    async fn unexpected_srt_packet_ignored() {
+        let mut recvr = new_receiver(1316);
+        let pack = Packet::Control(ControlPacket {
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(2),
+            control_type: ControlTypes::Srt(SrtControlPacket::StreamId("a".into())),
+        });
+
+        recvr
+            .handle_packet(
+                &mut Context::from_waker(noop_waker_ref()),
+                &pack,
+                &"127.0.0.1:1234".parse().unwrap(),
+            )
+            .unwrap();
+    }
+
+    fn timed_data_packet(seq: u32, timestamp: u64) -> Packet {
+        match data_packet(seq) {
+            Packet::Data(data) => Packet::Data(DataPacket {
+                timestamp: TimeStamp(timestamp),
+                ..data
+            }),
+            _ => unreachable!(),
+        }
+    }
+
+    fn congestion_warnings_sent(remote: &mut PackChan) -> usize {
+        let mut warnings = 0;
+        while let Some(Some(Ok((packet, _)))) = remote.next().now_or_never() {
+            if let Packet::Control(ControlPacket {
+                control_type: ControlTypes::CongestionWarning,
+                dest_sockid,
+                ..
+            }) = packet
+            {
+                assert_eq!(dest_sockid, SocketID(1));
+                warnings += 1;
+            }
+        }
+        warnings
+    }
+
+    #[test]
+    fn delay_trend() {
+        assert!(delay_increasing(&[10, 12, 11, 14, 16, 15, 18, 20]));
+        assert!(!delay_increasing(&[20, 18, 19, 16, 14, 15, 12, 10]));
+        // jitter around the same delay
+        assert!(!delay_increasing(&[10, 14, 9, 15, 10, 13, 9, 14]));
+        assert!(!delay_increasing(&[10, 10, 10, 10]));
+        assert!(!delay_increasing(&[10]));
+    }
+
+    #[tokio::test]
+    async fn congestion_warning_on_delay_increase() {
+        let (mut recvr, mut remote) = new_receiver_with_remote(1316, SrtShakeFlags::empty());
+        recvr.settings.congest_ctrl = CongestCtrlType::File;
+        // long enough for the test to run within one RTT
+        recvr.rtt = 10_000_000;
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let from = "127.0.0.1:1234".parse().unwrap();
+
+        // each packet claims to be sent a millisecond before the one it follows,
+        // so every one way delay is longer than the last
+        let mut seq = 0;
+        let mut send_packets = |recvr: &mut Receiver<PackChan>, count| {
+            for _ in 0..count {
+                let pack = timed_data_packet(seq, 500_000 - u64::from(seq) * 1_000);
+                recvr.handle_packet(&mut cx, &pack, &from).unwrap();
+                seq += 1;
+            }
+        };
+
+        send_packets(&mut recvr, DELAY_TREND_PACKETS - 1);
+        assert_eq!(congestion_warnings_sent(&mut remote), 0);
+        send_packets(&mut recvr, 1);
+        assert_eq!(congestion_warnings_sent(&mut remote), 1);
+
+        // no more than one per RTT
+        send_packets(&mut recvr, DELAY_TREND_PACKETS);
+        assert_eq!(congestion_warnings_sent(&mut remote), 0);
+    }
+
+    #[tokio::test]
+    async fn no_congestion_warning_for_live() {
+        let (mut recvr, mut remote) = new_receiver_with_remote(1316, SrtShakeFlags::empty());
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let from = "127.0.0.1:1234".parse().unwrap();
+
+        for seq in 0..DELAY_TREND_PACKETS as u32 {
+            let pack = timed_data_packet(seq, 500_000 - u64::from(seq) * 1_000);
+            recvr.handle_packet(&mut cx, &pack, &from).unwrap();
+        }
+        assert_eq!(congestion_warnings_sent(&mut remote), 0);
+    }
+
+    #[tokio::test]
+    async fn unordered_delivery_only_without_tsbpd() {
+        let unordered_delivered = |udt4, socket_type| {
+            let (mut recvr, _remote) = new_receiver_with_remote(1316, SrtShakeFlags::empty());
+            recvr.settings.udt4 = udt4;
+            recvr.settings.socket_type = socket_type;
+            let mut cx = Context::from_waker(noop_waker_ref());
+            let from = "127.0.0.1:1234".parse().unwrap();
+
+            // 0 is missing, and 1 isn't due for a while yet
+            let pack = match data_packet(1) {
+                Packet::Data(pack) => Packet::Data(DataPacket {
+                    timestamp: TimeStamp(2_000_000),
+                    ..pack
+                }),
+                _ => unreachable!(),
+            };
+            recvr.handle_packet(&mut cx, &pack, &from).unwrap();
+
+            matches!(recvr.next().now_or_never(), Some(Some(Ok(_))))
+        };
+
+        assert!(!unordered_delivered(false, SocketType::Datagram));
+        assert!(unordered_delivered(true, SocketType::Datagram));
+        assert!(!unordered_delivered(true, SocketType::Stream));
+    }
+
+    #[tokio::test]
+    async fn counts_retransmissions() {
+        let mut recvr = new_receiver(1316);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let from = "127.0.0.1:1234".parse().unwrap();
+
+        // the retransmission of 1 arrives after the original, so nothing is NAKed
+        for (seq, retransmitted) in [(0, false), (1, false), (1, true)].iter() {
+            let pack = Packet::Data(DataPacket {
+                seq_number: SeqNumber::new_truncate(*seq),
+                message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+                in_order_delivery: false,
+                retransmitted: *retransmitted,
+                encryption: DataEncryption::None,
+                message_number: MsgNumber::new_truncate(*seq),
+                timestamp: TimeStamp(0),
+                dest_sockid: SocketID(2),
+                payload: Bytes::from("hello"),
+            });
+            recvr.handle_packet(&mut cx, &pack, &from).unwrap();
+        }
+
+        assert_eq!(recvr.received_original_packets(), 2);
+        assert_eq!(recvr.received_retransmitted_packets(), 1);
+    }
+
+    #[tokio::test]
+    async fn undecryptable_not_delivered() {
+        let mut recvr = new_receiver(1316);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let from = "127.0.0.1:1234".parse().unwrap();
+
+        for (seq, encryption) in [(0, DataEncryption::Even), (1, DataEncryption::None)].iter() {
+            let pack = Packet::Data(DataPacket {
+                seq_number: SeqNumber::new_truncate(*seq),
+                message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+                in_order_delivery: false,
+                retransmitted: false,
+                encryption: *encryption,
+                message_number: MsgNumber::new_truncate(*seq),
+                timestamp: TimeStamp(0),
+                dest_sockid: SocketID(2),
+                payload: Bytes::from("hello"),
+            });
+            recvr.handle_packet(&mut cx, &pack, &from).unwrap();
+        }
+        assert_eq!(recvr.undecryptable_packets(), 1);
+
+        // the encrypted one is skipped, not waited for
+        assert_eq!(
+            recvr.buffer.next_msg().map(|(_, payload)| payload),
+            Some(Bytes::from("hello"))
+        );
+        assert_eq!(recvr.buffer.next_release(), SeqNumber::new_truncate(2));
+    }
+}
@@ -1,19 +1,33 @@
 use bytes::{Bytes, BytesMut};
 use log::debug;
+use std::cmp;
 use std::collections::VecDeque;
 use std::fmt;
 use std::time::{Duration, Instant};
 
 use crate::packet::PacketLocation;
-use crate::{DataPacket, SeqNumber};
+use crate::stats::JitterHistogram;
+use crate::{DataPacket, SeqNumber, TimeStamp};
 
 pub struct RecvBuffer {
-    // stores the incoming packets as they arrive
+    // stores the incoming packets as they arrive, along with their arrival time
     // `buffer[0]` will hold sequence number `head`
-    buffer: VecDeque<Option<DataPacket>>,
+    buffer: VecDeque<Option<(Instant, DataPacket)>>,
 
     // The next to be released sequence number
     head: SeqNumber,
+
+    // how long released packets waited in the buffer
+    jitter: JitterHistogram,
+
+    // inclusive ranges of sequence numbers the sender asked to be dropped, or
+    // of messages already delivered out of order, which are skipped over once
+    // `head` reaches them
+    dropped: Vec<(SeqNumber, SeqNumber)>,
+
+    // the first sequence number and length of each complete message not
+    // marked for in order delivery, in the order they were completed
+    unordered: Vec<(SeqNumber, usize)>,
 }
 
 impl RecvBuffer {
@@ -24,9 +38,28 @@ impl RecvBuffer {
         RecvBuffer {
             buffer: VecDeque::new(),
             head,
+            jitter: JitterHistogram::default(),
+            dropped: Vec::new(),
+            unordered: Vec::new(),
         }
     }
 
+    /// How long released packets waited in the buffer
+    pub fn jitter_histogram(&self) -> &JitterHistogram {
+        &self.jitter
+    }
+
+    /// Replace the jitter histogram with an empty one with buckets of `width`
+    pub fn set_jitter_bucket_width(&mut self, width: Duration) {
+        self.jitter = JitterHistogram::new(width);
+    }
+
+    /// The number of slots in the buffer, received or not, from the next
+    /// packet to be released to the latest received packet
+    pub fn buffered_packets(&self) -> usize {
+        self.buffer.len()
+    }
+
     /// The next to be released sequence number
     pub fn next_release(&self) -> SeqNumber {
         self.head
@@ -35,9 +68,17 @@ impl RecvBuffer {
     /// Adds a packet to the buffer
     /// If `pack.seq_number < self.head`, this is nop (ie it appears before an already released packet)
     pub fn add(&mut self, pack: DataPacket) {
+        self.add_at(pack, Instant::now())
+    }
+
+    /// Same as `add`, but with an explicit arrival time
+    pub fn add_at(&mut self, pack: DataPacket, arrival: Instant) {
         if pack.seq_number < self.head {
             return; // packet is too late
         }
+        if self.is_dropped(pack.seq_number) {
+            return; // the sender gave up on this packet's message
+        }
 
         // resize `buffer` if necessary
         let idx = (pack.seq_number - self.head) as usize;
@@ -45,29 +86,140 @@ impl RecvBuffer {
             self.buffer.resize(idx + 1, None);
         }
 
-        // add the new element
-        self.buffer[idx] = Some(pack)
+        // add the new element, noting if it completes a message that can be
+        // delivered out of order
+        let in_order = pack.in_order_delivery;
+        if self.buffer[idx].replace((arrival, pack)).is_none() && !in_order {
+            self.note_unordered(idx);
+        }
+    }
+
+    // record the message the packet at `idx` is in, if it's now complete
+    fn note_unordered(&mut self, idx: usize) {
+        let slot = |i: usize| self.buffer[i].as_ref().map(|(_, pack)| pack);
+        let msg = slot(idx).unwrap().message_number;
+        let in_msg = |i| slot(i).filter(|pack| pack.message_number == msg);
+
+        let mut first = idx;
+        while let Some(pack) = in_msg(first) {
+            if pack.message_loc.contains(PacketLocation::FIRST) {
+                break;
+            }
+            if first == 0 {
+                return;
+            }
+            first -= 1;
+        }
+        let mut last = idx;
+        while let Some(pack) = in_msg(last) {
+            if pack.message_loc.contains(PacketLocation::LAST) {
+                break;
+            }
+            if last + 1 == self.buffer.len() {
+                return;
+            }
+            last += 1;
+        }
+
+        if in_msg(first).is_some() && in_msg(last).is_some() {
+            self.unordered
+                .push((self.head + first as u32, last - first + 1));
+        }
+    }
+
+    /// Drops the packets `first..=last`, as the sender won't be sending them
+    /// any more. If they are ahead of the next packet to be released, they
+    /// are skipped once it gets there.
+    ///
+    /// Returns the number of packets dropped that had already arrived
+    pub fn drop_message(&mut self, first: SeqNumber, last: SeqNumber) -> usize {
+        if first > last || last < self.head {
+            return 0; // empty, or already released or dropped
+        }
+        let first = cmp::max(first, self.head);
+
+        // only what is buffered can have arrived
+        let start = (first - self.head) as usize;
+        let end = cmp::min((last - self.head) as usize + 1, self.buffer.len());
+        let dropped = if start < end {
+            self.buffer
+                .range_mut(start..end)
+                .filter_map(Option::take)
+                .count()
+        } else {
+            0
+        };
+
+        self.mark_dropped(first, last);
+        self.skip_dropped();
+
+        dropped
+    }
+
+    // record `first..=last` as dropped, merging it with the ranges it
+    // overlaps or touches so the list stays short
+    fn mark_dropped(&mut self, mut first: SeqNumber, mut last: SeqNumber) {
+        self.dropped.retain(|&(f, l)| {
+            if f <= last + 1 && first <= l + 1 {
+                first = cmp::min(first, f);
+                last = cmp::max(last, l);
+                false
+            } else {
+                true
+            }
+        });
+        self.dropped.push((first, last));
+    }
+
+    /// If `seq` is in a range the sender dropped, or was delivered out of
+    /// order, that hasn't been skipped yet
+    pub fn is_dropped(&self, seq: SeqNumber) -> bool {
+        self.dropped
+            .iter()
+            .any(|&(first, last)| first <= seq && seq <= last)
+    }
+
+    // move `head` past any dropped range it has reached
+    fn skip_dropped(&mut self) {
+        while let Some(&(_, last)) = self
+            .dropped
+            .iter()
+            .find(|&&(first, last)| first <= self.head && self.head <= last)
+        {
+            let count = last - self.head + 1;
+            self.buffer
+                .drain(..cmp::min(count as usize, self.buffer.len()));
+            self.head = last + 1;
+        }
+
+        let head = self.head;
+        self.dropped.retain(|&(_, last)| last >= head);
+        self.unordered.retain(|&(first, _)| first >= head);
     }
 
     /// Drops the packets that are deemed to be too late
     /// IE: there is a packet after it that is ready to be released
     ///
-    /// Returns the number of packets dropped
+    /// That gives up on the first missing packet, along with what arrived of
+    /// its message, even if the gap is in the middle of the message. If that
+    /// leaves the rest of a message whose beginning was dropped at the front,
+    /// that is dropped too, as it can never be delivered.
+    ///
+    /// Returns the number of packets dropped, counting missing ones
     pub fn drop_too_late_packets(&mut self, latency: Duration, start_time: Instant) -> usize {
-        let first_non_none_idx = self.buffer.iter().position(Option::is_some);
-
-        let first_non_none_idx = match first_non_none_idx {
+        let first_non_none_idx = match self.after_first_gap() {
             Some(i) => i,
             None => return 0, // even though some of these may be too late, there are none that can be released so they can't them back.
         };
 
-        let first_pack_ts_us = self.buffer[first_non_none_idx].as_ref().unwrap().timestamp;
+        let first_pack_ts = self.buffer[first_non_none_idx]
+            .as_ref()
+            .unwrap()
+            .1
+            .timestamp;
         // we are too late if that packet is ready
         // give a 2 ms buffer range, be ok with releasing them 2ms late
-        let too_late = start_time
-            + Duration::from_micros(first_pack_ts_us as u64)
-            + latency
-            + Duration::from_millis(2)
+        let too_late = first_pack_ts.instant(start_time) + latency + Duration::from_millis(2)
             <= Instant::now();
 
         if too_late {
@@ -76,40 +228,122 @@ impl RecvBuffer {
                 self.head,
                 self.head + first_non_none_idx as u32,
                 {
-                    let dur_too_late = Instant::now()
-                        - start_time
-                        - Duration::from_micros(first_pack_ts_us as u64)
-                        - latency;
+                    let dur_too_late = Instant::now() - first_pack_ts.instant(start_time) - latency;
 
                     dur_too_late.as_millis()
                 }
             );
-            // start dropping packets
+            // start dropping packets, not counting those the sender dropped or
+            // that were delivered out of order
+            let mut dropped = (0..first_non_none_idx)
+                .filter(|&idx| !self.is_dropped(self.head + idx as u32))
+                .count();
             self.head += first_non_none_idx as u32;
-            self.buffer.drain(0..first_non_none_idx).count()
+            self.buffer.drain(0..first_non_none_idx);
+
+            // drop the rest of a message if its first packet was dropped
+            while let Some(Some((_, pack))) = self.buffer.front() {
+                if pack.message_loc.contains(PacketLocation::FIRST) {
+                    break;
+                }
+                debug!("Dropping packet {}, its message was dropped", self.head);
+
+                self.buffer.pop_front();
+                self.head += 1;
+                dropped += 1;
+            }
+            self.skip_dropped();
+
+            dropped
         } else {
             0 // the next available packet isn't ready to be sent yet
         }
     }
 
+    /// Drops partial messages at the front of the buffer that can never be
+    /// completed: the rest of a message whose first packet was dropped, or
+    /// the start of a message that another one follows before its last packet
+    ///
+    /// Returns the number of packets dropped
+    pub fn drop_broken_messages(&mut self) -> usize {
+        let mut dropped = 0;
+        while let Some(count) = self.broken_message() {
+            debug!(
+                "Dropping packets {}..{}, their message can't be completed",
+                self.head,
+                self.head + count as u32
+            );
+            self.buffer.drain(..count);
+            self.head += count as u32;
+            dropped += count;
+            self.skip_dropped();
+        }
+
+        dropped
+    }
+
+    /// The number of packets at the front that are a partial message that
+    /// can never be completed, if there are any
+    fn broken_message(&self) -> Option<usize> {
+        let (_, first) = self.buffer.front()?.as_ref()?;
+        if !first.message_loc.contains(PacketLocation::FIRST) {
+            return Some(1);
+        }
+
+        for (i, slot) in self.buffer.iter().enumerate() {
+            match slot {
+                // the rest of the message may still arrive
+                None => return None,
+                Some((_, pack))
+                    if i > 0
+                        && (pack.message_number != first.message_number
+                            || pack.message_loc.contains(PacketLocation::FIRST)) =>
+                {
+                    return Some(i)
+                }
+                Some((_, pack)) if pack.message_loc.contains(PacketLocation::LAST) => return None,
+                Some(_) => {}
+            }
+        }
+
+        None
+    }
+
+    /// When `drop_too_late_packets` will give up on the first missing packet,
+    /// if there's one with a packet after it
+    pub fn next_drop_time(&self, start_time: Instant, latency: Duration) -> Option<Instant> {
+        let (_, pack) = self.buffer[self.after_first_gap()?].as_ref().unwrap();
+
+        Some(pack.timestamp.instant(start_time) + latency + Duration::from_millis(2))
+    }
+
+    /// The index of the first packet that arrived after the first missing one
+    ///
+    /// Anything before the first missing packet is either released or
+    /// waiting for the rest of its message
+    fn after_first_gap(&self) -> Option<usize> {
+        let gap = self.buffer.iter().position(Option::is_none)?;
+
+        Some(gap + self.buffer.iter().skip(gap).position(Option::is_some)?)
+    }
+
     /// Check if there is an available message to release with TSBPD
     /// ie - `start_time + timestamp + tsbpd <= now`
     ///
     /// * `latency` - The latency to release with
     /// * `start_time` - The start time of the socket to add to timestamps
-    /// TODO: this does not account for timestamp wrapping
     ///
     /// Returns `None` if there is no message available, or `Some(i)` if there is a packet available, `i` being the number of packets it spans.
     pub fn next_msg_ready_tsbpd(&self, latency: Duration, start_time: Instant) -> Option<usize> {
         let msg_size = self.next_msg_ready()?;
 
-        let pack = self.buffer.front().unwrap().as_ref().unwrap();
+        let (_, pack) = self.buffer.front().unwrap().as_ref().unwrap();
 
-        if (start_time + Duration::from_micros(pack.timestamp as u64) + latency) <= Instant::now() {
+        if pack.timestamp.instant(start_time) + latency <= Instant::now() {
             debug!(
-                "Packet was deemed reaady for release, Now={:?}, Ts={:?}, Latency={:?}, len={}, sn={}",
+                "Packet was deemed reaady for release, Now={:?}, Ts={}us, Latency={:?}, len={}, sn={}",
                 Instant::now() - start_time,
-                Duration::from_micros(pack.timestamp as u64),
+                pack.timestamp,
                 latency,
                 msg_size,
                 pack.seq_number
@@ -122,40 +356,60 @@ impl RecvBuffer {
 
     /// Check if the next message is available. Returns `None` if there is no message,
     /// and `Some(i)` if there is a message available, where `i` is the number of packets this message spans
+    ///
+    /// A message is only available once all of its packets arrived, in
+    /// order, with its message number. What can't make a message is left
+    /// for `drop_broken_messages`
     pub fn next_msg_ready(&self) -> Option<usize> {
-        let first = self.buffer.front();
-        if let Some(Some(first)) = first {
-            // we have a first packet, make sure it has the start flag set
-            assert!(first.message_loc.contains(PacketLocation::FIRST));
-
-            let mut count = 1;
-
-            for i in &self.buffer {
-                match i {
-                    Some(ref pack) if pack.message_loc.contains(PacketLocation::LAST) => {
-                        return Some(count)
-                    }
-                    None => return None,
-                    _ => count += 1,
+        if self.broken_message().is_some() {
+            return None;
+        }
+
+        let mut count = 1;
+        for i in &self.buffer {
+            match i {
+                Some((_, pack)) if pack.message_loc.contains(PacketLocation::LAST) => {
+                    return Some(count)
                 }
+                None => return None,
+                _ => count += 1,
             }
         }
 
         None
     }
 
+    /// Check if there is a complete message after a missing packet that can
+    /// be delivered before the messages ahead of it, as its packets aren't
+    /// marked for in order delivery
+    ///
+    /// Returns `None` if there is no such message, or `Some((idx, len))`,
+    /// `idx` being the index of its first packet and `len` the number of
+    /// packets it spans
+    pub fn unordered_msg_ready(&self) -> Option<(usize, usize)> {
+        self.unordered.iter().find_map(|&(first, len)| {
+            let idx = (first - self.head) as usize;
+            match self.buffer.get(idx) {
+                Some(Some(_)) if idx > 0 && !self.is_dropped(first) => Some((idx, len)),
+                _ => None,
+            }
+        })
+    }
+
     pub fn next_message_release_time(
         &self,
         start_time: Instant,
         latency: Duration,
     ) -> Option<Instant> {
-        let _msg_size = self.next_msg_ready()?;
+        self.next_msg_ready()?;
 
         Some(
-            start_time
-                + Duration::from_micros(
-                    self.buffer.front().unwrap().as_ref().unwrap().timestamp as u64,
-                )
+            self.buffer[0]
+                .as_ref()
+                .unwrap()
+                .1
+                .timestamp
+                .instant(start_time)
                 + latency,
         )
     }
@@ -166,35 +420,65 @@ impl RecvBuffer {
         &mut self,
         latency: Duration,
         start_time: Instant,
-    ) -> Option<(i32, Bytes)> {
+    ) -> Option<(TimeStamp, Bytes)> {
         self.next_msg_ready_tsbpd(latency, start_time)
             .map(|_| self.next_msg().unwrap())
     }
 
     /// Check if there is an available message, returning, and its origin timestamp it if found
-    pub fn next_msg(&mut self) -> Option<(i32, Bytes)> {
+    pub fn next_msg(&mut self) -> Option<(TimeStamp, Bytes)> {
         let count = self.next_msg_ready()?;
 
+        let msg = self.take_msg(0, count);
+        self.buffer.drain(0..count);
         self.head += count as u32;
+        self.skip_dropped();
 
-        let origin_ts = self.buffer[0].as_ref().unwrap().timestamp;
+        Some(msg)
+    }
 
-        // optimize for single packet messages
-        if count == 1 {
-            return Some((origin_ts, self.buffer.pop_front().unwrap().unwrap().payload));
-        }
+    /// Check if there is a message that can be delivered out of order,
+    /// returning it and its origin timestamp if found. Its packets are
+    /// skipped once the messages before it are released or dropped
+    pub fn next_unordered_msg(&mut self) -> Option<(TimeStamp, Bytes)> {
+        let (idx, count) = self.unordered_msg_ready()?;
 
-        // accumulate the rest
-        Some((
-            origin_ts,
-            self.buffer
-                .drain(0..count)
-                .fold(BytesMut::new(), |mut bytes, pack| {
-                    bytes.extend(pack.unwrap().payload);
+        let msg = self.take_msg(idx, count);
+        let first = self.head + idx as u32;
+        self.mark_dropped(first, first + (count - 1) as u32);
+        self.unordered.retain(|&(f, _)| f != first);
+
+        Some(msg)
+    }
+
+    /// Take the `count` packets from `idx` on out of the buffer, putting
+    /// their payloads together
+    fn take_msg(&mut self, idx: usize, count: usize) -> (TimeStamp, Bytes) {
+        let origin_ts = self.buffer[idx].as_ref().unwrap().1.timestamp;
+
+        let now = Instant::now();
+        let jitter = &mut self.jitter;
+        let mut release = |entry: Option<(Instant, DataPacket)>| {
+            let (arrival, pack) = entry.unwrap();
+            jitter.record(now.saturating_duration_since(arrival));
+            pack.payload
+        };
+
+        let buffer = &mut self.buffer;
+        let payload = if count == 1 {
+            // optimize for single packet messages
+            release(buffer[idx].take())
+        } else {
+            // accumulate the rest
+            (idx..idx + count)
+                .fold(BytesMut::new(), |mut bytes, i| {
+                    bytes.extend(release(buffer[i].take()));
                     bytes
                 })
-                .freeze(),
-        ))
+                .freeze()
+        };
+
+        (origin_ts, payload)
     }
 }
 
@@ -207,7 +491,7 @@ impl fmt::Debug for RecvBuffer {
                 .iter()
                 .map(|o| o
                     .as_ref()
-                    .map(|pack| (pack.seq_number.as_raw(), pack.message_loc)))
+                    .map(|(_, pack)| (pack.seq_number.as_raw(), pack.message_loc)))
                 .collect::<Vec<_>>()
         )
     }
@@ -217,16 +501,22 @@ impl fmt::Debug for RecvBuffer {
 mod test {
 
     use super::RecvBuffer;
-    use crate::{packet::PacketLocation, DataPacket, MsgNumber, SeqNumber, SocketID};
+    use crate::{
+        packet::PacketLocation, DataEncryption, DataPacket, MsgNumber, SeqNumber, SocketID,
+        TimeStamp,
+    };
     use bytes::Bytes;
+    use std::time::{Duration, Instant};
 
     fn basic_pack() -> DataPacket {
         DataPacket {
             seq_number: SeqNumber::new_truncate(5),
             message_loc: PacketLocation::FIRST,
             in_order_delivery: false,
+            retransmitted: false,
+            encryption: DataEncryption::None,
             message_number: MsgNumber(0),
-            timestamp: 0,
+            timestamp: TimeStamp(0),
             dest_sockid: SocketID(4),
             payload: Bytes::new(),
         }
@@ -310,7 +600,10 @@ mod test {
         });
 
         assert_eq!(buf.next_msg_ready(), Some(1));
-        assert_eq!(buf.next_msg(), Some((0, From::from(&b"hello"[..]))));
+        assert_eq!(
+            buf.next_msg(),
+            Some((TimeStamp(0), From::from(&b"hello"[..])))
+        );
         assert_eq!(buf.next_release(), SeqNumber(6));
         assert_eq!(buf.buffer.len(), 1);
     }
@@ -338,8 +631,364 @@ mod test {
         });
 
         assert_eq!(buf.next_msg_ready(), Some(3));
-        assert_eq!(buf.next_msg(), Some((0, From::from(&b"helloyasnas"[..]))));
+        assert_eq!(
+            buf.next_msg(),
+            Some((TimeStamp(0), From::from(&b"helloyasnas"[..])))
+        );
         assert_eq!(buf.next_release(), SeqNumber(8));
         assert_eq!(buf.buffer.len(), 0);
     }
+
+    #[test]
+    fn broken_messages_dropped() {
+        let mut buf = RecvBuffer::new(SeqNumber::new_truncate(5));
+        // the end of message 1, whose start was dropped, the start of
+        // message 2 cut off by message 3, then all of message 4
+        for &(seq, msg, loc) in &[
+            (5, 1, PacketLocation::empty()),
+            (6, 1, PacketLocation::LAST),
+            (7, 2, PacketLocation::FIRST),
+            (8, 3, PacketLocation::FIRST | PacketLocation::LAST),
+            (9, 4, PacketLocation::FIRST),
+            (10, 4, PacketLocation::LAST),
+        ] {
+            buf.add(DataPacket {
+                seq_number: SeqNumber(seq),
+                message_number: MsgNumber(msg),
+                message_loc: loc,
+                payload: Bytes::from(seq.to_string()),
+                ..basic_pack()
+            });
+        }
+
+        assert_eq!(buf.next_msg_ready(), None);
+        assert_eq!(buf.drop_broken_messages(), 3);
+        assert_eq!(buf.next_release(), SeqNumber(8));
+        assert_eq!(buf.next_msg(), Some((TimeStamp(0), Bytes::from("8"))));
+        assert_eq!(buf.drop_broken_messages(), 0);
+        assert_eq!(buf.next_msg(), Some((TimeStamp(0), Bytes::from("910"))));
+    }
+
+    #[test]
+    fn message_number_mismatch() {
+        let mut buf = RecvBuffer::new(SeqNumber::new_truncate(5));
+        for &(seq, msg, loc) in &[
+            (5, 1, PacketLocation::FIRST),
+            (6, 2, PacketLocation::LAST),
+            (7, 3, PacketLocation::FIRST),
+        ] {
+            buf.add(DataPacket {
+                seq_number: SeqNumber(seq),
+                message_number: MsgNumber(msg),
+                message_loc: loc,
+                ..basic_pack()
+            });
+        }
+
+        // neither packet can be part of a whole message
+        assert_eq!(buf.next_msg_ready(), None);
+        assert_eq!(buf.drop_broken_messages(), 2);
+
+        // the start of a message waits for the rest, even after a gap
+        buf.add(DataPacket {
+            seq_number: SeqNumber(9),
+            message_number: MsgNumber(4),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            ..basic_pack()
+        });
+        assert_eq!(buf.drop_broken_messages(), 0);
+        assert_eq!(buf.next_release(), SeqNumber(7));
+    }
+
+    #[test]
+    fn jitter_histogram() {
+        let mut buf = RecvBuffer::new(SeqNumber::new_truncate(5));
+        buf.set_jitter_bucket_width(Duration::from_millis(10));
+
+        let now = Instant::now();
+        // arrived 5, 25, and 45ms ago, the last two being a single message
+        for (sn, ago, loc) in &[
+            (5, 5, PacketLocation::FIRST | PacketLocation::LAST),
+            (6, 25, PacketLocation::FIRST),
+            (7, 45, PacketLocation::LAST),
+        ] {
+            buf.add_at(
+                DataPacket {
+                    seq_number: SeqNumber(*sn),
+                    message_loc: *loc,
+                    ..basic_pack()
+                },
+                now - Duration::from_millis(*ago),
+            );
+        }
+        assert_eq!(buf.jitter_histogram().count(), 0);
+
+        assert!(buf.next_msg().is_some());
+        assert!(buf.next_msg().is_some());
+
+        let hist = buf.jitter_histogram();
+        assert_eq!(hist.count(), 3);
+        assert_eq!(&hist.buckets()[..6], &[1, 0, 1, 0, 1, 0]);
+        assert_eq!(hist.p50(), Some(Duration::from_millis(30)));
+        assert_eq!(hist.p99(), Some(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn unordered_delivery() {
+        let mut buf = RecvBuffer::new(SeqNumber::new_truncate(5));
+        let pack = |seq, msg, loc, in_order| DataPacket {
+            seq_number: SeqNumber(seq),
+            message_number: MsgNumber(msg),
+            message_loc: loc,
+            in_order_delivery: in_order,
+            payload: Bytes::from(seq.to_string()),
+            ..basic_pack()
+        };
+
+        // 5 is missing, 6 has to wait for it, but 7..=8 and 9 don't
+        buf.add(pack(
+            6,
+            2,
+            PacketLocation::FIRST | PacketLocation::LAST,
+            true,
+        ));
+        buf.add(pack(8, 3, PacketLocation::LAST, false));
+        assert_eq!(buf.unordered_msg_ready(), None);
+        buf.add(pack(7, 3, PacketLocation::FIRST, false));
+        buf.add(pack(
+            9,
+            4,
+            PacketLocation::FIRST | PacketLocation::LAST,
+            false,
+        ));
+
+        assert_eq!(buf.next_msg(), None);
+        assert_eq!(buf.unordered_msg_ready(), Some((2, 2)));
+        assert_eq!(
+            buf.next_unordered_msg(),
+            Some((TimeStamp(0), Bytes::from("78")))
+        );
+        assert_eq!(
+            buf.next_unordered_msg(),
+            Some((TimeStamp(0), Bytes::from("9")))
+        );
+        assert_eq!(buf.next_unordered_msg(), None);
+
+        // they're not taken again, and the rest follow in order
+        buf.add(pack(8, 3, PacketLocation::LAST, false));
+        buf.add(pack(
+            5,
+            1,
+            PacketLocation::FIRST | PacketLocation::LAST,
+            true,
+        ));
+        assert_eq!(buf.next_msg(), Some((TimeStamp(0), Bytes::from("5"))));
+        assert_eq!(buf.next_msg(), Some((TimeStamp(0), Bytes::from("6"))));
+        assert_eq!(buf.next_msg(), None);
+        assert_eq!(buf.next_release(), SeqNumber(10));
+    }
+
+    #[test]
+    fn too_late_gap_dropped() {
+        let latency = Duration::from_millis(20);
+        let mut buf = RecvBuffer::new(SeqNumber::new_truncate(5));
+
+        // 5 never arrives
+        buf.add(DataPacket {
+            seq_number: SeqNumber(6),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            timestamp: TimeStamp(1_000),
+            payload: From::from(&b"six"[..]),
+            ..basic_pack()
+        });
+
+        // 6 isn't due yet, so keep waiting for 5
+        assert_eq!(buf.drop_too_late_packets(latency, Instant::now()), 0);
+        assert_eq!(buf.next_release(), SeqNumber(5));
+        assert_eq!(buf.next_msg_tsbpd(latency, Instant::now()), None);
+
+        // once 6 is due, 5 is given up on
+        let start = Instant::now() - Duration::from_millis(100);
+        assert_eq!(buf.drop_too_late_packets(latency, start), 1);
+        assert_eq!(buf.next_release(), SeqNumber(6));
+        assert_eq!(
+            buf.next_msg_tsbpd(latency, start),
+            Some((TimeStamp(1_000), From::from(&b"six"[..])))
+        );
+    }
+
+    #[test]
+    fn too_late_drops_rest_of_message() {
+        let latency = Duration::from_millis(20);
+        let mut buf = RecvBuffer::new(SeqNumber::new_truncate(5));
+
+        // 5 (the start of a message) never arrives
+        buf.add(DataPacket {
+            seq_number: SeqNumber(6),
+            message_loc: PacketLocation::empty(),
+            ..basic_pack()
+        });
+        buf.add(DataPacket {
+            seq_number: SeqNumber(7),
+            message_loc: PacketLocation::LAST,
+            ..basic_pack()
+        });
+        buf.add(DataPacket {
+            seq_number: SeqNumber(8),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            payload: From::from(&b"eight"[..]),
+            ..basic_pack()
+        });
+
+        let start = Instant::now() - Duration::from_millis(100);
+        assert_eq!(buf.drop_too_late_packets(latency, start), 3);
+        assert_eq!(buf.next_release(), SeqNumber(8));
+        assert_eq!(
+            buf.next_msg_tsbpd(latency, start),
+            Some((TimeStamp(0), From::from(&b"eight"[..])))
+        );
+    }
+
+    #[test]
+    fn too_late_gap_in_message() {
+        let mut buf = RecvBuffer::new(SeqNumber::new_truncate(5));
+        let latency = Duration::from_millis(20);
+
+        // 5..=7 is a message missing its middle
+        buf.add(DataPacket {
+            seq_number: SeqNumber(5),
+            message_loc: PacketLocation::FIRST,
+            ..basic_pack()
+        });
+        buf.add(DataPacket {
+            seq_number: SeqNumber(7),
+            message_loc: PacketLocation::LAST,
+            ..basic_pack()
+        });
+        buf.add(DataPacket {
+            seq_number: SeqNumber(8),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            payload: From::from(&b"eight"[..]),
+            ..basic_pack()
+        });
+
+        // not too late yet
+        assert_eq!(buf.drop_too_late_packets(latency, Instant::now()), 0);
+        assert_eq!(buf.next_msg_tsbpd(latency, Instant::now()), None);
+
+        let start = Instant::now() - Duration::from_millis(100);
+        assert_eq!(buf.drop_too_late_packets(latency, start), 3);
+        assert_eq!(buf.next_release(), SeqNumber(8));
+        assert_eq!(
+            buf.next_msg_tsbpd(latency, start),
+            Some((TimeStamp(0), From::from(&b"eight"[..])))
+        );
+    }
+
+    #[test]
+    fn drop_message_at_head() {
+        let mut buf = RecvBuffer::new(SeqNumber::new_truncate(5));
+
+        // 5..=7 is a message that will never be completed
+        buf.add(DataPacket {
+            seq_number: SeqNumber(6),
+            message_loc: PacketLocation::empty(),
+            ..basic_pack()
+        });
+        buf.add(DataPacket {
+            seq_number: SeqNumber(8),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            payload: From::from(&b"eight"[..]),
+            ..basic_pack()
+        });
+        assert_eq!(buf.next_msg(), None);
+
+        assert_eq!(buf.drop_message(SeqNumber(5), SeqNumber(7)), 1);
+        assert_eq!(buf.next_release(), SeqNumber(8));
+        assert_eq!(
+            buf.next_msg(),
+            Some((TimeStamp(0), From::from(&b"eight"[..])))
+        );
+    }
+
+    #[test]
+    fn drop_message_ahead() {
+        let mut buf = RecvBuffer::new(SeqNumber::new_truncate(5));
+
+        buf.drop_message(SeqNumber(6), SeqNumber(7));
+        assert_eq!(buf.next_release(), SeqNumber(5));
+
+        // packets from the dropped message are ignored if they show up
+        buf.add(DataPacket {
+            seq_number: SeqNumber(7),
+            message_loc: PacketLocation::LAST,
+            ..basic_pack()
+        });
+        buf.add(DataPacket {
+            seq_number: SeqNumber(8),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            payload: From::from(&b"eight"[..]),
+            ..basic_pack()
+        });
+        buf.add(DataPacket {
+            seq_number: SeqNumber(5),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            payload: From::from(&b"five"[..]),
+            ..basic_pack()
+        });
+
+        // once 5 is released, the dropped message is skipped
+        assert_eq!(
+            buf.next_msg(),
+            Some((TimeStamp(0), From::from(&b"five"[..])))
+        );
+        assert_eq!(buf.next_release(), SeqNumber(8));
+        assert_eq!(
+            buf.next_msg(),
+            Some((TimeStamp(0), From::from(&b"eight"[..])))
+        );
+    }
+
+    #[test]
+    fn drop_message_far_ahead() {
+        let mut buf = RecvBuffer::new(SeqNumber::new_truncate(5));
+        buf.add(DataPacket {
+            seq_number: SeqNumber(6),
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            ..basic_pack()
+        });
+
+        // only the buffered packets are looked at, not the whole range
+        assert_eq!(buf.drop_message(SeqNumber(6), SeqNumber(6) + (1 << 29)), 1);
+        assert!(buf.is_dropped(SeqNumber(6) + (1 << 28)));
+        assert_eq!(buf.next_release(), SeqNumber(5));
+    }
+
+    #[test]
+    fn drop_message_backwards() {
+        let mut buf = RecvBuffer::new(SeqNumber::new_truncate(5));
+
+        assert_eq!(buf.drop_message(SeqNumber(8), SeqNumber(6)), 0);
+        assert!(!buf.is_dropped(SeqNumber(7)));
+    }
+
+    #[test]
+    fn drop_message_ranges_merged() {
+        let mut buf = RecvBuffer::new(SeqNumber::new_truncate(5));
+
+        for _ in 0..10 {
+            buf.drop_message(SeqNumber(10), SeqNumber(12));
+        }
+        buf.drop_message(SeqNumber(13), SeqNumber(15));
+        buf.drop_message(SeqNumber(8), SeqNumber(11));
+        buf.drop_message(SeqNumber(20), SeqNumber(21));
+
+        assert_eq!(
+            buf.dropped,
+            [
+                (SeqNumber(8), SeqNumber(15)),
+                (SeqNumber(20), SeqNumber(21))
+            ]
+        );
+    }
 }
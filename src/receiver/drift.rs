@@ -0,0 +1,81 @@
+/// How many samples are averaged before the drift is checked
+const DRIFT_SAMPLES: u32 = 1_000;
+
+/// How far the averaged drift can get, in microseconds, before the TSBPD
+/// time base is moved to follow the peer's clock
+const MAX_DRIFT: i32 = 5_000;
+
+/// Follows how far the peer's clock drifts from this end's, like the
+/// reference implementation's drift tracer
+///
+/// Each sample is how late, in microseconds, a packet arrived compared to
+/// when the time base says it was sent plus the one way delay. The samples
+/// are averaged in batches of `DRIFT_SAMPLES`, and once the average gets past
+/// `MAX_DRIFT` the time base is moved by it. Smaller drifts are left alone,
+/// as they are well within the latency
+#[derive(Debug, Default)]
+pub struct DriftTracer {
+    sum: i64,
+    samples: u32,
+    drift: i32,
+}
+
+impl DriftTracer {
+    /// Add a sample, returning how far to move the time base, in
+    /// microseconds, if it should be
+    pub fn sample(&mut self, drift: i32) -> Option<i32> {
+        self.sum += i64::from(drift);
+        self.samples += 1;
+        if self.samples < DRIFT_SAMPLES {
+            return None;
+        }
+
+        let average = (self.sum / i64::from(self.samples)) as i32;
+        self.sum = 0;
+        self.samples = 0;
+        if average.abs() <= MAX_DRIFT {
+            return None;
+        }
+
+        self.drift += average;
+        Some(average)
+    }
+
+    /// How far the time base has been moved in all, in microseconds
+    pub fn drift(&self) -> i32 {
+        self.drift
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DriftTracer, DRIFT_SAMPLES};
+
+    #[test]
+    fn small_drift_ignored() {
+        let mut tracer = DriftTracer::default();
+        for _ in 0..DRIFT_SAMPLES * 3 {
+            assert_eq!(tracer.sample(4_000), None);
+        }
+        assert_eq!(tracer.drift(), 0);
+    }
+
+    #[test]
+    fn large_drift_followed() {
+        let mut tracer = DriftTracer::default();
+        for i in 1..DRIFT_SAMPLES {
+            // averages 6ms
+            let sample = if i % 2 == 0 { 4_000 } else { 8_000 };
+            assert_eq!(tracer.sample(sample), None);
+        }
+        assert_eq!(tracer.sample(4_000), Some(6_000));
+        assert_eq!(tracer.drift(), 6_000);
+
+        // the next batch starts over
+        for _ in 1..DRIFT_SAMPLES {
+            assert_eq!(tracer.sample(-7_000), None);
+        }
+        assert_eq!(tracer.sample(-7_000), Some(-7_000));
+        assert_eq!(tracer.drift(), -1_000);
+    }
+}
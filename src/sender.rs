@@ -3,23 +3,371 @@ use failure::{format_err, Error};
 use futures::prelude::*;
 use futures::ready;
 use log::{debug, info, trace, warn};
-use tokio::time::{delay_for, interval, Delay, Interval};
+use tokio::time::{delay_for, delay_until, interval, Delay, Interval};
 
 use crate::connection::HandshakeReturner;
+#[cfg(feature = "crypto")]
+use crate::crypto::{KeyRefresh, SenderKeys};
 use crate::loss_compression::decompress_loss_list;
 use crate::packet::{
-    ControlPacket, ControlTypes, DataPacket, Packet, PacketLocation, SrtControlPacket,
+    ControlPacket, ControlTypes, DataEncryption, DataPacket, KmState, Packet, PacketLocation,
+    PeerStats, SocketType, SrtControlPacket, SrtKeyMessage, SrtShakeFlags,
 };
 use crate::sink_send_wrapper::SinkSendWrapper;
-use crate::{CCData, CongestCtrl, ConnectionSettings, MsgNumber, SeqNumber, Stats};
+use crate::stats::JitterHistogram;
+use crate::{
+    CCData, CongestCtrl, ConnectionSettings, MsgCtrl, MsgNumber, PeerError, SeqNumber, Stats,
+    TimeStamp,
+};
 
+use std::cmp::{max, min};
 use std::collections::VecDeque;
+use std::fmt::{self, Debug, Formatter};
 use std::io;
 use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 
+/// A message, or what's left of one, waiting to be sent
+struct PendingMessage {
+    /// The time the message is timestamped with
+    time: Instant,
+    payload: Bytes,
+
+    /// When the message is dropped, if it has a time to live
+    expiry: Option<Instant>,
+
+    /// If the peer has to deliver the message after the ones before it
+    in_order: bool,
+}
+
+/// Which packets the peer reports lost are retransmitted, like
+/// SRTO_RETRANSMITALGO
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RetransmitAlgorithm {
+    /// Retransmit a packet each time the peer reports it lost
+    #[default]
+    EveryReport,
+
+    /// Retransmit a packet again only once a retransmission timeout has passed
+    /// since it last was, relying on the peer's periodic NAK reports to ask
+    /// for it again if it's still lost. Peers that don't announce NAKREPORT
+    /// in the handshake get every report answered
+    Reduced,
+}
+
+/// How retransmissions share the send slots with new data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RetransmitPriority {
+    /// Packets the peer reported lost are always sent before new data, for
+    /// the fastest recovery
+    #[default]
+    First,
+
+    /// While there are both lost packets and new data to send, one packet is
+    /// retransmitted for every this many new ones, so fresh data keeps
+    /// flowing smoothly during a burst of losses
+    Interleaved(u32),
+}
+
+/// Why the sender dropped a message instead of retransmitting it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendDropReason {
+    /// It was too old for the peer to deliver, see `SrtSocketBuilder::too_late_drop`
+    TooLate,
+
+    /// Its time to live ran out, see `MsgCtrl::ttl`
+    Expired,
+}
+
+/// The sender started dropping messages instead of retransmitting them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendDropEvent {
+    /// The peer the messages were for
+    pub remote: SocketAddr,
+
+    /// Why the message that started it was dropped
+    pub reason: SendDropReason,
+
+    /// The number of packets dropped so far, counting the ones that started it
+    pub dropped_packets: u32,
+
+    /// The number of payload bytes in those packets
+    pub dropped_bytes: u64,
+}
+
+/// What to call when the sender starts dropping messages
+#[derive(Clone)]
+pub(crate) struct SendDropHandler(Arc<dyn Fn(SendDropEvent) + Send + Sync>);
+
+impl SendDropHandler {
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(SendDropEvent) + Send + Sync + 'static,
+    {
+        SendDropHandler(Arc::new(handler))
+    }
+}
+
+impl Debug for SendDropHandler {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "SendDropHandler")
+    }
+}
+
+/// A packet that has been sent but not yet acknowledged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InFlightEntry {
+    /// The sequence number of the packet
+    pub seq_number: SeqNumber,
+
+    /// When the packet was last sent, either originally or as a retransmission
+    pub last_sent: Instant,
+
+    /// The number of times the packet has been retransmitted
+    pub retransmits: u32,
+}
+
+/// A packet in the sender's buffer, along with its send history
+struct SentPacket {
+    packet: DataPacket,
+    last_sent: Instant,
+    retransmits: u32,
+
+    /// When the message the packet is in is dropped if it isn't acknowledged
+    expiry: Option<Instant>,
+
+    /// If the packet won't be retransmitted any more, and is only kept until
+    /// the packets before it are acknowledged
+    dropped: bool,
+}
+
+/// How many times key material is sent before giving up on the peer
+/// confirming it, like the reference implementation
+const KM_MAX_SENDS: u32 = 10;
+
+/// How much older than the peer's latency a packet has to be to be dropped
+/// instead of retransmitted, two SYN intervals like the reference implementation
+const TOO_LATE_MARGIN: Duration = Duration::from_millis(20);
+
+/// UDT's SYN interval, added to the retransmission timeout
+const SYN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The size of the IPv4, UDP and SRT headers, which count towards the bandwidth
+const PACKET_OVERHEAD: usize = 44;
+
+/// The resolution of the timer, so how much before a send is due it wakes up
+/// when pacing precisely, to spin out the rest of the wait
+const TIMER_RESOLUTION: Duration = Duration::from_millis(1);
+
+/// How far behind schedule precise pacing can fall before the time is given
+/// up on, so a stall isn't made up for with a long burst
+const MAX_CATCH_UP: Duration = Duration::from_millis(2);
+
+/// Tells when the next packet is due. The timer only has millisecond
+/// resolution, so at high packet rates sends come late and bunched up. When
+/// pacing precisely, it wakes up early and spins out the rest of the wait
+/// while there's something to send, and schedules each send from when the
+/// last one was due rather than when it happened, making up for late ones
+struct SendTimer {
+    precise: bool,
+
+    /// When the next send is due
+    next: tokio::time::Instant,
+
+    delay: Delay,
+}
+
+impl SendTimer {
+    fn new() -> SendTimer {
+        let next = tokio::time::Instant::now() + Duration::from_millis(1);
+
+        SendTimer {
+            precise: false,
+            next,
+            delay: delay_until(next),
+        }
+    }
+
+    /// Schedule the next send `period` after this one, or, when pacing
+    /// precisely and `catch_up`, `period` after this one was due
+    fn schedule(&mut self, period: Duration, catch_up: bool) {
+        let now = tokio::time::Instant::now();
+        let due = self.next + period;
+
+        self.next = if !self.precise || !catch_up {
+            now + period
+        } else if now.saturating_duration_since(due) > MAX_CATCH_UP {
+            now
+        } else {
+            due
+        };
+    }
+
+    /// Hold the next send back until `deadline`, if it's later
+    fn delay_until(&mut self, deadline: tokio::time::Instant) {
+        self.next = max(self.next, deadline);
+    }
+
+    /// Wait for the next send to be due, spinning out the end of the wait if
+    /// pacing precisely and `spin`
+    fn poll(&mut self, cx: &mut Context, spin: bool) -> Poll<()> {
+        let spin = spin && self.precise;
+        if spin {
+            let now = tokio::time::Instant::now();
+            if now >= self.next {
+                return Poll::Ready(());
+            }
+            if self.next - now <= TIMER_RESOLUTION {
+                // the timer can't wait this precisely, so check again as
+                // soon as other tasks have had their turn
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+        }
+
+        let wake = if spin {
+            self.next - TIMER_RESOLUTION
+        } else {
+            self.next
+        };
+        if self.delay.deadline() != wake {
+            self.delay.reset(wake);
+        }
+        ready!(Pin::new(&mut self.delay).poll(cx));
+
+        if spin {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+}
+
+/// A token bucket that spaces packets out so that no more than a set number
+/// of bytes are sent each second, with bursts of at most `burst` bytes
+struct Pacer {
+    /// The bytes allowed per second
+    rate: f64,
+
+    /// The most bytes that can be saved up while nothing is sent
+    burst: f64,
+
+    /// The bytes that can be sent now, negative after a packet is sent that
+    /// didn't fit
+    tokens: f64,
+
+    last_refill: Instant,
+}
+
+impl Pacer {
+    /// Pace sending at `rate` bits/s, starting with a full bucket. A rate of
+    /// 0 doesn't hold anything back
+    fn new(rate: u64, burst: usize) -> Pacer {
+        Pacer {
+            rate: rate as f64 / 8.,
+            burst: burst as f64,
+            tokens: burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Pace at `rate` bits/s from now on
+    fn set_rate(&mut self, rate: u64) {
+        self.rate = rate as f64 / 8.;
+    }
+
+    /// Take a packet of `size` bytes sent at `now` out of the bucket,
+    /// returning how long until the next one may be sent
+    fn take(&mut self, size: usize, now: Instant) -> Duration {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = f64::min(self.tokens + elapsed * self.rate, self.burst) - size as f64;
+        self.last_refill = now;
+
+        if self.tokens >= 0. || self.rate <= 0. {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// How long the first input rate estimate is made over, so pacing starts soon
+const INPUT_RATE_FIRST_PERIOD: Duration = Duration::from_millis(500);
+
+/// How long each later input rate estimate is made over
+const INPUT_RATE_PERIOD: Duration = Duration::from_secs(1);
+
+/// The lowest input rate pacing follows, in bits/s, so a source that has
+/// been slow for a while isn't held to a trickle when it speeds up again
+const MIN_INPUT_RATE: u64 = 1_000_000;
+
+/// Estimates the rate data is given to the sender at, counting the headers it
+/// will be sent with, over periods starting from the first data given
+///
+/// A period the source was idle for the length of doesn't count, the next
+/// one starts with the data after it
+struct InputRate {
+    period: Duration,
+    period_start: Option<Instant>,
+    last_input: Option<Instant>,
+    bytes: u64,
+
+    /// The last estimate, in bits/s
+    rate: Option<u64>,
+}
+
+impl InputRate {
+    fn new() -> InputRate {
+        InputRate {
+            period: INPUT_RATE_FIRST_PERIOD,
+            period_start: None,
+            last_input: None,
+            bytes: 0,
+            rate: None,
+        }
+    }
+
+    /// Count `bytes` of payload given at `now`, to be sent in `packets`
+    /// packets, returning the new estimate if that ends a period
+    fn add(&mut self, bytes: usize, packets: usize, now: Instant) -> Option<u64> {
+        let idle = self
+            .last_input
+            .is_some_and(|last| now.saturating_duration_since(last) >= self.period);
+        if idle {
+            self.period_start = None;
+            self.bytes = 0;
+        }
+        self.last_input = Some(now);
+        self.bytes += (bytes + packets * PACKET_OVERHEAD) as u64;
+
+        let period_start = *self.period_start.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(period_start);
+        if elapsed < self.period {
+            return None;
+        }
+
+        self.rate = Some(((self.bytes * 8) as f64 / elapsed.as_secs_f64()) as u64);
+        self.period = INPUT_RATE_PERIOD;
+        self.period_start = Some(now);
+        self.bytes = 0;
+
+        self.rate
+    }
+}
+
+/// Key material announcing new keys, resent until the peer confirms it
+struct KeyAnnouncement {
+    key_msg: SrtKeyMessage,
+    last_sent: Option<Instant>,
+    sends: u32,
+}
+
 pub struct Sender<T, CC> {
     sock: T,
 
@@ -37,7 +385,20 @@ pub struct Sender<T, CC> {
     /// It will be split into multiple packets, and the remaining
     /// bits will be put back into pending packets, setting
     /// at_msg_beginning to false
-    pending_packets: VecDeque<(Instant, Bytes)>,
+    pending_packets: VecDeque<PendingMessage>,
+
+    /// The number of packets `pending_packets` will be sent as
+    pending_packet_count: usize,
+
+    /// The payload bytes waiting to be sent or waiting for an ACK
+    buffered_bytes: usize,
+
+    /// The most payload bytes that can be waiting to be sent or waiting for an
+    /// ACK before no more are accepted, if limited
+    send_buffer_size: Option<usize>,
+
+    /// If `poll_ready` fails instead of waiting when there's no room
+    nonblocking: bool,
 
     /// True if pending_packets.first() has the entirety of a message, and not
     /// just the last segment.
@@ -53,10 +414,11 @@ pub struct Sender<T, CC> {
     //    sequence numbers of the lost packets fed back by the receiver
     //    through NAK packets or inserted in a timeout event. The numbers
     //    are stored in increasing order.
-    loss_list: VecDeque<DataPacket>,
+    // Each is only in it once, and leaves it when it's retransmitted
+    loss_list: VecDeque<SeqNumber>,
 
     /// The buffer to store packets for retransmision, sorted chronologically
-    buffer: VecDeque<DataPacket>,
+    buffer: VecDeque<SentPacket>,
 
     /// The first sequence number in buffer, so seq number i would be found at
     /// buffer[i - first_seq]
@@ -74,6 +436,10 @@ pub struct Sender<T, CC> {
     /// Round trip time variance
     rtt_var: i32,
 
+    /// The packets the peer last said it has room for in its buffer, at most
+    /// the max flow size agreed in the handshake
+    peer_flow_window: u32,
+
     /// packet arrival rate
     pkt_arr_rate: i32,
 
@@ -86,11 +452,76 @@ pub struct Sender<T, CC> {
     /// Total retransmitted packets
     retrans_packets: u32,
 
+    /// Total retransmissions sent, counting each time a packet is sent again
+    retransmits: u32,
+
+    /// Total payload bytes sent in retransmissions
+    retransmitted_bytes: u64,
+
+    /// Total times the send buffer filled up, holding back data given to the sender
+    send_buffer_overruns: u32,
+
+    /// If the send buffer was full when data was last given to the sender,
+    /// so each time it fills up is only counted once
+    overrun: bool,
+
+    /// Total losses reported for packets that had just been retransmitted
+    suppressed_retransmits: u32,
+
     /// Total received packets (packets that have been ACKed)
     recvd_packets: u32,
 
+    /// Total payload bytes sent, not counting retransmissions
+    sent_bytes: u64,
+
+    /// If packets too old for the peer to deliver are dropped instead of retransmitted
+    too_late_drop: bool,
+
+    /// Which packets the peer reports lost are retransmitted
+    retransmit_algorithm: RetransmitAlgorithm,
+
+    /// How retransmissions share the send slots with new data
+    retransmit_priority: RetransmitPriority,
+
+    /// New packets sent since the last retransmission
+    new_since_retransmit: u32,
+
+    /// When the peer last sent an ACK or NAK, or when everything waiting for
+    /// an ACK was last retransmitted for it not responding
+    last_response: Instant,
+
+    /// Total packets dropped for being too late or past their time to live
+    dropped_packets: u32,
+
+    /// Total payload bytes dropped for being too late or past their time to live
+    dropped_bytes: u64,
+
+    /// If no packet has been acknowledged since the last drop, so each run of
+    /// them is only reported once
+    dropping: bool,
+
+    /// Called when messages start being dropped
+    drop_handler: Option<SendDropHandler>,
+
+    /// The earliest a message waiting for an ACK has to be dropped, if any
+    /// have a time to live
+    next_expiry: Option<Instant>,
+
     /// The send timer
-    snd_timer: Delay,
+    snd_timer: SendTimer,
+
+    /// The shortest time allowed between two packets, whatever congestion control says
+    min_send_interval: Duration,
+
+    /// What spaces out data packets to stay under the max bandwidth, if there is one
+    pacer: Option<Pacer>,
+
+    /// The estimate of the input rate the max bandwidth is derived from, if
+    /// it isn't set outright
+    input_rate: Option<InputRate>,
+
+    /// How much over the input rate the max bandwidth is, in percent
+    bandwidth_overhead: u32,
 
     /// The interval to report stats with
     stats_interval: Interval,
@@ -102,6 +533,31 @@ pub struct Sender<T, CC> {
     /// This means that `close` has been called and the sender has been flushed,
     /// and it's just waiting for the socket to flush
     closed: bool,
+
+    /// How long closing waits for everything to be acknowledged before
+    /// shutting down anyway, if it gives up at all
+    linger: Option<Duration>,
+
+    /// When closing gives up waiting, once it's started
+    linger_timer: Option<Delay>,
+
+    /// The last statistics received from the peer's receiver, if they are exchanged
+    peer_stats: Option<PeerStats>,
+
+    /// What payloads are encrypted with, if the connection is encrypted
+    #[cfg(feature = "crypto")]
+    keys: Option<SenderKeys>,
+
+    /// When `keys` replaces the key in use
+    #[cfg(feature = "crypto")]
+    key_refresh: KeyRefresh,
+
+    /// The last new keys announced, if the peer hasn't confirmed them yet
+    key_announcement: Option<KeyAnnouncement>,
+
+    /// The state of the keys payloads are encrypted with, as the peer last
+    /// told about them
+    km_state: KmState,
 }
 
 impl<T, CC> Sender<T, CC>
@@ -124,12 +580,24 @@ where
 
         let init_seq_num = settings.init_seq_num;
 
+        // a peer that didn't take part in the key exchange can't decrypt
+        // anything
+        let km_state = match (settings.key_size, settings.km_state) {
+            (0, _) => KmState::Unsecured,
+            (_, KmState::Unsecured) => KmState::NoSecret,
+            (_, state) => state,
+        };
+
         Sender {
             sock,
             congest_ctrl,
             settings,
             hs_returner,
             pending_packets: VecDeque::new(),
+            pending_packet_count: 0,
+            buffered_bytes: 0,
+            send_buffer_size: None,
+            nonblocking: false,
             at_msg_beginning: true,
             next_seq_number: init_seq_num,
             next_message_number: MsgNumber::new_truncate(0),
@@ -139,25 +607,171 @@ where
             lr_acked_packet: init_seq_num,
             rtt: 10_000,
             rtt_var: 0,
+            peer_flow_window: settings.max_flow_size,
             pkt_arr_rate: 0,
             est_link_cap: 0,
             lost_packets: 0,
             retrans_packets: 0,
+            retransmits: 0,
+            retransmitted_bytes: 0,
+            send_buffer_overruns: 0,
+            overrun: false,
+            suppressed_retransmits: 0,
             recvd_packets: 0,
+            sent_bytes: 0,
+            too_late_drop: false,
+            retransmit_algorithm: RetransmitAlgorithm::default(),
+            retransmit_priority: RetransmitPriority::default(),
+            new_since_retransmit: 0,
+            last_response: Instant::now(),
+            dropped_packets: 0,
+            dropped_bytes: 0,
+            dropping: false,
+            drop_handler: None,
+            next_expiry: None,
             lr_acked_ack: -1,
-            snd_timer: delay_for(Duration::from_millis(1)),
+            snd_timer: SendTimer::new(),
+            min_send_interval: Duration::from_secs(0),
+            pacer: None,
+            input_rate: None,
+            bandwidth_overhead: 0,
             stats_interval: interval(Duration::from_secs(1)),
             send_wrapper: SinkSendWrapper::new(),
             closed: false,
+            linger: None,
+            linger_timer: None,
+            peer_stats: None,
+            #[cfg(feature = "crypto")]
+            keys: None,
+            #[cfg(feature = "crypto")]
+            key_refresh: KeyRefresh::default(),
+            key_announcement: None,
+            km_state,
         }
     }
 
+    /// Set the shortest time allowed between two packets, capping the packet rate
+    /// Defaults to zero, leaving pacing entirely to congestion control
+    pub fn set_min_send_interval(&mut self, ivl: Duration) {
+        self.min_send_interval = ivl;
+    }
+
+    /// Drop packets that are older than the peer's latency, so it can't
+    /// deliver them any more, instead of retransmitting them, and tell the
+    /// peer to stop waiting for them
+    /// Defaults to false, retransmitting until the peer acknowledges them
+    pub fn set_too_late_drop(&mut self, enabled: bool) {
+        self.too_late_drop = enabled;
+    }
+
+    /// Set which packets the peer reports lost are retransmitted
+    /// Defaults to `RetransmitAlgorithm::EveryReport`
+    pub fn set_retransmit_algorithm(&mut self, algorithm: RetransmitAlgorithm) {
+        self.retransmit_algorithm = algorithm;
+    }
+
+    /// Set how retransmissions share the send slots with new data
+    /// Defaults to `RetransmitPriority::First`
+    pub fn set_retransmit_priority(&mut self, priority: RetransmitPriority) {
+        self.retransmit_priority = priority;
+    }
+
+    /// Hold back data once `size` payload bytes are waiting to be sent or
+    /// waiting for an ACK, on top of the flow window
+    /// A message is accepted as long as the limit isn't reached yet, so it can
+    /// go over by up to one message
+    pub fn set_send_buffer_size(&mut self, size: usize) {
+        self.send_buffer_size = Some(size);
+    }
+
+    /// Wait at most `linger` for what's been sent to be acknowledged when
+    /// closing, retransmitting as usual, before shutting down anyway
+    /// Defaults to waiting until everything is acknowledged
+    pub fn set_linger(&mut self, linger: Duration) {
+        self.linger = Some(linger);
+    }
+
+    /// Call `handler` each time messages start being dropped instead of
+    /// retransmitted, for being too late or past their time to live
+    pub(crate) fn set_drop_handler(&mut self, handler: SendDropHandler) {
+        self.drop_handler = Some(handler);
+    }
+
+    /// Space packets out to within tens of microseconds of the congestion
+    /// control's send period or the max bandwidth, instead of the timer's
+    /// millisecond resolution, at the cost of spinning while a packet is
+    /// nearly due
+    /// Defaults to false
+    pub fn set_precise_pacing(&mut self, precise: bool) {
+        self.snd_timer.precise = precise;
+    }
+
+    /// Make `poll_ready` fail with `io::ErrorKind::WouldBlock` when there's no
+    /// room for more data, instead of waiting for it
+    /// Defaults to false
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// Never send data faster than `rate` bits/s, counting retransmissions and
+    /// the packet headers, by spacing packets out. 0 is no limit
+    /// Defaults to no limit, leaving pacing entirely to congestion control
+    pub fn set_max_bandwidth(&mut self, rate: u64) {
+        let burst = self.settings.max_packet_size as usize + PACKET_OVERHEAD;
+        self.pacer = Some(Pacer::new(rate, burst));
+        self.input_rate = None;
+    }
+
+    /// Never send data faster than `overhead` percent over the rate it's given
+    /// to the sender at, which is estimated over the first half second and
+    /// then again every second, so it follows the source's bitrate. Estimates
+    /// under 1 Mbit/s are taken as that
+    ///
+    /// Until the first estimate, there's no limit
+    pub fn set_bandwidth_overhead(&mut self, overhead: u32) {
+        self.pacer = None;
+        self.input_rate = Some(InputRate::new());
+        self.bandwidth_overhead = overhead;
+    }
+
+    /// The estimated rate data is given to the sender at in bits/s, if the
+    /// max bandwidth is derived from it and it's been estimated yet
+    pub fn input_rate(&self) -> Option<u64> {
+        self.input_rate.as_ref().and_then(|input| input.rate)
+    }
+
     /// Set the interval to get statistics on
     /// Defaults to one second
     pub fn set_stats_interval(&mut self, ivl: Duration) {
         self.stats_interval = interval(ivl);
     }
 
+    /// Encrypt payloads with `keys`, announcing new ones to the peer as they
+    /// replace each other
+    #[cfg(feature = "crypto")]
+    pub fn set_keys(&mut self, keys: SenderKeys) {
+        self.keys = Some(keys);
+    }
+
+    /// Set when the key in use is replaced
+    /// Defaults to every 2^24 packets, announced 2^12 packets before
+    #[cfg(feature = "crypto")]
+    pub fn set_key_refresh(&mut self, refresh: KeyRefresh) {
+        self.key_refresh = refresh;
+    }
+
+    #[cfg(feature = "crypto")]
+    pub fn key_refresh(&self) -> KeyRefresh {
+        self.key_refresh
+    }
+
+    /// Set how long a key is used for at most before it's replaced, even if
+    /// fewer packets than the refresh rate were sent with it
+    #[cfg(feature = "crypto")]
+    pub fn set_key_refresh_period(&mut self, period: Duration) {
+        self.key_refresh.period = Some(period);
+    }
+
     pub fn settings(&self) -> &ConnectionSettings {
         &self.settings
     }
@@ -166,6 +780,47 @@ where
         self.settings.remote
     }
 
+    /// The state of the keys payloads are encrypted with: `Secured` once the
+    /// peer has confirmed it has them, `BadSecret` if it couldn't unwrap them,
+    /// `NoSecret` if it has no passphrase, and `Unsecured` if nothing is
+    /// encrypted
+    pub fn km_state(&self) -> KmState {
+        self.km_state
+    }
+
+    /// The latest statistics the peer's receiver sent
+    /// This is always `None` if the handshake didn't agree to exchange them
+    pub fn peer_stats(&self) -> Option<PeerStats> {
+        self.peer_stats
+    }
+
+    /// The estimated bandwidth-delay product in bytes, from the link capacity and
+    /// RTT reported by the receiver
+    ///
+    /// Returns `None` until both have been measured
+    pub fn estimated_bdp(&self) -> Option<u64> {
+        self.make_cc_info().estimated_bdp()
+    }
+
+    /// Wait until everything given to the sender has been acknowledged, or
+    /// dropped for being too late or past its time to live, sending and
+    /// retransmitting it meanwhile
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_flush(cx)).await
+    }
+
+    /// A snapshot of the packets that have been sent but not acknowledged, oldest first
+    pub fn in_flight(&self) -> Vec<InFlightEntry> {
+        self.buffer
+            .iter()
+            .map(|sent| InFlightEntry {
+                seq_number: sent.packet.seq_number,
+                last_sent: sent.last_sent,
+                retransmits: sent.retransmits,
+            })
+            .collect()
+    }
+
     pub fn stats(&self) -> Stats {
         Stats {
             timestamp: self.get_timestamp_now(),
@@ -174,6 +829,10 @@ where
             lost_packets: self.lost_packets,
             received_packets: self.recvd_packets,
             retransmitted_packets: self.retrans_packets,
+            retransmits: self.retransmits,
+            retransmitted_bytes: self.retransmitted_bytes,
+            send_buffer_overruns: self.send_buffer_overruns,
+            suppressed_retransmits: self.suppressed_retransmits,
             rtt: self.rtt,
             rtt_var: self.rtt_var,
             sender_buffer: self.buffer.len() as u32 * self.settings.max_packet_size,
@@ -182,6 +841,19 @@ where
 
                 si.subsec_nanos() as i32 / 1_000
             },
+            sent_bytes: self.sent_bytes,
+            input_rate: self.input_rate(),
+            send_dropped_packets: self.dropped_packets,
+            send_dropped_bytes: self.dropped_bytes,
+            received_bytes: 0,
+            received_original_packets: 0,
+            received_retransmitted_packets: 0,
+            recv_dropped_packets: 0,
+            undecryptable_packets: 0,
+            send_km_state: self.km_state,
+            recv_km_state: KmState::Unsecured,
+            tsbpd_drift: 0,
+            jitter_histogram: JitterHistogram::default(),
         }
     }
 
@@ -192,6 +864,7 @@ where
             latest_seq_num: Some(self.next_seq_number - 1),
             packet_arr_rate: self.pkt_arr_rate,
             rtt: Duration::from_micros(self.rtt as u64),
+            rtt_var: Duration::from_micros(self.rtt_var as u64),
         }
     }
 
@@ -204,8 +877,238 @@ where
             .send(&mut self.sock, (p, self.settings.remote), cx)
     }
 
+    /// Drop the messages waiting for an ACK that are too old for the peer to
+    /// deliver, asking the peer to drop them too
+    fn drop_too_late(&mut self, cx: &mut Context) -> Result<(), Error> {
+        let threshold = self.settings.peer_tsbpd_latency + TOO_LATE_MARGIN;
+        let start = self.settings.socket_start_time;
+        let now = Instant::now();
+
+        // the buffer is in the order the packets were given to the sender, so
+        // the too late ones are at the front
+        let mut idx = 0;
+        while let Some(sent) = self.buffer.get(idx) {
+            let origin = sent.packet.timestamp.instant(start);
+            if now.saturating_duration_since(origin) <= threshold {
+                break;
+            }
+
+            idx = if sent.dropped {
+                idx + 1
+            } else {
+                self.drop_message(cx, idx, SendDropReason::TooLate)?
+            };
+        }
+
+        self.release_dropped();
+        Ok(())
+    }
+
+    /// Drop the messages waiting for an ACK whose time to live ran out,
+    /// asking the peer to drop them too
+    fn drop_expired(&mut self, cx: &mut Context) -> Result<(), Error> {
+        let now = Instant::now();
+        match self.next_expiry {
+            Some(expiry) if expiry <= now => self.next_expiry = None,
+            _ => return Ok(()),
+        }
+
+        let mut idx = 0;
+        while let Some(sent) = self.buffer.get(idx) {
+            idx = match sent.expiry {
+                Some(expiry) if !sent.dropped && expiry <= now => {
+                    self.drop_message(cx, idx, SendDropReason::Expired)?
+                }
+                Some(expiry) if !sent.dropped => {
+                    self.next_expiry = Some(self.next_expiry.map_or(expiry, |e| min(e, expiry)));
+                    idx + 1
+                }
+                _ => idx + 1,
+            };
+        }
+
+        self.release_dropped();
+        Ok(())
+    }
+
+    /// Mark the packets of the message starting at `idx` in the buffer as
+    /// dropped and send a drop request for them, returning the index after them
+    fn drop_message(
+        &mut self,
+        cx: &mut Context,
+        idx: usize,
+        reason: SendDropReason,
+    ) -> Result<usize, Error> {
+        let msg_to_drop = self.buffer[idx].packet.message_number;
+        let first = self.buffer[idx].packet.seq_number;
+
+        let mut end = idx;
+        while let Some(sent) = self.buffer.get_mut(end) {
+            if sent.packet.message_number != msg_to_drop {
+                break;
+            }
+            sent.dropped = true;
+            self.dropped_packets += 1;
+            self.dropped_bytes += sent.packet.payload.len() as u64;
+            end += 1;
+        }
+        let last = self.buffer[end - 1].packet.seq_number;
+
+        let why = match reason {
+            SendDropReason::TooLate => "too late to be delivered",
+            SendDropReason::Expired => "its time to live ran out",
+        };
+        warn!(
+            "Dropping message {}, packets {}..={}, as {}",
+            msg_to_drop, first, last, why
+        );
+        self.report_drop(reason);
+        let drop_request = Packet::Control(ControlPacket {
+            timestamp: self.get_timestamp_now(),
+            dest_sockid: self.settings.remote_sockid,
+            control_type: ControlTypes::DropRequest {
+                msg_to_drop,
+                first,
+                last,
+            },
+        });
+        self.send_to_remote(cx, drop_request)?;
+
+        Ok(end)
+    }
+
+    /// Tell the application that messages started being dropped, once for
+    /// each run of them
+    fn report_drop(&mut self, reason: SendDropReason) {
+        if self.dropping {
+            return;
+        }
+        self.dropping = true;
+
+        if let Some(handler) = &self.drop_handler {
+            (handler.0)(SendDropEvent {
+                remote: self.settings.remote,
+                reason,
+                dropped_packets: self.dropped_packets,
+                dropped_bytes: self.dropped_bytes,
+            });
+        }
+    }
+
+    /// The time after which a packet that hasn't been acknowledged is
+    /// retransmitted without being reported lost
+    fn retransmit_timeout(&self) -> Duration {
+        Duration::from_micros((self.rtt + 4 * self.rtt_var) as u64) + SYN_INTERVAL
+    }
+
+    /// Retransmit the packets waiting for an ACK if the peer hasn't responded
+    /// for a retransmission timeout (FASTREXMIT), as a peer that doesn't
+    /// announce NAKREPORT only reports each loss once, and that report may be
+    /// lost too
+    fn check_retransmit_timer(&mut self) {
+        if self.settings.srt_flags.contains(SrtShakeFlags::NAKREPORT) || self.buffer.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let rto = self.retransmit_timeout();
+        if now.saturating_duration_since(self.last_response) < rto {
+            return;
+        }
+        self.last_response = now;
+
+        for sent in &self.buffer {
+            // the ones sent since it last responded may still get through
+            if sent.dropped || now.saturating_duration_since(sent.last_sent) < rto {
+                continue;
+            }
+            let seq = sent.packet.seq_number;
+            if let Err(idx) = self.loss_list.binary_search(&seq) {
+                self.loss_list.insert(idx, seq);
+            }
+        }
+        debug!(
+            "No response from the peer, retransmitting {:?}",
+            self.loss_list
+        );
+    }
+
+    /// Stop retransmitting dropped packets, and release the ones at the front
+    /// of the buffer as if they were acknowledged, so they aren't waited for
+    fn release_dropped(&mut self) {
+        let (buffer, first_seq) = (&self.buffer, self.first_seq);
+        self.loss_list.retain(|&seq| {
+            seq >= first_seq
+                && !buffer
+                    .get((seq - first_seq) as usize)
+                    .is_some_and(|sent| sent.dropped)
+        });
+
+        while self.buffer.front().is_some_and(|sent| sent.dropped) {
+            if let Some(sent) = self.buffer.pop_front() {
+                self.buffered_bytes -= sent.packet.payload.len();
+            }
+            self.first_seq += 1;
+        }
+        if self.lr_acked_packet < self.first_seq {
+            self.lr_acked_packet = self.first_seq;
+        }
+    }
+
+    /// Send a data packet, telling congestion control, and holding the next
+    /// one back if it went over the max bandwidth
+    fn send_data(&mut self, cx: &mut Context, p: DataPacket) -> Result<(), Error> {
+        if let Some(pacer) = &mut self.pacer {
+            let wait = pacer.take(p.payload.len() + PACKET_OVERHEAD, Instant::now());
+            self.snd_timer
+                .delay_until(tokio::time::Instant::now() + wait);
+        }
+
+        self.send_to_remote(cx, Packet::Data(p))?;
+
+        let cc_info = self.make_cc_info();
+        self.congest_ctrl.on_packet_sent(&cc_info);
+        Ok(())
+    }
+
+    /// Drop everything before `ack_number` from the buffer and the loss list,
+    /// as the peer has it
+    fn release_acked(&mut self, ack_number: SeqNumber) {
+        // 9) Update sender's buffer (by releasing the buffer that has been
+        //    acknowledged).
+        while ack_number > self.first_seq {
+            if let Some(sent) = self.buffer.pop_front() {
+                self.buffered_bytes -= sent.packet.payload.len();
+                if sent.retransmits > 0 && !sent.dropped {
+                    // this means a packet was lost then retransmitted
+                    self.retrans_packets += 1;
+                }
+                if !sent.dropped {
+                    // data is getting through again
+                    self.dropping = false;
+                }
+            }
+            self.first_seq += 1;
+        }
+
+        // 10) Update sender's loss list (by removing all those that has been
+        //     acknowledged).
+        while self.loss_list.front().is_some_and(|&seq| seq < ack_number) {
+            self.loss_list.pop_front();
+        }
+    }
+
     // Returns if shutdown was requested
     fn handle_packet(&mut self, cx: &mut Context, pack: &Packet) -> Result<bool, Error> {
+        if !self.settings.accepts_dest_sockid(pack) {
+            info!(
+                "Packet send to socket id ({}) that does not match local ({})",
+                pack.dest_sockid().0,
+                self.settings.local_sockid.0
+            );
+            return Ok(false);
+        }
+
         match pack {
             Packet::Control(ctrl) => {
                 match &ctrl.control_type {
@@ -214,14 +1117,37 @@ where
                         ack_number,
                         rtt,
                         rtt_variance,
+                        buffer_available,
                         packet_recv_rate,
                         est_link_cap,
-                        ..
                     } => {
-                        // if this ack number is less than or equal to
-                        // the largest received ack number, than discard it
+                        // Light ACKs, sent between full ones at high packet
+                        // rates, only carry the ACK number
+                        let light = rtt.is_none()
+                            && rtt_variance.is_none()
+                            && buffer_available.is_none()
+                            && packet_recv_rate.is_none()
+                            && est_link_cap.is_none();
+
+                        // if this ack number is less than the largest received
+                        // ack number, than discard it
                         // this can happen thorough packet reordering OR losing an ACK2 packet
-                        if *ack_number <= self.lr_acked_packet {
+                        // A full ACK of the number a light ACK already
+                        // brought still needs its ACK2 for the peer's RTT
+                        self.last_response = Instant::now();
+                        if *ack_number < self.lr_acked_packet
+                            || (light && *ack_number == self.lr_acked_packet)
+                        {
+                            return Ok(false);
+                        }
+
+                        // 6) If this is a Light ACK, stop, after releasing
+                        //    what it acknowledges. It has no ACK sequence
+                        //    number, and isn't answered with an ACK2.
+                        if light {
+                            self.recvd_packets += *ack_number - self.lr_acked_packet;
+                            self.lr_acked_packet = *ack_number;
+                            self.release_acked(*ack_number);
                             return Ok(false);
                         }
 
@@ -250,96 +1176,120 @@ where
                         )?;
 
                         // 3) Update RTT and RTTVar.
-                        self.rtt = rtt.unwrap_or(0);
-                        self.rtt_var = rtt_variance.unwrap_or(0);
+                        // Lite ACKs don't carry these, so keep the last values
+                        self.rtt = rtt.unwrap_or(self.rtt);
+                        self.rtt_var = rtt_variance.unwrap_or(self.rtt_var);
 
                         // 4) Update both ACK and NAK period to 4 * RTT + RTTVar + SYN.
                         // TODO: figure out why this makes sense, the sender shouldn't send ACK or NAK packets.
 
                         // 5) Update flow window size.
-                        {
-                            let cc_info = self.make_cc_info();
-                            self.congest_ctrl.on_ack(&cc_info);
+                        // Lite ACKs don't carry the peer's free buffer space
+                        if let Some(available) = buffer_available {
+                            self.peer_flow_window =
+                                min(max(*available, 0) as u32, self.settings.max_flow_size);
                         }
-
-                        // 6) If this is a Light ACK, stop.
-                        // TODO: wat
+                        // the congestion window is updated below, once
+                        // congestion control can see the rates in this ACK
 
                         // 7) Update packet arrival rate: A = (A * 7 + a) / 8, where a is the
                         //    value carried in the ACK.
-                        self.pkt_arr_rate =
-                            self.pkt_arr_rate / 8 * 7 + packet_recv_rate.unwrap_or(0) / 8;
+                        self.pkt_arr_rate = self.pkt_arr_rate / 8 * 7
+                            + packet_recv_rate.unwrap_or(self.pkt_arr_rate) / 8;
 
                         // 8) Update estimated link capacity: B = (B * 7 + b) / 8, where b is
                         //    the value carried in the ACK.
-                        self.est_link_cap = (self.est_link_cap * 7 + est_link_cap.unwrap_or(0)) / 8;
+                        self.est_link_cap =
+                            (self.est_link_cap * 7 + est_link_cap.unwrap_or(self.est_link_cap)) / 8;
 
-                        // 9) Update sender's buffer (by releasing the buffer that has been
-                        //    acknowledged).
-                        while *ack_number > self.first_seq {
-                            self.buffer.pop_front();
-                            self.first_seq += 1;
+                        {
+                            let cc_info = self.make_cc_info();
+                            self.congest_ctrl.on_ack(&cc_info);
                         }
 
-                        // 10) Update sender's loss list (by removing all those that has been
-                        //     acknowledged).
-                        while let Some(x) = self.loss_list.pop_front() {
-                            if *ack_number > x.seq_number {
-                                // this means a packet was lost then retransmitted
-                                self.retrans_packets += 1;
-                            } else {
-                                // pop it back on and finish
-                                self.loss_list.push_front(x);
-                                break;
-                            }
-                        }
+                        // 9) & 10) Release what the peer has
+                        self.release_acked(*ack_number);
                     }
                     ControlTypes::Ack2(_) => warn!("Sender received ACK2, unusual"),
-                    ControlTypes::DropRequest { .. } => unimplemented!(),
+                    ControlTypes::DropRequest { .. } => {
+                        warn!("Sender received DropRequest, unusual")
+                    }
                     ControlTypes::Handshake(_shake) => {
                         if let Some(ret) = self.hs_returner.as_ref() {
-                            if let Some(pack) = (*ret)(&pack) {
+                            if let Some(pack) = (*ret)(pack) {
                                 self.send_to_remote(cx, pack)?;
                             }
                         }
                     }
-                    // TODO: reset EXP-ish
                     ControlTypes::KeepAlive => {}
                     ControlTypes::Nak(info) => {
                         // 1) Add all sequence numbers carried in the NAK into the sender's loss list.
                         // 2) Update the SND period by rate control (see section 3.6).
                         // 3) Reset the EXP time variable.
 
+                        let now = Instant::now();
+                        self.last_response = now;
+
+                        // a packet retransmitted less than an RTT ago can't
+                        // have got through yet, so reporting it again is
+                        // just the peer's periodic NAK report. With the
+                        // reduced algorithm, it waits for a whole
+                        // retransmission timeout, relying on those reports
+                        let reduced = self.retransmit_algorithm == RetransmitAlgorithm::Reduced
+                            && self.settings.srt_flags.contains(SrtShakeFlags::NAKREPORT);
+                        let min_interval = if reduced {
+                            self.retransmit_timeout()
+                        } else {
+                            Duration::from_micros(self.rtt as u64)
+                        };
+
+                        let mut largest_lost = None;
                         for lost in decompress_loss_list(info.iter().cloned()) {
-                            let packet = match self.buffer.get((lost - self.first_seq) as usize) {
-                                Some(p) => p,
+                            if lost < self.first_seq {
+                                debug!("NAK received for packet {} that's not in the buffer, maybe it's already been ACKed", lost);
+                                continue;
+                            }
+                            match self.buffer.get((lost - self.first_seq) as usize) {
+                                Some(sent)
+                                    if !sent.dropped
+                                        && sent.retransmits > 0
+                                        && now.saturating_duration_since(sent.last_sent)
+                                            < min_interval =>
+                                {
+                                    trace!("Not retransmitting {} again so soon", lost);
+                                    self.suppressed_retransmits += 1;
+                                    continue;
+                                }
+                                Some(sent) if !sent.dropped => {}
+                                Some(_) => continue,
                                 None => {
-                                    debug!("NAK received for packet {} that's not in the buffer, maybe it's already been ACKed", lost);
+                                    warn!("NAK received for packet {} that hasn't been sent", lost);
                                     continue;
                                 }
-                            };
+                            }
 
-                            self.loss_list.push_back(packet.clone());
+                            // kept in increasing order, and a packet already
+                            // waiting to be retransmitted isn't added again
+                            if let Err(idx) = self.loss_list.binary_search(&lost) {
+                                self.loss_list.insert(idx, lost);
+                            }
+                            largest_lost = Some(max(largest_lost.unwrap_or(lost), lost));
                         }
 
                         // update CC
-                        if !self.loss_list.is_empty() {
+                        if let Some(largest_lost) = largest_lost {
                             let cc_info = self.make_cc_info();
-                            self.congest_ctrl
-                                .on_nak(self.loss_list.back().unwrap().seq_number, &cc_info);
+                            self.congest_ctrl.on_nak(largest_lost, &cc_info);
                         }
 
-                        trace!(
-                            "Loss list={:?}",
-                            self.loss_list
-                                .iter()
-                                .map(|ll| ll.seq_number)
-                                .collect::<Vec<_>>()
-                        );
-
-                        // TODO: reset EXP
+                        trace!("Loss list={:?}", self.loss_list);
+                    }
+                    ControlTypes::CongestionWarning => {
+                        let cc_info = self.make_cc_info();
+                        self.congest_ctrl.on_congestion_warning(&cc_info);
                     }
                     ControlTypes::Shutdown => return Ok(true),
+                    ControlTypes::PeerError(code) => return Err(PeerError { code: *code }.into()),
                     ControlTypes::Srt(srt_packet) => {
                         self.handle_srt_control_packet(srt_packet)?;
                     }
@@ -358,14 +1308,112 @@ where
             HandshakeRequest(_) | HandshakeResponse(_) => {
                 warn!("Received handshake request or response for an already setup SRT connection")
             }
-            _ => unimplemented!(),
-        }
+            KeyManagerResponse(key_msg) => match &self.key_announcement {
+                Some(announcement) if announcement.key_msg == *key_msg => {
+                    debug!("Peer took the new keys");
+                    self.key_announcement = None;
+                    self.km_state = KmState::Secured;
+                }
+                _ => debug!("Received a response to outdated key material"),
+            },
+            KeyManagerError(state) => {
+                warn!("Peer couldn't take the new keys: {:?}", state);
+                self.key_announcement = None;
+                self.km_state = *state;
+            }
+            PeerStats(stats) => {
+                if self.settings.peer_stats {
+                    trace!("Received peer stats {:?}", stats);
+                    self.peer_stats = Some(*stats);
+                } else {
+                    warn!("Received peer stats, but they weren't agreed upon in the handshake");
+                }
+            }
+            other => warn!(
+                "Received {:?} from {}, which the sender doesn't handle, ignoring it",
+                other, self.settings.remote
+            ),
+        }
 
         Ok(())
     }
 
+    /// Send the last key material announced, resending it every 1.5 RTT
+    /// until the peer confirms it
+    fn send_key_announcement(&mut self, cx: &mut Context) -> Result<(), Error> {
+        let resend_after = Duration::from_micros(self.rtt as u64 * 3 / 2);
+        let key_msg = match &mut self.key_announcement {
+            None => return Ok(()),
+            Some(KeyAnnouncement {
+                last_sent: Some(last_sent),
+                ..
+            }) if last_sent.elapsed() < resend_after => return Ok(()),
+            Some(announcement) if announcement.sends < KM_MAX_SENDS => {
+                announcement.last_sent = Some(Instant::now());
+                announcement.sends += 1;
+                announcement.key_msg.clone()
+            }
+            Some(_) => {
+                warn!(
+                    "Peer didn't confirm the new keys after {} tries",
+                    KM_MAX_SENDS
+                );
+                self.key_announcement = None;
+                return Ok(());
+            }
+        };
+
+        let packet = Packet::Control(ControlPacket {
+            timestamp: self.get_timestamp_now(),
+            dest_sockid: self.settings.remote_sockid,
+            control_type: ControlTypes::Srt(SrtControlPacket::KeyManagerRequest(key_msg)),
+        });
+        self.send_to_remote(cx, packet)
+    }
+
+    /// Encrypt `payload`, the payload of packet `seq_number`, if the
+    /// connection is encrypted
+    #[cfg(feature = "crypto")]
+    fn encrypt(
+        &mut self,
+        seq_number: SeqNumber,
+        payload: Bytes,
+    ) -> Result<(DataEncryption, Bytes), Error> {
+        let keys = match &mut self.keys {
+            Some(keys) => keys,
+            None => return Ok((DataEncryption::None, payload)),
+        };
+
+        let (encryption, payload, key_msg) =
+            keys.encrypt(self.key_refresh, seq_number, &payload, Instant::now())?;
+        // the new keys are sent before the packet, so the peer can have them
+        // in time
+        if let Some(key_msg) = key_msg {
+            self.key_announcement = Some(KeyAnnouncement {
+                key_msg,
+                last_sent: None,
+                sends: 0,
+            });
+        }
+        Ok((encryption, payload))
+    }
+
+    /// Without the `crypto` feature nothing is encrypted
+    #[cfg(not(feature = "crypto"))]
+    fn encrypt(
+        &mut self,
+        _seq_number: SeqNumber,
+        payload: Bytes,
+    ) -> Result<(DataEncryption, Bytes), Error> {
+        Ok((DataEncryption::None, payload))
+    }
+
     /// Gets the next available message number
     fn get_new_message_number(&mut self) -> MsgNumber {
+        // zero is for packet filter control packets
+        if self.next_message_number == DataPacket::FILTER_CONTROL_MSG_NUMBER {
+            self.next_message_number += 1;
+        }
         self.next_message_number += 1;
         self.next_message_number - 1
     }
@@ -380,9 +1428,37 @@ where
 
     /// Gets the next packet, removing it from `pending_packets` and also adding an entry at the end of `buffer`
     /// Returns none if there are no packets availavle
-    fn get_next_payload(&mut self) -> Option<Packet> {
-        let (payload, time, is_msg_end, is_msg_begin) = {
-            let (time, payload) = self.pending_packets.pop_front()?;
+    fn get_next_payload(&mut self) -> Result<Option<DataPacket>, Error> {
+        let max_packet_size = self.settings.max_packet_size as usize;
+        let (payload, time, expiry, in_order, is_msg_end, is_msg_begin) = {
+            let PendingMessage {
+                time,
+                payload,
+                expiry,
+                in_order,
+            } = loop {
+                match self.pending_packets.pop_front() {
+                    // what's left of a message whose time to live ran out
+                    // isn't sent, what was already is dropped from the buffer
+                    Some(PendingMessage {
+                        payload,
+                        expiry: Some(expiry),
+                        ..
+                    }) if expiry <= Instant::now() => {
+                        let packets = max(payload.len().div_ceil(max_packet_size), 1);
+                        self.pending_packet_count =
+                            self.pending_packet_count.saturating_sub(packets);
+                        self.dropped_packets += packets as u32;
+                        self.dropped_bytes += payload.len() as u64;
+                        self.buffered_bytes -= payload.len();
+                        self.at_msg_beginning = true;
+                        self.report_drop(SendDropReason::Expired);
+                    }
+                    Some(pending) => break pending,
+                    None => return Ok(None),
+                }
+            };
+            self.pending_packet_count = self.pending_packet_count.saturating_sub(1);
 
             // cache this so we don't overwrite it
             let is_msg_begin = self.at_msg_beginning;
@@ -390,27 +1466,36 @@ where
             // if we need to break this packet up
             if payload.len() > self.settings.max_packet_size as usize {
                 // re-add the rest of the packet
-                self.pending_packets.push_front((
+                self.pending_packets.push_front(PendingMessage {
                     time,
-                    payload.slice(self.settings.max_packet_size as usize..payload.len()),
-                ));
+                    payload: payload.slice(max_packet_size..payload.len()),
+                    expiry,
+                    in_order,
+                });
                 self.at_msg_beginning = false;
 
                 (
-                    payload.slice(0..self.settings.max_packet_size as usize),
+                    payload.slice(0..max_packet_size),
                     time,
+                    expiry,
+                    in_order,
                     false,
                     is_msg_begin,
                 )
             } else {
                 self.at_msg_beginning = true;
-                (payload, time, true, is_msg_begin)
+                (payload, time, expiry, in_order, true, is_msg_begin)
             }
         };
 
+        let seq_number = self.get_new_sequence_number();
+        let (encryption, payload) = self.encrypt(seq_number, payload)?;
+
         let pack = DataPacket {
             dest_sockid: self.settings.remote_sockid,
-            in_order_delivery: false, // TODO: research this
+            in_order_delivery: in_order,
+            retransmitted: false,
+            encryption,
             message_loc: if is_msg_begin {
                 PacketLocation::FIRST
             } else {
@@ -427,22 +1512,146 @@ where
             } else {
                 self.next_message_number - 1
             },
-            seq_number: self.get_new_sequence_number(),
+            seq_number,
             timestamp: self.get_timestamp(time),
             payload,
         };
 
+        self.sent_bytes += pack.payload.len() as u64;
+        self.new_since_retransmit = self.new_since_retransmit.saturating_add(1);
+
         // add it to the buffer
-        self.buffer.push_back(pack.clone());
+        self.buffer.push_back(SentPacket {
+            packet: pack.clone(),
+            last_sent: Instant::now(),
+            retransmits: 0,
+            expiry,
+            dropped: false,
+        });
+        if let Some(expiry) = expiry {
+            self.next_expiry = Some(self.next_expiry.map_or(expiry, |e| min(e, expiry)));
+        }
+
+        Ok(Some(pack))
+    }
 
-        Some(Packet::Data(pack))
+    /// The most packets allowed in flight: the congestion window, capped by
+    /// the peer's free buffer space
+    fn flow_window(&self) -> u32 {
+        min(
+            min(self.congest_ctrl.window_size(), self.settings.max_flow_size),
+            self.peer_flow_window,
+        )
     }
 
-    fn get_timestamp_now(&self) -> i32 {
+    /// If there's a packet to send as soon as the send timer allows, either
+    /// one the peer reported lost or new data that fits in the flow window
+    fn has_sendable(&self) -> bool {
+        !self.loss_list.is_empty() || self.has_new_sendable()
+    }
+
+    /// If there's new data that fits in the flow window
+    fn has_new_sendable(&self) -> bool {
+        let in_flight = self.next_seq_number - self.lr_acked_packet;
+
+        !self.pending_packets.is_empty() && (in_flight == 0 || in_flight < self.flow_window())
+    }
+
+    /// The next packet the peer reported lost to retransmit, unless it's new
+    /// data's turn
+    fn next_retransmit(&mut self) -> Option<SeqNumber> {
+        if let RetransmitPriority::Interleaved(new_per_retransmit) = self.retransmit_priority {
+            if self.new_since_retransmit < new_per_retransmit && self.has_new_sendable() {
+                return None;
+            }
+        }
+
+        let seq = self.loss_list.pop_front()?;
+        self.new_since_retransmit = 0;
+        Some(seq)
+    }
+
+    /// If there's room for more data, counting what's waiting to be sent and
+    /// what's waiting for an ACK against the flow window and the send buffer
+    fn has_send_room(&self) -> bool {
+        let window = min(self.congest_ctrl.window_size(), self.settings.max_flow_size);
+
+        self.pending_packet_count + self.buffer.len() < window as usize
+            && self
+                .send_buffer_size
+                .is_none_or(|size| self.buffered_bytes < size)
+    }
+
+    /// Send a message with its own options, with the same backpressure as
+    /// sending it with a timestamp, waiting until it's acknowledged. A
+    /// message that isn't acknowledged within its time to live isn't sent or
+    /// retransmitted any more, and the peer is asked to drop it
+    pub async fn send_msg(&mut self, payload: Bytes, ctrl: MsgCtrl) -> Result<(), Error> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_ready(cx)).await?;
+        self.start_send_msg(payload, ctrl);
+        self.flush().await
+    }
+
+    /// Queue a message with its own options, once `poll_ready` says there's
+    /// room, like `start_send` does for one with a timestamp
+    pub fn start_send_msg(&mut self, payload: Bytes, ctrl: MsgCtrl) {
+        assert!(!self.closed, "`start_send_msg` called after sender close");
+
+        let now = Instant::now();
+        self.queue(PendingMessage {
+            time: ctrl.src_time_or(now),
+            payload,
+            expiry: ctrl.expiry(now),
+            in_order: ctrl.is_in_order(),
+        });
+    }
+
+    /// Queue a message to be sent
+    fn queue(&mut self, mut msg: PendingMessage) {
+        // the source time is carried as the packet timestamp, which can only
+        // be from the socket's start up to when the packet is sent
+        let now = Instant::now();
+        let start = self.settings.socket_start_time;
+        if msg.time < start || msg.time > now {
+            debug!("Source time isn't between the socket's start and now, clamping it");
+            msg.time = max(min(msg.time, now), start);
+        }
+
+        let payload = &msg.payload;
+        let max_packet_size = self.settings.max_packet_size as usize;
+        let packets = max(payload.len().div_ceil(max_packet_size), 1);
+        self.pending_packet_count += packets;
+        self.buffered_bytes += payload.len();
+
+        // the max bandwidth follows the input rate, if it's derived from it
+        let estimate = match &mut self.input_rate {
+            Some(input) => input.add(payload.len(), packets, Instant::now()),
+            None => None,
+        };
+        if let Some(rate) = estimate {
+            let max_bandwidth =
+                max(rate, MIN_INPUT_RATE) * u64::from(100 + self.bandwidth_overhead) / 100;
+            debug!(
+                "Input rate is {} bits/s, pacing at {} bits/s",
+                rate, max_bandwidth
+            );
+            match &mut self.pacer {
+                Some(pacer) => pacer.set_rate(max_bandwidth),
+                None => {
+                    let burst = max_packet_size + PACKET_OVERHEAD;
+                    self.pacer = Some(Pacer::new(max_bandwidth, burst));
+                }
+            }
+        }
+
+        self.pending_packets.push_back(msg);
+    }
+
+    fn get_timestamp_now(&self) -> TimeStamp {
         self.settings.get_timestamp_now()
     }
 
-    fn get_timestamp(&self, at: Instant) -> i32 {
+    fn get_timestamp(&self, at: Instant) -> TimeStamp {
         self.settings.get_timestamp(at)
     }
 }
@@ -456,16 +1665,51 @@ where
 {
     type Error = Error;
 
-    fn start_send(mut self: Pin<&mut Self>, item: (Instant, Bytes)) -> Result<(), Error> {
-        assert!(!self.closed, "`start_send` called after sender close");
-
-        self.pending_packets.push_back(item);
-
+    fn start_send(self: Pin<&mut Self>, item: (Instant, Bytes)) -> Result<(), Error> {
+        let pin = self.get_mut();
+        assert!(!pin.closed, "`start_send` called after sender close");
+
+        pin.queue(PendingMessage {
+            time: item.0,
+            payload: item.1,
+            expiry: None,
+            in_order: true,
+        });
         Ok(())
     }
 
-    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Error>> {
-        Poll::Ready(Ok(()))
+    /// Ready once what's waiting to be sent and what's waiting for an ACK
+    /// fit in the flow window, so producers are held back instead of data
+    /// piling up in the sender
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let pin = self.get_mut();
+        if pin.has_send_room() {
+            pin.overrun = false;
+            return Poll::Ready(Ok(()));
+        }
+
+        // room is only made by sending and handling ACKs, which is done while flushing
+        let ready = Pin::new(&mut *pin).poll_flush(cx);
+        if ready.is_pending() && !pin.has_send_room() {
+            if !pin.overrun {
+                pin.overrun = true;
+                pin.send_buffer_overruns += 1;
+            }
+            if pin.nonblocking {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "Send buffer is full",
+                )
+                .into()));
+            }
+            return Poll::Pending;
+        }
+
+        pin.overrun = false;
+        match ready {
+            Poll::Pending => Poll::Ready(Ok(())),
+            ready => ready,
+        }
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
@@ -482,14 +1726,13 @@ where
 
         // we need to poll_complete this until completion
         // this poll_complete could have come from a wakeup of that, so call it
-        if let Poll::Ready(_) = pin.sock().poll_flush(cx)? {
+        if pin.sock().poll_flush(cx)?.is_ready() {
             // if everything is flushed, return Ok
             if pin.loss_list.is_empty()
                 && pin.pending_packets.is_empty()
                 && pin.lr_acked_packet == pin.next_seq_number
                 && pin.buffer.is_empty()
             {
-                // TODO: this is wrong for KeepAlive
                 debug!("Returning ready");
                 return Poll::Ready(Ok(()));
             }
@@ -523,75 +1766,110 @@ where
             // if we're here, we are guaranteed to have a NotReady, so returning NotReady is OK
 
             // wait for the SND timer to timeout
-            ready!(Pin::new(&mut pin.snd_timer).poll(cx));
+            let sendable = pin.has_sendable();
+            ready!(pin.snd_timer.poll(cx, sendable));
 
             // 6) Wait (SND - t) time, where SND is the inter-packet interval
             //     updated by congestion control and t is the total time used by step
             //     1 to step 5. Go to 1).
-            {
-                let cc_info = pin.make_cc_info();
-                pin.congest_ctrl.on_packet_sent(&cc_info);
-            }
 
             // reset the timer
-            let new_snd_time = Instant::now() + pin.congest_ctrl.send_interval();
-            pin.snd_timer
-                .reset(tokio::time::Instant::from_std(new_snd_time));
+            let snd_period = max(pin.congest_ctrl.send_interval(), pin.min_send_interval);
+            pin.snd_timer.schedule(snd_period, sendable);
+
+            // new keys that the peer hasn't confirmed
+            pin.send_key_announcement(cx)?;
+
+            // buffer mode has no TSBPD, so nothing is ever too late
+            if pin.too_late_drop && pin.settings.socket_type == SocketType::Datagram {
+                pin.drop_too_late(cx)?;
+            }
+            pin.drop_expired(cx)?;
+            pin.check_retransmit_timer();
 
             // 1) If the sender's loss list is not empty, send all the packets it in
-            if let Some(pack) = pin.loss_list.pop_front() {
-                debug!("Sending packet in loss list, seq={:?}", pack.seq_number);
-                pin.send_to_remote(cx, Packet::Data(pack))?;
+            if let Some(seq) = pin.next_retransmit() {
+                debug!("Sending packet in loss list, seq={:?}", seq);
+                let sent = match pin.buffer.get_mut((seq - pin.first_seq) as usize) {
+                    Some(sent) => sent,
+                    None => continue,
+                };
+                sent.last_sent = Instant::now();
+                sent.retransmits += 1;
+                let pack = sent.packet.clone();
+                pin.retransmits += 1;
+                pin.retransmitted_bytes += pack.payload.len() as u64;
+                pin.send_data(
+                    cx,
+                    DataPacket {
+                        // peers that don't have the flag, like UDT4, use
+                        // this bit for the message number
+                        retransmitted: pin.settings.srt_flags.contains(SrtShakeFlags::REXMITFLG),
+                        ..pack
+                    },
+                )?;
             } else {
                 // 2) In messaging mode, if the packets has been the loss list for a
                 //    time more than the application specified TTL (time-to-live), send
                 //    a message drop request and remove all related packets from the
                 //    loss list. Go to 1).
 
-                // TODO: I honestly don't know what this means
+                // done by drop_expired above, for every packet waiting for an ACK
 
                 // 3) Wait until there is application data to be sent.
 
                 // a. If the number of unacknowledged packets exceeds the
                 //    flow/congestion window size, wait until an ACK comes. Go to
                 //    1).
-                // TODO: account for looping here
-                if pin.lr_acked_packet < pin.next_seq_number - pin.congest_ctrl.window_size() {
+                //    One packet is always allowed in flight, so an ACK comes
+                //    to tell when the peer has room again
+                let in_flight = pin.next_seq_number - pin.lr_acked_packet;
+                if in_flight > 0 && in_flight >= pin.flow_window() {
                     // flow window exceeded, wait for ACK
-                    trace!("Flow window exceeded lr_acked={:?}, next_seq={:?}, window_size={}, next_seq-window={:?}", 
+                    trace!(
+                        "Flow window exceeded lr_acked={:?}, next_seq={:?}, window_size={}",
                         pin.lr_acked_packet,
                         pin.next_seq_number,
-                        pin.congest_ctrl.window_size(),
-                        pin.next_seq_number - pin.congest_ctrl.window_size());
+                        pin.flow_window()
+                    );
 
                     continue;
                 }
 
                 // b. Pack a new data packet and send it out.
                 {
-                    let payload = match pin.get_next_payload() {
+                    let payload = match pin.get_next_payload()? {
                         Some(p) => p,
                         // All packets have been flushed
                         None => continue,
                     };
+                    pin.send_key_announcement(cx)?;
                     debug!(
                         "Sending packet: {}; pending.len={}; SND={:?}",
                         pin.next_seq_number - 1,
                         pin.pending_packets.len(),
                         pin.congest_ctrl.send_interval(),
                     );
-                    pin.send_to_remote(cx, payload)?;
+                    pin.send_data(cx, payload)?;
                 }
 
                 // 5) If the sequence number of the current packet is 16n, where n is an
                 //     integer, go to 2) (which is send another packet).
-                if (pin.next_seq_number - 1) % 16 == 0 {
-                    let payload = match pin.get_next_payload() {
+                //     Sending back to back would exceed the packet rate cap or burst
+                //     over the max bandwidth, so skip it then, and it has to fit
+                //     in the window like any other.
+                if (pin.next_seq_number - 1) % 16 == 0
+                    && pin.min_send_interval == Duration::from_secs(0)
+                    && pin.pacer.is_none()
+                    && pin.next_seq_number - pin.lr_acked_packet < pin.flow_window()
+                {
+                    let payload = match pin.get_next_payload()? {
                         Some(p) => p,
                         // All packets have been flushed
                         None => continue,
                     };
-                    pin.send_to_remote(cx, payload)?;
+                    pin.send_key_announcement(cx)?;
+                    pin.send_data(cx, payload)?;
                 }
             }
             let _ = pin.sock().poll_flush(cx)?;
@@ -599,7 +1877,22 @@ where
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
-        ready!(self.as_mut().poll_flush(cx))?;
+        if !self.closed && self.as_mut().poll_flush(cx)?.is_pending() {
+            // keep retransmitting until everything is acknowledged, or
+            // the linger time runs out
+            let linger = match self.linger {
+                Some(linger) => linger,
+                None => return Poll::Pending,
+            };
+            let timer = self.linger_timer.get_or_insert_with(|| delay_for(linger));
+            ready!(Pin::new(timer).poll(cx));
+
+            warn!(
+                "Closing with {} packets unacknowledged after lingering for {:?}",
+                self.buffer.len() + self.pending_packet_count,
+                linger
+            );
+        }
 
         let pin = self.get_mut();
 
@@ -637,3 +1930,1266 @@ where
         Poll::Ready(Some(Ok(self.stats())))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        InputRate, Pacer, RetransmitAlgorithm, RetransmitPriority, SendDropEvent, SendDropHandler,
+        SendDropReason, Sender,
+    };
+    use crate::channel::Channel;
+    use crate::loss_compression::compress_loss_list;
+    use crate::packet::{ControlTypes, KmState, SocketType, SrtControlPacket, SrtShakeFlags};
+    use crate::{
+        CCData, CongestCtrl, CongestCtrlType, ConnectionSettings, ControlPacket, DataPacket,
+        MsgCtrl, Packet, PeerError, SeqNumber, SocketID, SrtCongestCtrl, TimeStamp,
+    };
+
+    use bytes::Bytes;
+    use futures::{select, FutureExt, SinkExt, StreamExt};
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tokio::spawn;
+    use tokio::time::{self, timeout};
+
+    type PackChan = Channel<(Packet, SocketAddr)>;
+
+    fn new_sender<CC: CongestCtrl + Unpin>(
+        congest_ctrl: CC,
+        init_seq_num: SeqNumber,
+    ) -> (Sender<PackChan, CC>, PackChan) {
+        let (chan, remote) = Channel::channel(100);
+
+        let sender = Sender::new(
+            chan,
+            congest_ctrl,
+            ConnectionSettings {
+                remote: "127.0.0.1:1234".parse().unwrap(),
+                remote_sockid: SocketID(1),
+                local_sockid: SocketID(2),
+                socket_start_time: Instant::now(),
+                init_seq_num,
+                max_packet_size: 1316,
+                max_flow_size: 8192,
+                peer_version: None,
+                // like this crate, so losses are only retransmitted when
+                // reported, and retransmissions are flagged
+                srt_flags: SrtShakeFlags::NAKREPORT | SrtShakeFlags::REXMITFLG,
+                key_size: 0,
+                km_state: KmState::Unsecured,
+                tsbpd_latency: Duration::from_millis(50),
+                peer_tsbpd_latency: Duration::from_millis(50),
+                peer_stats: false,
+                congest_ctrl: CongestCtrlType::Live,
+                socket_type: SocketType::Datagram,
+                udt4: false,
+            },
+            None,
+        );
+
+        (sender, remote)
+    }
+
+    #[tokio::test]
+    async fn in_flight_without_acks() {
+        let init_seq_num = SeqNumber::new_truncate(100);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        assert!(sender.in_flight().is_empty());
+
+        let before = Instant::now();
+        for i in 0..5 {
+            sender
+                .feed((Instant::now(), Bytes::from(i.to_string())))
+                .await
+                .unwrap();
+        }
+
+        // nothing will ever be ACKed, so flushing never finishes, but it sends everything
+        assert!(timeout(Duration::from_millis(100), sender.flush())
+            .await
+            .is_err());
+
+        for _ in 0..5 {
+            assert!(matches!(
+                remote.next().await,
+                Some(Ok((Packet::Data(_), _)))
+            ));
+        }
+
+        let in_flight = sender.in_flight();
+        assert_eq!(
+            in_flight.iter().map(|e| e.seq_number).collect::<Vec<_>>(),
+            (0..5).map(|i| init_seq_num + i).collect::<Vec<_>>()
+        );
+        assert!(in_flight
+            .iter()
+            .all(|e| e.retransmits == 0 && e.last_sent >= before));
+    }
+
+    // whether the packet a sender with `srt_flags` retransmits is flagged as
+    // retransmitted
+    async fn retransmission_flag(srt_flags: SrtShakeFlags) -> bool {
+        let init_seq_num = SeqNumber::new_truncate(100);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        sender.settings.srt_flags = srt_flags;
+
+        sender
+            .feed((Instant::now(), Bytes::from("hello")))
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+        assert!(matches!(
+            remote.next().await,
+            Some(Ok((
+                Packet::Data(DataPacket {
+                    retransmitted: false,
+                    ..
+                }),
+                _
+            )))
+        ));
+
+        remote
+            .send((
+                Packet::Control(ControlPacket {
+                    timestamp: TimeStamp(0),
+                    dest_sockid: SocketID(2),
+                    control_type: ControlTypes::Nak(vec![init_seq_num.as_raw()]),
+                }),
+                "127.0.0.1:1234".parse().unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+
+        match remote.next().await {
+            Some(Ok((Packet::Data(data), _))) => {
+                assert_eq!(data.seq_number, init_seq_num);
+                data.retransmitted
+            }
+            p => panic!("Expected retransmitted data packet, got {:?}", p),
+        }
+    }
+
+    #[tokio::test]
+    async fn retransmission_flagged() {
+        assert!(retransmission_flag(SrtShakeFlags::NAKREPORT | SrtShakeFlags::REXMITFLG).await);
+        // the peer would take the flag as part of the message number
+        assert!(!retransmission_flag(SrtShakeFlags::NAKREPORT).await);
+    }
+
+    #[tokio::test]
+    async fn nak_retransmission() {
+        let init_seq_num = SeqNumber::new_truncate(100);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        let from: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let control = |control_type| {
+            Packet::Control(ControlPacket {
+                timestamp: TimeStamp(0),
+                dest_sockid: SocketID(2),
+                control_type,
+            })
+        };
+
+        for payload in &["a", "b", "c", "d"] {
+            sender
+                .feed((Instant::now(), Bytes::from(*payload)))
+                .await
+                .unwrap();
+        }
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+        for _ in 0..4 {
+            remote.next().await.unwrap().unwrap();
+        }
+
+        // a range, the same packet again, and one before the others
+        let naks = vec![
+            compress_loss_list(vec![init_seq_num + 1, init_seq_num + 2].into_iter()).collect(),
+            vec![(init_seq_num + 1).as_raw()],
+            vec![init_seq_num.as_raw()],
+        ];
+        for nak in naks {
+            remote
+                .send((control(ControlTypes::Nak(nak)), from))
+                .await
+                .unwrap();
+        }
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+
+        // each is retransmitted once, in order, before anything else
+        let mut retransmitted = Vec::new();
+        while let Some(Some(Ok((Packet::Data(data), _)))) = remote.next().now_or_never() {
+            assert!(data.retransmitted);
+            retransmitted.push(data.seq_number);
+        }
+        assert_eq!(
+            retransmitted,
+            vec![init_seq_num, init_seq_num + 1, init_seq_num + 2]
+        );
+        let stats = sender.stats();
+        assert_eq!(stats.retransmits, 3);
+        assert_eq!(stats.retransmitted_bytes, 3);
+
+        remote
+            .send((
+                control(ControlTypes::Ack {
+                    ack_seq_num: 1,
+                    ack_number: init_seq_num + 4,
+                    rtt: None,
+                    rtt_variance: None,
+                    buffer_available: None,
+                    packet_recv_rate: None,
+                    est_link_cap: None,
+                }),
+                from,
+            ))
+            .await
+            .unwrap();
+        timeout(Duration::from_secs(1), sender.flush())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sender.stats().retransmitted_packets, 3);
+    }
+
+    #[tokio::test]
+    async fn fast_retransmit() {
+        let init_seq_num = SeqNumber::new_truncate(100);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        // a peer that only reports each loss once
+        sender.settings.srt_flags = SrtShakeFlags::REXMITFLG;
+
+        sender
+            .feed((Instant::now(), Bytes::from("hello")))
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_millis(100), sender.flush())
+            .await
+            .is_err());
+
+        // it never responds, so the packet is sent again without a NAK
+        let mut sent = Vec::new();
+        while let Some(Some(Ok((Packet::Data(data), _)))) = remote.next().now_or_never() {
+            assert_eq!(data.seq_number, init_seq_num);
+            sent.push(data.retransmitted);
+        }
+        assert!(sent.len() > 1, "Not retransmitted: {:?}", sent);
+        assert!(!sent[0]);
+        assert!(sent[1..].iter().all(|&retransmitted| retransmitted));
+    }
+
+    /// Report the first packet sent lost, waiting the given milliseconds after
+    /// each report, returning how many times it was retransmitted for each and
+    /// the number of retransmissions suppressed
+    async fn repeated_naks(algorithm: RetransmitAlgorithm, waits: &[u64]) -> (Vec<usize>, u32) {
+        let init_seq_num = SeqNumber::new_truncate(100);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        sender.set_retransmit_algorithm(algorithm);
+        // an RTT of 50ms, and a retransmission timeout of 160ms
+        sender.rtt = 50_000;
+        sender.rtt_var = 25_000;
+
+        sender
+            .feed((Instant::now(), Bytes::from("hello")))
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_millis(20), sender.flush())
+            .await
+            .is_err());
+        remote.next().await.unwrap().unwrap();
+
+        let mut retransmissions = Vec::new();
+        for wait in waits {
+            remote
+                .send((
+                    Packet::Control(ControlPacket {
+                        timestamp: TimeStamp(0),
+                        dest_sockid: SocketID(2),
+                        control_type: ControlTypes::Nak(vec![init_seq_num.as_raw()]),
+                    }),
+                    "127.0.0.1:1234".parse().unwrap(),
+                ))
+                .await
+                .unwrap();
+            assert!(timeout(Duration::from_millis(*wait), sender.flush())
+                .await
+                .is_err());
+
+            let mut count = 0;
+            while let Some(Some(Ok((packet, _)))) = remote.next().now_or_never() {
+                if let Packet::Data(_) = packet {
+                    count += 1;
+                }
+            }
+            retransmissions.push(count);
+        }
+
+        (retransmissions, sender.stats().suppressed_retransmits)
+    }
+
+    #[tokio::test]
+    async fn repeated_naks_suppressed() {
+        // reported again before an RTT passed, then after
+        let (retransmissions, suppressed) =
+            repeated_naks(RetransmitAlgorithm::EveryReport, &[20, 80, 20]).await;
+        assert_eq!(retransmissions, vec![1, 0, 1]);
+        assert_eq!(suppressed, 1);
+    }
+
+    #[tokio::test]
+    async fn reduced_retransmit() {
+        // the first report is answered, the next only once the timeout passed
+        let (retransmissions, suppressed) =
+            repeated_naks(RetransmitAlgorithm::Reduced, &[20, 80, 100, 20]).await;
+        assert_eq!(retransmissions, vec![1, 0, 0, 1]);
+        assert_eq!(suppressed, 2);
+    }
+
+    /// The order packets go out in when 4 are reported lost as 6 new ones
+    /// are waiting, R for a retransmission and N for new data
+    async fn send_order(priority: RetransmitPriority) -> String {
+        let init_seq_num = SeqNumber::new_truncate(100);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        sender.set_retransmit_priority(priority);
+
+        for i in 0..4 {
+            sender
+                .feed((Instant::now(), Bytes::from(i.to_string())))
+                .await
+                .unwrap();
+        }
+        assert!(timeout(Duration::from_millis(20), sender.flush())
+            .await
+            .is_err());
+        while let Some(Some(_)) = remote.next().now_or_never() {}
+
+        remote
+            .send((
+                Packet::Control(ControlPacket {
+                    timestamp: TimeStamp(0),
+                    dest_sockid: SocketID(2),
+                    control_type: ControlTypes::Nak(
+                        (0..4).map(|i| (init_seq_num + i).as_raw()).collect(),
+                    ),
+                }),
+                "127.0.0.1:1234".parse().unwrap(),
+            ))
+            .await
+            .unwrap();
+        for i in 4..10 {
+            sender
+                .feed((Instant::now(), Bytes::from(i.to_string())))
+                .await
+                .unwrap();
+        }
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+
+        let mut order = String::new();
+        while let Some(Some(Ok((packet, _)))) = remote.next().now_or_never() {
+            if let Packet::Data(data) = packet {
+                order.push(if data.retransmitted { 'R' } else { 'N' });
+            }
+        }
+        order
+    }
+
+    #[tokio::test]
+    async fn retransmit_priority() {
+        assert_eq!(send_order(RetransmitPriority::First).await, "RRRRNNNNNN");
+        assert_eq!(
+            send_order(RetransmitPriority::Interleaved(2)).await,
+            "RNNRNNRNNR"
+        );
+    }
+
+    #[tokio::test]
+    async fn min_send_interval() {
+        time::pause();
+
+        const PACKETS: u32 = 40;
+        let floor = Duration::from_millis(5);
+
+        // 10 Gb/s gives a send period of around a microsecond
+        let (mut sender, mut remote) = new_sender(
+            SrtCongestCtrl::with_send_rate(10_000_000_000, 1316),
+            SeqNumber::new_truncate(0),
+        );
+        sender.set_min_send_interval(floor);
+
+        for i in 0..PACKETS {
+            sender
+                .feed((Instant::now(), Bytes::from(i.to_string())))
+                .await
+                .unwrap();
+        }
+
+        // record when each packet arrives, in its own task so it's seen right away
+        let recv = spawn(async move {
+            let mut sent_at = Vec::new();
+            while sent_at.len() < PACKETS as usize {
+                match remote.next().await {
+                    Some(Ok((Packet::Data(_), _))) => sent_at.push(time::Instant::now()),
+                    other => panic!("Unexpected {:?}", other),
+                }
+            }
+            // hand the channel back, dropping it would end the sender's stream
+            (sent_at, remote)
+        });
+        let (sent_at, _remote) = select! {
+            res = recv.fuse() => res.unwrap(),
+            _ = sender.flush().fuse() => panic!("Flushed without ACKs"),
+        };
+
+        for pair in sent_at.windows(2) {
+            assert!(pair[1] - pair[0] >= floor, "{:?}", pair[1] - pair[0]);
+        }
+    }
+
+    /// How long it takes to send 50 packets paced 200us apart
+    async fn pacing_spread(precise: bool) -> Duration {
+        const PACKETS: usize = 50;
+
+        // 1316 byte packets at 52.64 Mb/s are 200us apart
+        let (mut sender, mut remote) = new_sender(
+            SrtCongestCtrl::with_send_rate(52_640_000, 1316),
+            SeqNumber::new_truncate(0),
+        );
+        sender.set_precise_pacing(precise);
+
+        for i in 0..PACKETS {
+            sender
+                .feed((Instant::now(), Bytes::from(i.to_string())))
+                .await
+                .unwrap();
+        }
+
+        let recv = spawn(async move {
+            let mut sent_at = Vec::new();
+            while sent_at.len() < PACKETS {
+                match remote.next().await {
+                    Some(Ok((Packet::Data(_), _))) => sent_at.push(Instant::now()),
+                    other => panic!("Unexpected {:?}", other),
+                }
+            }
+            (sent_at, remote)
+        });
+        let (sent_at, _remote) = select! {
+            res = recv.fuse() => res.unwrap(),
+            _ = sender.flush().fuse() => panic!("Flushed without ACKs"),
+        };
+
+        sent_at[PACKETS - 1] - sent_at[0]
+    }
+
+    #[tokio::test]
+    async fn precise_pacing() {
+        // the timer on its own can't wait less than a millisecond
+        let coarse = pacing_spread(false).await;
+        assert!(coarse > Duration::from_millis(30), "{:?}", coarse);
+
+        // every 16th packet is sent with the one after it, so 46 periods.
+        // Only the lower bound is exact, a busy machine can only slow it down
+        let precise = pacing_spread(true).await;
+        assert!(precise > Duration::from_millis(8), "{:?}", precise);
+        assert!(precise < coarse, "{:?} vs {:?}", precise, coarse);
+    }
+
+    /// Counts the congestion warnings it gets
+    struct WarningCongestCtrl {
+        warnings: Arc<AtomicUsize>,
+    }
+
+    impl CongestCtrl for WarningCongestCtrl {
+        fn on_congestion_warning(&mut self, _data: &CCData) {
+            self.warnings.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn send_interval(&self) -> Duration {
+            Duration::from_millis(1)
+        }
+
+        fn window_size(&self) -> u32 {
+            10_000
+        }
+    }
+
+    #[tokio::test]
+    async fn congestion_warning() {
+        let init_seq_num = SeqNumber::new_truncate(0);
+        let warnings = Arc::new(AtomicUsize::new(0));
+        let (mut sender, mut remote) = new_sender(
+            WarningCongestCtrl {
+                warnings: warnings.clone(),
+            },
+            init_seq_num,
+        );
+
+        sender
+            .feed((Instant::now(), Bytes::from("asdf")))
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+        assert!(matches!(
+            remote.next().await,
+            Some(Ok((Packet::Data(_), _)))
+        ));
+
+        let from = "127.0.0.1:1234".parse().unwrap();
+        let control = |control_type| {
+            Packet::Control(ControlPacket {
+                timestamp: TimeStamp(0),
+                dest_sockid: SocketID(2),
+                control_type,
+            })
+        };
+        remote
+            .send((control(ControlTypes::CongestionWarning), from))
+            .await
+            .unwrap();
+        remote
+            .send((
+                control(ControlTypes::Ack {
+                    ack_seq_num: 1,
+                    ack_number: init_seq_num + 1,
+                    rtt: Some(10_000),
+                    rtt_variance: Some(1_000),
+                    buffer_available: Some(8192),
+                    packet_recv_rate: Some(0),
+                    est_link_cap: Some(0),
+                }),
+                from,
+            ))
+            .await
+            .unwrap();
+
+        // the ACK lets the flush finish, by then the warning was handled
+        timeout(Duration::from_secs(1), sender.flush())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(warnings.load(Ordering::SeqCst), 1);
+
+        // warnings come from the receiving end, the sender never sends one
+        while let Some(Some(Ok((pack, _)))) = remote.next().now_or_never() {
+            assert!(
+                !matches!(
+                    pack,
+                    Packet::Control(ControlPacket {
+                        control_type: ControlTypes::CongestionWarning,
+                        ..
+                    })
+                ),
+                "Sender sent a congestion warning"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn too_late_drop() {
+        let init_seq_num = SeqNumber::new_truncate(0);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        sender.set_too_late_drop(true);
+
+        // a message of two packets, and one of one
+        sender
+            .feed((Instant::now(), Bytes::from(vec![0; 2000])))
+            .await
+            .unwrap();
+        sender
+            .feed((Instant::now(), Bytes::from("asdf")))
+            .await
+            .unwrap();
+
+        // nothing is ever ACKed, but once the packets are older than the
+        // latency they're dropped, so flushing finishes
+        timeout(Duration::from_secs(1), sender.flush())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut drop_requests = Vec::new();
+        while let Some(Some(Ok((pack, _)))) = remote.next().now_or_never() {
+            if let Packet::Control(ControlPacket {
+                control_type: ControlTypes::DropRequest { first, last, .. },
+                ..
+            }) = pack
+            {
+                drop_requests.push((first, last));
+            }
+        }
+        assert_eq!(
+            drop_requests,
+            vec![
+                (init_seq_num, init_seq_num + 1),
+                (init_seq_num + 2, init_seq_num + 2)
+            ]
+        );
+
+        let stats = sender.stats();
+        assert_eq!(stats.send_dropped_packets, 3);
+        assert_eq!(stats.send_dropped_bytes, 2004);
+    }
+
+    #[tokio::test]
+    async fn ttl_drop() {
+        let init_seq_num = SeqNumber::new_truncate(0);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let handler_events = events.clone();
+        sender.set_drop_handler(SendDropHandler::new(move |event| {
+            handler_events.lock().unwrap().push(event)
+        }));
+
+        // one message expires before it's sent, the other while it's waiting
+        // for an ACK
+        sender.start_send_msg(
+            Bytes::from("asdf"),
+            MsgCtrl::new().ttl(Duration::from_secs(0)),
+        );
+        sender.start_send_msg(
+            Bytes::from(vec![0; 2000]),
+            MsgCtrl::new().ttl(Duration::from_millis(50)),
+        );
+
+        // nothing is ever ACKed, but flushing finishes once they're dropped
+        timeout(Duration::from_secs(1), sender.flush())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut data = Vec::new();
+        let mut drop_requests = Vec::new();
+        while let Some(Some(Ok((pack, _)))) = remote.next().now_or_never() {
+            match pack {
+                Packet::Data(data_pack) => data.push(data_pack.seq_number),
+                Packet::Control(ControlPacket {
+                    control_type: ControlTypes::DropRequest { first, last, .. },
+                    ..
+                }) => drop_requests.push((first, last)),
+                _ => {}
+            }
+        }
+        // the expired message never got sequence numbers
+        assert_eq!(data, vec![init_seq_num, init_seq_num + 1]);
+        assert_eq!(drop_requests, vec![(init_seq_num, init_seq_num + 1)]);
+
+        let stats = sender.stats();
+        assert_eq!(stats.send_dropped_packets, 3);
+        assert_eq!(stats.send_dropped_bytes, 2004);
+
+        // nothing got through in between, so it's one run of drops
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![SendDropEvent {
+                remote: "127.0.0.1:1234".parse().unwrap(),
+                reason: SendDropReason::Expired,
+                dropped_packets: 1,
+                dropped_bytes: 4,
+            }]
+        );
+    }
+
+    #[test]
+    fn pacer() {
+        // 8000 bits/s is a byte every millisecond
+        let mut pacer = Pacer::new(8000, 100);
+        let start = Instant::now();
+
+        // the bucket starts full, and goes into debt for the rest
+        assert_eq!(pacer.take(100, start), Duration::from_secs(0));
+        assert_eq!(pacer.take(50, start), Duration::from_millis(50));
+
+        // it refills with time, up to the burst size
+        let later = start + Duration::from_millis(100);
+        assert_eq!(pacer.take(50, later), Duration::from_secs(0));
+        let much_later = later + Duration::from_secs(10);
+        assert_eq!(pacer.take(150, much_later), Duration::from_millis(50));
+
+        // no rate, no waiting
+        pacer.set_rate(0);
+        assert_eq!(pacer.take(1000, much_later), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn input_rate() {
+        let mut input = InputRate::new();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // the first estimate is over half a second, counting the headers
+        assert_eq!(input.add(1000, 1, at(0)), None);
+        assert_eq!(input.add(1000, 1, at(250)), None);
+        assert_eq!(input.add(1000, 1, at(500)), Some(3 * 1044 * 8 * 2));
+
+        // then every second, following the source
+        assert_eq!(input.add(2000, 2, at(1000)), None);
+        assert_eq!(input.add(1000, 1, at(1500)), Some(3 * 1044 * 8));
+
+        // a pause doesn't count, the next period starts when data comes again
+        assert_eq!(input.add(1000, 1, at(5000)), None);
+        assert_eq!(input.add(1000, 1, at(5500)), None);
+        assert_eq!(input.add(1000, 1, at(6000)), Some(3 * 1044 * 8));
+    }
+
+    /// Has a window of two packets, and records what it's told
+    #[derive(Default)]
+    struct RecordingCongestCtrl {
+        sent: Arc<AtomicUsize>,
+        acks: Arc<Mutex<Vec<(Duration, i32)>>>,
+    }
+
+    impl CongestCtrl for RecordingCongestCtrl {
+        fn on_ack(&mut self, data: &CCData) {
+            self.acks
+                .lock()
+                .unwrap()
+                .push((data.rtt, data.packet_arr_rate));
+        }
+
+        fn on_packet_sent(&mut self, _data: &CCData) {
+            self.sent.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn send_interval(&self) -> Duration {
+            Duration::from_millis(1)
+        }
+
+        fn window_size(&self) -> u32 {
+            2
+        }
+    }
+
+    #[tokio::test]
+    async fn congestion_window() {
+        let init_seq_num = SeqNumber::new_truncate(0);
+        let cc = RecordingCongestCtrl::default();
+        let (sent, acks) = (cc.sent.clone(), cc.acks.clone());
+        let (mut sender, mut remote) = new_sender(cc, init_seq_num);
+        let sent_data = |remote: &mut PackChan| {
+            let mut count = 0;
+            while let Some(Some(Ok((packet, _)))) = remote.next().now_or_never() {
+                if let Packet::Data(_) = packet {
+                    count += 1;
+                }
+            }
+            count
+        };
+
+        // only sends count, not every tick of the send timer
+        for _ in 0..2 {
+            sender
+                .feed((Instant::now(), Bytes::from("asdf")))
+                .await
+                .unwrap();
+        }
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+        assert_eq!(sent_data(&mut remote), 2);
+        assert_eq!(sent.load(Ordering::SeqCst), 2);
+
+        // the window is full until an ACK comes
+        assert!(timeout(
+            Duration::from_millis(50),
+            sender.feed((Instant::now(), Bytes::from("asdf")))
+        )
+        .await
+        .is_err());
+
+        // congestion control sees the RTT and rates of the ACK that made room
+        let (mut packet, from) = ack(init_seq_num + 2);
+        if let Packet::Control(ControlPacket {
+            control_type:
+                ControlTypes::Ack {
+                    rtt,
+                    packet_recv_rate,
+                    ..
+                },
+            ..
+        }) = &mut packet
+        {
+            *rtt = Some(20_000);
+            *packet_recv_rate = Some(800);
+        }
+        remote.send((packet, from)).await.unwrap();
+        timeout(
+            Duration::from_secs(1),
+            sender.feed((Instant::now(), Bytes::from("asdf"))),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+        assert_eq!(sent_data(&mut remote), 1);
+        assert_eq!(sent.load(Ordering::SeqCst), 3);
+
+        assert_eq!(
+            *acks.lock().unwrap(),
+            vec![(Duration::from_millis(20), 100)]
+        );
+    }
+
+    /// Has a flow window of four packets
+    struct SmallWindowCongestCtrl;
+
+    impl CongestCtrl for SmallWindowCongestCtrl {
+        fn send_interval(&self) -> Duration {
+            Duration::from_millis(1)
+        }
+
+        fn window_size(&self) -> u32 {
+            4
+        }
+    }
+
+    #[tokio::test]
+    async fn light_ack() {
+        let init_seq_num = SeqNumber::new_truncate(0);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        let from: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let ack = |ack_seq_num, ack_number, full: bool| {
+            let full = |value| if full { Some(value) } else { None };
+            let control_type = ControlTypes::Ack {
+                ack_seq_num,
+                ack_number,
+                rtt: full(10_000),
+                rtt_variance: full(1_000),
+                buffer_available: full(8192),
+                packet_recv_rate: full(0),
+                est_link_cap: full(0),
+            };
+            let packet = Packet::Control(ControlPacket {
+                timestamp: TimeStamp(0),
+                dest_sockid: SocketID(2),
+                control_type,
+            });
+            (packet, from)
+        };
+        let ack2s = |remote: &mut PackChan| {
+            let mut ack2s = Vec::new();
+            while let Some(Some(Ok((packet, _)))) = remote.next().now_or_never() {
+                if let Packet::Control(ControlPacket {
+                    control_type: ControlTypes::Ack2(ack_seq_num),
+                    ..
+                }) = packet
+                {
+                    ack2s.push(ack_seq_num);
+                }
+            }
+            ack2s
+        };
+
+        for _ in 0..3 {
+            sender
+                .feed((Instant::now(), Bytes::from("asdf")))
+                .await
+                .unwrap();
+        }
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+
+        // a light ACK releases packets, but isn't answered
+        remote.send(ack(0, init_seq_num + 2, false)).await.unwrap();
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+        assert!(ack2s(&mut remote).is_empty());
+
+        // a full ACK of the same number still gets its ACK2
+        remote.send(ack(1, init_seq_num + 2, true)).await.unwrap();
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+        assert_eq!(ack2s(&mut remote), vec![1]);
+
+        remote.send(ack(0, init_seq_num + 3, false)).await.unwrap();
+        timeout(Duration::from_secs(1), sender.flush())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(ack2s(&mut remote).is_empty());
+    }
+
+    #[tokio::test]
+    async fn backpressure() {
+        let init_seq_num = SeqNumber::new_truncate(0);
+        let (mut sender, mut remote) = new_sender(SmallWindowCongestCtrl, init_seq_num);
+
+        // a message is counted as the packets it's split into
+        sender
+            .feed((Instant::now(), Bytes::from(vec![0; 3000])))
+            .await
+            .unwrap();
+        sender
+            .feed((Instant::now(), Bytes::from("asdf")))
+            .await
+            .unwrap();
+        assert!(
+            timeout(
+                Duration::from_millis(50),
+                sender.feed((Instant::now(), Bytes::from("asdf")))
+            )
+            .await
+            .is_err(),
+            "Accepted more than the flow window"
+        );
+
+        for _ in 0..4 {
+            assert!(matches!(
+                remote.next().await,
+                Some(Ok((Packet::Data(_), _)))
+            ));
+        }
+
+        // ACKing some of them makes room again
+        remote
+            .send((
+                Packet::Control(ControlPacket {
+                    timestamp: TimeStamp(0),
+                    dest_sockid: SocketID(2),
+                    control_type: ControlTypes::Ack {
+                        ack_seq_num: 1,
+                        ack_number: init_seq_num + 2,
+                        rtt: None,
+                        rtt_variance: None,
+                        buffer_available: None,
+                        packet_recv_rate: None,
+                        est_link_cap: None,
+                    },
+                }),
+                "127.0.0.1:1234".parse().unwrap(),
+            ))
+            .await
+            .unwrap();
+        timeout(
+            Duration::from_secs(1),
+            sender.feed((Instant::now(), Bytes::from("asdf"))),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    }
+
+    /// An ACK of everything before `ack_number`
+    fn ack(ack_number: SeqNumber) -> (Packet, SocketAddr) {
+        (
+            Packet::Control(ControlPacket {
+                timestamp: TimeStamp(0),
+                dest_sockid: SocketID(2),
+                control_type: ControlTypes::Ack {
+                    ack_seq_num: 1,
+                    ack_number,
+                    rtt: None,
+                    rtt_variance: None,
+                    buffer_available: None,
+                    packet_recv_rate: None,
+                    est_link_cap: None,
+                },
+            }),
+            "127.0.0.1:1234".parse().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn msg_ctrl() {
+        let init_seq_num = SeqNumber::new_truncate(0);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        let src_time = Instant::now();
+        time::delay_for(Duration::from_millis(10)).await;
+
+        sender.start_send_msg(Bytes::from(vec![0; 2000]), MsgCtrl::new().in_order(false));
+        sender.start_send_msg(Bytes::from("asdf"), MsgCtrl::new().src_time(src_time));
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+
+        // each of a message's packets gets its options
+        let mut sent = Vec::new();
+        while let Some(Some(Ok((Packet::Data(data), _)))) = remote.next().now_or_never() {
+            sent.push((data.in_order_delivery, data.timestamp));
+        }
+        assert_eq!(sent.len(), 3);
+        assert!(!sent[0].0 && !sent[1].0);
+        assert!(sent[2].0);
+        assert!(sent[2].1 < sent[0].1);
+        assert_eq!(sent[2].1, sender.settings.get_timestamp(src_time));
+    }
+
+    #[tokio::test]
+    async fn src_time_clamped() {
+        let init_seq_num = SeqNumber::new_truncate(0);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        let start = sender.settings.socket_start_time;
+
+        // from before the socket started, and from the future
+        let times = [
+            start - Duration::from_secs(1),
+            Instant::now() + Duration::from_secs(1),
+        ];
+        for time in &times {
+            sender.feed((*time, Bytes::from("asdf"))).await.unwrap();
+        }
+        let fed = sender.get_timestamp_now();
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+
+        let mut timestamps = Vec::new();
+        while let Some(Some(Ok((Packet::Data(data), _)))) = remote.next().now_or_never() {
+            timestamps.push(data.timestamp);
+        }
+        assert_eq!(timestamps.len(), 2);
+        assert_eq!(timestamps[0], TimeStamp(0));
+        assert!(timestamps[1] <= fed);
+    }
+
+    #[tokio::test]
+    async fn linger() {
+        let init_seq_num = SeqNumber::new_truncate(0);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        sender.set_linger(Duration::from_millis(100));
+
+        sender
+            .feed((Instant::now(), Bytes::from("asdf")))
+            .await
+            .unwrap();
+
+        // nothing is ever ACKed, but closing gives up once the linger time passed
+        let start = Instant::now();
+        timeout(Duration::from_secs(1), sender.close())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // the data is sent before the shutdown
+        assert!(matches!(
+            remote.next().await,
+            Some(Ok((Packet::Data(_), _)))
+        ));
+        assert!(matches!(
+            remote.next().await,
+            Some(Ok((
+                Packet::Control(ControlPacket {
+                    control_type: ControlTypes::Shutdown,
+                    ..
+                }),
+                _
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    async fn peer_flow_window() {
+        let init_seq_num = SeqNumber::new_truncate(0);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        // a full ACK, with the peer's free buffer space
+        let ack_with_room = |ack_number, room| {
+            let (mut packet, from) = ack(ack_number);
+            if let Packet::Control(ControlPacket {
+                control_type:
+                    ControlTypes::Ack {
+                        ack_seq_num,
+                        buffer_available,
+                        ..
+                    },
+                ..
+            }) = &mut packet
+            {
+                *ack_seq_num = ack_number.as_raw() as i32;
+                *buffer_available = Some(room);
+            }
+            (packet, from)
+        };
+        let sent_data = |remote: &mut PackChan| {
+            let mut count = 0;
+            // skipping the ACK2s
+            while let Some(Some(Ok((packet, _)))) = remote.next().now_or_never() {
+                if let Packet::Data(_) = packet {
+                    count += 1;
+                }
+            }
+            count
+        };
+
+        sender
+            .feed((Instant::now(), Bytes::from("asdf")))
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_millis(20), sender.flush())
+            .await
+            .is_err());
+        assert_eq!(sent_data(&mut remote), 1);
+
+        // the peer only has room for two more
+        remote
+            .send(ack_with_room(init_seq_num + 1, 2))
+            .await
+            .unwrap();
+        for _ in 0..5 {
+            sender
+                .feed((Instant::now(), Bytes::from("asdf")))
+                .await
+                .unwrap();
+        }
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+        assert_eq!(sent_data(&mut remote), 2);
+
+        // then for the rest
+        remote
+            .send(ack_with_room(init_seq_num + 3, 8))
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+        assert_eq!(sent_data(&mut remote), 3);
+    }
+
+    #[tokio::test]
+    async fn send_buffer_size() {
+        let init_seq_num = SeqNumber::new_truncate(0);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        sender.set_send_buffer_size(2500);
+
+        // messages are accepted until the limit is reached
+        for _ in 0..3 {
+            sender
+                .feed((Instant::now(), Bytes::from(vec![0; 1000])))
+                .await
+                .unwrap();
+        }
+        assert!(
+            timeout(
+                Duration::from_millis(50),
+                sender.feed((Instant::now(), Bytes::from("asdf")))
+            )
+            .await
+            .is_err(),
+            "Accepted more than the send buffer"
+        );
+        assert_eq!(sender.stats().send_buffer_overruns, 1);
+
+        // ACKing some of them makes room again
+        remote.send(ack(init_seq_num + 2)).await.unwrap();
+        timeout(
+            Duration::from_secs(1),
+            sender.feed((Instant::now(), Bytes::from("asdf"))),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(sender.stats().send_buffer_overruns, 1);
+    }
+
+    #[tokio::test]
+    async fn nonblocking_send() {
+        let init_seq_num = SeqNumber::new_truncate(0);
+        let (mut sender, mut remote) = new_sender(SrtCongestCtrl::default(), init_seq_num);
+        sender.set_send_buffer_size(1000);
+        sender.set_nonblocking(true);
+
+        sender
+            .feed((Instant::now(), Bytes::from(vec![0; 1000])))
+            .await
+            .unwrap();
+        let err = sender
+            .feed((Instant::now(), Bytes::from("asdf")))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<io::Error>().map(io::Error::kind),
+            Some(io::ErrorKind::WouldBlock)
+        );
+
+        // the sender is still usable once there's room
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+        remote.next().await.unwrap().unwrap();
+        remote.send(ack(init_seq_num + 1)).await.unwrap();
+        timeout(Duration::from_secs(1), sender.flush())
+            .await
+            .unwrap()
+            .unwrap();
+        sender
+            .feed((Instant::now(), Bytes::from("asdf")))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn peer_error() {
+        let (mut sender, mut remote) =
+            new_sender(SrtCongestCtrl::default(), SeqNumber::new_truncate(0));
+
+        sender
+            .feed((Instant::now(), Bytes::from("asdf")))
+            .await
+            .unwrap();
+        remote
+            .send((
+                Packet::Control(ControlPacket {
+                    timestamp: TimeStamp(0),
+                    dest_sockid: SocketID(2),
+                    control_type: ControlTypes::PeerError(4000),
+                }),
+                "127.0.0.1:1234".parse().unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        let err = timeout(Duration::from_secs(1), sender.flush())
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&PeerError { code: 4000 }));
+    }
+
+    #[tokio::test]
+    async fn unexpected_srt_packet_ignored() {
+        let (mut sender, mut remote) =
+            new_sender(SrtCongestCtrl::default(), SeqNumber::new_truncate(0));
+
+        sender
+            .feed((Instant::now(), Bytes::from("asdf")))
+            .await
+            .unwrap();
+        remote
+            .send((
+                Packet::Control(ControlPacket {
+                    timestamp: TimeStamp(0),
+                    dest_sockid: SocketID(2),
+                    control_type: ControlTypes::Srt(SrtControlPacket::StreamId("a".into())),
+                }),
+                "127.0.0.1:1234".parse().unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        // still waiting for the ACK, not failed
+        assert!(timeout(Duration::from_millis(50), sender.flush())
+            .await
+            .is_err());
+    }
+}
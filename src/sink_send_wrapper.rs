@@ -3,7 +3,7 @@ use futures::sink::Sink;
 use std::collections::VecDeque;
 use std::marker::Unpin;
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::task::Context;
 
 use log::info;
 
@@ -31,7 +31,7 @@ impl<Item> SinkSendWrapper<Item> {
         S: Sink<Item, Error = Error> + Unpin,
     {
         let mut pin = Pin::new(sink);
-        if let Poll::Ready(_) = pin.as_mut().poll_ready(cx) {
+        if pin.as_mut().poll_ready(cx).is_ready() {
             pin.start_send(i)?;
         } else {
             info!("Sink not ready, buffering buffer.len={}", self.buffer.len());
@@ -45,7 +45,7 @@ impl<Item> SinkSendWrapper<Item> {
         S: Sink<Item, Error = Error> + Unpin,
     {
         let mut pin = Pin::new(sink);
-        while let Poll::Ready(_) = pin.as_mut().poll_ready(cx) {
+        while pin.as_mut().poll_ready(cx).is_ready() {
             if !self.buffer.is_empty() {
                 pin.as_mut().start_send(self.buffer.pop_front().unwrap())?
             } else {
@@ -1,23 +1,41 @@
 use crate::channel::Channel;
-use crate::packet::ControlTypes;
-use crate::receiver::Receiver;
-use crate::sender::Sender;
-use crate::{Connection, ConnectionSettings, Packet, SrtCongestCtrl};
+#[cfg(feature = "crypto")]
+use crate::crypto::{KeyRefresh, ReceiverKeys, SenderKeys};
+use crate::packet::{
+    ControlPacket, ControlTypes, KmState, PacketParseError, PeerStats, SrtControlPacket,
+};
+use crate::receiver::{Receiver, UndecryptableEvent, UndecryptableHandler};
+use crate::sender::{
+    RetransmitAlgorithm, RetransmitPriority, SendDropEvent, SendDropHandler, Sender,
+};
+use crate::stream_writer::StreamWriter;
+use crate::{CongestCtrl, Connection, ConnectionSettings, MsgCtrl, Packet, Stats};
 
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use bytes::Bytes;
 use failure::Error;
-use futures::channel::oneshot;
-use futures::{stream, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt, TryStreamExt};
-use log::debug;
+use futures::channel::{mpsc, oneshot};
+use futures::{
+    future, stream, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt, TryStreamExt,
+};
+use log::{debug, info, log_enabled, trace, warn, Level};
 use tokio::spawn;
+use tokio::time::timeout;
 
 type PackChan = Channel<(Packet, SocketAddr)>;
 
+/// The log target that packets are traced to, for connections built with
+/// `SrtSocketBuilder::trace_packets`
+pub const PACKET_TRACE_TARGET: &str = "srt::packet_trace";
+
+/// How long the connection can go without sending anything before a
+/// keepalive is sent, so NAT bindings and the peer don't give up on it
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct SrtSocket {
     // The two tasks started need to be stopped when this struct is dropped
     // because those tasks own the socket, so the file handles won't be released
@@ -31,15 +49,30 @@ pub struct SrtSocket {
     // This isn't actually used as a sender, it is just used because when the
     // sender gets dropped the receiver gets notified immediately.
     _drop_oneshot: oneshot::Sender<()>,
-    sender: Sender<PackChan, SrtCongestCtrl>,
+    sender: Sender<PackChan, Box<dyn CongestCtrl + Send>>,
     receiver: Receiver<PackChan>,
+    name: Option<String>,
+    stream_id: Option<String>,
 }
 
 /// This spawns two new tasks:
 /// 1. Receive packets and send them to either the sender or the receiver through
 ///    a channel
 /// 2. Take outgoing packets and send them on the socket
-pub fn create_bidrectional_srt<T>(sock: T, conn: Connection) -> SrtSocket
+///
+/// The first one also answers handshakes, so a peer whose last handshake
+/// response was lost finishes connecting even if this socket isn't being polled
+///
+/// The second one sends a keepalive whenever nothing has gone out for
+/// `KEEPALIVE_INTERVAL`, so an idle connection stays up even if this socket
+/// isn't being polled either
+pub fn create_bidrectional_srt<T>(
+    sock: T,
+    conn: Connection,
+    congest_ctrl: Box<dyn CongestCtrl + Send>,
+    name: Option<String>,
+    trace_packets: bool,
+) -> SrtSocket
 where
     T: Stream<Item = Result<(Packet, SocketAddr), Error>>
         + Sink<(Packet, SocketAddr), Error = Error>
@@ -52,12 +85,41 @@ where
     let (mut to_s_tx, to_s_rx) = to_s.split();
     let (mut to_r_tx, to_r_rx) = to_r.split();
 
-    let (mut sock_tx, mut sock_rx) = sock.split();
+    let (mut sock_tx, sock_rx) = sock.split();
+
+    // anyone can send garbage to a UDP port, so malformed packets are dropped
+    // instead of taking down the connection. Other errors are still fatal
+    let mut sock_rx = sock_rx.filter(|res| {
+        future::ready(match res {
+            Err(e) => match e.downcast_ref::<PacketParseError>() {
+                Some(parse_err) => {
+                    warn!("Dropping malformed packet: {}", parse_err);
+                    false
+                }
+                None => true,
+            },
+            Ok(_) => true,
+        })
+    });
 
     let (drop_tx, drop_rx) = oneshot::channel();
+    let (mut hs_tx, hs_rx) = mpsc::channel(10);
+    let hs_returner = conn.hs_returner;
+
+    let early_data = conn.early_data;
+    let recv_prefix = log_prefix(name.as_deref());
+    let send_prefix = recv_prefix.clone();
+    let settings = conn.settings;
 
     // socket -> sender, receiver
     spawn(async move {
+        // data that arrived during the handshake goes first
+        for (pack, addr) in early_data {
+            if to_r_tx.send((pack, addr)).await.is_err() {
+                return;
+            }
+        }
+
         // this needs to be fused here so it actually doesn't get polled after
         // completion
         // the stream doesn't need this as we construct a new futures each time
@@ -66,24 +128,49 @@ where
             recv_e =  sock_rx.try_next().fuse() => recv_e.expect("Underlying stream failed"),
             _ = &mut drop_fut => None,
         } {
+            if trace_packets {
+                trace!(target: PACKET_TRACE_TARGET, "{}{} <- {}", recv_prefix, addr, pack);
+            }
+
             use ControlTypes::*;
             use Packet::*;
             let res = match &pack {
                 Data(_) => to_r_tx.send((pack, addr)).await,
                 Control(cpk) => match &cpk.control_type {
-                    Handshake(_) => to_s_tx.send((pack, addr)).await,
-                    KeepAlive => unimplemented!(),
+                    Handshake(_) => match hs_returner(&pack) {
+                        Some(resp) => hs_tx.send((resp, addr)).await.map_err(Error::from),
+                        None => Ok(()),
+                    },
+                    // getting here is all it's for
+                    KeepAlive => Ok(()),
                     Ack { .. } => to_s_tx.send((pack, addr)).await,
                     Nak { .. } => to_s_tx.send((pack, addr)).await,
+                    CongestionWarning => to_s_tx.send((pack, addr)).await,
                     Shutdown => {
                         to_r_tx
                             .send((pack.clone(), addr))
                             .and_then(|_| to_s_tx.send((pack, addr)))
                             .await
                     }
+                    PeerError(_) => {
+                        to_r_tx
+                            .send((pack.clone(), addr))
+                            .and_then(|_| to_s_tx.send((pack, addr)))
+                            .await
+                    }
                     Ack2(_) => to_r_tx.send((pack, addr)).await,
-                    DropRequest { .. } => to_s_tx.send((pack, addr)).await,
-                    Srt(_) => unimplemented!(),
+                    DropRequest { .. } => to_r_tx.send((pack, addr)).await,
+                    Srt(SrtControlPacket::PeerStats(_)) => to_s_tx.send((pack, addr)).await,
+                    // the peer's new keys are for decrypting what it sends
+                    Srt(SrtControlPacket::KeyManagerRequest(_)) => to_r_tx.send((pack, addr)).await,
+                    Srt(SrtControlPacket::KeyManagerResponse(_))
+                    | Srt(SrtControlPacket::KeyManagerError(_)) => to_s_tx.send((pack, addr)).await,
+                    Srt(srt) => {
+                        // HSv4 peers send these after connecting, but the
+                        // connection is already set up from the HSv5 handshake
+                        warn!("Ignoring SRT control packet {:?} from {}", srt, addr);
+                        Ok(())
+                    }
                 },
             };
             if res.is_err() {
@@ -94,8 +181,27 @@ where
     });
     // sender, receiver -> socket
     spawn(async move {
-        let mut combined = stream::select(to_s_rx, to_r_rx);
-        while let Some(pa) = combined.try_next().await.expect("underlying stream failed") {
+        let mut combined =
+            stream::select(stream::select(to_s_rx, to_r_rx), hs_rx.map(Ok::<_, Error>));
+        loop {
+            let pa = match timeout(KEEPALIVE_INTERVAL, combined.try_next()).await {
+                Ok(next) => match next.expect("underlying stream failed") {
+                    Some(pa) => pa,
+                    None => break,
+                },
+                Err(_) => (
+                    Packet::Control(ControlPacket {
+                        timestamp: settings.get_timestamp_now(),
+                        dest_sockid: settings.remote_sockid,
+                        control_type: ControlTypes::KeepAlive,
+                    }),
+                    settings.remote,
+                ),
+            };
+            if trace_packets {
+                trace!(target: PACKET_TRACE_TARGET, "{}{} -> {}", send_prefix, pa.1, pa.0);
+            }
+
             sock_tx
                 .send(pa)
                 .await
@@ -104,16 +210,45 @@ where
         debug!("Closing tx task!");
     });
 
-    // Arbitrarilly make the sender responsible for returning handshakes
+    info!(
+        "{}Connected to {}, local socket id={}, remote socket id={}",
+        log_prefix(name.as_deref()),
+        conn.settings.remote,
+        conn.settings.local_sockid.0,
+        conn.settings.remote_sockid.0
+    );
+
+    // only set up with keys when encryption is built in
+    #[cfg_attr(not(feature = "crypto"), allow(unused_mut))]
+    let mut sender = Sender::new(sender_chan, congest_ctrl, conn.settings, None);
+    #[cfg_attr(not(feature = "crypto"), allow(unused_mut))]
+    let mut receiver = Receiver::new(recvr_chan, conn.settings, None);
+    #[cfg(feature = "crypto")]
+    if let (Some(cipher), Some(passphrase)) = (conn.crypto, conn.passphrase) {
+        sender.set_keys(SenderKeys::new(passphrase.clone(), cipher.clone()));
+        // without an agreed key, what the peer encrypts can't be decrypted
+        // until it announces new keys
+        let secured = conn.settings.km_state == KmState::Secured;
+        receiver.set_keys(ReceiverKeys::new(
+            passphrase,
+            if secured { Some(cipher) } else { None },
+        ));
+    }
+
     SrtSocket {
         _drop_oneshot: drop_tx,
-        sender: Sender::new(
-            sender_chan,
-            SrtCongestCtrl,
-            conn.settings,
-            Some(conn.hs_returner),
-        ),
-        receiver: Receiver::new(recvr_chan, conn.settings, None),
+        sender,
+        receiver,
+        name,
+        stream_id: conn.stream_id,
+    }
+}
+
+/// Prefix for log lines about a connection, so named connections can be told apart
+fn log_prefix(name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("[{}] ", name),
+        None => String::new(),
     }
 }
 
@@ -121,8 +256,255 @@ impl SrtSocket {
     pub fn settings(&self) -> &ConnectionSettings {
         self.sender.settings()
     }
+
+    /// The diagnostic name given with `SrtSocketBuilder::name`
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The stream ID sent by the caller in the handshake, set with
+    /// `SrtSocketBuilder::stream_id`
+    pub fn stream_id(&self) -> Option<&str> {
+        self.stream_id.as_deref()
+    }
+
+    /// The TSBPD latency this socket receives with, agreed upon in the
+    /// handshake as the max of this side's receive latency and the peer's
+    /// peer latency.
+    ///
+    /// If the latency wasn't negotiated (no SRT handshake extension), this is
+    /// the locally configured latency.
+    pub fn latency(&self) -> Duration {
+        self.settings().latency()
+    }
+
+    /// The TSBPD latency the peer receives with, agreed upon in the handshake
+    /// as the max of this side's peer latency and the peer's receive latency
+    pub fn peer_latency(&self) -> Duration {
+        self.settings().peer_tsbpd_latency
+    }
+
+    /// The latest statistics the peer's receiver sent about the data sent from this socket
+    ///
+    /// This is `None` until the first report arrives, and always `None` if
+    /// either side didn't enable `SrtSocketBuilder::peer_stats`
+    pub fn peer_stats(&self) -> Option<PeerStats> {
+        self.sender.peer_stats()
+    }
+
+    /// The estimated bandwidth-delay product of the link to the peer, in bytes
+    ///
+    /// Returns `None` until the link capacity and RTT have been measured
+    pub fn estimated_bdp(&self) -> Option<u64> {
+        self.sender.estimated_bdp()
+    }
+
+    /// The current statistics of the connection, including the receiver's jitter histogram
+    pub fn stats(&self) -> Stats {
+        Stats {
+            received_bytes: self.receiver.received_bytes(),
+            received_original_packets: self.receiver.received_original_packets(),
+            received_retransmitted_packets: self.receiver.received_retransmitted_packets(),
+            recv_dropped_packets: self.receiver.dropped_packets(),
+            undecryptable_packets: self.receiver.undecryptable_packets(),
+            recv_km_state: self.receiver.km_state(),
+            tsbpd_drift: self.receiver.tsbpd_drift(),
+            jitter_histogram: *self.receiver.jitter_histogram(),
+            ..self.sender.stats()
+        }
+    }
+
+    /// The state of the keys data sent to the peer is encrypted with, see
+    /// `Sender::km_state`
+    pub fn send_km_state(&self) -> KmState {
+        self.sender.km_state()
+    }
+
+    /// The state of the keys data from the peer is encrypted with, see
+    /// `Receiver::km_state`
+    ///
+    /// `BadSecret` here means the passphrases don't match, while a peer that
+    /// just isn't sending anything leaves it as it was
+    pub fn recv_km_state(&self) -> KmState {
+        self.receiver.km_state()
+    }
+
+    /// Call `handler` each time encrypted packets from the peer start being
+    /// dropped for there being no key to decrypt them with
+    ///
+    /// It's called again if packets start being dropped again after some got
+    /// through, for example after the peer's new keys couldn't be unwrapped
+    pub fn on_undecryptable<F>(&mut self, handler: F)
+    where
+        F: Fn(UndecryptableEvent) + Send + Sync + 'static,
+    {
+        self.set_undecryptable_handler(UndecryptableHandler::new(handler));
+    }
+
+    pub(crate) fn set_undecryptable_handler(&mut self, handler: UndecryptableHandler) {
+        self.receiver.set_undecryptable_handler(handler);
+    }
+
+    /// Call `handler` each time the sender starts dropping messages instead
+    /// of retransmitting them, for being too late or past their time to live
+    ///
+    /// It's called again if messages start being dropped again after some got
+    /// through, so an encoder can lower its bitrate while the link can't keep up
+    pub fn on_send_drop<F>(&mut self, handler: F)
+    where
+        F: Fn(SendDropEvent) + Send + Sync + 'static,
+    {
+        self.set_send_drop_handler(SendDropHandler::new(handler));
+    }
+
+    pub(crate) fn set_send_drop_handler(&mut self, handler: SendDropHandler) {
+        self.sender.set_drop_handler(handler);
+    }
+
+    /// Write a byte stream to the peer with `AsyncWrite`, in payloads as
+    /// large as fit in a packet, see `StreamWriter`
+    ///
+    /// Only for `StreamSocket`, so a message mode connection can't carry a
+    /// byte stream the handshake didn't announce
+    pub(crate) fn stream_writer(self) -> StreamWriter<Self> {
+        let chunk_size = self.settings().max_packet_size as usize;
+
+        StreamWriter::new(self, chunk_size)
+    }
+
+    /// Wait until everything sent has been acknowledged by the peer, or
+    /// dropped for being too late or past its time to live, see `Sender::flush`
+    ///
+    /// A file transfer has only truly finished once this completes
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        self.sender.flush().await
+    }
+
+    /// Send a message with its own options, waiting for it to be acknowledged
+    /// or dropped, like `SinkExt::send`
+    pub async fn send_msg(&mut self, payload: Bytes, ctrl: MsgCtrl) -> Result<(), Error> {
+        self.sender.send_msg(payload, ctrl).await
+    }
+
+    /// Set the shortest time allowed between two sent packets
+    pub fn set_min_send_interval(&mut self, ivl: Duration) {
+        self.sender.set_min_send_interval(ivl);
+    }
+
+    /// Space packets out to within tens of microseconds, see
+    /// `SrtSocketBuilder::precise_pacing`
+    pub fn set_precise_pacing(&mut self, precise: bool) {
+        self.sender.set_precise_pacing(precise);
+    }
+
+    /// Drop packets that are too late for the peer to deliver instead of
+    /// retransmitting them, see `SrtSocketBuilder::too_late_drop`
+    pub fn set_too_late_drop(&mut self, enabled: bool) {
+        self.sender.set_too_late_drop(enabled);
+    }
+
+    /// Set which packets the peer reports lost are retransmitted, see
+    /// `SrtSocketBuilder::retransmit_algorithm`
+    pub fn set_retransmit_algorithm(&mut self, algorithm: RetransmitAlgorithm) {
+        self.sender.set_retransmit_algorithm(algorithm);
+    }
+
+    /// Set how retransmissions share the send slots with new data, see
+    /// `SrtSocketBuilder::retransmit_priority`
+    pub fn set_retransmit_priority(&mut self, priority: RetransmitPriority) {
+        self.sender.set_retransmit_priority(priority);
+    }
+
+    /// Hold back data once `size` payload bytes are waiting to be sent or
+    /// waiting for an ACK, see `SrtSocketBuilder::send_buffer_size`
+    pub fn set_send_buffer_size(&mut self, size: usize) {
+        self.sender.set_send_buffer_size(size);
+    }
+
+    /// Fail sending with `io::ErrorKind::WouldBlock` when there's no room for
+    /// more data, see `SrtSocketBuilder::nonblocking_send`
+    pub fn set_nonblocking_send(&mut self, nonblocking: bool) {
+        self.sender.set_nonblocking(nonblocking);
+    }
+
+    /// Wait at most `linger` for the data sent to be acknowledged when closing,
+    /// see `SrtSocketBuilder::linger`
+    pub fn set_linger(&mut self, linger: Duration) {
+        self.sender.set_linger(linger);
+    }
+
+    /// Never send data faster than `rate` bits/s, counting retransmissions and
+    /// the packet headers
+    pub fn set_max_bandwidth(&mut self, rate: u64) {
+        self.sender.set_max_bandwidth(rate);
+    }
+
+    /// Never send data faster than `overhead` percent over the estimated rate
+    /// it's given to the socket at, see `SrtSocketBuilder::bandwidth_overhead`
+    pub fn set_bandwidth_overhead(&mut self, overhead: u32) {
+        self.sender.set_bandwidth_overhead(overhead);
+    }
+
+    /// Set how often the key payloads are encrypted with is replaced, if the
+    /// connection is encrypted: after `rate` packets, announcing the new key
+    /// `pre_announce` packets before switching to it
+    #[cfg(feature = "crypto")]
+    pub fn set_key_refresh(&mut self, rate: u64, pre_announce: u64) {
+        self.sender.set_key_refresh(KeyRefresh {
+            rate,
+            pre_announce,
+            ..self.sender.key_refresh()
+        });
+    }
+
+    /// Also replace the key once it has been used for `period`, see
+    /// `SrtSocketBuilder::key_refresh_period`
+    #[cfg(feature = "crypto")]
+    pub fn set_key_refresh_period(&mut self, period: Duration) {
+        self.sender.set_key_refresh_period(period);
+    }
+
+    /// Set how many packets can arrive after a missing one before it's
+    /// reported lost, see `SrtSocketBuilder::reorder_tolerance`
+    pub fn set_reorder_tolerance(&mut self, packets: u32) {
+        self.receiver.set_reorder_tolerance(packets);
+    }
+
+    /// Set how often full ACKs are sent, see `SrtSocketBuilder::ack_interval`
+    pub fn set_ack_interval(&mut self, period: Duration) {
+        self.receiver.set_ack_interval(period);
+    }
+
+    /// Set the bucket width of the jitter histogram, clearing it
+    /// Defaults to one millisecond
+    pub fn set_jitter_bucket_width(&mut self, width: Duration) {
+        self.receiver.set_jitter_bucket_width(width);
+    }
+}
+
+impl Drop for SrtSocket {
+    fn drop(&mut self) {
+        // collecting the stats isn't free, so only do it if it would be logged
+        if log_enabled!(Level::Info) {
+            let stats = self.stats();
+
+            info!(
+                "{}Connection to {} closed: sent {} bytes, received {} bytes, {} lost, {} retransmitted, {} dropped, rtt={}us",
+                log_prefix(self.name()),
+                self.settings().remote,
+                stats.sent_bytes,
+                stats.received_bytes,
+                stats.lost_packets,
+                stats.retransmitted_packets,
+                stats.send_dropped_packets,
+                stats.rtt
+            );
+        }
+    }
 }
 
+/// Received messages with their source time, see the `Stream` implementation
+/// of `Receiver`
 impl Stream for SrtSocket {
     type Item = Result<(Instant, Bytes), Error>;
 
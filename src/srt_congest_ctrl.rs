@@ -2,14 +2,57 @@ use std::time::Duration;
 
 use crate::CongestCtrl;
 
-pub struct SrtCongestCtrl;
+/// The congestion control used by `SrtSocket`
+///
+/// By default this doesn't pace packets at all (there is no slow start to
+/// wait through), but a known link rate can be given to pace packets from the
+/// very first one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SrtCongestCtrl {
+    send_interval: Duration,
+}
+
+impl SrtCongestCtrl {
+    /// Pace packets so at most `rate` bits/s are sent, assuming each packet is
+    /// `packet_size` bytes
+    pub fn with_send_rate(rate: u64, packet_size: u32) -> SrtCongestCtrl {
+        assert!(rate > 0, "Send rate must be nonzero");
+
+        let bits = u64::from(packet_size) * 8;
+
+        SrtCongestCtrl {
+            send_interval: Duration::from_nanos(bits * 1_000_000_000 / rate),
+        }
+    }
+}
 
 impl CongestCtrl for SrtCongestCtrl {
     fn send_interval(&self) -> Duration {
-        Duration::from_secs(0)
+        self.send_interval
     }
 
     fn window_size(&self) -> u32 {
         10_0000
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::SrtCongestCtrl;
+    use crate::CongestCtrl;
+    use std::time::Duration;
+
+    #[test]
+    fn send_rate() {
+        assert_eq!(
+            SrtCongestCtrl::default().send_interval(),
+            Duration::from_secs(0)
+        );
+
+        // 1500 byte packets at 12 Mbps is 1000 packets/s
+        assert_eq!(
+            SrtCongestCtrl::with_send_rate(12_000_000, 1500).send_interval(),
+            Duration::from_millis(1)
+        );
+    }
+}
@@ -1,8 +1,10 @@
-use std::{cmp::Ordering, fmt};
+use core::{cmp::Ordering, fmt};
+
+use serde::{Deserialize, Serialize};
 
 /// Serialied, it looks like:
 /// major * 0x10000 + minor * 0x100 + patch
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 pub struct SrtVersion {
     pub major: u8,
     pub minor: u8,
@@ -42,19 +44,19 @@ impl SrtVersion {
 
 impl PartialOrd for SrtVersion {
     fn partial_cmp(&self, other: &SrtVersion) -> Option<Ordering> {
-        Some(match self.major.cmp(&other.major) {
-            Ordering::Equal => match self.minor.cmp(&other.minor) {
-                Ordering::Equal => self.patch.cmp(&other.patch),
-                o => o,
-            },
-            o => o,
-        })
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for SrtVersion {
     fn cmp(&self, other: &SrtVersion) -> Ordering {
-        self.partial_cmp(other).unwrap() // this cannot fail
+        match self.major.cmp(&other.major) {
+            Ordering::Equal => match self.minor.cmp(&other.minor) {
+                Ordering::Equal => self.patch.cmp(&other.patch),
+                o => o,
+            },
+            o => o,
+        }
     }
 }
 
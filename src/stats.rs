@@ -1,8 +1,17 @@
+use std::time::Duration;
+
+use crate::packet::KmState;
+use crate::TimeStamp;
+
+/// The number of buckets in a `JitterHistogram`. The last bucket catches
+/// everything that doesn't fit into the others.
+pub const JITTER_BUCKETS: usize = 32;
+
 /// Statistics that SRT can capture on
 #[derive(Debug, Copy, Clone)]
 pub struct Stats {
     /// Timestamp that the stats was captured, in us from socket start
-    pub timestamp: i32,
+    pub timestamp: TimeStamp,
 
     /// Round trip time, in us
     pub rtt: i32,
@@ -23,6 +32,20 @@ pub struct Stats {
     /// The number of packets that have been retransmitted but still received in time
     pub retransmitted_packets: u32,
 
+    /// The number of retransmissions sent, counting each time a packet is sent again
+    pub retransmits: u32,
+
+    /// The number of payload bytes in those retransmissions
+    pub retransmitted_bytes: u64,
+
+    /// The number of times the send buffer filled up, so sending had to wait
+    /// or, when nonblocking, failed, see `SrtSocketBuilder::send_buffer_size`
+    pub send_buffer_overruns: u32,
+
+    /// The number of times the peer reported a packet lost that had been
+    /// retransmitted too recently to be sent again
+    pub suppressed_retransmits: u32,
+
     /// The number of bytes in the sender buffer waiting to be sent
     pub sender_buffer: u32,
 
@@ -31,4 +54,210 @@ pub struct Stats {
 
     /// Estimated link capacity, in bps
     pub est_link_cap: i32,
+
+    /// The number of payload bytes sent, not counting retransmissions
+    pub sent_bytes: u64,
+
+    /// The estimated rate data is given to the sender at, in bits/s, counting
+    /// the packet headers. Only estimated when the max bandwidth is derived
+    /// from it, see `SrtSocketBuilder::bandwidth_overhead`
+    pub input_rate: Option<u64>,
+
+    /// The number of packets the sender dropped instead of retransmitting,
+    /// as they were too late to be delivered, see `SrtSocketBuilder::too_late_drop`
+    pub send_dropped_packets: u32,
+
+    /// The number of payload bytes in those packets
+    pub send_dropped_bytes: u64,
+
+    /// The number of payload bytes received from the peer
+    /// Always zero for stats that come from just a `Sender`
+    pub received_bytes: u64,
+
+    /// The number of data packets received from the peer on their first transmission
+    /// Always zero for stats that come from just a `Sender`
+    pub received_original_packets: u32,
+
+    /// The number of data packets received from the peer that were marked as retransmissions
+    /// Always zero for stats that come from just a `Sender`
+    pub received_retransmitted_packets: u32,
+
+    /// The number of data packets from the peer given up on as their TSBPD
+    /// deadline passed, whether they never arrived or the rest of their
+    /// message didn't, so the stream could move on. Also counts what arrived
+    /// of messages that can never be put together, such as the end of one
+    /// whose beginning the sender dropped
+    /// Always zero for stats that come from just a `Sender`
+    pub recv_dropped_packets: u32,
+
+    /// The number of encrypted data packets from the peer dropped for there
+    /// being no key to decrypt them with
+    /// Always zero for stats that come from just a `Sender`
+    pub undecryptable_packets: u32,
+
+    /// The state of the keys data sent to the peer is encrypted with
+    pub send_km_state: KmState,
+
+    /// The state of the keys data from the peer is encrypted with
+    /// Always `Unsecured` for stats that come from just a `Sender`
+    pub recv_km_state: KmState,
+
+    /// How far the TSBPD time base has been moved to follow the peer's clock
+    /// as it drifts, in microseconds
+    /// Always zero for stats that come from just a `Sender`
+    pub tsbpd_drift: i32,
+
+    pub(crate) jitter_histogram: JitterHistogram,
+}
+
+impl Stats {
+    /// How long packets waited in the receive buffer between arrival and TSBPD release
+    ///
+    /// This is empty for stats that come from just a `Sender`
+    pub fn jitter_histogram(&self) -> &JitterHistogram {
+        &self.jitter_histogram
+    }
+}
+
+/// A histogram of the time each packet waited in the receive buffer, from
+/// arrival until it was released by TSBPD
+///
+/// If most packets wait close to the full latency, the latency could be lowered,
+/// if many wait for almost no time, the latency is too tight.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct JitterHistogram {
+    bucket_width: Duration,
+    buckets: [u32; JITTER_BUCKETS],
+}
+
+impl JitterHistogram {
+    /// Create an empty histogram, where bucket `i` counts the waits in
+    /// `[i * bucket_width, (i + 1) * bucket_width)`
+    pub fn new(bucket_width: Duration) -> JitterHistogram {
+        assert!(
+            bucket_width > Duration::from_secs(0),
+            "Bucket width must be nonzero"
+        );
+
+        JitterHistogram {
+            bucket_width,
+            buckets: [0; JITTER_BUCKETS],
+        }
+    }
+
+    /// Count a packet that waited for `wait`
+    pub fn record(&mut self, wait: Duration) {
+        let idx = (wait.as_micros() / self.bucket_width.as_micros().max(1)) as usize;
+
+        let bucket = &mut self.buckets[idx.min(JITTER_BUCKETS - 1)];
+        *bucket = bucket.saturating_add(1);
+    }
+
+    /// How wide a range of waits each bucket counts
+    pub fn bucket_width(&self) -> Duration {
+        self.bucket_width
+    }
+
+    /// The count in each bucket
+    pub fn buckets(&self) -> &[u32] {
+        &self.buckets[..]
+    }
+
+    /// The total number of packets recorded, saturating at `u32::MAX`
+    pub fn count(&self) -> u32 {
+        self.buckets.iter().fold(0, |sum, &n| sum.saturating_add(n))
+    }
+
+    /// The upper edge of the bucket containing the `p`th percentile wait, `p` being in `[0, 1]`
+    ///
+    /// If that is in the last bucket, it has no upper edge, so its lower edge is returned.
+    /// `p` outside of `[0, 1]` is clamped to it. Returns `None` if nothing has been recorded.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let target = ((f64::from(count) * p.clamp(0.0, 1.0)).ceil() as u32).max(1);
+
+        let mut seen = 0u32;
+        let i = self.buckets.iter().position(|&bucket| {
+            seen = seen.saturating_add(bucket);
+            seen >= target
+        })?;
+
+        let edge = if i == JITTER_BUCKETS - 1 { i } else { i + 1 };
+        Some(self.bucket_width * edge as u32)
+    }
+
+    /// The median wait, see `percentile`
+    pub fn p50(&self) -> Option<Duration> {
+        self.percentile(0.50)
+    }
+
+    /// The 95th percentile wait, see `percentile`
+    pub fn p95(&self) -> Option<Duration> {
+        self.percentile(0.95)
+    }
+
+    /// The 99th percentile wait, see `percentile`
+    pub fn p99(&self) -> Option<Duration> {
+        self.percentile(0.99)
+    }
+}
+
+impl Default for JitterHistogram {
+    /// 1 ms buckets
+    fn default() -> JitterHistogram {
+        JitterHistogram::new(Duration::from_millis(1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JitterHistogram, JITTER_BUCKETS};
+    use std::time::Duration;
+
+    #[test]
+    fn percentiles() {
+        let mut hist = JitterHistogram::new(Duration::from_millis(10));
+        assert_eq!(hist.p50(), None);
+
+        for _ in 0..90 {
+            hist.record(Duration::from_millis(5));
+        }
+        for _ in 0..9 {
+            hist.record(Duration::from_millis(42));
+        }
+        hist.record(Duration::from_secs(10));
+
+        assert_eq!(hist.count(), 100);
+        assert_eq!(hist.buckets()[0], 90);
+        assert_eq!(hist.buckets()[4], 9);
+        assert_eq!(hist.buckets()[JITTER_BUCKETS - 1], 1);
+
+        assert_eq!(hist.p50(), Some(Duration::from_millis(10)));
+        assert_eq!(hist.p95(), Some(Duration::from_millis(50)));
+        assert_eq!(hist.p99(), Some(Duration::from_millis(50)));
+        assert_eq!(
+            hist.percentile(1.0),
+            Some(Duration::from_millis(10) * (JITTER_BUCKETS as u32 - 1))
+        );
+
+        // out of range is clamped
+        assert_eq!(hist.percentile(1.5), hist.percentile(1.0));
+        assert_eq!(hist.percentile(-1.0), Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn counts_saturate() {
+        let mut hist = JitterHistogram::new(Duration::from_millis(10));
+        hist.buckets[0] = u32::MAX;
+        hist.buckets[1] = u32::MAX;
+
+        hist.record(Duration::from_millis(5));
+        assert_eq!(hist.buckets()[0], u32::MAX);
+        assert_eq!(hist.count(), u32::MAX);
+        assert_eq!(hist.p99(), Some(Duration::from_millis(10)));
+    }
 }
@@ -0,0 +1,86 @@
+use std::cmp::min;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, Bytes};
+use failure::Error;
+use futures::{ready, Stream};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::stream_writer::StreamWriter;
+use crate::{ConnectionSettings, SrtSocket, Stats};
+
+/// A connection in buffer mode, like SRTO_MESSAGEAPI turned off, carrying a
+/// continuous byte stream instead of messages. Got from
+/// `SrtSocketBuilder::connect_stream`
+///
+/// Writes are gathered into payloads as large as fit in a packet, and reads
+/// give back the payloads concatenated in order, so the stream is split
+/// wherever it fits the packets best. There's no way to send or receive
+/// messages here, and the handshake announces buffer mode so a message mode
+/// peer refuses the connection, which keeps the two from being mixed
+pub struct StreamSocket {
+    writer: StreamWriter<SrtSocket>,
+    read_buf: Bytes,
+}
+
+impl StreamSocket {
+    pub(crate) fn new(socket: SrtSocket) -> Self {
+        StreamSocket {
+            writer: socket.stream_writer(),
+            read_buf: Bytes::new(),
+        }
+    }
+
+    pub fn settings(&self) -> &ConnectionSettings {
+        self.writer.get_ref().settings()
+    }
+
+    /// Statistics of the connection, see `SrtSocket::stats`
+    pub fn stats(&self) -> Stats {
+        self.writer.get_ref().stats()
+    }
+}
+
+impl AsyncRead for StreamSocket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let pin = self.get_mut();
+        while pin.read_buf.is_empty() {
+            match ready!(Pin::new(pin.writer.get_mut()).poll_next(cx)) {
+                Some(Ok((_, payload))) => pin.read_buf = payload,
+                Some(Err(e)) => return Poll::Ready(Err(to_io_error(e))),
+                // end of stream
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+
+        let len = min(buf.len(), pin.read_buf.len());
+        buf[..len].copy_from_slice(&pin.read_buf[..len]);
+        pin.read_buf.advance(len);
+
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl AsyncWrite for StreamSocket {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().writer).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().writer).poll_shutdown(cx)
+    }
+}
+
+fn to_io_error(e: Error) -> io::Error {
+    io::Error::other(e.compat())
+}
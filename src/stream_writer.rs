@@ -0,0 +1,125 @@
+use std::cmp::min;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use bytes::{Bytes, BytesMut};
+use failure::Error;
+use futures::{ready, Sink};
+use tokio::io::AsyncWrite;
+
+/// Writes a byte stream to a connection, for file and TCP-like workloads
+/// that don't have messages of their own, used by `StreamSocket`
+///
+/// Writes are gathered into payloads of up to `chunk_size` bytes, each sent
+/// as a message of its own in one packet, so the stream is split wherever it
+/// fits the packets best. The peer gets the stream back by concatenating the
+/// payloads in order. Flushing sends what's gathered so far, even if it
+/// doesn't fill a packet, and waits for everything to be acknowledged
+pub struct StreamWriter<S> {
+    sink: S,
+    chunk_size: usize,
+    buf: BytesMut,
+}
+
+impl<S> StreamWriter<S>
+where
+    S: Sink<(Instant, Bytes), Error = Error> + Unpin,
+{
+    /// Write to `sink` in payloads of up to `chunk_size` bytes, which is
+    /// usually the largest payload that fits in a packet
+    pub fn new(sink: S, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "Chunk size must be nonzero");
+
+        StreamWriter {
+            sink,
+            chunk_size,
+            buf: BytesMut::with_capacity(chunk_size),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Send what's gathered as one payload, if there's anything
+    fn poll_send_chunk(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        if self.buf.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+
+        ready!(Pin::new(&mut self.sink).poll_ready(cx)).map_err(to_io_error)?;
+        let chunk = self.buf.split().freeze();
+        Pin::new(&mut self.sink)
+            .start_send((Instant::now(), chunk))
+            .map_err(to_io_error)?;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncWrite for StreamWriter<S>
+where
+    S: Sink<(Instant, Bytes), Error = Error> + Unpin,
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let pin = self.get_mut();
+        if pin.buf.len() >= pin.chunk_size {
+            ready!(pin.poll_send_chunk(cx))?;
+        }
+
+        let len = min(buf.len(), pin.chunk_size - pin.buf.len());
+        pin.buf.extend_from_slice(&buf[..len]);
+
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let pin = self.get_mut();
+        ready!(pin.poll_send_chunk(cx))?;
+
+        Pin::new(&mut pin.sink).poll_flush(cx).map_err(to_io_error)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let pin = self.get_mut();
+        ready!(pin.poll_send_chunk(cx))?;
+
+        Pin::new(&mut pin.sink).poll_close(cx).map_err(to_io_error)
+    }
+}
+
+fn to_io_error(e: Error) -> io::Error {
+    io::Error::other(e.compat())
+}
+
+#[cfg(test)]
+mod test {
+    use super::StreamWriter;
+
+    use bytes::Bytes;
+    use futures::channel::mpsc;
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn chunks() {
+        let (sink, chunks) = mpsc::unbounded();
+        let mut writer = StreamWriter::new(sink.sink_map_err(failure::Error::from), 4);
+
+        // small writes are gathered, large ones split
+        writer.write_all(b"ab").await.unwrap();
+        writer.write_all(b"cdefghij").await.unwrap();
+        writer.write_all(b"k").await.unwrap();
+        writer.shutdown().await.unwrap();
+        drop(writer);
+
+        let chunks: Vec<Bytes> = chunks.map(|(_, chunk)| chunk).collect().await;
+        assert_eq!(chunks, vec!["abcd", "efgh", "ijk"]);
+    }
+}
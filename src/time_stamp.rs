@@ -0,0 +1,127 @@
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+use crate::modular_num;
+
+// A packet timestamp, in microseconds since the socket started
+//
+// It is 32 bits on the wire, so it wraps about every 71 minutes. It's stored
+// in a u64 so the modulus (2^32) fits.
+modular_num! {
+    pub TimeStamp(u64, 32)
+}
+
+impl TimeStamp {
+    /// The timestamp of `at`, for a socket started at `start`
+    ///
+    /// Times before the socket started are stamped as when it did
+    #[cfg(feature = "std")]
+    pub fn from_instant(start: Instant, at: Instant) -> TimeStamp {
+        TimeStamp::new_truncate(at.saturating_duration_since(start).as_micros() as u64)
+    }
+
+    /// The signed number of microseconds from `other` to `self`
+    ///
+    /// Wrapping is accounted for, so this is right as long as the two are
+    /// within about 35 minutes of each other
+    pub fn diff(self, other: TimeStamp) -> i32 {
+        if self >= other {
+            (self - other) as i32
+        } else {
+            -((other - self) as i32)
+        }
+    }
+
+    /// The instant this timestamp refers to, for a socket started at `start`
+    ///
+    /// Which wrap of the timestamp is meant is decided by picking the one
+    /// closest to now
+    #[cfg(feature = "std")]
+    pub fn instant(self, start: Instant) -> Instant {
+        self.instant_near(start, Instant::now())
+    }
+
+    #[cfg(feature = "std")]
+    fn instant_near(self, start: Instant, now: Instant) -> Instant {
+        let elapsed = now.saturating_duration_since(start).as_micros() as u64;
+        let mut us = (elapsed & !(TimeStamp::MAX - 1)) | self.0;
+
+        if us > elapsed + TimeStamp::MAX / 2 && us >= TimeStamp::MAX {
+            us -= TimeStamp::MAX;
+        } else if us + TimeStamp::MAX / 2 < elapsed {
+            us += TimeStamp::MAX;
+        }
+
+        start + Duration::from_micros(us)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TimeStamp;
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn diff_wraps() {
+        let before_wrap = TimeStamp::new_truncate(TimeStamp::MAX - 10);
+        let after_wrap = TimeStamp::new_truncate(5);
+
+        assert!(after_wrap > before_wrap);
+        assert_eq!(after_wrap.diff(before_wrap), 15);
+        assert_eq!(before_wrap.diff(after_wrap), -15);
+        assert_eq!(after_wrap + 10 - after_wrap, 10);
+        assert_eq!(
+            TimeStamp::new_truncate(u64::from(u32::MAX) + 3),
+            TimeStamp(2)
+        );
+    }
+
+    #[test]
+    fn instant_across_wrap() {
+        let start = Instant::now();
+        let wrap = Duration::from_micros(TimeStamp::MAX);
+        let now = start + wrap + Duration::from_secs(1);
+
+        // sent before the timestamp wrapped
+        assert_eq!(
+            TimeStamp(TimeStamp::MAX - 500_000).instant_near(start, now),
+            start + wrap - Duration::from_millis(500)
+        );
+        assert_eq!(
+            TimeStamp(1_250_000).instant_near(start, now),
+            start + wrap + Duration::from_millis(1_250)
+        );
+
+        // never before the socket started
+        assert_eq!(
+            TimeStamp(TimeStamp::MAX - 1).instant_near(start, start),
+            start + Duration::from_micros(TimeStamp::MAX - 1)
+        );
+    }
+
+    #[test]
+    fn instant_round_trip() {
+        let start = Instant::now() - Duration::from_secs(1);
+        let at = start + Duration::from_micros(500_000);
+
+        assert_eq!(TimeStamp::from_instant(start, at).instant(start), at);
+    }
+
+    #[test]
+    fn from_instant_wraps() {
+        let start = Instant::now();
+        let wrap = Duration::from_micros(TimeStamp::MAX);
+        let at = start + wrap + Duration::from_micros(1_500);
+
+        assert_eq!(TimeStamp::from_instant(start, at), TimeStamp(1_500));
+        assert_eq!(
+            TimeStamp::from_instant(start, at).instant_near(start, at),
+            at
+        );
+        assert_eq!(
+            TimeStamp::from_instant(start + Duration::from_secs(1), start),
+            TimeStamp(0)
+        );
+    }
+}
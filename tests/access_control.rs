@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use srt::{
+    AcceptParams, AccessDecision, ConnInitMethod, ConnectTimeout, RejectReason, SrtSocketBuilder,
+};
+
+use failure::Error;
+use futures::{join, TryStreamExt};
+
+// only "public" may connect, and it gets more latency
+async fn by_stream_id(addr: std::net::SocketAddr, stream_id: Option<String>) -> AccessDecision {
+    assert!(addr.ip().is_loopback());
+
+    match stream_id.as_deref() {
+        Some("public") => {
+            AccessDecision::Accept(AcceptParams::new().latency(Duration::from_millis(200)))
+        }
+        _ => AccessDecision::Reject(RejectReason::User(2001)),
+    }
+}
+
+fn caller(port: u16, stream_id: &str) -> SrtSocketBuilder {
+    SrtSocketBuilder::new(ConnInitMethod::Connect(
+        format!("127.0.0.1:{}", port).parse().unwrap(),
+    ))
+    .stream_id(stream_id.to_string())
+}
+
+#[tokio::test]
+async fn refused_then_accepted() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6016)
+        .access_control(by_stream_id)
+        .connect();
+
+    // the refused caller doesn't stop the listener from taking the next one
+    let callers = async {
+        let refused = caller(6016, "private").connect().await;
+        let accepted = caller(6016, "public").connect().await;
+        (refused, accepted)
+    };
+
+    let (listener, (refused, accepted)) = join!(listener, callers);
+
+    let err = refused.err().expect("Private stream was accepted");
+    assert_eq!(
+        err.downcast_ref::<RejectReason>(),
+        Some(&RejectReason::User(2001))
+    );
+
+    let (listener, accepted) = (listener?, accepted?);
+    assert_eq!(listener.stream_id(), Some("public"));
+    assert_eq!(
+        listener.settings().tsbpd_latency,
+        Duration::from_millis(200)
+    );
+    assert_eq!(
+        accepted.settings().tsbpd_latency,
+        Duration::from_millis(200)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn listener_refuses() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let mut listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6017)
+        .access_control(by_stream_id)
+        .build_listener()
+        .await?;
+
+    tokio::spawn(async move {
+        while let Some(sock) = listener.try_next().await.unwrap() {
+            assert_eq!(sock.stream_id(), Some("public"));
+        }
+    });
+
+    let (refused, accepted) = join!(
+        caller(6017, "private").connect(),
+        caller(6017, "public").connect()
+    );
+
+    let err = refused.err().expect("Private stream was accepted");
+    assert_eq!(
+        err.downcast_ref::<RejectReason>(),
+        Some(&RejectReason::User(2001))
+    );
+    assert_eq!(
+        accepted?.settings().tsbpd_latency,
+        Duration::from_millis(200)
+    );
+
+    Ok(())
+}
+
+// callers from these ports are ignored
+const DENIED_PORTS: [u16; 2] = [6021, 6023];
+
+fn not_denied(addr: std::net::SocketAddr) -> bool {
+    !DENIED_PORTS.contains(&addr.port())
+}
+
+#[tokio::test]
+async fn address_filtered() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6020)
+        .address_filter(not_denied)
+        .connect();
+
+    let callers = async {
+        let denied = caller(6020, "public")
+            .local_port(DENIED_PORTS[0])
+            .connect_timeout(Duration::from_millis(300))
+            .connect()
+            .await;
+        let allowed = caller(6020, "public").connect().await;
+        (denied, allowed)
+    };
+
+    let (listener, (denied, allowed)) = join!(listener, callers);
+
+    let err = denied.err().expect("Filtered caller connected");
+    assert!(err.downcast_ref::<ConnectTimeout>().is_some());
+
+    listener?;
+    allowed?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn listener_address_filtered() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let mut listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6022)
+        .address_filter(not_denied)
+        .build_listener()
+        .await?;
+    tokio::spawn(async move { while listener.try_next().await.unwrap().is_some() {} });
+
+    let (denied, allowed) = join!(
+        caller(6022, "public")
+            .local_port(DENIED_PORTS[1])
+            .connect_timeout(Duration::from_millis(300))
+            .connect(),
+        caller(6022, "public").connect()
+    );
+
+    assert!(denied.is_err());
+    allowed?;
+
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{future, stream, try_join, SinkExt, StreamExt, TryStreamExt};
+use tokio::net::UdpSocket;
+use tokio::time::interval;
+use tokio_util::udp::UdpFramed;
+
+use srt::{ConnInitMethod, ControlTypes, Packet, PacketCodec, SrtSocketBuilder};
+
+// full ACKs come at the set period, and their ACK2s still measure the RTT
+#[tokio::test]
+async fn ack_interval() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let full_acks = Arc::new(AtomicUsize::new(0));
+    let counter = full_acks.clone();
+    let sock = UdpFramed::new(UdpSocket::bind("127.0.0.1:6073").await?, PacketCodec {}).with(
+        move |(pack, addr): (Packet, SocketAddr)| {
+            if let Packet::Control(ctrl) = &pack {
+                if let ControlTypes::Ack { rtt: Some(_), .. } = ctrl.control_type {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            future::ready(Ok::<_, Error>((pack, addr)))
+        },
+    );
+
+    let sender =
+        SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6073".parse()?)).connect();
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .ack_interval(Duration::from_millis(200))
+        .connect_with_sock(sock);
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr)?;
+
+    let send = async {
+        let mut packets = stream::iter(0..100)
+            .zip(interval(Duration::from_millis(10)))
+            .map(|_| Ok((Instant::now(), Bytes::from("asdf"))));
+        sender.send_all(&mut packets).await?;
+        let rtt = sender.stats().rtt;
+        sender.close().await?;
+        Ok::<_, Error>(rtt)
+    };
+    let recv = async {
+        let mut received = 0;
+        while recvr.try_next().await?.is_some() {
+            received += 1;
+        }
+        Ok::<_, Error>(received)
+    };
+    let (rtt, received) = try_join!(send, recv)?;
+    assert_eq!(received, 100);
+
+    // every 10ms or more without the set period
+    let full_acks = full_acks.load(Ordering::SeqCst);
+    assert!((3..=8).contains(&full_acks), "{} full ACKs", full_acks);
+    assert!(rtt < 10_000, "RTT {}us wasn't measured", rtt);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn ack_interval_validated() {
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6074".parse().unwrap()))
+        .ack_interval(Duration::from_secs(0))
+        .connect()
+        .await;
+
+    assert_eq!(
+        res.err().unwrap().to_string(),
+        "ACK interval must be nonzero"
+    );
+}
@@ -0,0 +1,31 @@
+use std::sync::{Mutex, MutexGuard};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+static LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct CaptureLogger;
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        LOGS.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Keep every message logged at info and above from now on. This sets the
+/// global logger, so it can only be called once per test binary
+pub fn capture_logs() {
+    log::set_boxed_logger(Box::new(CaptureLogger)).unwrap();
+    log::set_max_level(LevelFilter::Info);
+}
+
+/// The messages logged since `capture_logs`
+pub fn logs() -> MutexGuard<'static, Vec<String>> {
+    LOGS.lock().unwrap()
+}
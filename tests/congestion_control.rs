@@ -0,0 +1,104 @@
+use srt::{CongestCtrlType, ConnInitMethod, RejectReason, SrtSocketBuilder};
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{future, join, try_join, SinkExt, TryStreamExt};
+use std::time::Instant;
+
+fn caller(port: u16, congest_ctrl: CongestCtrlType) -> SrtSocketBuilder {
+    SrtSocketBuilder::new(ConnInitMethod::Connect(
+        format!("127.0.0.1:{}", port).parse().unwrap(),
+    ))
+    .congestion_control(congest_ctrl)
+}
+
+fn listener(port: u16, congest_ctrl: CongestCtrlType) -> SrtSocketBuilder {
+    SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(port)
+        .congestion_control(congest_ctrl)
+}
+
+#[tokio::test]
+async fn file_transfer() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let (mut sender, recvr) = try_join!(
+        caller(6028, CongestCtrlType::File).connect(),
+        listener(6028, CongestCtrlType::File).connect()
+    )?;
+    assert_eq!(sender.settings().congest_ctrl, CongestCtrlType::File);
+    assert_eq!(recvr.settings().congest_ctrl, CongestCtrlType::File);
+
+    let (_, received) = join!(
+        async {
+            for i in 0..100 {
+                sender
+                    .send((Instant::now(), Bytes::from(i.to_string())))
+                    .await?;
+            }
+            sender.close().await?;
+            Ok(()) as Result<_, Error>
+        },
+        recvr.try_collect::<Vec<_>>()
+    );
+
+    let received: Vec<_> = received?.into_iter().map(|(_, data)| data).collect();
+    let expected: Vec<_> = (0..100).map(|i| Bytes::from(i.to_string())).collect();
+    assert_eq!(received, expected);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn listener_refuses_other_congestion_control() {
+    let _ = env_logger::try_init();
+
+    let listener = listener(6029, CongestCtrlType::File).connect();
+    let caller = caller(6029, CongestCtrlType::Live).connect();
+
+    // the listener keeps waiting for a caller that matches
+    let refused = match future::select(Box::pin(listener), Box::pin(caller)).await {
+        future::Either::Left(_) => panic!("Listener accepted a live caller"),
+        future::Either::Right((refused, _)) => refused,
+    };
+
+    let err = refused.err().expect("Live caller was accepted");
+    assert_eq!(
+        err.downcast_ref::<RejectReason>(),
+        Some(&RejectReason::Congestion)
+    );
+}
+
+#[tokio::test]
+async fn file_caller_refused_by_live_listener() {
+    let _ = env_logger::try_init();
+
+    let listener = listener(6030, CongestCtrlType::Live).connect();
+    let caller = caller(6030, CongestCtrlType::File)
+        .stream_id("transfer".to_string())
+        .connect();
+
+    let refused = match future::select(Box::pin(listener), Box::pin(caller)).await {
+        future::Either::Left(_) => panic!("Listener accepted a file caller"),
+        future::Either::Right((refused, _)) => refused,
+    };
+
+    let err = refused.err().expect("File caller was accepted");
+    assert_eq!(
+        err.downcast_ref::<RejectReason>(),
+        Some(&RejectReason::Congestion)
+    );
+}
+
+#[tokio::test]
+async fn rendezvous_is_live() {
+    let res = SrtSocketBuilder::new(ConnInitMethod::Rendezvous(
+        "127.0.0.1:6031".parse().unwrap(),
+    ))
+    .local_port(6032)
+    .congestion_control(CongestCtrlType::File)
+    .connect()
+    .await;
+
+    assert!(res.is_err());
+}
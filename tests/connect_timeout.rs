@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+use failure::Error;
+use srt::{ConnInitMethod, ConnectTimeout, SrtSocketBuilder};
+use tokio::net::UdpSocket;
+
+#[tokio::test]
+async fn connect_times_out() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    // something is there, but it never answers
+    let _silent = UdpSocket::bind("127.0.0.1:6013").await?;
+
+    let start = Instant::now();
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6013".parse()?))
+        .connect_timeout(Duration::from_millis(300))
+        .connect()
+        .await;
+
+    let err = res.err().expect("Connected to nobody");
+    assert_eq!(
+        err.downcast_ref::<ConnectTimeout>(),
+        Some(&ConnectTimeout {
+            remote: "127.0.0.1:6013".parse()?,
+            timeout: Duration::from_millis(300),
+        })
+    );
+    assert_eq!(
+        err.to_string(),
+        "Connecting to 127.0.0.1:6013 timed out after 300ms"
+    );
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rendezvous_times_out() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let _silent = UdpSocket::bind("127.0.0.1:6014").await?;
+
+    let res = SrtSocketBuilder::new(ConnInitMethod::Rendezvous("127.0.0.1:6014".parse()?))
+        .local_port(6015)
+        .connect_timeout(Duration::from_millis(300))
+        .connect()
+        .await;
+
+    let err = res.err().expect("Rendezvous with nobody");
+    assert!(err.downcast_ref::<ConnectTimeout>().is_some());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn zero_timeout_refused() {
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6013".parse().unwrap()))
+        .connect_timeout(Duration::from_secs(0))
+        .connect()
+        .await;
+
+    assert_eq!(
+        res.err().expect("Connected with no time").to_string(),
+        "Connect timeout must be nonzero"
+    );
+}
@@ -0,0 +1,42 @@
+use failure::Error;
+use futures::try_join;
+
+use srt::{ConnInitMethod, SrtSocketBuilder};
+
+mod capture_logs;
+use crate::capture_logs::{capture_logs, logs};
+
+#[tokio::test]
+async fn connection_name() -> Result<(), Error> {
+    capture_logs();
+
+    let named = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6004".parse()?))
+        .name("camera-1".into())
+        .connect();
+    let unnamed = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6004)
+        .connect();
+
+    let (named, unnamed) = try_join!(named, unnamed)?;
+
+    assert_eq!(named.name(), Some("camera-1"));
+    assert_eq!(unnamed.name(), None);
+
+    drop(named);
+
+    let logs = logs();
+    assert!(
+        logs.iter()
+            .any(|l| l.starts_with("[camera-1] Connected to 127.0.0.1:6004")),
+        "{:?}",
+        logs
+    );
+    assert!(
+        logs.iter()
+            .any(|l| l.starts_with("[camera-1] Connection to 127.0.0.1:6004 closed")),
+        "{:?}",
+        logs
+    );
+
+    Ok(())
+}
@@ -1,30 +1,42 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use srt::{ConnInitMethod, SrtSocketBuilder};
-
-use futures::{join, try_join, SinkExt, TryStreamExt};
+use srt::{
+    AcceptParams, AccessDecision, ConnInitMethod, KeyLengthMismatch, KmState, RejectReason,
+    SrtSocketBuilder, UndecryptableEvent,
+};
 
 use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::{join, try_join, SinkExt, StreamExt, TryStreamExt};
+use tokio::time::delay_for;
 
 #[tokio::test]
-#[ignore]
 async fn crypto_exchange() {
+    let _ = env_logger::try_init();
+
     let sender = SrtSocketBuilder::new(ConnInitMethod::Listen)
-        .crypto(24, "password123".into())
-        .local_port(2000)
+        .passphrase("password123".into())
+        .local_port(6047)
         .connect();
 
-    let recvr = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:2000".parse().unwrap()))
-        .crypto(24, "password123".into())
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6047".parse().unwrap()))
+        .passphrase("password123".into())
         .connect();
 
     let (mut sender, mut recvr) = try_join!(sender, recvr).unwrap();
+    assert_eq!(sender.settings().key_size, 16);
+    assert_eq!(recvr.settings().key_size, 16);
+    assert_eq!(sender.settings().km_state, KmState::Secured);
+    assert_eq!(recvr.settings().km_state, KmState::Secured);
+    assert_eq!(sender.send_km_state(), KmState::Secured);
+    assert_eq!(recvr.recv_km_state(), KmState::Secured);
 
     let s_fut = async {
         sender
             .send((Instant::now(), Bytes::from("Hello")))
             .await
             .unwrap();
+        sender.close().await.unwrap();
     };
 
     let r_fut = async {
@@ -34,3 +46,474 @@ async fn crypto_exchange() {
 
     join!(s_fut, r_fut);
 }
+
+#[tokio::test]
+async fn encrypted_listener() {
+    let _ = env_logger::try_init();
+
+    let mut listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .passphrase("password123".into())
+        .local_port(6048)
+        .build_listener()
+        .await
+        .unwrap();
+
+    // packets are only routed while the listener is polled
+    tokio::spawn(async move {
+        while let Some(mut sock) = listener.try_next().await.unwrap() {
+            tokio::spawn(async move {
+                assert_eq!(sock.settings().key_size, 16);
+
+                let (_, by) = sock.try_next().await.unwrap().unwrap();
+                sock.send((Instant::now(), by)).await.unwrap();
+                sock.close().await.unwrap();
+            });
+        }
+    });
+
+    let mut caller =
+        SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6048".parse().unwrap()))
+            .passphrase("password123".into())
+            .connect()
+            .await
+            .unwrap();
+    assert_eq!(caller.settings().key_size, 16);
+
+    caller
+        .send((Instant::now(), Bytes::from("Hello")))
+        .await
+        .unwrap();
+    let (_, by) = caller.try_next().await.unwrap().unwrap();
+    assert_eq!(&by[..], b"Hello");
+}
+
+#[tokio::test]
+async fn passphrase_length() {
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6049".parse().unwrap()))
+        .passphrase("short".into())
+        .connect()
+        .await;
+
+    assert_eq!(
+        res.err().unwrap().to_string(),
+        "Passphrase is 5 characters, it has to be 10 to 79"
+    );
+}
+
+// nothing is delivered when the passphrases don't match, as neither end can
+// decrypt what the other sends
+#[tokio::test]
+async fn bad_passphrase() {
+    let _ = env_logger::try_init();
+
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .passphrase("password123".into())
+        .local_port(6050)
+        .connect();
+
+    let caller = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6050".parse().unwrap()))
+        .passphrase("password456".into())
+        .connect();
+
+    let (mut listener, mut caller) = try_join!(listener, caller).unwrap();
+    assert_eq!(listener.settings().km_state, KmState::BadSecret);
+    assert_eq!(caller.settings().km_state, KmState::BadSecret);
+
+    let s_fut = async {
+        caller
+            .send((Instant::now(), Bytes::from("Hello")))
+            .await
+            .unwrap();
+        caller.close().await.unwrap();
+    };
+
+    let r_fut = async {
+        assert_eq!(listener.try_next().await.unwrap(), None);
+    };
+
+    join!(s_fut, r_fut);
+}
+
+#[tokio::test]
+async fn listener_without_passphrase() {
+    let _ = env_logger::try_init();
+
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6051)
+        .connect();
+
+    let caller = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6051".parse().unwrap()))
+        .passphrase("password123".into())
+        .connect();
+
+    let (mut listener, mut caller) = try_join!(listener, caller).unwrap();
+    assert_eq!(listener.settings().km_state, KmState::NoSecret);
+    assert_eq!(caller.settings().km_state, KmState::NoSecret);
+
+    // the caller still encrypts, the listener drops what it can't decrypt
+    let s_fut = async {
+        caller
+            .send((Instant::now(), Bytes::from("Hello")))
+            .await
+            .unwrap();
+        caller.close().await.unwrap();
+    };
+
+    let r_fut = async {
+        assert_eq!(listener.try_next().await.unwrap(), None);
+    };
+
+    join!(s_fut, r_fut);
+}
+
+// the key is replaced every 20 packets, so the data has to survive several
+// switchovers between the even and odd keys
+#[tokio::test]
+async fn key_refresh() {
+    let _ = env_logger::try_init();
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .passphrase("password123".into())
+        .key_refresh(20, 5)
+        .local_port(6052)
+        .connect();
+
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6052".parse().unwrap()))
+        .passphrase("password123".into())
+        .connect();
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr).unwrap();
+
+    let s_fut = async {
+        for i in 0..100 {
+            sender
+                .send((Instant::now(), Bytes::from(i.to_string())))
+                .await
+                .unwrap();
+            delay_for(Duration::from_millis(1)).await;
+        }
+        sender.close().await.unwrap();
+    };
+
+    let r_fut = async {
+        for i in 0..100 {
+            let (_, by) = recvr.try_next().await.unwrap().unwrap();
+            assert_eq!(by, i.to_string());
+        }
+        assert_eq!(recvr.try_next().await.unwrap(), None);
+    };
+
+    join!(s_fut, r_fut);
+}
+
+// the key is replaced every 20ms, long before the refresh rate in packets
+#[tokio::test]
+async fn key_refresh_period() {
+    let _ = env_logger::try_init();
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .passphrase("password123".into())
+        .key_refresh(1000, 5)
+        .key_refresh_period(Duration::from_millis(20))
+        .local_port(6090)
+        .connect();
+
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6090".parse().unwrap()))
+        .passphrase("password123".into())
+        .connect();
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr).unwrap();
+
+    let s_fut = async {
+        for i in 0..100 {
+            sender
+                .send((Instant::now(), Bytes::from(i.to_string())))
+                .await
+                .unwrap();
+            delay_for(Duration::from_millis(1)).await;
+        }
+        sender.close().await.unwrap();
+    };
+
+    let r_fut = async {
+        for i in 0..100 {
+            let (_, by) = recvr.try_next().await.unwrap().unwrap();
+            assert_eq!(by, i.to_string());
+        }
+        assert_eq!(recvr.try_next().await.unwrap(), None);
+    };
+
+    join!(s_fut, r_fut);
+}
+
+#[tokio::test]
+async fn key_refresh_validated() {
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6053".parse().unwrap()))
+        .passphrase("password123".into())
+        .key_refresh(20, 11)
+        .connect()
+        .await;
+
+    assert_eq!(
+        res.err().unwrap().to_string(),
+        "Key pre-announce is 11 packets, it has to be 1 to half the refresh rate of 20"
+    );
+}
+
+#[tokio::test]
+async fn key_length() {
+    let _ = env_logger::try_init();
+
+    // a listener without a length set takes the caller's
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .passphrase("password123".into())
+        .local_port(6054)
+        .connect();
+
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6054".parse().unwrap()))
+        .passphrase("password123".into())
+        .key_length(32)
+        .connect();
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr).unwrap();
+    assert_eq!(sender.settings().key_size, 32);
+    assert_eq!(recvr.settings().key_size, 32);
+    assert_eq!(sender.settings().km_state, KmState::Secured);
+
+    let s_fut = async {
+        sender
+            .send((Instant::now(), Bytes::from("Hello")))
+            .await
+            .unwrap();
+        sender.close().await.unwrap();
+    };
+
+    let r_fut = async {
+        let (_, by) = recvr.try_next().await.unwrap().unwrap();
+        assert_eq!(&by[..], b"Hello");
+    };
+
+    join!(s_fut, r_fut);
+}
+
+#[tokio::test]
+async fn key_length_mismatch() {
+    let _ = env_logger::try_init();
+
+    let mut listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .passphrase("password123".into())
+        .key_length(32)
+        .local_port(6055)
+        .build_listener()
+        .await
+        .unwrap();
+    tokio::spawn(async move { while listener.try_next().await.unwrap().is_some() {} });
+
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6055".parse().unwrap()))
+        .passphrase("password123".into())
+        .key_length(16)
+        .connect()
+        .await;
+
+    let err = res.err().unwrap();
+    let mismatch = err.downcast_ref::<KeyLengthMismatch>().unwrap();
+    assert_eq!((mismatch.local, mismatch.peer), (16, 32));
+}
+
+#[tokio::test]
+async fn key_length_validated() {
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6056".parse().unwrap()))
+        .passphrase("password123".into())
+        .key_length(20)
+        .connect()
+        .await;
+
+    assert_eq!(
+        res.err().unwrap().to_string(),
+        "Key length is 20 bytes, it has to be 16, 24 or 32"
+    );
+}
+
+/// Why connecting to a listener built with `listener` on `port` as `caller`
+/// says is refused
+async fn refusal(listener: SrtSocketBuilder, caller: SrtSocketBuilder, port: u16) -> RejectReason {
+    let mut listener = listener.local_port(port).build_listener().await.unwrap();
+    tokio::spawn(async move { while listener.try_next().await.unwrap().is_some() {} });
+
+    let err = caller.connect().await.err().unwrap();
+    *err.downcast_ref::<RejectReason>().unwrap()
+}
+
+fn caller(port: u16) -> SrtSocketBuilder {
+    SrtSocketBuilder::new(ConnInitMethod::Connect(
+        format!("127.0.0.1:{}", port).parse().unwrap(),
+    ))
+}
+
+#[tokio::test]
+async fn enforced_bad_passphrase() {
+    let _ = env_logger::try_init();
+
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .passphrase("password123".into())
+        .enforced_encryption(true);
+    let caller = caller(6057).passphrase("password456".into());
+
+    assert_eq!(
+        refusal(listener, caller, 6057).await,
+        RejectReason::BadSecret
+    );
+}
+
+#[tokio::test]
+async fn enforced_without_passphrase() {
+    let _ = env_logger::try_init();
+
+    // the listener has none
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen).enforced_encryption(true);
+    let with_passphrase = caller(6058).passphrase("password123".into());
+    assert_eq!(
+        refusal(listener, with_passphrase, 6058).await,
+        RejectReason::Unsecure
+    );
+
+    // the caller has none
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .passphrase("password123".into())
+        .enforced_encryption(true);
+    assert_eq!(
+        refusal(listener, caller(6059), 6059).await,
+        RejectReason::Unsecure
+    );
+}
+
+// the caller refuses what the listener let through
+#[tokio::test]
+async fn enforced_by_caller() {
+    let _ = env_logger::try_init();
+
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen).passphrase("password123".into());
+    let caller = caller(6060)
+        .passphrase("password456".into())
+        .enforced_encryption(true);
+
+    assert_eq!(
+        refusal(listener, caller, 6060).await,
+        RejectReason::BadSecret
+    );
+}
+
+// alice has a passphrase of her own, everyone else uses the listener's
+#[tokio::test]
+async fn passphrase_per_caller() {
+    let _ = env_logger::try_init();
+
+    let mut listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6096)
+        .passphrase("password123".into())
+        .enforced_encryption(true)
+        .access_control(|_, stream_id| async move {
+            match stream_id.as_deref() {
+                Some("alice") => {
+                    AccessDecision::Accept(AcceptParams::new().passphrase("alice's secret".into()))
+                }
+                _ => AccessDecision::Accept(AcceptParams::new()),
+            }
+        })
+        .build_listener()
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Some(sock) = listener.try_next().await.unwrap() {
+            assert_eq!(sock.settings().km_state, KmState::Secured);
+        }
+    });
+
+    let alice = caller(6096)
+        .stream_id("alice".into())
+        .passphrase("alice's secret".into())
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(alice.settings().km_state, KmState::Secured);
+
+    let bob = caller(6096)
+        .stream_id("bob".into())
+        .passphrase("alice's secret".into())
+        .connect()
+        .await;
+    assert_eq!(
+        bob.err().unwrap().downcast_ref::<RejectReason>(),
+        Some(&RejectReason::BadSecret)
+    );
+
+    let bob = caller(6096)
+        .stream_id("bob".into())
+        .passphrase("password123".into())
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(bob.settings().km_state, KmState::Secured);
+}
+
+/// The undecryptable packet events the listener on `port` reports, and its
+/// stats once the caller has sent one packet and closed
+async fn undecryptable(
+    listener_passphrase: Option<&str>,
+    port: u16,
+) -> (Vec<UndecryptableEvent>, srt::Stats) {
+    let (events, reported) = mpsc::unbounded();
+
+    let mut listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(port)
+        .on_undecryptable(move |event| events.unbounded_send(event).unwrap());
+    if let Some(passphrase) = listener_passphrase {
+        listener = listener.passphrase(passphrase.into());
+    }
+
+    let caller = caller(port).passphrase("password456".into()).connect();
+    let (mut listener, mut caller) = try_join!(listener.connect(), caller).unwrap();
+
+    let s_fut = async {
+        caller
+            .send((Instant::now(), Bytes::from("Hello")))
+            .await
+            .unwrap();
+        caller.close().await.unwrap();
+    };
+
+    let r_fut = async {
+        assert_eq!(listener.try_next().await.unwrap(), None);
+    };
+
+    join!(s_fut, r_fut);
+    let stats = listener.stats();
+    drop(listener);
+
+    (reported.collect().await, stats)
+}
+
+// a wrong passphrase can be told apart from the peer not sending anything
+#[tokio::test]
+async fn undecryptable_bad_passphrase() {
+    let _ = env_logger::try_init();
+
+    let (events, stats) = undecryptable(Some("password123"), 6061).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].km_state, KmState::BadSecret);
+    assert_eq!(events[0].undecryptable_packets, 1);
+    assert_eq!(stats.undecryptable_packets, 1);
+    assert_eq!(stats.recv_km_state, KmState::BadSecret);
+    assert_eq!(stats.send_km_state, KmState::BadSecret);
+}
+
+#[tokio::test]
+async fn undecryptable_without_passphrase() {
+    let _ = env_logger::try_init();
+
+    let (events, stats) = undecryptable(None, 6062).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].km_state, KmState::NoSecret);
+    assert_eq!(stats.recv_km_state, KmState::NoSecret);
+    assert_eq!(stats.send_km_state, KmState::Unsecured);
+}
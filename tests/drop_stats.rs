@@ -0,0 +1,46 @@
+use std::time::Instant;
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{stream, try_join, SinkExt, TryStreamExt};
+
+use srt::{ConnInitMethod, SrtSocketBuilder};
+
+mod capture_logs;
+use crate::capture_logs::{capture_logs, logs};
+
+#[tokio::test]
+async fn drop_logs_stats() -> Result<(), Error> {
+    capture_logs();
+
+    let sender =
+        SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6003".parse()?)).connect();
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6003)
+        .connect();
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr)?;
+
+    let mut packets = stream::iter((0..10).map(|_| Ok((Instant::now(), Bytes::from("asdf")))));
+    try_join!(sender.send_all(&mut packets), async {
+        for _ in 0..10 {
+            recvr.try_next().await?.unwrap();
+        }
+        Ok(())
+    })?;
+
+    drop(sender);
+    drop(recvr);
+
+    let logs = logs();
+    let closed = logs
+        .iter()
+        .filter(|l| l.contains("closed"))
+        .collect::<Vec<_>>();
+
+    assert_eq!(closed.len(), 2, "{:?}", closed);
+    assert!(closed[0].contains("sent 40 bytes, received 0 bytes"));
+    assert!(closed[1].contains("sent 0 bytes, received 40 bytes"));
+
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use bytes::BytesMut;
+use failure::Error;
+use futures::try_join;
+use srt::{
+    ConnInitMethod, ConnectTimeout, ControlPacket, ControlTypes, HandshakeControlInfo,
+    HandshakeVSInfo, Packet, ShakeType, SocketID, SrtSocketBuilder, TimeStamp,
+};
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn falls_back_to_next_address() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    // something is at the first address, but it never answers
+    let _silent = UdpSocket::bind("127.0.0.1:6036").await?;
+
+    let caller = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6036".parse()?))
+        .fallback_addresses(vec!["127.0.0.1:6035".parse()?])
+        .fallback_delay(Duration::from_millis(100))
+        .connect();
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6035)
+        .connect();
+
+    let (caller, _listener) = try_join!(caller, listener)?;
+    assert_eq!(caller.settings().remote, "127.0.0.1:6035".parse()?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn losing_listener_shut_down() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    // the first address answers the induction but is slow to accept the
+    // conclusion, returning the socket ID it was told to shut down
+    let mut slow = UdpSocket::bind("127.0.0.1:6078").await?;
+    let slow = tokio::spawn(async move {
+        let mut buf = [0; 1500];
+        loop {
+            let (len, from) = slow.recv_from(&mut buf).await?;
+            match Packet::parse(&mut &buf[..len])? {
+                Packet::Control(ControlPacket {
+                    control_type: ControlTypes::Handshake(info),
+                    ..
+                }) if info.shake_type == ShakeType::Induction => {
+                    let response = Packet::Control(ControlPacket {
+                        timestamp: TimeStamp::new_truncate(0),
+                        dest_sockid: info.socket_id,
+                        control_type: ControlTypes::Handshake(HandshakeControlInfo {
+                            socket_id: SocketID(1234),
+                            syn_cookie: 5678,
+                            info: HandshakeVSInfo::V5 {
+                                crypto_size: 0,
+                                ext_hs: None,
+                                ext_km: None,
+                                ext_config: None,
+                                ext_other: Vec::new(),
+                            },
+                            ..info
+                        }),
+                    });
+                    let mut out = BytesMut::new();
+                    response.serialize(&mut out);
+                    slow.send_to(&out, &from).await?;
+                }
+                Packet::Control(ControlPacket {
+                    dest_sockid,
+                    control_type: ControlTypes::Shutdown,
+                    ..
+                }) => return Ok::<_, Error>(dest_sockid),
+                _ => {}
+            }
+        }
+    });
+
+    let caller = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6078".parse()?))
+        .fallback_addresses(vec!["127.0.0.1:6079".parse()?])
+        .fallback_delay(Duration::from_millis(100))
+        .connect();
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6079)
+        .connect();
+
+    let (caller, _listener) = try_join!(caller, listener)?;
+    assert_eq!(caller.settings().remote, "127.0.0.1:6079".parse()?);
+
+    let shut_down = timeout(Duration::from_secs(1), slow).await???;
+    assert_eq!(shut_down, SocketID(1234));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn host_name_resolved() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let caller = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:0".parse()?))
+        .fallback_delay(Duration::from_millis(100))
+        .connect_host("localhost:6080");
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6080)
+        .connect();
+
+    // the listener is only on IPv4, whether or not localhost is also ::1
+    let (caller, _listener) = try_join!(caller, listener)?;
+    assert_eq!(caller.settings().remote, "127.0.0.1:6080".parse()?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn all_addresses_time_out() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let _silent1 = UdpSocket::bind("127.0.0.1:6037").await?;
+    let _silent2 = UdpSocket::bind("127.0.0.1:6038").await?;
+
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6037".parse()?))
+        .fallback_addresses(vec!["127.0.0.1:6038".parse()?])
+        .fallback_delay(Duration::from_millis(100))
+        .connect_timeout(Duration::from_millis(300))
+        .connect()
+        .await;
+
+    // the last attempt to fail is the one reported
+    let err = res.err().expect("Connected to nobody");
+    assert_eq!(
+        err.downcast_ref::<ConnectTimeout>(),
+        Some(&ConnectTimeout {
+            remote: "127.0.0.1:6038".parse()?,
+            timeout: Duration::from_millis(300),
+        })
+    );
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use std::time::Instant;
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{try_join, SinkExt, TryStreamExt};
+
+use srt::{ConnInitMethod, SrtSocketBuilder};
+
+// flushing only finishes once the peer has acknowledged everything, not just
+// once it's been sent
+#[tokio::test]
+async fn flush_waits_for_acks() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6070)
+        .connect();
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6070".parse()?)).connect();
+
+    let (mut sender, recvr) = try_join!(sender, recvr)?;
+
+    let s_fut = async {
+        for _ in 0..100 {
+            sender
+                .feed((Instant::now(), Bytes::from(vec![0; 1000])))
+                .await?;
+        }
+        sender.flush().await?;
+
+        let stats = sender.stats();
+        assert_eq!(stats.received_packets, 100);
+        assert_eq!(stats.sender_buffer, 0);
+
+        sender.close().await
+    };
+    let r_fut = recvr.try_collect::<Vec<_>>();
+
+    let ((), received) = try_join!(s_fut, r_fut)?;
+    assert_eq!(received.len(), 100);
+
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use srt::{
+    AcceptParams, AccessDecision, ConnInitMethod, ControlPacket, ControlTypes,
+    HandshakeControlInfo, HandshakeVSInfo, Packet, SeqNumber, ShakeType, SocketID, SocketType,
+    SrtControlPacket, SrtHandshake, SrtShakeFlags, SrtSocketBuilder, SrtVersion, TimeStamp,
+};
+
+use bytes::BytesMut;
+use failure::Error;
+use futures::TryStreamExt;
+use tokio::net::UdpSocket;
+use tokio::time::{delay_for, timeout};
+
+async fn send_handshake(
+    sock: &mut UdpSocket,
+    to: &SocketAddr,
+    info: HandshakeControlInfo,
+) -> Result<(), Error> {
+    let packet = Packet::Control(ControlPacket {
+        timestamp: TimeStamp::new_truncate(0),
+        dest_sockid: SocketID(0),
+        control_type: ControlTypes::Handshake(info),
+    });
+    let mut buf = BytesMut::new();
+    packet.serialize(&mut buf);
+    sock.send_to(&buf, to).await?;
+
+    Ok(())
+}
+
+// the handshakes that come back to `sock` until it's quiet for a while, with
+// the socket they're sent to
+async fn handshakes_received(
+    sock: &mut UdpSocket,
+) -> Result<Vec<(SocketID, HandshakeControlInfo)>, Error> {
+    let mut received = Vec::new();
+    let mut buf = [0; 1500];
+    while let Ok(res) = timeout(Duration::from_millis(300), sock.recv_from(&mut buf)).await {
+        let (len, _) = res?;
+        match Packet::parse(&mut &buf[..len])? {
+            Packet::Control(ControlPacket {
+                dest_sockid,
+                control_type: ControlTypes::Handshake(info),
+                ..
+            }) => received.push((dest_sockid, info)),
+            p => panic!("Expected a handshake, got {:?}", p),
+        }
+    }
+
+    Ok(received)
+}
+
+// do the caller's side of the handshake with `port` from `callers` different
+// sockets at once, returning how many inductions and how many conclusions
+// were answered
+async fn handshakes_answered(port: u16, callers: u32) -> Result<(usize, usize), Error> {
+    let mut sock = UdpSocket::bind("127.0.0.1:0").await?;
+    let target: SocketAddr = format!("127.0.0.1:{}", port).parse()?;
+
+    let induction = |id| HandshakeControlInfo {
+        init_seq_num: SeqNumber::new_truncate(0),
+        max_packet_size: 1500,
+        max_flow_size: 8192,
+        shake_type: ShakeType::Induction,
+        socket_id: SocketID(id),
+        syn_cookie: 0,
+        peer_addr: target.ip(),
+        info: HandshakeVSInfo::V4(SocketType::Datagram),
+    };
+
+    for id in 1..=callers {
+        send_handshake(&mut sock, &target, induction(id)).await?;
+    }
+    let inductions = handshakes_received(&mut sock).await?;
+
+    for (SocketID(id), resp) in &inductions {
+        let conclusion = HandshakeControlInfo {
+            shake_type: ShakeType::Conclusion,
+            syn_cookie: resp.syn_cookie,
+            info: HandshakeVSInfo::V5 {
+                crypto_size: 0,
+                ext_hs: Some(SrtControlPacket::HandshakeRequest(SrtHandshake {
+                    version: SrtVersion::CURRENT,
+                    flags: SrtShakeFlags::TSBPDSND | SrtShakeFlags::TSBPDRCV,
+                    peer_latency: Duration::from_millis(20),
+                    latency: Duration::from_millis(20),
+                })),
+                ext_km: None,
+                ext_config: None,
+                ext_other: Vec::new(),
+            },
+            ..induction(*id)
+        };
+        send_handshake(&mut sock, &target, conclusion).await?;
+    }
+    let conclusions = handshakes_received(&mut sock).await?;
+
+    Ok((inductions.len(), conclusions.len()))
+}
+
+#[tokio::test]
+async fn pending_handshakes_limited() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    // callers are pending while the access control is deciding
+    let mut listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6018)
+        .max_pending_handshakes(2)
+        .access_control(|_, _| async {
+            delay_for(Duration::from_millis(100)).await;
+            AccessDecision::Accept(AcceptParams::new())
+        })
+        .build_listener()
+        .await?;
+    tokio::spawn(async move { while listener.try_next().await.unwrap().is_some() {} });
+
+    // every induction is answered, as answering one takes no room
+    assert_eq!(handshakes_answered(6018, 4).await?, (4, 2));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn handshake_rate_limited() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let mut listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6019)
+        .handshake_rate(3)
+        .build_listener()
+        .await?;
+    tokio::spawn(async move { while listener.try_next().await.unwrap().is_some() {} });
+
+    assert_eq!(handshakes_answered(6019, 6).await?, (6, 3));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn conclusion_without_cookie_ignored() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let mut listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6095)
+        .build_listener()
+        .await?;
+    tokio::spawn(async move { while listener.try_next().await.unwrap().is_some() {} });
+
+    let mut sock = UdpSocket::bind("127.0.0.1:0").await?;
+    let target: SocketAddr = "127.0.0.1:6095".parse()?;
+    send_handshake(
+        &mut sock,
+        &target,
+        HandshakeControlInfo {
+            init_seq_num: SeqNumber::new_truncate(0),
+            max_packet_size: 1500,
+            max_flow_size: 8192,
+            shake_type: ShakeType::Conclusion,
+            socket_id: SocketID(1),
+            syn_cookie: 1234,
+            peer_addr: target.ip(),
+            info: HandshakeVSInfo::V4(SocketType::Datagram),
+        },
+    )
+    .await?;
+
+    assert!(handshakes_received(&mut sock).await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn limits_nonzero() {
+    let res = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .handshake_rate(0)
+        .build_listener()
+        .await;
+
+    assert!(res.is_err());
+}
@@ -0,0 +1,45 @@
+use srt::{ConnInitMethod, SrtSocketBuilder};
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{stream, try_join, SinkExt, TryStreamExt};
+use std::time::{Duration, Instant};
+use tokio::spawn;
+
+#[tokio::test]
+async fn initial_send_rate() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    const PACKETS: u32 = 30;
+
+    // 1500 byte packets at 1.2Mbps is a packet every 10ms
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6002".parse()?))
+        .initial_send_rate(1_200_000)
+        .connect();
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6002)
+        .connect();
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr)?;
+    assert_eq!(sender.settings().max_packet_size, 1500);
+
+    spawn(async move { while recvr.try_next().await.unwrap().is_some() {} });
+
+    // queue everything at once, so only the pacing spreads it out
+    let start = Instant::now();
+    let mut packets =
+        stream::iter((0..PACKETS).map(|i| Ok((Instant::now(), Bytes::from(i.to_string())))));
+    sender.send_all(&mut packets).await?;
+    let elapsed = start.elapsed();
+
+    // every 16th packet is sent in a pair, so there are at least PACKETS - 2 intervals
+    assert!(
+        elapsed >= Duration::from_millis(10) * (PACKETS - 2),
+        "Sending took {:?}, packets weren't paced",
+        elapsed
+    );
+
+    sender.close().await?;
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{future, try_join, SinkExt, TryStreamExt};
+use tokio::net::UdpSocket;
+use tokio::time::delay_for;
+use tokio_util::udp::UdpFramed;
+
+use srt::{ConnInitMethod, ControlTypes, Packet, PacketCodec, SrtSocketBuilder};
+
+// an idle connection sends keepalives, and stays up
+#[tokio::test]
+async fn keepalive_when_idle() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let keepalives = Arc::new(AtomicUsize::new(0));
+    let counter = keepalives.clone();
+    let sock = UdpFramed::new(UdpSocket::bind("127.0.0.1:0").await?, PacketCodec {}).with(
+        move |(pack, addr): (Packet, SocketAddr)| {
+            if let Packet::Control(ctrl) = &pack {
+                if let ControlTypes::KeepAlive = ctrl.control_type {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            future::ready(Ok::<_, Error>((pack, addr)))
+        },
+    );
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6072".parse()?))
+        .connect_with_sock(sock);
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6072)
+        .connect();
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr)?;
+
+    // neither end is polled in the meantime
+    delay_for(Duration::from_millis(2500)).await;
+    assert!(keepalives.load(Ordering::SeqCst) >= 2);
+
+    let (_, received) = try_join!(
+        sender.send((Instant::now(), Bytes::from("still here"))),
+        recvr.try_next()
+    )?;
+    let (_, data) = received.expect("Connection closed");
+    assert_eq!(data, "still here");
+
+    sender.close().await?;
+
+    Ok(())
+}
@@ -20,15 +20,18 @@ async fn test_latency_exchange(
 
     let expected = Duration::max(connecter_latency, listener_latency);
 
-    assert_eq!(connector.settings().tsbpd_latency, expected);
-    assert_eq!(listener.settings().tsbpd_latency, expected);
+    assert_eq!(connector.settings().latency(), expected);
+    assert_eq!(listener.settings().latency(), expected);
+
+    assert_eq!(connector.latency(), expected);
+    assert_eq!(listener.latency(), expected);
 
     Ok(())
 }
 
 #[tokio::test]
 async fn latency_exchange() -> Result<(), Error> {
-    env_logger::init();
+    let _ = env_logger::try_init();
 
     test_latency_exchange(Duration::from_secs(3), Duration::from_secs(4)).await?;
     delay_for(Duration::from_secs(2)).await;
@@ -36,3 +39,30 @@ async fn latency_exchange() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn latency_per_direction() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let connecter = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6024".parse()?))
+        .receive_latency(Duration::from_millis(100))
+        .peer_latency(Duration::from_millis(300))
+        .connect();
+
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6024)
+        .receive_latency(Duration::from_millis(200))
+        .peer_latency(Duration::from_millis(50))
+        .connect();
+
+    let (connector, listener) = futures::try_join!(connecter, listener)?;
+
+    // each receiver gets the larger of what it wants and what its peer wants
+    assert_eq!(connector.latency(), Duration::from_millis(100));
+    assert_eq!(listener.latency(), Duration::from_millis(300));
+
+    assert_eq!(connector.peer_latency(), listener.latency());
+    assert_eq!(listener.peer_latency(), connector.latency());
+
+    Ok(())
+}
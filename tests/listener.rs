@@ -0,0 +1,58 @@
+use std::time::Instant;
+
+use srt::{ConnInitMethod, SrtSocketBuilder};
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{SinkExt, TryStreamExt};
+
+#[tokio::test]
+async fn listener_accepts_many() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let mut listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6011)
+        .build_listener()
+        .await?;
+
+    // serve each caller from its own task, while accepting more
+    tokio::spawn(async move {
+        while let Some(mut sock) = listener.try_next().await.unwrap() {
+            tokio::spawn(async move {
+                let (_, name) = sock.try_next().await.unwrap().unwrap();
+                sock.send((Instant::now(), Bytes::from(format!("hello {}", name.len()))))
+                    .await
+                    .unwrap();
+                sock.close().await.unwrap();
+            });
+        }
+    });
+
+    let callers = (1..=5).map(|i| async move {
+        let mut sock = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6011".parse()?))
+            .connect()
+            .await?;
+
+        sock.send((Instant::now(), Bytes::from("x".repeat(i))))
+            .await?;
+        let (_, reply) = sock.try_next().await?.expect("Connection closed");
+        assert_eq!(reply, format!("hello {}", i));
+
+        Ok(()) as Result<_, Error>
+    });
+
+    for res in futures::future::join_all(callers).await {
+        res?;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn listener_must_listen() {
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6012".parse().unwrap()))
+        .build_listener()
+        .await;
+
+    assert!(res.is_err());
+}
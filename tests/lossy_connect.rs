@@ -24,7 +24,9 @@ async fn connect() {
     let a = SrtSocketBuilder::new(ConnInitMethod::Listen)
         .local_port(1111)
         .connect_with_sock(send);
+    // it can take many tries to get through, so give it longer than usual
     let b = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:1111".parse().unwrap()))
+        .connect_timeout(Duration::from_secs(60))
         .connect_with_sock(recv);
 
     let (s1, r1) = oneshot::channel();
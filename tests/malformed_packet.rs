@@ -0,0 +1,42 @@
+use srt::{ConnInitMethod, SrtSocketBuilder};
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{try_join, SinkExt, TryStreamExt};
+use std::time::Instant;
+use tokio::net::UdpSocket;
+
+#[tokio::test]
+async fn malformed_packet_dropped() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let sender =
+        SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6007".parse()?)).connect();
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6007)
+        .connect();
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr)?;
+
+    // too short for a header, and a control packet of an unknown type
+    let mut garbage = UdpSocket::bind("127.0.0.1:0").await?;
+    garbage.send_to(&[0xAB; 5], "127.0.0.1:6007").await?;
+    garbage
+        .send_to(
+            &hex::decode("8009000000000000000F424000000005")?,
+            "127.0.0.1:6007",
+        )
+        .await?;
+
+    // the sender waits for an ACK, so the receiver needs to be running too
+    let (_, received) = try_join!(
+        sender.send((Instant::now(), Bytes::from("still here"))),
+        recvr.try_next()
+    )?;
+    let (_, data) = received.expect("Connection closed");
+    assert_eq!(data, "still here");
+
+    sender.close().await?;
+
+    Ok(())
+}
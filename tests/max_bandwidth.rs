@@ -0,0 +1,117 @@
+use srt::{ConnInitMethod, SrtSocketBuilder};
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{stream, try_join, SinkExt, StreamExt, TryStreamExt};
+use std::time::{Duration, Instant};
+use tokio::spawn;
+use tokio::time::interval;
+
+#[tokio::test]
+async fn max_bandwidth() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    const PACKETS: u32 = 30;
+
+    // 1000 byte payloads with 44 bytes of headers at 835.2kbps is a packet
+    // every 10ms
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6064".parse()?))
+        .max_bandwidth(835_200)
+        .connect();
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6064)
+        .connect();
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr)?;
+
+    spawn(async move { while recvr.try_next().await.unwrap().is_some() {} });
+
+    // queue everything at once, so only the pacing spreads it out
+    let start = Instant::now();
+    let mut packets =
+        stream::iter((0..PACKETS).map(|_| Ok((Instant::now(), Bytes::from(vec![0; 1000])))));
+    sender.send_all(&mut packets).await?;
+    let elapsed = start.elapsed();
+
+    // the first packet and a half go out at once, as a full packet can be
+    // saved up
+    assert!(
+        elapsed >= Duration::from_millis(10) * (PACKETS - 2),
+        "Sending took {:?}, packets weren't paced",
+        elapsed
+    );
+
+    sender.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn max_bandwidth_validated() {
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6065".parse().unwrap()))
+        .max_bandwidth(0)
+        .connect()
+        .await;
+
+    assert_eq!(
+        res.err().unwrap().to_string(),
+        "Max bandwidth must be nonzero"
+    );
+}
+
+// the max bandwidth follows the rate data is sent at
+#[tokio::test]
+async fn bandwidth_overhead() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6066".parse()?))
+        .bandwidth_overhead(25)
+        .connect();
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6066)
+        .connect();
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr)?;
+
+    spawn(async move { while recvr.try_next().await.unwrap().is_some() {} });
+
+    // 1000 byte payloads with 44 bytes of headers every 10ms is 835.2kbps
+    assert_eq!(sender.stats().input_rate, None);
+    let mut packets = interval(Duration::from_millis(10))
+        .take(60)
+        .map(|_| Ok((Instant::now(), Bytes::from(vec![0; 1000]))));
+    sender.send_all(&mut packets).await?;
+
+    let input_rate = sender.stats().input_rate.unwrap();
+    assert!(
+        input_rate > 750_000 && input_rate < 920_000,
+        "Input rate estimated at {} bits/s",
+        input_rate
+    );
+
+    sender.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn bandwidth_overhead_validated() {
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6067".parse().unwrap()))
+        .bandwidth_overhead(200)
+        .connect()
+        .await;
+    assert_eq!(
+        res.err().unwrap().to_string(),
+        "Bandwidth overhead is 200%, it has to be 5 to 100"
+    );
+
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6067".parse().unwrap()))
+        .bandwidth_overhead(25)
+        .max_bandwidth(1_000_000)
+        .connect()
+        .await;
+    assert_eq!(
+        res.err().unwrap().to_string(),
+        "Max bandwidth and bandwidth overhead can't both be set"
+    );
+}
@@ -0,0 +1,75 @@
+use srt::{ConnInitMethod, RejectReason, SrtSocketBuilder, SrtVersion};
+
+use failure::Error;
+use futures::{future, join};
+
+// srt-rs speaks 1.3.1, so it's too old for whichever end requires this
+fn too_new() -> SrtVersion {
+    SrtVersion::new(1, 4, 0)
+}
+
+fn caller(port: u16) -> SrtSocketBuilder {
+    SrtSocketBuilder::new(ConnInitMethod::Connect(
+        format!("127.0.0.1:{}", port).parse().unwrap(),
+    ))
+}
+
+fn listener(port: u16) -> SrtSocketBuilder {
+    SrtSocketBuilder::new(ConnInitMethod::Listen).local_port(port)
+}
+
+#[tokio::test]
+async fn listener_refuses_old_caller() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let listener = listener(6025).min_peer_version(too_new()).connect();
+    let caller = caller(6025).connect();
+
+    // the listener keeps waiting for a caller that's new enough
+    let refused = match future::select(Box::pin(listener), Box::pin(caller)).await {
+        future::Either::Left(_) => panic!("Listener accepted an old caller"),
+        future::Either::Right((refused, _)) => refused,
+    };
+
+    let err = refused.err().expect("Old caller was accepted");
+    assert_eq!(
+        err.downcast_ref::<RejectReason>(),
+        Some(&RejectReason::Version)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn caller_refuses_old_listener() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let (_, refused) = join!(
+        listener(6026).connect(),
+        caller(6026).min_peer_version(too_new()).connect()
+    );
+
+    let err = refused.err().expect("Connected to an old listener");
+    assert_eq!(
+        err.downcast_ref::<RejectReason>(),
+        Some(&RejectReason::Version)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn new_enough_peers_connect() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let (listener, caller) = join!(
+        listener(6027)
+            .min_peer_version(SrtVersion::CURRENT)
+            .connect(),
+        caller(6027).min_peer_version(SrtVersion::CURRENT).connect()
+    );
+    listener?;
+    caller?;
+
+    Ok(())
+}
@@ -30,7 +30,7 @@ async fn multiplexer() -> Result<(), Error> {
         {
             let mut sender = Sender::new(
                 channel,
-                SrtCongestCtrl,
+                SrtCongestCtrl::default(),
                 settings.settings,
                 Some(settings.hs_returner),
             );
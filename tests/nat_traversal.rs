@@ -0,0 +1,119 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{try_join, SinkExt, TryStreamExt};
+use srt::{ConnInitMethod, NatTraversal, SrtSocketBuilder};
+use tokio::net::UdpSocket;
+
+// a STUN server answering binding requests with the XOR-MAPPED-ADDRESS they came from
+async fn stun_server(addr: &str) -> Result<(), Error> {
+    let mut sock = UdpSocket::bind(addr).await?;
+    tokio::spawn(async move {
+        let mut buf = [0; 1500];
+        loop {
+            let (len, from) = sock.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 20);
+            assert_eq!(buf[..2], [0x00, 0x01]);
+
+            let ip = match from.ip() {
+                IpAddr::V4(ip) => ip.octets(),
+                IpAddr::V6(_) => panic!("IPv6 request"),
+            };
+            let mut response = vec![0x01, 0x01, 0x00, 0x0c];
+            response.extend_from_slice(&buf[4..20]);
+            response.extend_from_slice(&[0x00, 0x20, 0x00, 0x08, 0x00, 0x01]);
+            response.extend_from_slice(&(from.port() ^ 0x2112).to_be_bytes());
+            response.extend(ip.iter().zip(&buf[4..8]).map(|(a, b)| a ^ b));
+
+            sock.send_to(&response, &from).await.unwrap();
+        }
+    });
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rendezvous_with_discovered_addresses() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    stun_server("127.0.0.1:6043").await?;
+    let stun: SocketAddr = "127.0.0.1:6043".parse()?;
+
+    let a = NatTraversal::discover("127.0.0.1:6044".parse()?, stun).await?;
+    let b = NatTraversal::discover("127.0.0.1:6045".parse()?, stun).await?;
+    assert_eq!(a.public_addr(), "127.0.0.1:6044".parse()?);
+    assert_eq!(b.public_addr(), "127.0.0.1:6045".parse()?);
+
+    // the addresses would be exchanged out of band here
+    let (a_public, b_public) = (a.public_addr(), b.public_addr());
+    let (mut a, mut b) = try_join!(
+        a.rendezvous(SrtSocketBuilder::new(ConnInitMethod::Rendezvous(b_public))),
+        b.rendezvous(SrtSocketBuilder::new(ConnInitMethod::Rendezvous(a_public)))
+    )?;
+
+    let (_, received) = try_join!(
+        a.send((Instant::now(), Bytes::from("through the NAT"))),
+        b.try_next()
+    )?;
+    let (_, data) = received.expect("Connection closed");
+    assert_eq!(data, "through the NAT");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn public_address_differs_from_bound() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    stun_server("127.0.0.1:6092").await?;
+    let stun: SocketAddr = "127.0.0.1:6092".parse()?;
+
+    // the handshakes are addressed to the public address, not the bound one
+    let a = NatTraversal::discover("0.0.0.0:6093".parse()?, stun).await?;
+    let b = NatTraversal::discover("0.0.0.0:6094".parse()?, stun).await?;
+    assert_eq!(a.public_addr(), "127.0.0.1:6093".parse()?);
+    assert_eq!(b.public_addr(), "127.0.0.1:6094".parse()?);
+
+    let (a_public, b_public) = (a.public_addr(), b.public_addr());
+    let (mut a, mut b) = try_join!(
+        a.rendezvous(
+            SrtSocketBuilder::new(ConnInitMethod::Rendezvous(b_public))
+                .local_addr("127.0.0.2".parse()?)
+                .verify_peer_address(true)
+        ),
+        b.rendezvous(
+            SrtSocketBuilder::new(ConnInitMethod::Rendezvous(a_public))
+                .local_addr("127.0.0.2".parse()?)
+                .verify_peer_address(true)
+        )
+    )?;
+
+    let (_, received) = try_join!(
+        a.send((Instant::now(), Bytes::from("through the NAT"))),
+        b.try_next()
+    )?;
+    let (_, data) = received.expect("Connection closed");
+    assert_eq!(data, "through the NAT");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn unanswered_stun() -> Result<(), Error> {
+    tokio::time::pause();
+
+    // something is there, but it never answers
+    let _silent = UdpSocket::bind("127.0.0.1:6046").await?;
+
+    let res = NatTraversal::discover("127.0.0.1:0".parse()?, "127.0.0.1:6046".parse()?).await;
+    assert_eq!(
+        res.err()
+            .expect("Discovered with no STUN server")
+            .to_string(),
+        "STUN server 127.0.0.1:6046 didn't answer"
+    );
+
+    Ok(())
+}
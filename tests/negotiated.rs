@@ -0,0 +1,68 @@
+use srt::{ConnInitMethod, SrtShakeFlags, SrtSocketBuilder, SrtVersion};
+
+use failure::Error;
+use futures::try_join;
+use std::time::Duration;
+
+#[tokio::test]
+async fn negotiated_parameters() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let caller = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6033".parse()?))
+        .latency(Duration::from_millis(120))
+        .stream_id("feed".to_string())
+        .peer_stats(true)
+        .connect();
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6033)
+        .latency(Duration::from_millis(80))
+        .connect();
+
+    let (caller, listener) = try_join!(caller, listener)?;
+
+    for sock in &[&caller, &listener] {
+        let settings = sock.settings();
+        assert_eq!(settings.peer_version, Some(SrtVersion::CURRENT));
+        assert_eq!(settings.max_packet_size, 1500);
+        assert_eq!(settings.max_flow_size, 8192);
+        assert_eq!(settings.tsbpd_latency, Duration::from_millis(120));
+        assert_eq!(settings.peer_tsbpd_latency, Duration::from_millis(120));
+        assert_eq!(settings.key_size, 0);
+        assert_eq!(sock.stream_id(), Some("feed"));
+
+        // only the caller wanted stats
+        assert!(settings
+            .srt_flags
+            .contains(SrtShakeFlags::TSBPDSND | SrtShakeFlags::TSBPDRCV));
+        assert!(!settings.srt_flags.contains(SrtShakeFlags::PEERSTATS));
+        assert!(!settings.peer_stats);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn nothing_negotiated_in_udt4() -> Result<(), Error> {
+    let caller = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6034".parse()?))
+        .udt4(true)
+        .latency(Duration::from_millis(100))
+        .connect();
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6034)
+        .udt4(true)
+        .latency(Duration::from_millis(200))
+        .connect();
+
+    let (caller, listener) = try_join!(caller, listener)?;
+
+    for sock in &[&caller, &listener] {
+        assert_eq!(sock.settings().peer_version, None);
+        assert_eq!(sock.settings().srt_flags, SrtShakeFlags::empty());
+    }
+
+    // without a handshake extension each end keeps its own latency
+    assert_eq!(caller.settings().latency(), Duration::from_millis(100));
+    assert_eq!(listener.settings().latency(), Duration::from_millis(200));
+
+    Ok(())
+}
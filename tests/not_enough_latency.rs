@@ -30,7 +30,9 @@ async fn not_enough_latency() {
     let (send, recv) = LossyConn::channel(0.04, Duration::from_secs(4), Duration::from_millis(200));
 
     let sender = SrtSocketBuilder::new(ConnInitMethod::Listen).connect_with_sock(send);
+    // every round trip of the handshake takes 8 seconds
     let recvr = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:0".parse().unwrap()))
+        .connect_timeout(Duration::from_secs(60))
         .connect_with_sock(recv);
 
     let (mut sender, mut recvr) = futures::try_join!(sender, recvr).unwrap();
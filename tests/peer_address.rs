@@ -0,0 +1,64 @@
+use srt::{ConnInitMethod, SrtSocketBuilder};
+
+use failure::Error;
+use futures::try_join;
+use std::net::{IpAddr, Ipv4Addr};
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+// handshakes carry the address they're sent to, so a listener bound to the
+// address the caller connects to accepts it
+#[tokio::test]
+async fn listener_verifies_address() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let caller = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6075".parse()?))
+        .verify_peer_address(true)
+        .connect();
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_addr(LOCALHOST)
+        .local_port(6075)
+        .verify_peer_address(true)
+        .connect();
+
+    let (caller, listener) = try_join!(caller, listener)?;
+    assert_eq!(
+        caller.settings().remote_sockid,
+        listener.settings().local_sockid
+    );
+
+    Ok(())
+}
+
+// there's nothing to check handshakes against when bound to every interface
+#[tokio::test]
+async fn verification_needs_local_address() {
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6091)
+        .verify_peer_address(true)
+        .connect()
+        .await;
+
+    assert!(listener.is_err());
+}
+
+#[tokio::test]
+async fn rendezvous_verifies_address() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let a = SrtSocketBuilder::new(ConnInitMethod::Rendezvous("127.0.0.1:6076".parse()?))
+        .local_addr(LOCALHOST)
+        .local_port(6077)
+        .verify_peer_address(true)
+        .connect();
+    let b = SrtSocketBuilder::new(ConnInitMethod::Rendezvous("127.0.0.1:6077".parse()?))
+        .local_addr(LOCALHOST)
+        .local_port(6076)
+        .verify_peer_address(true)
+        .connect();
+
+    let (a, b) = try_join!(a, b)?;
+    assert_eq!(a.settings().remote_sockid, b.settings().local_sockid);
+
+    Ok(())
+}
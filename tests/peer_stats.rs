@@ -0,0 +1,66 @@
+use srt::{ConnInitMethod, SrtSocketBuilder};
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{stream, try_join, SinkExt, StreamExt, TryStreamExt};
+use std::time::{Duration, Instant};
+use tokio::spawn;
+use tokio::time::interval;
+
+#[tokio::test]
+async fn peer_stats() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    const PACKETS: u32 = 150;
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6000".parse()?))
+        .peer_stats(true)
+        .connect();
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6000)
+        .peer_stats(true)
+        .connect();
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr)?;
+
+    assert!(sender.settings().peer_stats);
+    assert!(recvr.settings().peer_stats);
+    assert_eq!(sender.peer_stats(), None);
+
+    spawn(async move { while recvr.try_next().await.unwrap().is_some() {} });
+
+    // send for long enough that the receiver sends a report after receiving some packets
+    let mut counting_stream = stream::iter(0..PACKETS)
+        .zip(interval(Duration::from_millis(10)))
+        .map(|(i, _)| Ok((Instant::now(), Bytes::from(i.to_string()))));
+    sender.send_all(&mut counting_stream).await?;
+
+    let stats = sender.peer_stats().expect("No peer stats received");
+    assert!(stats.received_packets > 0);
+    assert!(stats.received_packets <= PACKETS);
+    assert_eq!(stats.lost_packets, 0);
+    assert_eq!(stats.loss_rate(), 0.0);
+
+    sender.close().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn peer_stats_not_negotiated() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6001".parse()?))
+        .peer_stats(true)
+        .connect();
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6001)
+        .connect();
+
+    let (sender, recvr) = try_join!(sender, recvr)?;
+
+    assert!(!sender.settings().peer_stats);
+    assert!(!recvr.settings().peer_stats);
+
+    Ok(())
+}
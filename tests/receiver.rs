@@ -0,0 +1,213 @@
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{SinkExt, TryStreamExt};
+use tokio::net::UdpSocket;
+use tokio_util::udp::UdpFramed;
+
+use srt::{
+    CongestCtrlType, ConnectionSettings, DataEncryption, DataPacket, KmState, MsgNumber, Packet,
+    PacketCodec, PacketLocation, Receiver, SeqNumber, SocketID, SocketType, SrtShakeFlags,
+    TimeStamp,
+};
+
+#[tokio::test]
+async fn receiver_from_udp_socket() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let recv_sock = UdpSocket::bind("127.0.0.1:0").await?;
+    let send_sock = UdpSocket::bind("127.0.0.1:0").await?;
+    let recv_addr = recv_sock.local_addr()?;
+    let send_addr = send_sock.local_addr()?;
+
+    let init_seq_num = SeqNumber::new_truncate(1234);
+    let start = Instant::now();
+
+    let mut recvr = Receiver::new(
+        UdpFramed::new(recv_sock, PacketCodec {}),
+        ConnectionSettings {
+            remote: send_addr,
+            remote_sockid: SocketID(1),
+            local_sockid: SocketID(2),
+            socket_start_time: start,
+            init_seq_num,
+            max_packet_size: 1316,
+            max_flow_size: 8192,
+            peer_version: None,
+            srt_flags: SrtShakeFlags::empty(),
+            key_size: 0,
+            km_state: KmState::Unsecured,
+            tsbpd_latency: Duration::from_millis(20),
+            peer_tsbpd_latency: Duration::from_millis(20),
+            peer_stats: false,
+            congest_ctrl: CongestCtrlType::Live,
+            socket_type: SocketType::Datagram,
+            udt4: false,
+        },
+        None,
+    );
+    let mut sender = UdpFramed::new(send_sock, PacketCodec {});
+
+    for i in 0..3 {
+        let pack = Packet::Data(DataPacket {
+            seq_number: init_seq_num + i,
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            in_order_delivery: false,
+            retransmitted: false,
+            encryption: DataEncryption::None,
+            message_number: MsgNumber::new_truncate(i),
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(2),
+            payload: Bytes::from(i.to_string()),
+        });
+        sender.send((pack, recv_addr)).await?;
+    }
+
+    for i in 0..3 {
+        let (ts, payload) = recvr.try_next().await?.unwrap();
+
+        assert_eq!(payload, Bytes::from(i.to_string()));
+        assert_eq!(ts, start);
+    }
+
+    // only released after the latency has passed
+    assert!(Instant::now() >= start + Duration::from_millis(20));
+
+    Ok(())
+}
+
+// a message missing a packet in its middle is given up on once its deadline
+// passes, instead of holding back everything after it
+#[tokio::test]
+async fn gap_in_message_skipped() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let recv_sock = UdpSocket::bind("127.0.0.1:0").await?;
+    let send_sock = UdpSocket::bind("127.0.0.1:0").await?;
+    let recv_addr = recv_sock.local_addr()?;
+    let send_addr = send_sock.local_addr()?;
+
+    let init_seq_num = SeqNumber::new_truncate(1234);
+    let start = Instant::now();
+
+    let mut recvr = Receiver::new(
+        UdpFramed::new(recv_sock, PacketCodec {}),
+        ConnectionSettings {
+            remote: send_addr,
+            remote_sockid: SocketID(1),
+            local_sockid: SocketID(2),
+            socket_start_time: start,
+            init_seq_num,
+            max_packet_size: 1316,
+            max_flow_size: 8192,
+            peer_version: None,
+            srt_flags: SrtShakeFlags::empty(),
+            key_size: 0,
+            km_state: KmState::Unsecured,
+            tsbpd_latency: Duration::from_millis(20),
+            peer_tsbpd_latency: Duration::from_millis(20),
+            peer_stats: false,
+            congest_ctrl: CongestCtrlType::Live,
+            socket_type: SocketType::Datagram,
+            udt4: false,
+        },
+        None,
+    );
+    let mut sender = UdpFramed::new(send_sock, PacketCodec {});
+
+    // the middle of the first message never arrives, and the second has to
+    // be delivered after it
+    for (i, msg, loc) in [
+        (0, 0, PacketLocation::FIRST),
+        (2, 0, PacketLocation::LAST),
+        (3, 1, PacketLocation::FIRST | PacketLocation::LAST),
+    ]
+    .iter()
+    {
+        let pack = Packet::Data(DataPacket {
+            seq_number: init_seq_num + *i,
+            message_loc: *loc,
+            in_order_delivery: true,
+            retransmitted: false,
+            encryption: DataEncryption::None,
+            message_number: MsgNumber::new_truncate(*msg),
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(2),
+            payload: Bytes::from(i.to_string()),
+        });
+        sender.send((pack, recv_addr)).await?;
+    }
+
+    let (_, payload) = recvr.try_next().await?.unwrap();
+    assert_eq!(payload, "3");
+    assert_eq!(recvr.dropped_packets(), 3);
+
+    Ok(())
+}
+
+// in message mode without TSBPD, a message that isn't marked for in order
+// delivery is delivered even though a packet before it is still missing
+#[tokio::test]
+async fn unordered_message_not_held_back() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let recv_sock = UdpSocket::bind("127.0.0.1:0").await?;
+    let send_sock = UdpSocket::bind("127.0.0.1:0").await?;
+    let recv_addr = recv_sock.local_addr()?;
+    let send_addr = send_sock.local_addr()?;
+
+    let init_seq_num = SeqNumber::new_truncate(1234);
+    let start = Instant::now();
+
+    let mut recvr = Receiver::new(
+        UdpFramed::new(recv_sock, PacketCodec {}),
+        ConnectionSettings {
+            remote: send_addr,
+            remote_sockid: SocketID(1),
+            local_sockid: SocketID(2),
+            socket_start_time: start,
+            init_seq_num,
+            max_packet_size: 1316,
+            max_flow_size: 8192,
+            peer_version: None,
+            srt_flags: SrtShakeFlags::empty(),
+            key_size: 0,
+            km_state: KmState::Unsecured,
+            tsbpd_latency: Duration::from_millis(20),
+            peer_tsbpd_latency: Duration::from_millis(20),
+            peer_stats: false,
+            congest_ctrl: CongestCtrlType::Live,
+            socket_type: SocketType::Datagram,
+            udt4: true,
+        },
+        None,
+    );
+    let mut sender = UdpFramed::new(send_sock, PacketCodec {});
+
+    let pack = |i: u32| {
+        Packet::Data(DataPacket {
+            seq_number: init_seq_num + i,
+            message_loc: PacketLocation::FIRST | PacketLocation::LAST,
+            in_order_delivery: false,
+            retransmitted: false,
+            encryption: DataEncryption::None,
+            message_number: MsgNumber::new_truncate(i),
+            timestamp: TimeStamp(0),
+            dest_sockid: SocketID(2),
+            payload: Bytes::from(i.to_string()),
+        })
+    };
+
+    // 0 is missing, so 1 goes first, and 0 isn't given up on
+    sender.send((pack(1), recv_addr)).await?;
+    let (_, payload) = recvr.try_next().await?.unwrap();
+    assert_eq!(payload, "1");
+    assert_eq!(recvr.dropped_packets(), 0);
+
+    sender.send((pack(0), recv_addr)).await?;
+    let (_, payload) = recvr.try_next().await?.unwrap();
+    assert_eq!(payload, "0");
+
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use bytes::Bytes;
+use failure::Error;
+use futures::{try_join, SinkExt, StreamExt, TryStreamExt};
+use tokio::time::delay_for;
+
+use srt::{ConnInitMethod, MsgCtrl, SrtSocketBuilder};
+
+use std::time::{Duration, Instant};
+
+// a message's source time is what it's delivered with, and its latency counts
+// from then
+#[tokio::test]
+async fn send_msg_src_time() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6068)
+        .latency(Duration::from_millis(200))
+        .connect();
+
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6068".parse().unwrap()))
+        .latency(Duration::from_millis(200))
+        .connect();
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr)?;
+    // timestamps can't be from before the connection
+    delay_for(Duration::from_millis(150)).await;
+
+    let src_time = Instant::now() - Duration::from_millis(100);
+    let ctrl = MsgCtrl::new()
+        .in_order(true)
+        .src_time(src_time)
+        .ttl(Duration::from_secs(1));
+
+    let start = Instant::now();
+    let (received, ()) = try_join!(
+        async { recvr.next().await.unwrap() },
+        sender.send_msg(Bytes::from("Hello World!"), ctrl),
+    )?;
+    let (time, payload) = received;
+
+    assert_eq!(&payload, "Hello World!");
+    assert!(
+        start.elapsed() < Duration::from_millis(180),
+        "Delivered {:?} after sending, not counting from the source time",
+        start.elapsed()
+    );
+    assert!(
+        time <= src_time + Duration::from_millis(5),
+        "Delivered with {:?} after the source time",
+        time - src_time
+    );
+
+    Ok(())
+}
+
+// messages given to the socket in a burst are delivered with the spacing of
+// their source times, not of when they were sent
+#[tokio::test]
+async fn source_pacing() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6069)
+        .latency(Duration::from_millis(500))
+        .connect();
+
+    // long enough to cover the wait before sending
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6069".parse().unwrap()))
+        .latency(Duration::from_millis(500))
+        .connect();
+
+    let (mut sender, recvr) = try_join!(sender, recvr)?;
+
+    // captured 40ms apart, but only sent once the last one was
+    let first = Instant::now();
+    delay_for(Duration::from_millis(120)).await;
+    let src_times: Vec<_> = (0..4)
+        .map(|i| first + Duration::from_millis(40 * i))
+        .collect();
+
+    let s_fut = async {
+        for (i, src_time) in src_times.iter().enumerate() {
+            sender
+                .send_msg(
+                    Bytes::from(i.to_string()),
+                    MsgCtrl::new().src_time(*src_time),
+                )
+                .await?;
+        }
+        sender.close().await
+    };
+    let r_fut = recvr
+        .map(|received| received.map(|(time, _)| (time, Instant::now())))
+        .try_collect::<Vec<_>>();
+
+    let ((), received) = try_join!(s_fut, r_fut)?;
+    assert_eq!(received.len(), 4);
+    for (i, window) in received.windows(2).enumerate() {
+        let ((time, delivered), (next_time, next_delivered)) = (window[0], window[1]);
+        assert_eq!(next_time - time, src_times[i + 1] - src_times[i]);
+
+        let spacing = next_delivered - delivered;
+        assert!(
+            spacing > Duration::from_millis(30) && spacing < Duration::from_millis(50),
+            "Delivered {:?} apart",
+            spacing
+        );
+    }
+
+    Ok(())
+}
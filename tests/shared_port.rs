@@ -0,0 +1,89 @@
+use std::time::Instant;
+
+use bytes::Bytes;
+use failure::Error;
+use futures::channel::mpsc;
+use futures::{try_join, SinkExt, StreamExt, TryStreamExt};
+use srt::{ConnInitMethod, SrtListener, SrtSocket, SrtSocketBuilder};
+
+// keep the listener polled, handing over the connections it accepts
+fn accept(mut listener: SrtListener) -> mpsc::UnboundedReceiver<SrtSocket> {
+    let (tx, rx) = mpsc::unbounded();
+    tokio::spawn(async move {
+        while let Some(sock) = listener.try_next().await.unwrap() {
+            if tx.unbounded_send(sock).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+async fn exchange(a: &mut SrtSocket, b: &mut SrtSocket) -> Result<(), Error> {
+    let (_, received) = try_join!(
+        a.send((Instant::now(), Bytes::from("one port"))),
+        b.try_next()
+    )?;
+    let (_, data) = received.expect("Connection closed");
+    assert_eq!(data, "one port");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn connect_from_listener_port() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6039)
+        .build_listener()
+        .await?;
+    let connector = listener.connector();
+    let mut accepted = accept(listener);
+
+    // one connection out of the listener's port, and one into it
+    let remote = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6040)
+        .connect();
+    let outgoing = connector.connect(SrtSocketBuilder::new(ConnInitMethod::Connect(
+        "127.0.0.1:6040".parse()?,
+    )));
+    let caller =
+        SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6039".parse()?)).connect();
+
+    let (mut remote, mut outgoing, mut caller) = try_join!(remote, outgoing, caller)?;
+    let mut incoming = accepted.next().await.expect("Listener stopped");
+
+    assert_eq!(remote.settings().remote, "127.0.0.1:6039".parse()?);
+    exchange(&mut outgoing, &mut remote).await?;
+    exchange(&mut remote, &mut outgoing).await?;
+    exchange(&mut caller, &mut incoming).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rendezvous_from_listener_port() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6041)
+        .build_listener()
+        .await?;
+    let connector = listener.connector();
+    let _accepted = accept(listener);
+
+    let outgoing = connector.connect(SrtSocketBuilder::new(ConnInitMethod::Rendezvous(
+        "127.0.0.1:6042".parse()?,
+    )));
+    let peer = SrtSocketBuilder::new(ConnInitMethod::Rendezvous("127.0.0.1:6041".parse()?))
+        .local_port(6042)
+        .connect();
+
+    let (mut outgoing, mut peer) = try_join!(outgoing, peer)?;
+
+    exchange(&mut outgoing, &mut peer).await?;
+    exchange(&mut peer, &mut outgoing).await?;
+
+    Ok(())
+}
@@ -18,9 +18,9 @@ use futures::{stream, FutureExt, SinkExt, StreamExt, TryStreamExt};
 use bytes::Bytes;
 
 #[cfg(target_os = "windows")]
-const STRANSMIT_NAME: &'static str = "stransmit-rs.exe";
+const STRANSMIT_NAME: &str = "stransmit-rs.exe";
 #[cfg(not(target_os = "windows"))]
-const STRANSMIT_NAME: &'static str = "stransmit-rs";
+const STRANSMIT_NAME: &str = "stransmit-rs";
 
 fn find_stransmit_rs() -> PathBuf {
     let mut stransmit_rs_path = env::current_exe().unwrap();
@@ -127,7 +127,7 @@ fn ui_test(flags: &[&str], stderr: &str) {
 
             let mut string = String::new();
             child.stderr.unwrap().read_to_string(&mut string).unwrap();
-            if &string != stderr {
+            if string != stderr {
                 panic!(
                     "Expected stderr did not match actual. Actual:\n{}\n\nExpected:\n{}\n",
                     string, stderr
@@ -0,0 +1,33 @@
+use srt::{ConnInitMethod, SrtSocketBuilder};
+
+use failure::Error;
+use futures::try_join;
+
+#[tokio::test]
+async fn stream_id() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6005".parse()?))
+        .stream_id("live/feed 1".to_string())
+        .connect();
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6005)
+        .connect();
+
+    let (sender, recvr) = try_join!(sender, recvr)?;
+
+    assert_eq!(sender.stream_id(), Some("live/feed 1"));
+    assert_eq!(recvr.stream_id(), Some("live/feed 1"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn stream_id_too_long() {
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6006".parse().unwrap()))
+        .stream_id("a".repeat(513))
+        .connect()
+        .await;
+
+    assert!(res.is_err());
+}
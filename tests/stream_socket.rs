@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use failure::Error;
+use futures::{future, try_join};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use srt::{ConnInitMethod, RejectReason, SrtSocketBuilder};
+
+mod lossy_conn;
+use crate::lossy_conn::LossyConn;
+
+// a byte stream written in pieces of any size arrives whole, in payloads that
+// each fit in a packet
+#[tokio::test]
+async fn stream_socket() {
+    let _ = env_logger::try_init();
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6063)
+        .connect_stream();
+
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6063".parse().unwrap()))
+        .connect_stream();
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr).unwrap();
+
+    let data: Vec<u8> = (0..20_000).map(|i| i as u8).collect();
+
+    let s_fut = async {
+        for piece in data.chunks(777) {
+            sender.write_all(piece).await?;
+        }
+        sender.shutdown().await?;
+        Ok(()) as Result<_, Error>
+    };
+
+    let r_fut = async {
+        let mut received = Vec::new();
+        recvr.read_to_end(&mut received).await?;
+        Ok(received) as Result<_, Error>
+    };
+
+    let ((), received) = try_join!(s_fut, r_fut).unwrap();
+    assert_eq!(received, data);
+}
+
+// with far more delay than latency, message mode would drop most of the
+// payloads for being too late, but buffer mode waits for all of them
+#[tokio::test]
+async fn lossy_stream_arrives_whole() {
+    let _ = env_logger::try_init();
+
+    // 5% packet loss, 200ms delay
+    let (send, recv) =
+        LossyConn::channel(0.05, Duration::from_millis(200), Duration::from_millis(50));
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(1111)
+        .latency(Duration::from_millis(20))
+        .too_late_drop(true)
+        .connect_stream_with_sock(send);
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:1111".parse().unwrap()))
+        .latency(Duration::from_millis(20))
+        .connect_stream_with_sock(recv);
+
+    let (mut sender, mut recvr) = try_join!(sender, recvr).unwrap();
+
+    let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+
+    let s_fut = async {
+        for piece in data.chunks(777) {
+            sender.write_all(piece).await?;
+        }
+        sender.shutdown().await?;
+        Ok(()) as Result<_, Error>
+    };
+
+    let r_fut = async {
+        let mut received = Vec::new();
+        recvr.read_to_end(&mut received).await?;
+        Ok(received) as Result<_, Error>
+    };
+
+    let ((), received): ((), Vec<u8>) = try_join!(s_fut, r_fut).unwrap();
+    assert_eq!(received.len(), data.len());
+    assert_eq!(received, data);
+    assert_eq!(recvr.stats().recv_dropped_packets, 0);
+}
+
+// a buffer mode caller can't connect to a message mode listener
+#[tokio::test]
+async fn message_listener_refuses_stream_caller() {
+    let _ = env_logger::try_init();
+
+    let listener = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6071)
+        .connect();
+
+    let caller = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6071".parse().unwrap()))
+        .connect_stream();
+
+    // the listener keeps waiting for a caller that matches
+    let refused = match future::select(Box::pin(listener), Box::pin(caller)).await {
+        future::Either::Left(_) => panic!("Listener accepted a stream caller"),
+        future::Either::Right((refused, _)) => refused,
+    };
+
+    let err = refused.err().expect("Stream caller was accepted");
+    assert_eq!(
+        err.downcast_ref::<RejectReason>(),
+        Some(&RejectReason::MessageApi)
+    );
+}
@@ -0,0 +1,78 @@
+use srt::{ConnInitMethod, SrtSocketBuilder};
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{try_join, SinkExt, TryStreamExt};
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn udt4_round_trip() -> Result<(), Error> {
+    let _ = env_logger::try_init();
+
+    let sender = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6008".parse()?))
+        .udt4(true)
+        .connect();
+    let recvr = SrtSocketBuilder::new(ConnInitMethod::Listen)
+        .local_port(6008)
+        .udt4(true)
+        .connect();
+
+    let (mut sender, recvr) = try_join!(sender, recvr)?;
+    assert!(sender.settings().udt4);
+    assert!(recvr.settings().udt4);
+    // nothing negotiated, but messages are released without waiting for it
+    assert_eq!(recvr.latency(), Duration::from_millis(50));
+
+    let (_, received) = futures::join!(
+        async {
+            for i in 0..10 {
+                sender
+                    .send((Instant::now(), Bytes::from(i.to_string())))
+                    .await?;
+            }
+            sender.close().await?;
+            Ok(()) as Result<_, Error>
+        },
+        recvr.try_collect::<Vec<_>>()
+    );
+
+    let received: Vec<_> = received?.into_iter().map(|(_, data)| data).collect();
+    let expected: Vec<_> = (0..10).map(|i| Bytes::from(i.to_string())).collect();
+    assert_eq!(received, expected);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn srt_caller_refuses_udt4_listener() {
+    // the listener waits for a conclusion that never comes, so leave it be
+    tokio::spawn(
+        SrtSocketBuilder::new(ConnInitMethod::Listen)
+            .local_port(6009)
+            .udt4(true)
+            .connect(),
+    );
+
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6009".parse().unwrap()))
+        .connect()
+        .await;
+
+    assert_eq!(
+        res.err().unwrap().to_string(),
+        "127.0.0.1:6009 only speaks HSv4 (UDT4), expected HSv5"
+    );
+}
+
+#[tokio::test]
+async fn udt4_without_srt_options() {
+    let res = SrtSocketBuilder::new(ConnInitMethod::Connect("127.0.0.1:6010".parse().unwrap()))
+        .udt4(true)
+        .stream_id("feed".to_string())
+        .connect()
+        .await;
+
+    assert_eq!(
+        res.err().unwrap().to_string(),
+        "Stream IDs aren't available in UDT4 mode"
+    );
+}